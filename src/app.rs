@@ -1,212 +1,1760 @@
+use crate::audio::{AudioSystem, EmitterHandle, SoundHandle};
+use crate::build_mode::BuildMode;
+use crate::camera::CameraMode;
+use crate::debug_ui::{DebugUi, PaletteSource};
+use crate::gamepad::{GamepadInput, GamepadSettings};
+use crate::input_map::InputMap;
 use crate::physics::ColliderShape;
-use crate::player::Player;
-use crate::renderer::PbrMaterialDefinition;
+use crate::player::{InteriorPlayer, PhysicsPlayer, Player};
+use crate::projectile::{ProjectileEntity, ProjectileMode};
+use crate::renderer::{
+    DrawMode, MaterialHandle, MeshHandle, PbrMaterialDefinition, RenderStats, SceneData,
+    ShadingModel, SkyboxSource, HEADLESS_FORMAT,
+};
+use crate::settings::{Backend, FullscreenMode, Settings, VsyncMode};
+use crate::space_craft::{AttachmentDefinition, ModuleTable};
+use crate::thumbnails::{ModuleThumbnails, THUMBNAIL_DIRECTORY};
+use crate::trajectory::TrajectoryPredictor;
 use crate::transform::Transform;
-use crate::world::{DynamicEntity, World};
+use crate::world::{
+    Autopilot, DynamicEntity, Entity, EntityId, FlightMode, PlayerAction, SensorZoneEntity,
+    SpaceCraftEntity, StaticEntity, StaticPart, World,
+};
 use crate::Renderer;
-use glam::Vec3;
+use glam::{DVec3, Vec2, Vec3};
 use log::{error, info, warn};
-use nalgebra::Point;
-use rapier3d::prelude::SharedShape;
-use std::collections::HashMap;
-use std::fmt::Debug;
+use std::collections::{HashMap, VecDeque};
 use std::path::Path;
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::Arc;
 use winit::dpi::PhysicalSize;
-use winit::event::VirtualKeyCode;
-use winit::window::Window;
+use winit::event::WindowEvent;
+use winit::window::{CursorGrabMode, Window};
 use winit_input_helper::WinitInputHelper;
 
+const QUICK_SAVE_PATH: &str = "quicksave.json";
+const BINDINGS_PATH: &str = "bindings.ron";
+const SETTINGS_PATH: &str = "settings.ron";
+const MODULE_DIRECTORY: &str = "resource/module/";
+/// Where the interior player starts inside the test craft, relative to its body
+const INTERIOR_SPAWN_POSITION: Vec3 = Vec3::new(0.0, 0.0, 1.0);
+const GAME_WORLD: &str = "game";
+const MENU_WORLD: &str = "menu";
+const PROJECTILE_SPEED: f32 = 100.0;
+const PROJECTILE_DAMAGE: f32 = 25.0;
+const PROJECTILE_LIFETIME: f32 = 3.0;
+const PROJECTILE_RADIUS: f32 = 0.05;
+/// In front of the camera so shots start outside the player's own collider
+const PROJECTILE_SPAWN_DISTANCE: f32 = 1.0;
+/// How far from the camera a cockpit can be to take control of its craft
+const INTERACT_RANGE: f32 = 10.0;
+/// Where the player is put when leaving a craft, relative to the craft's camera
+const EXIT_OFFSET: Vec3 = Vec3::new(0.0, 2.0, 0.0);
+/// Meters the third person camera moves per step of the mouse wheel
+const CAMERA_ZOOM_STEP: f32 = 1.0;
+/// How far from the target the approach autopilot stops
+const APPROACH_STANDOFF_DISTANCE: f32 = 25.0;
+const ASTEROID_FIELD_SEED: u64 = 1;
+/// Far enough out that f32 positions would visibly jitter without the floating origin
+const FAR_TEST_POSITION: DVec3 = DVec3::new(100000.0, 0.0, 100000.0);
+/// Spin of the ship shown behind the main menu in rad/s
+const MENU_SHIP_SPIN: Vec3 = Vec3::new(0.0, 0.2, 0.05);
+/// Frames the profiler's averages and percentiles are taken over
+const PROFILE_HISTORY: usize = 300;
+/// Seconds between profile reports in the log
+const PROFILE_REPORT_INTERVAL: f32 = 5.0;
+const IMPACT_SOUND_PATH: &str = "resource/sound/impact.wav";
+const THRUSTER_SOUND_PATH: &str = "resource/sound/thruster_loop.wav";
+/// Quieter impacts don't make a sound, in N*s
+const IMPACT_SOUND_THRESHOLD: f32 = 5.0;
+/// Impulse that plays the impact sound at full volume
+const IMPACT_SOUND_FULL_IMPULSE: f32 = 200.0;
+/// Pitch of the thruster rumble at no and full throttle
+const THRUSTER_PITCH_RANGE: (f32, f32) = (0.7, 1.4);
+
+/// Where frames end up
+enum RenderOutput {
+    Surface {
+        surface: wgpu::Surface,
+        present_modes: Vec<wgpu::PresentMode>,
+    },
+    /// Headless, frames are rendered into this and never shown
+    Offscreen(wgpu::Texture),
+}
+
 pub struct App {
     pub input: WinitInputHelper,
-    surface: wgpu::Surface,
+    pub profiler: FrameProfiler,
+    output: RenderOutput,
     device: Arc<wgpu::Device>,
+    queue: Arc<wgpu::Queue>,
+    /// Set by the device's error handler, the device is recreated before the next frame
+    device_lost: Arc<AtomicBool>,
 
     surface_size: [u32; 2],
     surface_config: wgpu::SurfaceConfiguration,
+    /// What was loaded at startup, runtime changes are copied in when saving
+    settings: Settings,
 
     renderer: Renderer,
 
-    world: World,
-}
+    worlds: HashMap<String, World>,
+    active_world: String,
+    //The player and craft ids below belong to the game world
+    /// Entity the player returns to when leaving a craft
+    player_entity: EntityId,
+    test_craft: Option<EntityId>,
+    /// Walking around inside the test craft
+    interior_player: Option<EntityId>,
+    module_table: ModuleTable,
+    /// Pictures of the modules for the build palette
+    module_thumbnails: ModuleThumbnails,
+    build_mode: BuildMode,
+    attachment_table: HashMap<String, AttachmentDefinition>,
+    draw_physics_debug: bool,
+    trajectory: TrajectoryPredictor,
+    weapon_mode: ProjectileMode,
+    projectile_model: Option<(MeshHandle, MaterialHandle)>,
+    /// Spawned by the debug inspector
+    cube_model: Option<(MeshHandle, MaterialHandle)>,
 
-impl App {
-    pub fn new(window: &Window) -> Self {
-        let instance = wgpu::Instance::new(wgpu::InstanceDescriptor {
-            backends: wgpu::Backends::all(),
-            dx12_shader_compiler: Default::default(),
-        });
-        let surface = unsafe { instance.create_surface(&window) }.unwrap();
+    /// Raw mouse motion since the last update, in pixels
+    mouse_delta: Vec2,
+    mouse_sensitivity: f32,
+    /// Wanted grab state, kept while the window is unfocused so it can be restored
+    cursor_grabbed: bool,
 
-        let adapter = pollster::block_on(instance.request_adapter(&wgpu::RequestAdapterOptions {
-            power_preference: wgpu::PowerPreference::HighPerformance,
-            compatible_surface: Some(&surface),
-            force_fallback_adapter: false,
-        }))
-        .unwrap();
+    gamepad: GamepadInput,
+    input_map: InputMap,
+    debug_ui: DebugUi,
+    /// Shown by the debug ui on the next update
+    last_render_stats: RenderStats,
 
-        let info: wgpu::AdapterInfo = adapter.get_info();
+    audio: AudioSystem,
+    impact_sound: Option<SoundHandle>,
+    thruster_sound: Option<SoundHandle>,
+    /// Thruster rumble of every craft in the active world
+    thruster_emitters: HashMap<EntityId, EmitterHandle>,
+}
 
-        let (device, queue) = pollster::block_on(adapter.request_device(
-            &wgpu::DeviceDescriptor {
-                label: None,
-                features: wgpu::Features::default(),
-                limits: wgpu::Limits::default(),
-            },
-            None,
-        ))
-        .unwrap();
+impl App {
+    pub fn new(window: &Window, settings: Settings) -> Self {
+        let (surface, adapter) = create_adapter(&settings, Some(window));
+        let surface = surface.unwrap();
+        let (device, queue) = crate::renderer::request_device(&adapter);
 
+        let adapter = Arc::new(adapter);
         let device = Arc::new(device);
         let queue = Arc::new(queue);
 
+        let surface_capabilities = surface.get_capabilities(&adapter);
+
+        //Prefer an srgb format so the hardware handles the output encoding
+        let surface_formats = surface_capabilities.formats;
+        let surface_format = surface_formats
+            .iter()
+            .copied()
+            .find(|format| format.describe().srgb)
+            .unwrap_or(surface_formats[0]);
+
+        //USG_VSYNC=off for uncapped frame rates, USG_VSYNC=on for strict vsync, overriding the settings
+        let present_modes = surface_capabilities.present_modes;
+        let present_mode = supported_present_mode(
+            match std::env::var("USG_VSYNC").as_deref() {
+                Ok("off") => wgpu::PresentMode::Immediate,
+                Ok("on") => wgpu::PresentMode::Fifo,
+                _ => settings.vsync.present_mode(),
+            },
+            &present_modes,
+        );
+
         let window_size = window.inner_size();
         let surface_config = wgpu::SurfaceConfiguration {
             usage: wgpu::TextureUsages::RENDER_ATTACHMENT,
-            format: surface.get_capabilities(&adapter).formats[0],
+            format: surface_format,
             width: window_size.width,
             height: window_size.height,
-            present_mode: wgpu::PresentMode::Mailbox,
+            present_mode,
             alpha_mode: wgpu::CompositeAlphaMode::Auto,
             view_formats: Vec::new(),
         };
         surface.configure(&device, &surface_config);
 
-        let mut renderer = Renderer::new(device.clone(), queue);
+        Self::with_output(
+            adapter,
+            device,
+            queue,
+            RenderOutput::Surface {
+                surface,
+                present_modes,
+            },
+            surface_config,
+            window.scale_factor() as f32,
+            settings,
+        )
+    }
+
+    /// No window or surface, frames are rendered offscreen so the game can be driven for profiling or CI
+    pub fn new_headless(settings: Settings) -> Self {
+        let (_, adapter) = create_adapter(&settings, None);
+        let (device, queue) = crate::renderer::request_device(&adapter);
+
+        let surface_config = wgpu::SurfaceConfiguration {
+            usage: wgpu::TextureUsages::RENDER_ATTACHMENT,
+            format: HEADLESS_FORMAT,
+            width: settings.window_size[0],
+            height: settings.window_size[1],
+            present_mode: wgpu::PresentMode::Fifo,
+            alpha_mode: wgpu::CompositeAlphaMode::Auto,
+            view_formats: Vec::new(),
+        };
+        let offscreen_texture = create_offscreen_texture(&device, &surface_config);
+
+        let mut app = Self::with_output(
+            Arc::new(adapter),
+            Arc::new(device),
+            Arc::new(queue),
+            RenderOutput::Offscreen(offscreen_texture),
+            surface_config,
+            1.0,
+            settings,
+        );
+        //Nothing can leave the menu without input
+        app.set_active_world(GAME_WORLD);
+        app
+    }
+
+    fn with_output(
+        adapter: Arc<wgpu::Adapter>,
+        device: Arc<wgpu::Device>,
+        queue: Arc<wgpu::Queue>,
+        output: RenderOutput,
+        surface_config: wgpu::SurfaceConfiguration,
+        scale_factor: f32,
+        settings: Settings,
+    ) -> Self {
+        let device_lost = crate::renderer::watch_device_errors(&device);
+        let mut audio = AudioSystem::new(
+            settings.master_volume,
+            settings.sfx_volume,
+            matches!(output, RenderOutput::Surface { .. }),
+        );
+        let impact_sound = load_sound(&mut audio, IMPACT_SOUND_PATH);
+        let thruster_sound = load_sound(&mut audio, THRUSTER_SOUND_PATH);
+        let mut renderer = Renderer::new(
+            adapter,
+            device.clone(),
+            queue.clone(),
+            surface_config.format,
+        );
+        let debug_ui = DebugUi::new(&device, surface_config.format, scale_factor);
+        renderer.set_sample_count(settings.msaa_samples);
+        if settings.flip_front_faces {
+            renderer.set_front_face(wgpu::FrontFace::Ccw);
+        }
+        renderer.set_skybox(SkyboxSource::Procedural {
+            star_density: 0.05,
+            seed: 0,
+        });
 
         let mut world = World::new(&mut renderer);
+        world.world_info.player_camera.set_fov(settings.fov_deg);
+        world
+            .world_info
+            .rendering
+            .set_distance_scale(settings.draw_distance_scale);
 
-        let camera_id = world.add_entity(Player::new(Transform::default()));
+        let camera_id = world.add_entity_named(
+            Player::new_inertial(Transform::default(), 0.5),
+            "Player",
+            &["player"],
+        );
         world.set_player(camera_id);
 
-        world.add_entity(DynamicEntity::new(
-            Transform::new_pos(Vec3::new(0.0, 0.0, 15.0)),
-            Some((
-                renderer.load_mesh("resource/mesh/Cube.obj").unwrap(),
+        let cube_material = renderer
+            .get_or_create_material(
+                "cube",
+                PbrMaterialDefinition {
+                    color: [0.1, 0.75, 0.55, 1.0],
+                    metallic: 0.5,
+                    roughness: 0.5,
+                    albedo_texture: None,
+                    normal_texture: None,
+                    emissive: [0.0; 3],
+                    transparent: false,
+                    shading_model: ShadingModel::Lit,
+                    cull_mode: None,
+                },
+            )
+            .unwrap();
+
+        let cube_model = (
+            renderer.get_or_load_mesh("resource/mesh/Cube.obj").unwrap(),
+            cube_material,
+        );
+        world.add_entity(
+            DynamicEntity::new(
+                Transform::new_pos(Vec3::new(0.0, 0.0, 15.0)),
+                Some(cube_model),
+                Some(ColliderShape::Box(glam::Vec3::splat(0.5))),
+            )
+            .with_velocity(Vec3::new(0.0, 0.0, 2.0), Vec3::ZERO),
+        );
+
+        //The cube drifts into this and bounces off
+        let asteroid_material = crate::asteroid_field::asteroid_material(&mut renderer).unwrap();
+        let asteroid_radius = 3.0;
+        let asteroid_part = StaticPart {
+            transform: Transform {
+                //The sphere mesh has a radius of 0.5
+                scale: Vec3::splat(asteroid_radius * 2.0),
+                ..Default::default()
+            },
+            model: Some((
                 renderer
-                    .create_material(PbrMaterialDefinition {
-                        color: [0.1, 0.75, 0.55, 1.0],
-                        metallic: 0.5,
-                        roughness: 0.5,
-                    })
+                    .get_or_load_mesh("resource/mesh/Sphere.obj")
                     .unwrap(),
+                asteroid_material,
             )),
-            Some(ColliderShape::Box(glam::Vec3::splat(0.5))),
+            collider: Some(ColliderShape::Sphere(asteroid_radius)),
+        };
+        world.add_entity_named(
+            StaticEntity::new(
+                Transform::new_pos(Vec3::new(0.0, 0.0, 25.0)),
+                vec![asteroid_part.clone()],
+            ),
+            "Asteroid",
+            &["environment"],
+        );
+        //F8 teleports there to check the floating origin keeps it steady
+        world.add_entity_named(
+            StaticEntity::new(
+                Transform::new_pos(FAR_TEST_POSITION.as_vec3()),
+                vec![asteroid_part],
+            ),
+            "Far Asteroid",
+            &["environment"],
+        );
+
+        let field = world.spawn_asteroid_field(
+            ASTEROID_FIELD_SEED,
+            Vec3::new(0.0, 0.0, 600.0),
+            400.0,
+            1500,
+            &mut renderer,
+        );
+        info!("Spawned {} asteroids", field.len());
+
+        world.add_entity(SensorZoneEntity::new(
+            "Cube Zone",
+            Transform::new_pos(Vec3::new(0.0, 0.0, 15.0)),
+            ColliderShape::Sphere(3.0),
         ));
 
-        let mut module_table = HashMap::new();
+        if std::env::var("USG_STRESS_SCENE").is_ok() {
+            create_stress_scene(&mut renderer, &mut world, 10_000);
+        }
+
+        let projectile_model = renderer.get_or_load_mesh("resource/mesh/Sphere.obj").zip(
+            renderer.get_or_create_material(
+                "projectile",
+                PbrMaterialDefinition {
+                    color: [1.0, 0.6, 0.2, 1.0],
+                    metallic: 0.0,
+                    roughness: 0.5,
+                    albedo_texture: None,
+                    normal_texture: None,
+                    emissive: [4.0, 2.0, 0.5],
+                    transparent: false,
+                    shading_model: ShadingModel::Emissive,
+                    cull_mode: None,
+                },
+            ),
+        );
+
+        let mut module_table = ModuleTable::default();
         crate::space_craft::load_modules_from_directory(
-            Path::new("resource/module/"),
+            Path::new(MODULE_DIRECTORY),
             &mut module_table,
         );
+        let module_thumbnails =
+            ModuleThumbnails::new(&mut renderer, &module_table, Path::new(THUMBNAIL_DIRECTORY));
+        let mut attachment_table = HashMap::new();
+        crate::space_craft::load_attachments_from_directory(
+            Path::new(MODULE_DIRECTORY),
+            &mut attachment_table,
+        );
 
-        Self {
+        let test_craft = crate::space_craft::load_space_craft_definition(
+            "resource/spacecraft/test_craft.spacecraft",
+            &module_table,
+        )
+        .map(|definition| {
+            world.add_entity_named(
+                SpaceCraftEntity::new(
+                    Transform::new_pos(Vec3::new(5.0, 0.0, 20.0)),
+                    &definition,
+                    &module_table,
+                    &attachment_table,
+                    &mut renderer,
+                )
+                .with_interior(true),
+                &definition.name,
+                &["spacecraft"],
+            )
+        });
+
+        if let Some(definition) = crate::space_craft::load_space_craft_definition(
+            "resource/spacecraft/test_shuttle.spacecraft",
+            &module_table,
+        ) {
+            world.add_entity_named(
+                SpaceCraftEntity::new(
+                    Transform::new_pos(Vec3::new(5.0, 0.0, 28.0)),
+                    &definition,
+                    &module_table,
+                    &attachment_table,
+                    &mut renderer,
+                ),
+                &definition.name,
+                &["spacecraft"],
+            );
+        }
+
+        let mut app = Self {
             input: WinitInputHelper::new(),
-            surface,
+            output,
             device,
-            surface_size: [window_size.width, window_size.height],
+            queue,
+            device_lost,
+            surface_size: [surface_config.width, surface_config.height],
             surface_config,
             renderer,
-            world,
+            worlds: HashMap::from([(GAME_WORLD.to_string(), world)]),
+            active_world: MENU_WORLD.to_string(),
+            player_entity: camera_id,
+            test_craft,
+            interior_player: None,
+            build_mode: BuildMode::new(&module_table),
+            module_table,
+            module_thumbnails,
+            attachment_table,
+            draw_physics_debug: false,
+            trajectory: TrajectoryPredictor::default(),
+            weapon_mode: ProjectileMode::Physical,
+            projectile_model,
+            cube_model: Some(cube_model),
+            mouse_delta: Vec2::ZERO,
+            //USG_MOUSE_SENSITIVITY overrides the settings
+            mouse_sensitivity: std::env::var("USG_MOUSE_SENSITIVITY")
+                .ok()
+                .and_then(|value| value.parse().ok())
+                .unwrap_or(settings.mouse_sensitivity),
+            cursor_grabbed: false,
+            gamepad: GamepadInput::new(GamepadSettings::default()),
+            input_map: load_input_map(),
+            debug_ui,
+            last_render_stats: RenderStats::default(),
+            audio,
+            impact_sound,
+            thruster_sound,
+            thruster_emitters: HashMap::new(),
+            settings,
+            profiler: FrameProfiler::default(),
+        };
+        app.create_menu_world();
+        app
+    }
+
+    /// A slowly spinning copy of the test craft in front of the camera
+    fn create_menu_world(&mut self) {
+        self.create_world(MENU_WORLD);
+        let world = self.worlds.get_mut(MENU_WORLD).unwrap();
+
+        let camera_id = world.add_entity_named(
+            PhysicsPlayer::new(Transform::new_pos(Vec3::new(0.0, 0.0, -10.0)), 0.5),
+            "Menu Camera",
+            &["player"],
+        );
+        world.set_player(camera_id);
+
+        if let Some(definition) = crate::space_craft::load_space_craft_definition(
+            "resource/spacecraft/test_craft.spacecraft",
+            &self.module_table,
+        ) {
+            let mut craft = SpaceCraftEntity::new(
+                Transform::default(),
+                &definition,
+                &self.module_table,
+                &self.attachment_table,
+                &mut self.renderer,
+            );
+            //Assist would hold the craft still
+            craft.set_flight_mode(FlightMode::Direct);
+            let craft = world.add_entity_named(craft, &definition.name, &["spacecraft"]);
+
+            if let Some(rigid_body) = world
+                .get_entity::<SpaceCraftEntity>(craft)
+                .and_then(|craft| craft.get_rigid_body())
+            {
+                world
+                    .world_info
+                    .physics
+                    .set_rigid_body_angular_velocity(rigid_body, MENU_SHIP_SPIN);
+            }
         }
     }
 
+    /// Creates an empty world, an existing world with the same name is destroyed first
+    pub fn create_world(&mut self, name: &str) -> &mut World {
+        if let Some(world) = self.worlds.remove(name) {
+            warn!("Replacing existing world {}", name);
+            world.destroy();
+        }
+        let mut world = World::new(&mut self.renderer);
+        world
+            .world_info
+            .player_camera
+            .set_fov(self.settings.fov_deg);
+        world
+            .world_info
+            .rendering
+            .set_distance_scale(self.settings.draw_distance_scale);
+        self.worlds.entry(name.to_string()).or_insert(world)
+    }
+
+    /// Returns false if there is no world with that name
+    pub fn set_active_world(&mut self, name: &str) -> bool {
+        if !self.worlds.contains_key(name) {
+            warn!("No world named {}", name);
+            return false;
+        }
+        if self.active_world != name {
+            let build_mode = &mut self.build_mode;
+            build_mode.disable(self.worlds.get_mut(&self.active_world).unwrap());
+            //Stop whatever the old world's player was doing
+            self.world_mut().update_player_input(Vec3::ZERO, Vec3::ZERO);
+            self.active_world = name.to_string();
+            info!("Switched to world {}", name);
+        }
+        true
+    }
+
+    /// The active world can't be destroyed, returns false if the world wasn't destroyed
+    pub fn destroy_world(&mut self, name: &str) -> bool {
+        if self.active_world == name {
+            warn!("Can't destroy the active world {}", name);
+            return false;
+        }
+        match self.worlds.remove(name) {
+            Some(world) => {
+                world.destroy();
+                true
+            }
+            None => false,
+        }
+    }
+
+    pub fn world(&self) -> &World {
+        &self.worlds[&self.active_world]
+    }
+
+    pub fn world_mut(&mut self) -> &mut World {
+        self.worlds.get_mut(&self.active_world).unwrap()
+    }
+
+    pub fn module_table(&self) -> &ModuleTable {
+        &self.module_table
+    }
+
+    /// Rereads module definitions and meshes from disk, already spawned instances pick up the new meshes
+    pub fn reload_assets(&mut self) {
+        let module_count = crate::space_craft::reload_modules_from_directory(
+            Path::new(MODULE_DIRECTORY),
+            &mut self.module_table,
+        );
+        let attachment_count = crate::space_craft::reload_attachments_from_directory(
+            Path::new(MODULE_DIRECTORY),
+            &mut self.attachment_table,
+        );
+        let mesh_count = self.renderer.reload_meshes();
+        self.module_thumbnails = ModuleThumbnails::new(
+            &mut self.renderer,
+            &self.module_table,
+            Path::new(THUMBNAIL_DIRECTORY),
+        );
+        info!(
+            "Reloaded {} modules, {} attachments and {} meshes",
+            module_count, attachment_count, mesh_count
+        );
+    }
+
+    /// A zero size (minimized window) stops rendering until a nonzero size arrives
     pub fn resize(&mut self, new_size: PhysicalSize<u32>) {
+        self.surface_size = [new_size.width, new_size.height];
         if new_size.width > 0 && new_size.height > 0 {
-            self.surface_size = [new_size.width, new_size.height];
             self.surface_config.width = new_size.width;
             self.surface_config.height = new_size.height;
-            self.surface.configure(&self.device, &self.surface_config);
+            self.configure_output();
+        }
+        self.renderer.resize(self.surface_size);
+    }
+
+    /// Applies the surface config to whatever frames are rendered into
+    fn configure_output(&mut self) {
+        match &mut self.output {
+            RenderOutput::Surface { surface, .. } => {
+                surface.configure(&self.device, &self.surface_config)
+            }
+            RenderOutput::Offscreen(texture) => {
+                *texture = create_offscreen_texture(&self.device, &self.surface_config)
+            }
         }
     }
 
-    pub fn update(&mut self, delta_time: f32) {
+    /// Reconfigures the surface with the new mode, unsupported modes fall back to Fifo
+    pub fn set_present_mode(&mut self, present_mode: wgpu::PresentMode) {
+        let RenderOutput::Surface {
+            surface,
+            present_modes,
+        } = &self.output
+        else {
+            return;
+        };
+
+        self.surface_config.present_mode = supported_present_mode(present_mode, present_modes);
+        info!("Present mode: {:?}", self.surface_config.present_mode);
+
+        if self.surface_size[0] > 0 && self.surface_size[1] > 0 {
+            surface.configure(&self.device, &self.surface_config);
+        }
+    }
+
+    /// Raw device motion, unlike window positions it keeps coming while the cursor is locked
+    pub fn mouse_motion(&mut self, delta: (f64, f64)) {
+        self.mouse_delta += Vec2::new(delta.0 as f32, delta.1 as f32);
+    }
+
+    /// Empty when running headless
+    fn present_modes(&self) -> &[wgpu::PresentMode] {
+        match &self.output {
+            RenderOutput::Surface { present_modes, .. } => present_modes,
+            RenderOutput::Offscreen(_) => &[],
+        }
+    }
+
+    /// Writes the settings back with whatever was changed while running
+    pub fn save_settings(&mut self) {
+        if let RenderOutput::Surface { .. } = self.output {
+            self.settings.vsync = VsyncMode::from_present_mode(self.surface_config.present_mode);
+        }
+        self.settings.msaa_samples = self.renderer.get_sample_count();
+        self.settings.mouse_sensitivity = self.mouse_sensitivity;
+        self.settings.master_volume = self.audio.master_volume;
+        self.settings.sfx_volume = self.audio.sfx_volume;
+
+        match self.settings.save(SETTINGS_PATH) {
+            Ok(()) => info!("Saved settings to {}", SETTINGS_PATH),
+            Err(e) => warn!("Failed to save settings: {}", e),
+        }
+    }
+
+    pub fn set_mouse_sensitivity(&mut self, sensitivity: f32) {
+        self.mouse_sensitivity = sensitivity;
+    }
+
+    /// Mouse-look only rotates the player while the cursor is grabbed
+    pub fn set_cursor_grabbed(&mut self, window: &Window, grabbed: bool) {
+        self.cursor_grabbed = grabbed;
+        apply_cursor_grab(window, grabbed);
+    }
+
+    /// The system drops the grab when the window loses focus, so it's reapplied when focus comes back
+    pub fn focus_changed(&mut self, window: &Window, focused: bool) {
+        if self.cursor_grabbed {
+            apply_cursor_grab(window, focused);
+        }
+        self.mouse_delta = Vec2::ZERO;
+
+        //Leaving exclusive fullscreen can resize or minimize the window without a resize event on some platforms
+        if focused {
+            self.resize(window.inner_size());
+        }
+    }
+
+    /// Switches between a window and the fullscreen mode from the settings
+    pub fn set_fullscreen(&mut self, window: &Window, fullscreen: bool) {
+        self.settings.fullscreen = fullscreen;
+        let mode = if fullscreen {
+            fullscreen_mode(window, self.settings.fullscreen_mode, self.settings.monitor)
+        } else {
+            None
+        };
+        info!("Fullscreen: {:?}", mode);
+        window.set_fullscreen(mode);
+
+        //Not every platform sends a resize for the switch, the surface and depth buffer need the new size either way
+        self.resize(window.inner_size());
+    }
+
+    /// Window events the input helper doesn't cover, like the cursor position for the debug ui
+    pub fn window_event(&mut self, event: &WindowEvent) {
+        self.debug_ui.window_event(event);
+    }
+
+    /// `window` is None when running headless
+    pub fn update(&mut self, window: Option<&Window>, delta_time: f32) {
+        self.profiler.next_frame();
+        self.profiler.begin("input");
+        self.debug_ui.record_frame(delta_time);
+
+        //Keyboard and gamepad bindings of an action are combined, whichever is pushed further wins
+        let gamepad_frame = self.gamepad.poll();
+        let ui_wants_pointer = self.debug_ui.wants_pointer_input();
+        self.input_map
+            .set_blocked(self.debug_ui.wants_keyboard_input(), ui_wants_pointer);
+        self.input_map.update(&self.input, gamepad_frame.as_ref());
+
         let linear_input = Vec3::new(
-            keys_to_axis(&self.input, VirtualKeyCode::D, VirtualKeyCode::A),
-            keys_to_axis(&self.input, VirtualKeyCode::Space, VirtualKeyCode::LShift),
-            keys_to_axis(&self.input, VirtualKeyCode::W, VirtualKeyCode::S),
+            self.input_map.axis("move_x"),
+            self.input_map.axis("move_y"),
+            self.input_map.axis("move_z"),
         );
-
         let angular_input = Vec3::new(
-            keys_to_axis(&self.input, VirtualKeyCode::Right, VirtualKeyCode::Left),
-            keys_to_axis(&self.input, VirtualKeyCode::Up, VirtualKeyCode::Down),
-            keys_to_axis(&self.input, VirtualKeyCode::E, VirtualKeyCode::Q),
+            self.input_map.axis("yaw"),
+            self.input_map.axis("pitch"),
+            self.input_map.axis("roll"),
+        );
+
+        if self.input_map.action_pressed("toggle_cursor_grab") {
+            if let Some(window) = window {
+                self.set_cursor_grabbed(window, !self.cursor_grabbed);
+            }
+        }
+
+        if self.input_map.action_pressed("toggle_draw_mode") {
+            self.renderer
+                .set_draw_mode(match self.renderer.get_draw_mode() {
+                    DrawMode::Filled => DrawMode::Wireframe,
+                    DrawMode::Wireframe => DrawMode::Filled,
+                });
+        }
+
+        if self.input_map.action_pressed("toggle_physics_debug") {
+            self.draw_physics_debug = !self.draw_physics_debug;
+        }
+
+        if self.input_map.action_pressed("toggle_trajectory") {
+            self.trajectory.toggle();
+        }
+
+        if self.input_map.action_pressed("toggle_trajectory_thrust") {
+            self.trajectory.toggle_thrust();
+            info!(
+                "Trajectory prediction includes thrust: {}",
+                self.trajectory.include_thrust
+            );
+        }
+
+        if self.input_map.action_pressed("toggle_debug_ui") {
+            self.debug_ui.toggle();
+        }
+
+        //Cycle through the supported present modes
+        if self.input_map.action_pressed("cycle_present_mode") {
+            let modes = [
+                wgpu::PresentMode::Fifo,
+                wgpu::PresentMode::Mailbox,
+                wgpu::PresentMode::Immediate,
+            ];
+            let current = modes
+                .iter()
+                .position(|mode| *mode == self.surface_config.present_mode)
+                .unwrap_or(0);
+            if let Some(next) = (1..modes.len())
+                .map(|offset| modes[(current + offset) % modes.len()])
+                .find(|mode| self.present_modes().contains(mode))
+            {
+                self.set_present_mode(next);
+            }
+        }
+
+        if self.input_map.action_pressed("reload_assets") {
+            self.reload_assets();
+        }
+
+        //Runs the same recovery as a real loss, the old device just keeps working until it's dropped
+        if self.input_map.action_pressed("simulate_device_lost") {
+            warn!("Simulating a lost device");
+            self.device_lost.store(true, Ordering::Relaxed);
+        }
+
+        if self.input_map.action_pressed("toggle_fullscreen") {
+            if let Some(window) = window {
+                self.set_fullscreen(window, !self.settings.fullscreen);
+            }
+        }
+
+        //Toggle between the main menu and the game
+        if self.input_map.action_pressed("toggle_menu") {
+            if self.active_world == MENU_WORLD {
+                self.set_active_world(GAME_WORLD);
+            } else {
+                self.set_active_world(MENU_WORLD);
+            }
+        }
+        let in_game = self.active_world == GAME_WORLD;
+
+        if in_game && self.input_map.action_pressed("quick_save") {
+            match self.world().save(QUICK_SAVE_PATH, &self.renderer) {
+                Ok(()) => info!("Saved world to {}", QUICK_SAVE_PATH),
+                Err(e) => error!("Failed to save world: {}", e),
+            }
+        }
+
+        if in_game && self.input_map.action_pressed("quick_load") {
+            match World::load(QUICK_SAVE_PATH, &mut self.renderer) {
+                Ok(mut world) => {
+                    world
+                        .world_info
+                        .rendering
+                        .set_distance_scale(self.settings.draw_distance_scale);
+                    //The ghost belongs to the old world's scene
+                    self.build_mode
+                        .disable(self.worlds.get_mut(GAME_WORLD).unwrap());
+                    self.player_entity = world.player_entity;
+                    if let Some(old_world) = self.worlds.insert(GAME_WORLD.to_string(), world) {
+                        old_world.destroy();
+                    }
+                    //Spacecraft aren't saved yet
+                    self.test_craft = None;
+                    self.interior_player = None;
+                    info!("Loaded world from {}", QUICK_SAVE_PATH);
+                }
+                Err(e) => error!("Failed to load world: {}", e),
+            }
+        }
+
+        if self.input_map.action_pressed("pause") {
+            let world = self.world_mut();
+            world.set_paused(!world.is_paused());
+            info!(
+                "World {}",
+                if world.is_paused() {
+                    "paused"
+                } else {
+                    "resumed"
+                }
+            );
+        }
+
+        const MIN_TIME_SCALE: f32 = 1.0 / 64.0;
+        const MAX_TIME_SCALE: f32 = 64.0;
+        let mut time_scale = self.world().get_time_scale();
+        if self.input_map.action_pressed("slow_down_time") {
+            time_scale = (time_scale * 0.5).max(MIN_TIME_SCALE);
+        }
+        if self.input_map.action_pressed("speed_up_time") {
+            time_scale = (time_scale * 2.0).min(MAX_TIME_SCALE);
+        }
+        if time_scale != self.world().get_time_scale() {
+            self.world_mut().set_time_scale(time_scale);
+            info!("Time scale: {time_scale}x");
+        }
+
+        //Swap between flying the test craft and the free player
+        if in_game
+            && self.input_map.action_pressed("toggle_craft_control")
+            && !self.leave_controlled_craft()
+        {
+            if let Some(craft) = self.test_craft {
+                self.world_mut().set_controlled_entity(craft);
+            }
+        }
+
+        //Take the seat of the cockpit the camera is looking at, or get out again
+        if in_game && self.input_map.action_pressed("interact") && !self.leave_controlled_craft() {
+            self.enter_looked_at_cockpit();
+        }
+
+        //Walk around inside the test craft, or go back to the free player
+        if in_game && self.input_map.action_pressed("toggle_interior") {
+            let player_entity = self.player_entity;
+            let world = self.worlds.get_mut(GAME_WORLD).unwrap();
+            world.update_player_input(Vec3::ZERO, Vec3::ZERO);
+            if let Some(interior_player) = self.interior_player.take() {
+                world.remove_entity(interior_player);
+                world.set_player(player_entity);
+            } else if let Some(craft_rigid_body) = self
+                .test_craft
+                .and_then(|craft| world.get_entity::<SpaceCraftEntity>(craft))
+                .and_then(|craft| craft.get_rigid_body())
+            {
+                let interior_player = world.add_entity_named(
+                    InteriorPlayer::new(craft_rigid_body, INTERIOR_SPAWN_POSITION, 0.2),
+                    "Interior Player",
+                    &["player"],
+                );
+                world.set_player(interior_player);
+                self.interior_player = Some(interior_player);
+            }
+        }
+
+        if in_game && self.input_map.action_pressed("cycle_target") {
+            let world = self.world_mut();
+            match world.cycle_target() {
+                Some(target) => info!("Target: {}", world.display_name(target).unwrap_or_default()),
+                None => info!("Target cleared"),
+            }
+        }
+
+        if in_game && self.input_map.action_pressed("teleport_far_test") {
+            self.teleport_to_far_test();
+        }
+
+        if in_game && self.input_map.action_pressed("switch_weapon_mode") {
+            self.weapon_mode = match self.weapon_mode {
+                ProjectileMode::Physical => ProjectileMode::Hitscan,
+                ProjectileMode::Hitscan => ProjectileMode::Physical,
+            };
+            info!("Weapon mode: {:?}", self.weapon_mode);
+        }
+        if in_game && self.input_map.action_pressed("toggle_build_mode") {
+            self.build_mode
+                .toggle(self.worlds.get_mut(GAME_WORLD).unwrap());
+            //The cursor has to be free to point at cells
+            if let (true, Some(window)) = (self.build_mode.is_enabled(), window) {
+                self.set_cursor_grabbed(window, false);
+            }
+        }
+        let building = in_game && self.build_mode.is_enabled() && !ui_wants_pointer;
+        if building {
+            let cursor = self.input.mouse().map(|(x, y)| [x, y]);
+            let world = self.worlds.get_mut(GAME_WORLD).unwrap();
+            self.build_mode.update(
+                world,
+                &self.module_table,
+                &mut self.renderer,
+                cursor,
+                self.surface_size,
+            );
+        }
+
+        let selecting = !self.cursor_grabbed && self.input_map.action_pressed("select");
+        if building && self.input_map.action_pressed("select") {
+            let world = self.worlds.get_mut(GAME_WORLD).unwrap();
+            self.build_mode
+                .place(world, &self.module_table, &mut self.renderer);
+        } else if building && self.input_map.action_pressed("build_remove") {
+            self.build_mode
+                .remove_hovered(self.worlds.get_mut(GAME_WORLD).unwrap());
+        } else if in_game && selecting {
+            self.select_under_cursor();
+        } else if in_game && self.input_map.action_pressed("fire") {
+            self.fire_projectile();
+        }
+
+        //Top down view over the craft for building, toggling again goes back to first person
+        if self.input_map.action_pressed("toggle_build_view") {
+            let world = self.world_mut();
+            let camera_mode = match world.get_camera_mode() {
+                CameraMode::Overhead { .. } => CameraMode::FirstPerson,
+                _ => CameraMode::overhead(),
+            };
+            world.set_camera_mode(camera_mode);
+            info!("Camera mode: {:?}", camera_mode);
+        }
+        if self.input_map.action_pressed("cycle_camera_mode") {
+            let world = self.world_mut();
+            let camera_mode = world.get_camera_mode().next();
+            world.set_camera_mode(camera_mode);
+            info!("Camera mode: {:?}", camera_mode);
+        }
+        //Scrolling up brings the camera closer, in build mode it picks the module instead
+        let scroll = self.input.scroll_diff();
+        if building && scroll != 0.0 {
+            self.build_mode.cycle(-scroll.signum() as i32);
+        } else if scroll != 0.0 && !ui_wants_pointer {
+            self.world_mut()
+                .adjust_camera_distance(-scroll * CAMERA_ZOOM_STEP);
+        }
+
+        if self.input_map.action_pressed("toggle_flight_assist") {
+            self.world_mut()
+                .player_action(PlayerAction::CycleFlightMode);
+        }
+        if self.input_map.action_pressed("toggle_dampeners") {
+            self.world_mut()
+                .player_action(PlayerAction::ToggleDampeners);
+        }
+        if self.input_map.action_pressed("toggle_noclip") {
+            self.world_mut().player_action(PlayerAction::ToggleNoclip);
+        }
+        if self.input_map.action_pressed("toggle_docking") {
+            self.world_mut().player_action(PlayerAction::ToggleDocking);
+        }
+        if self.input_map.action_pressed("save_craft") {
+            self.world_mut().player_action(PlayerAction::SaveDefinition);
+        }
+
+        let target = self.world().get_selected_target();
+        let autopilot = [
+            ("autopilot_off", Some(Autopilot::Off)),
+            ("autopilot_kill_rotation", Some(Autopilot::KillRotation)),
+            ("autopilot_prograde", Some(Autopilot::Prograde)),
+            ("autopilot_retrograde", Some(Autopilot::Retrograde)),
+            ("autopilot_face_target", target.map(Autopilot::FaceTarget)),
+            (
+                "autopilot_approach",
+                target.map(|target| Autopilot::Approach {
+                    target,
+                    standoff_distance: APPROACH_STANDOFF_DISTANCE,
+                }),
+            ),
+        ]
+        .into_iter()
+        .find(|(action, _)| self.input_map.action_pressed(action));
+        match autopilot {
+            Some((_, Some(autopilot))) => self
+                .world_mut()
+                .player_action(PlayerAction::SetAutopilot(autopilot)),
+            Some((_, None)) => info!("No target selected for the autopilot"),
+            None => {}
+        }
+
+        //Only the active world is simulated, the others stay frozen until switched back to
+        let mouse_look = if self.cursor_grabbed {
+            self.mouse_delta * self.mouse_sensitivity
+        } else {
+            Vec2::ZERO
+        };
+        self.mouse_delta = Vec2::ZERO;
+
+        self.profiler.end("input");
+
+        self.profiler.begin("world");
+        let world = self.worlds.get_mut(&self.active_world).unwrap();
+        world.update_player_input(linear_input, angular_input);
+        if mouse_look != Vec2::ZERO {
+            world.update_player_look(mouse_look.x, mouse_look.y);
+        }
+        world.update(delta_time);
+        self.profiler.end("world");
+        self.profiler.record("physics", world.physics_step_ms());
+        self.update_audio();
+        let world = self.worlds.get_mut(&self.active_world).unwrap();
+
+        self.debug_ui.update(
+            self.surface_size,
+            world,
+            &self.last_render_stats,
+            &self.profiler,
+            self.cube_model,
+            self.build_mode.is_enabled().then_some(PaletteSource {
+                build_mode: &mut self.build_mode,
+                module_table: &self.module_table,
+                thumbnails: &self.module_thumbnails,
+            }),
         );
+    }
+
+    /// Impact sounds and thruster rumble of the active world, heard from the player camera
+    fn update_audio(&mut self) {
+        if !self.audio.is_enabled() {
+            return;
+        }
+        let world = &self.worlds[&self.active_world];
+        let (_, camera_transform) = world.get_player_camera();
+        self.audio.set_listener(&camera_transform);
+
+        if let Some(sound) = self.impact_sound {
+            for impact in world
+                .impacts()
+                .iter()
+                .filter(|impact| impact.impulse >= IMPACT_SOUND_THRESHOLD)
+            {
+                let volume = (impact.impulse / IMPACT_SOUND_FULL_IMPULSE).min(1.0);
+                if let Err(e) = self.audio.play_3d(sound, impact.position, volume) {
+                    warn!("Failed to play impact sound: {}", e);
+                    break;
+                }
+            }
+        }
+
+        if let Some(sound) = self.thruster_sound {
+            for (id, entity) in world.entities.iter() {
+                let Some(craft) = entity.as_ref().as_any().downcast_ref::<SpaceCraftEntity>()
+                else {
+                    continue;
+                };
+                let emitter = match self.thruster_emitters.get(&id) {
+                    Some(emitter) => *emitter,
+                    None => match self.audio.add_emitter(sound, id) {
+                        Ok(emitter) => *self.thruster_emitters.entry(id).or_insert(emitter),
+                        Err(e) => {
+                            warn!("Failed to add thruster sound: {}", e);
+                            continue;
+                        }
+                    },
+                };
+                let throttle = craft.throttle_fraction();
+                let (min_pitch, max_pitch) = THRUSTER_PITCH_RANGE;
+                self.audio.set_emitter(
+                    emitter,
+                    throttle,
+                    min_pitch + (max_pitch - min_pitch) * throttle,
+                );
+            }
+        }
 
-        self.world.update_player_input(linear_input, angular_input);
-        self.world.update(delta_time);
+        self.audio.update(|id| {
+            world
+                .get_world_transform(id)
+                .map(|transform| transform.position)
+        });
+        let audio = &self.audio;
+        self.thruster_emitters
+            .retain(|_, emitter| audio.emitter_entity(*emitter).is_some());
     }
 
-    pub fn render(&mut self) {
-        let output_texture = self.surface.get_current_texture().unwrap();
+    /// Puts the free player next to the craft it was flying, returns false if nothing was controlled
+    fn leave_controlled_craft(&mut self) -> bool {
+        let world = self.world_mut();
+        let Some(craft) = world.release_control() else {
+            return false;
+        };
 
-        let output_view = output_texture
-            .texture
-            .create_view(&wgpu::TextureViewDescriptor::default());
+        let (_, camera_transform) = world.get_player_camera();
+        let exit_transform = world
+            .entities
+            .get(craft)
+            .and_then(|craft| craft.get_camera_transform())
+            .map(|craft_camera| craft_camera.transform_by(&Transform::new_pos(EXIT_OFFSET)))
+            .unwrap_or(camera_transform);
+        let craft_velocity = world
+            .entities
+            .get(craft)
+            .and_then(|craft| craft.get_rigid_body())
+            .map(|rigid_body| {
+                world
+                    .world_info
+                    .physics
+                    .get_rigid_body_linear_velocity(rigid_body)
+            })
+            .unwrap_or(Vec3::ZERO);
 
-        let (camera, camera_transform) = self.world.get_player_camera();
+        let player_entity = world.player_entity;
+        if let Some(player) = world
+            .entities
+            .get_mut(player_entity)
+            .and_then(|entity| entity.as_mut().as_any_mut().downcast_mut::<Player>())
+        {
+            player.teleport(&exit_transform, craft_velocity, &mut world.world_info);
+        }
+        info!("Left craft {:?}", craft);
+        true
+    }
 
-        let light_dir = glam::Vec3::new(0.5, -2.0, 1.0).normalize();
+    /// Puts the free player next to the asteroid placed far from the starting origin
+    fn teleport_to_far_test(&mut self) {
+        let world = self.worlds.get_mut(GAME_WORLD).unwrap();
+        world.release_control();
+        let position =
+            (FAR_TEST_POSITION - world.get_origin()).as_vec3() + Vec3::new(0.0, 0.0, -20.0);
+        let player_entity = world.player_entity;
+        if let Some(player) = world
+            .entities
+            .get_mut(player_entity)
+            .and_then(|entity| entity.as_mut().as_any_mut().downcast_mut::<Player>())
+        {
+            player.teleport(
+                &Transform::new_pos(position),
+                Vec3::ZERO,
+                &mut world.world_info,
+            );
+            info!("Teleported to the far test asteroid");
+        }
+    }
 
-        let scene_data = crate::renderer::SceneData {
-            view_projection_matrix: *(camera
-                .as_infinite_reverse_perspective_matrix(self.surface_size)
-                * camera_transform.as_view_matrix())
-            .as_ref(),
-            ambient_light_color: [0.1; 4],
-            sun_light_direction_intensity: [light_dir.x, light_dir.y, light_dir.z, 0.5],
-            sun_light_color: [1.0; 4],
+    /// Raycast from the camera, only a cockpit module of a craft can be entered
+    fn enter_looked_at_cockpit(&mut self) {
+        let world = self.world_mut();
+        let (_, camera_transform) = world.get_player_camera();
+        let player_rigid_body = world
+            .entities
+            .get(world.player_entity)
+            .and_then(|entity| entity.get_rigid_body());
+
+        let physics = &world.world_info.physics;
+        let Some(craft) = physics
+            .cast_ray(
+                camera_transform.position,
+                camera_transform.rotation * Vec3::Z,
+                INTERACT_RANGE,
+                player_rigid_body,
+            )
+            .and_then(|(collider, _)| {
+                let entity_id = physics.get_collider_entity(collider)?;
+                world
+                    .get_entity::<SpaceCraftEntity>(entity_id)
+                    .filter(|craft| craft.is_cockpit_collider(collider))
+                    .map(|_| entity_id)
+            })
+        else {
+            return;
         };
 
+        if world.set_controlled_entity(craft) {
+            info!("Took control of craft {:?}", craft);
+        }
+    }
+
+    /// Targets whatever is under the cursor, clicking empty space clears the target
+    fn select_under_cursor(&mut self) {
+        let Some((x, y)) = self.input.mouse() else {
+            return;
+        };
+        let surface_size = self.surface_size;
+        let world = self.world_mut();
+        match world.pick([x, y], surface_size) {
+            Some((entity_id, hit_point)) => {
+                info!(
+                    "Selected {} at {:.1}",
+                    world.display_name(entity_id).unwrap_or_default(),
+                    hit_point
+                );
+                world.set_selected_target(Some(entity_id));
+            }
+            None => {
+                info!("Nothing under the cursor, target cleared");
+                world.set_selected_target(None);
+            }
+        }
+    }
+
+    /// Shoots along the camera's forward, adding the velocity of the player's body if it has one
+    fn fire_projectile(&mut self) {
+        let weapon_mode = self.weapon_mode;
+        let projectile_model = self.projectile_model;
+        let world = self.world_mut();
+
+        let (_, camera_transform) = world.get_player_camera();
+        let shooter = world.input_entity();
+        let shooter_rigid_body = world
+            .entities
+            .get(shooter)
+            .and_then(|entity| entity.get_rigid_body());
+        let inherited_velocity = shooter_rigid_body
+            .map(|rigid_body| {
+                world
+                    .world_info
+                    .physics
+                    .get_rigid_body_linear_velocity(rigid_body)
+            })
+            .unwrap_or(Vec3::ZERO);
+
+        let forward = camera_transform.rotation * Vec3::Z;
+        let mut projectile = ProjectileEntity::new(
+            weapon_mode,
+            Transform {
+                position: camera_transform.position + forward * PROJECTILE_SPAWN_DISTANCE,
+                rotation: camera_transform.rotation,
+                ..Default::default()
+            },
+            inherited_velocity + forward * PROJECTILE_SPEED,
+            PROJECTILE_DAMAGE,
+            PROJECTILE_LIFETIME,
+        )
+        .with_shooter(shooter, shooter_rigid_body);
+        if let Some((mesh, material)) = projectile_model {
+            projectile = projectile.with_model(mesh, material, PROJECTILE_RADIUS);
+        }
+        world.add_entity(projectile);
+    }
+
+    pub fn surface_size(&self) -> [u32; 2] {
+        self.surface_size
+    }
+
+    pub fn render(&mut self) -> RenderStats {
+        if self.device_lost.load(Ordering::Relaxed) {
+            self.recover_device();
+        }
+
+        if self.surface_size[0] == 0 || self.surface_size[1] == 0 {
+            return RenderStats::default();
+        }
+
+        self.profiler.begin("encode");
+        let mut frame = match &self.output {
+            RenderOutput::Surface { surface, .. } => match surface.get_current_texture() {
+                Ok(output_texture) => self.renderer.begin_frame(output_texture),
+                Err(wgpu::SurfaceError::Outdated) | Err(wgpu::SurfaceError::Lost) => {
+                    warn!("Surface outdated or lost, reconfiguring");
+                    surface.configure(&self.device, &self.surface_config);
+                    return RenderStats::default();
+                }
+                Err(wgpu::SurfaceError::Timeout) => {
+                    warn!("Timed out acquiring the next surface texture, skipping frame");
+                    return RenderStats::default();
+                }
+                Err(wgpu::SurfaceError::OutOfMemory) => {
+                    error!("Out of memory acquiring the next surface texture");
+                    panic!("Surface out of memory");
+                }
+            },
+            RenderOutput::Offscreen(texture) => self.renderer.begin_offscreen_frame(
+                texture.create_view(&wgpu::TextureViewDescriptor::default()),
+            ),
+        };
+
+        let scene_data = self.prepare_scene();
+        let world = self.worlds.get_mut(&self.active_world).unwrap();
         self.renderer.render_scene(
+            &mut frame,
             self.surface_size,
-            &output_view,
             &scene_data,
-            &self.world.world_info.rendering,
+            &mut world.world_info.rendering,
         );
+        self.debug_ui
+            .render(&self.device, &self.queue, &mut frame, self.surface_size);
+        self.profiler.end("encode");
 
-        output_texture.present();
+        self.profiler.begin("submit");
+        let render_stats = self.renderer.end_frame(frame);
+        self.profiler.end("submit");
+        self.profiler
+            .record("instances", render_stats.instance_flush_ms);
+
+        self.last_render_stats = render_stats;
+        render_stats
+    }
+
+    /// Recreates the device and everything on it from the cpu side copies, the worlds and their physics are untouched
+    fn recover_device(&mut self) {
+        warn!("Gpu device lost, recreating it");
+        let (device, queue) = crate::renderer::request_device(&self.renderer.adapter());
+        let device = Arc::new(device);
+        let queue = Arc::new(queue);
+        self.device_lost = crate::renderer::watch_device_errors(&device);
+
+        self.renderer.recreate_device(device.clone(), queue.clone());
+        for world in self.worlds.values_mut() {
+            self.renderer
+                .recreate_scene(&mut world.world_info.rendering);
+        }
+        self.debug_ui
+            .recreate_device(&device, self.surface_config.format);
+
+        self.device = device;
+        self.queue = queue;
+        if self.surface_size[0] > 0 && self.surface_size[1] > 0 {
+            self.configure_output();
+        }
+        info!("Recreated the gpu device");
+    }
+
+    /// Renders the active world into a texture and reads it back as rgba8 rows, without the debug ui
+    pub fn capture_frame(&mut self) -> Vec<u8> {
+        let scene_data = self.prepare_scene();
+        let world = self.worlds.get_mut(&self.active_world).unwrap();
+        self.renderer.render_to_texture(
+            self.surface_size,
+            &scene_data,
+            &mut world.world_info.rendering,
+        )
+    }
+
+    /// Queues the hud, name labels, trajectory and physics debug lines, returns the scene's uniforms
+    fn prepare_scene(&mut self) -> SceneData {
+        let world = &self.worlds[&self.active_world];
+        let (camera, camera_transform) = world.get_player_camera();
+
+        let light_dir = glam::Vec3::new(0.5, -2.0, 1.0).normalize();
+
+        let view_projection =
+            camera.as_projection_matrix(self.surface_size) * camera_transform.as_view_matrix();
+        let scene_data = SceneData {
+            view_projection_matrix: *view_projection.as_ref(),
+            ambient_light_color: [0.1; 4],
+            sun_light_direction_intensity: [light_dir.x, light_dir.y, light_dir.z, 0.5],
+            sun_light_color: [1.0; 4],
+            camera_position: camera_transform.position.extend(1.0).to_array(),
+        };
+
+        if self.active_world == GAME_WORLD {
+            crate::hud::draw_name_labels(&mut self.renderer, world, camera_transform.position);
+            crate::hud::draw_hud(
+                self.renderer.hud(),
+                world,
+                &view_projection,
+                camera_transform.position,
+                self.surface_size,
+            );
+
+            self.trajectory.update(world);
+            self.trajectory
+                .draw(self.renderer.debug_lines(), world.get_origin());
+            self.build_mode.draw(world, self.renderer.debug_lines());
+        }
+
+        if self.draw_physics_debug {
+            world
+                .world_info
+                .physics
+                .debug_render(self.renderer.debug_lines());
+        }
+
+        scene_data
     }
 }
 
-fn load_convex_hull_from_obj<P: AsRef<std::path::Path> + Debug>(path: P) -> Option<SharedShape> {
-    const LOAD_OPTIONS: tobj::LoadOptions = tobj::LoadOptions {
-        single_index: true,
-        triangulate: true,
-        ignore_points: false,
-        ignore_lines: true,
+/// Exclusive mode uses the monitor's largest video mode with the highest refresh rate
+pub fn fullscreen_mode(
+    window: &Window,
+    mode: FullscreenMode,
+    monitor_index: Option<usize>,
+) -> Option<winit::window::Fullscreen> {
+    let monitor = match monitor_index {
+        Some(index) => {
+            let monitor = window.available_monitors().nth(index);
+            if monitor.is_none() {
+                warn!("No monitor {}, using the current one", index);
+            }
+            monitor.or_else(|| window.current_monitor())
+        }
+        None => window.current_monitor(),
     };
 
-    let (models, _materials) = match tobj::load_obj(path, &LOAD_OPTIONS) {
-        Ok(values) => values,
+    if mode == FullscreenMode::Exclusive {
+        let video_mode = monitor.as_ref().and_then(|monitor| {
+            monitor.video_modes().max_by_key(|video_mode| {
+                let size = video_mode.size();
+                (
+                    size.width * size.height,
+                    video_mode.refresh_rate_millihertz(),
+                )
+            })
+        });
+        match video_mode {
+            Some(video_mode) => return Some(winit::window::Fullscreen::Exclusive(video_mode)),
+            None => warn!("Exclusive fullscreen isn't supported here, using borderless"),
+        }
+    }
+
+    Some(winit::window::Fullscreen::Borderless(monitor))
+}
+
+fn load_sound(audio: &mut AudioSystem, path: &str) -> Option<SoundHandle> {
+    match audio.load_sound(path) {
+        Ok(sound) => Some(sound),
         Err(e) => {
-            error!("Failed to load obj file: {}", e);
-            return None;
+            warn!("Failed to load sound {}: {}", path, e);
+            None
         }
+    }
+}
+
+/// Writes the defaults out when there's no settings file yet, so there's something to edit
+pub fn load_settings() -> Settings {
+    let settings = Settings::load_or_default(SETTINGS_PATH);
+    if !Path::new(SETTINGS_PATH).exists() {
+        match settings.save(SETTINGS_PATH) {
+            Ok(()) => info!("Wrote default settings to {}", SETTINGS_PATH),
+            Err(e) => warn!("Failed to write default settings: {}", e),
+        }
+    }
+    settings
+}
+
+/// The preferred backend is tried first, then every backend before giving up
+fn create_adapter(
+    settings: &Settings,
+    window: Option<&Window>,
+) -> (Option<wgpu::Surface>, wgpu::Adapter) {
+    let power_preference = if settings.low_power_gpu {
+        wgpu::PowerPreference::LowPower
+    } else {
+        wgpu::PowerPreference::HighPerformance
     };
-    let model = &models[0];
-    let mesh = &model.mesh;
 
-    let mut points = Vec::new();
+    let mut backends = vec![settings.backend];
+    if settings.backend != Backend::Auto {
+        backends.push(Backend::Auto);
+    }
 
-    for i in 0..(mesh.positions.len() / 3) {
-        let i3 = i * 3;
-        points.push(Point::from_slice(&mesh.positions[i3..(i3 + 3)]));
+    for backend in backends {
+        let instance = wgpu::Instance::new(wgpu::InstanceDescriptor {
+            backends: backend.backends(),
+            dx12_shader_compiler: Default::default(),
+        });
+        let surface = match window.map(|window| unsafe { instance.create_surface(window) }) {
+            Some(Ok(surface)) => Some(surface),
+            Some(Err(e)) => {
+                warn!("Failed to create a {:?} surface: {}", backend, e);
+                continue;
+            }
+            None => None,
+        };
+
+        match crate::renderer::request_adapter(&instance, surface.as_ref(), power_preference) {
+            Some(adapter) => {
+                info!("Using adapter {:?}", adapter.get_info());
+                return (surface, adapter);
+            }
+            None => warn!("No adapter found for the {:?} backend", backend),
+        }
     }
+    panic!("No adapter available");
+}
 
-    SharedShape::convex_hull(&points)
+/// Writes the defaults out when there's no bindings file yet, so there's something to edit
+fn load_input_map() -> InputMap {
+    let input_map = InputMap::load_or_default(BINDINGS_PATH);
+    if !Path::new(BINDINGS_PATH).exists() {
+        match input_map.save(BINDINGS_PATH) {
+            Ok(()) => info!("Wrote default bindings to {}", BINDINGS_PATH),
+            Err(e) => warn!("Failed to write default bindings: {}", e),
+        }
+    }
+    input_map
+}
+
+fn create_offscreen_texture(
+    device: &wgpu::Device,
+    surface_config: &wgpu::SurfaceConfiguration,
+) -> wgpu::Texture {
+    device.create_texture(&wgpu::TextureDescriptor {
+        label: Some("Headless Output Texture"),
+        size: wgpu::Extent3d {
+            width: surface_config.width,
+            height: surface_config.height,
+            depth_or_array_layers: 1,
+        },
+        mip_level_count: 1,
+        sample_count: 1,
+        dimension: wgpu::TextureDimension::D2,
+        format: surface_config.format,
+        usage: wgpu::TextureUsages::RENDER_ATTACHMENT,
+        view_formats: &[],
+    })
+}
+
+fn apply_cursor_grab(window: &Window, grabbed: bool) {
+    let result = if grabbed {
+        //Locked isn't available on every platform, confined at least keeps the cursor in the window
+        window
+            .set_cursor_grab(CursorGrabMode::Locked)
+            .or_else(|_| window.set_cursor_grab(CursorGrabMode::Confined))
+    } else {
+        window.set_cursor_grab(CursorGrabMode::None)
+    };
+    if let Err(e) = result {
+        warn!("Failed to change the cursor grab: {}", e);
+    }
+    window.set_cursor_visible(!grabbed);
 }
 
-fn keys_to_axis(
-    input: &WinitInputHelper,
-    positive_key: VirtualKeyCode,
-    negative_key: VirtualKeyCode,
-) -> f32 {
-    if input.key_held(positive_key) && !input.key_held(negative_key) {
-        1.0
-    } else if !input.key_held(positive_key) && input.key_held(negative_key) {
-        -1.0
+fn supported_present_mode(
+    present_mode: wgpu::PresentMode,
+    supported_modes: &[wgpu::PresentMode],
+) -> wgpu::PresentMode {
+    if supported_modes.contains(&present_mode) {
+        present_mode
     } else {
-        0.0
+        //Fifo is the only mode every surface has to support
+        warn!(
+            "Present mode {:?} is not supported (supported: {:?}), using Fifo",
+            present_mode, supported_modes
+        );
+        wgpu::PresentMode::Fifo
+    }
+}
+
+/// Fills a cube shaped grid in front of the player with `count` render only cubes sharing one mesh and material
+fn create_stress_scene(renderer: &mut Renderer, world: &mut World, count: usize) {
+    let mesh = renderer.get_or_load_mesh("resource/mesh/Cube.obj").unwrap();
+    let material = renderer
+        .create_pbr_material(PbrMaterialDefinition {
+            color: [0.75, 0.25, 0.1, 1.0],
+            metallic: 0.5,
+            roughness: 0.5,
+            albedo_texture: None,
+            normal_texture: None,
+            emissive: [0.0; 3],
+            transparent: false,
+            shading_model: ShadingModel::Lit,
+            cull_mode: None,
+        })
+        .unwrap();
+
+    let grid_size = (count as f32).cbrt().ceil() as usize;
+    let spacing = 2.0;
+    let offset = Vec3::new(-0.5, -0.5, 0.0) * (grid_size as f32 * spacing) + Vec3::Z * 30.0;
+
+    for i in 0..count {
+        let grid_position = Vec3::new(
+            (i % grid_size) as f32,
+            ((i / grid_size) % grid_size) as f32,
+            (i / (grid_size * grid_size)) as f32,
+        );
+        world.world_info.rendering.create_instance(
+            mesh,
+            material,
+            &Transform::new_pos(offset + grid_position * spacing),
+        );
+    }
+
+    info!("Created stress scene with {} cubes", count);
+}
+
+/// Averages and percentiles of one scope, in milliseconds
+#[derive(Clone, Copy, Debug)]
+pub struct ScopeSummary {
+    pub name: &'static str,
+    pub average_ms: f32,
+    pub p50_ms: f32,
+    pub p95_ms: f32,
+    pub p99_ms: f32,
+}
+
+struct ProfileScope {
+    name: &'static str,
+    /// Newest last
+    samples: VecDeque<f32>,
+}
+
+/// Named cpu timers over the last `PROFILE_HISTORY` frames, "frame" is the time between `next_frame` calls
+pub struct FrameProfiler {
+    /// In the order they were first used, which is the order they're reported in
+    scopes: Vec<ProfileScope>,
+    /// Scopes begun but not ended yet
+    open: Vec<(&'static str, std::time::Instant)>,
+    /// Totals of this frame, a scope hit more than once a frame is summed
+    current: Vec<(&'static str, f32)>,
+    frame_start: std::time::Instant,
+    last_report: std::time::Instant,
+}
+
+impl Default for FrameProfiler {
+    fn default() -> Self {
+        Self {
+            scopes: Vec::new(),
+            open: Vec::new(),
+            current: Vec::new(),
+            frame_start: std::time::Instant::now(),
+            last_report: std::time::Instant::now(),
+        }
+    }
+}
+
+impl FrameProfiler {
+    pub fn begin(&mut self, name: &'static str) {
+        self.open.push((name, std::time::Instant::now()));
+    }
+
+    /// Ending a scope that wasn't begun does nothing
+    pub fn end(&mut self, name: &'static str) {
+        if let Some(index) = self.open.iter().rposition(|(open, _)| *open == name) {
+            let (_, start) = self.open.remove(index);
+            self.record(name, start.elapsed().as_secs_f32() * 1000.0);
+        }
+    }
+
+    /// Adds time measured somewhere else to this frame's total of `name`
+    pub fn record(&mut self, name: &'static str, ms: f32) {
+        match self
+            .current
+            .iter_mut()
+            .find(|(current, _)| *current == name)
+        {
+            Some((_, total)) => *total += ms,
+            None => self.current.push((name, ms)),
+        }
+    }
+
+    /// Closes the last frame's samples and starts a new one, logs a report every `PROFILE_REPORT_INTERVAL`
+    pub fn next_frame(&mut self) {
+        let frame_ms = self.frame_start.elapsed().as_secs_f32() * 1000.0;
+        self.frame_start = std::time::Instant::now();
+        self.record("frame", frame_ms);
+
+        //Scopes left open by an early return are dropped rather than carried into the next frame
+        self.open.clear();
+        for (name, ms) in std::mem::take(&mut self.current) {
+            let index = match self.scopes.iter().position(|scope| scope.name == name) {
+                Some(index) => index,
+                None => {
+                    self.scopes.push(ProfileScope {
+                        name,
+                        samples: VecDeque::with_capacity(PROFILE_HISTORY),
+                    });
+                    self.scopes.len() - 1
+                }
+            };
+            let samples = &mut self.scopes[index].samples;
+            if samples.len() == PROFILE_HISTORY {
+                samples.pop_front();
+            }
+            samples.push_back(ms);
+        }
+
+        if self.last_report.elapsed().as_secs_f32() >= PROFILE_REPORT_INTERVAL {
+            self.last_report = std::time::Instant::now();
+            self.report();
+        }
+    }
+
+    pub fn summaries(&self) -> Vec<ScopeSummary> {
+        self.scopes
+            .iter()
+            .filter(|scope| !scope.samples.is_empty())
+            .map(|scope| {
+                let mut sorted: Vec<f32> = scope.samples.iter().copied().collect();
+                sorted.sort_by(f32::total_cmp);
+                let percentile = |p: f32| sorted[((sorted.len() - 1) as f32 * p).round() as usize];
+                ScopeSummary {
+                    name: scope.name,
+                    average_ms: sorted.iter().sum::<f32>() / sorted.len() as f32,
+                    p50_ms: percentile(0.5),
+                    p95_ms: percentile(0.95),
+                    p99_ms: percentile(0.99),
+                }
+            })
+            .collect()
+    }
+
+    /// The uncapped rate leaves out the frame limiter's "wait" scope
+    pub fn fps(&self) -> (f32, f32) {
+        let summaries = self.summaries();
+        let average = |name| {
+            summaries
+                .iter()
+                .find(|summary| summary.name == name)
+                .map_or(0.0, |summary| summary.average_ms)
+        };
+        let frame_ms = average("frame");
+        let to_fps = |ms: f32| if ms > 0.0 { 1000.0 / ms } else { 0.0 };
+        (to_fps(frame_ms), to_fps(frame_ms - average("wait")))
+    }
+
+    fn report(&self) {
+        let (fps, uncapped_fps) = self.fps();
+        let scopes: Vec<String> = self
+            .summaries()
+            .iter()
+            .map(|summary| {
+                format!(
+                    "{} {:.2}/{:.2}/{:.2}",
+                    summary.name, summary.average_ms, summary.p95_ms, summary.p99_ms
+                )
+            })
+            .collect();
+        info!(
+            "FPS: {:.0} ({:.0} uncapped) | avg/p95/p99 ms: {}",
+            fps,
+            uncapped_fps,
+            scopes.join(" | ")
+        );
     }
 }
@@ -0,0 +1,365 @@
+use crate::physics::ColliderShape;
+use crate::renderer::{
+    cluster_vertices, LodLevel, MaterialHandle, MeshHandle, PbrMaterialDefinition, ShadingModel,
+};
+use crate::transform::Transform;
+use crate::world::{DynamicEntity, EntityId, StaticEntity, StaticPart, World};
+use crate::Renderer;
+use glam::{Quat, Vec3};
+use std::collections::HashMap;
+
+/// Number of different rock shapes, every field shares them
+const MESH_VARIANTS: usize = 4;
+/// How far the vertices of the rocks are pushed in or out, as a fraction of the radius
+const MESH_ROUGHNESS: f32 = 0.25;
+/// Vertex clustering cell size of each lower LOD, for a rock at scale 1
+const LOD_CELL_SIZES: [f32; 2] = [0.2, 0.4];
+/// The full mesh and then each lower LOD is drawn up to these distances for a rock at scale 1,
+/// the last LOD is drawn past them
+const LOD_DISTANCES: [f32; 2] = [50.0, 150.0];
+/// Rocks stop being drawn this many meters away per meter of size, so the small ones go first
+const DRAW_DISTANCE_PER_SIZE: f32 = 400.0;
+/// Gives up on placing an asteroid after this many rejected positions
+const MAX_PLACEMENT_ATTEMPTS: usize = 64;
+
+/// Small deterministic generator so a seed always produces the same field, splitmix64
+pub struct FieldRng(u64);
+
+impl FieldRng {
+    pub fn new(seed: u64) -> Self {
+        Self(seed)
+    }
+
+    pub fn next_u64(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9E37_79B9_7F4A_7C15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+        z ^ (z >> 31)
+    }
+
+    /// Uniform in 0 to 1
+    pub fn next_f32(&mut self) -> f32 {
+        (self.next_u64() >> 40) as f32 / (1u64 << 24) as f32
+    }
+
+    pub fn range(&mut self, min: f32, max: f32) -> f32 {
+        min + (max - min) * self.next_f32()
+    }
+
+    /// Uniform inside the unit sphere
+    pub fn in_unit_sphere(&mut self) -> Vec3 {
+        loop {
+            let point = Vec3::new(
+                self.range(-1.0, 1.0),
+                self.range(-1.0, 1.0),
+                self.range(-1.0, 1.0),
+            );
+            if point.length_squared() <= 1.0 {
+                return point;
+            }
+        }
+    }
+
+    /// Uniformly distributed rotation
+    pub fn rotation(&mut self) -> Quat {
+        let (u1, u2, u3) = (self.next_f32(), self.next_f32(), self.next_f32());
+        let tau = std::f32::consts::TAU;
+        Quat::from_xyzw(
+            (1.0 - u1).sqrt() * (tau * u2).sin(),
+            (1.0 - u1).sqrt() * (tau * u2).cos(),
+            u1.sqrt() * (tau * u3).sin(),
+            u1.sqrt() * (tau * u3).cos(),
+        )
+    }
+}
+
+/// Where and how many asteroids to generate, the same settings always give the same field
+#[derive(Clone, Debug)]
+pub struct AsteroidField {
+    pub seed: u64,
+    pub center: Vec3,
+    pub radius: f32,
+    pub count: usize,
+    /// Outer fraction of the radius where the density fades to zero, 0.0 gives a hard edge
+    pub falloff: f32,
+    /// Radius range of a single asteroid in meters
+    pub min_size: f32,
+    pub max_size: f32,
+    /// Fraction of the asteroids that get a dynamic body and tumble, the rest are static
+    pub tumbling_fraction: f32,
+    /// Fastest tumble in rad/s
+    pub max_tumble_speed: f32,
+}
+
+impl AsteroidField {
+    pub fn new(seed: u64, center: Vec3, radius: f32, count: usize) -> Self {
+        Self {
+            seed,
+            center,
+            radius,
+            count,
+            falloff: 0.4,
+            min_size: 1.0,
+            max_size: 6.0,
+            tumbling_fraction: 0.1,
+            max_tumble_speed: 0.3,
+        }
+    }
+
+    pub fn with_falloff(mut self, falloff: f32) -> Self {
+        self.falloff = falloff.clamp(0.0, 1.0);
+        self
+    }
+
+    /// Relative density at `distance` from the center
+    fn density(&self, distance: f32) -> f32 {
+        let t = distance / self.radius;
+        let fade_start = 1.0 - self.falloff;
+        if t <= fade_start {
+            1.0
+        } else if t >= 1.0 {
+            0.0
+        } else {
+            let fade = (t - fade_start) / self.falloff;
+            //Smoothstep so the falloff doesn't start with a visible ring
+            1.0 - fade * fade * (3.0 - 2.0 * fade)
+        }
+    }
+
+    /// Offset from the center, rejection sampled against the density
+    fn sample_position(&self, rng: &mut FieldRng) -> Option<Vec3> {
+        (0..MAX_PLACEMENT_ATTEMPTS).find_map(|_| {
+            let offset = rng.in_unit_sphere() * self.radius;
+            (rng.next_f32() < self.density(offset.length())).then_some(offset)
+        })
+    }
+}
+
+/// Rock shared by every asteroid of one variant, vertices lie roughly on a sphere with a radius of 0.5
+struct AsteroidMesh {
+    mesh: MeshHandle,
+    points: Vec<Vec3>,
+}
+
+pub fn asteroid_material(renderer: &mut Renderer) -> Option<MaterialHandle> {
+    renderer.get_or_create_material(
+        "asteroid",
+        PbrMaterialDefinition {
+            color: [0.4, 0.38, 0.35, 1.0],
+            metallic: 0.0,
+            roughness: 0.9,
+            albedo_texture: None,
+            normal_texture: None,
+            emissive: [0.0; 3],
+            transparent: false,
+            shading_model: ShadingModel::Lit,
+            cull_mode: Some(wgpu::Face::Back),
+        },
+    )
+}
+
+/// Meshes are registered by name so saved asteroids find them again, each variant always has the same shape
+fn asteroid_meshes(renderer: &mut Renderer) -> Vec<AsteroidMesh> {
+    (0..MESH_VARIANTS)
+        .filter_map(|variant| {
+            let (positions, indices) = perturbed_icosphere(&mut FieldRng::new(variant as u64));
+            let name = format!("procedural/asteroid_{}", variant);
+            let mesh = match renderer.assets().get_mesh(&name) {
+                Some(mesh) => mesh,
+                None => {
+                    let positions: Vec<[f32; 3]> = positions.iter().map(|p| p.to_array()).collect();
+                    let lods: Vec<(Vec<[f32; 3]>, Vec<u32>)> = LOD_CELL_SIZES
+                        .iter()
+                        .map(|cell_size| cluster_vertices(&positions, &indices, *cell_size))
+                        .collect();
+
+                    let levels: Vec<LodLevel<[f32; 3]>> = std::iter::once((&positions, &indices))
+                        .chain(lods.iter().map(|(positions, indices)| (positions, indices)))
+                        .enumerate()
+                        .map(|(level, (positions, indices))| LodLevel {
+                            vertices: positions,
+                            indices,
+                            max_distance: LOD_DISTANCES
+                                .get(level)
+                                .copied()
+                                .unwrap_or(f32::INFINITY),
+                        })
+                        .collect();
+
+                    let mesh = renderer.create_flat_mesh_with_lods(&levels)?;
+                    renderer.register_mesh(&name, mesh);
+                    mesh
+                }
+            };
+            Some(AsteroidMesh {
+                mesh,
+                points: positions,
+            })
+        })
+        .collect()
+}
+
+/// Icosahedron subdivided once with every vertex pushed in or out a random amount
+fn perturbed_icosphere(rng: &mut FieldRng) -> (Vec<Vec3>, Vec<u32>) {
+    let phi = (1.0 + 5.0f32.sqrt()) / 2.0;
+    let mut positions: Vec<Vec3> = [
+        [-1.0, phi, 0.0],
+        [1.0, phi, 0.0],
+        [-1.0, -phi, 0.0],
+        [1.0, -phi, 0.0],
+        [0.0, -1.0, phi],
+        [0.0, 1.0, phi],
+        [0.0, -1.0, -phi],
+        [0.0, 1.0, -phi],
+        [phi, 0.0, -1.0],
+        [phi, 0.0, 1.0],
+        [-phi, 0.0, -1.0],
+        [-phi, 0.0, 1.0],
+    ]
+    .map(|p| Vec3::from(p).normalize())
+    .to_vec();
+    let faces: [[u32; 3]; 20] = [
+        [0, 11, 5],
+        [0, 5, 1],
+        [0, 1, 7],
+        [0, 7, 10],
+        [0, 10, 11],
+        [1, 5, 9],
+        [5, 11, 4],
+        [11, 10, 2],
+        [10, 7, 6],
+        [7, 1, 8],
+        [3, 9, 4],
+        [3, 4, 2],
+        [3, 2, 6],
+        [3, 6, 8],
+        [3, 8, 9],
+        [4, 9, 5],
+        [2, 4, 11],
+        [6, 2, 10],
+        [8, 6, 7],
+        [9, 8, 1],
+    ];
+
+    let mut midpoints: HashMap<(u32, u32), u32> = HashMap::new();
+    let mut midpoint = |a: u32, b: u32, positions: &mut Vec<Vec3>| -> u32 {
+        *midpoints.entry((a.min(b), a.max(b))).or_insert_with(|| {
+            positions.push(((positions[a as usize] + positions[b as usize]) * 0.5).normalize());
+            positions.len() as u32 - 1
+        })
+    };
+    let mut indices = Vec::with_capacity(faces.len() * 12);
+    for [a, b, c] in faces {
+        let ab = midpoint(a, b, &mut positions);
+        let bc = midpoint(b, c, &mut positions);
+        let ca = midpoint(c, a, &mut positions);
+        indices.extend_from_slice(&[a, ab, ca, b, bc, ab, c, ca, bc, ab, bc, ca]);
+    }
+
+    for position in positions.iter_mut() {
+        *position *= 0.5 * (1.0 + rng.range(-MESH_ROUGHNESS, MESH_ROUGHNESS));
+    }
+    (positions, indices)
+}
+
+impl World {
+    /// Generates the field with the default falloff and sizes, see `spawn_asteroid_field_with`
+    pub fn spawn_asteroid_field(
+        &mut self,
+        seed: u64,
+        center: Vec3,
+        radius: f32,
+        count: usize,
+        renderer: &mut Renderer,
+    ) -> Vec<EntityId> {
+        self.spawn_asteroid_field_with(&AsteroidField::new(seed, center, radius, count), renderer)
+    }
+
+    /// Every asteroid of a variant shares a mesh and material so they're drawn as one instanced batch.
+    /// Colliders are convex hulls of the scaled rock
+    pub fn spawn_asteroid_field_with(
+        &mut self,
+        field: &AsteroidField,
+        renderer: &mut Renderer,
+    ) -> Vec<EntityId> {
+        let meshes = asteroid_meshes(renderer);
+        let Some(material) = asteroid_material(renderer) else {
+            return Vec::new();
+        };
+        if meshes.is_empty() {
+            return Vec::new();
+        }
+
+        let mut rng = FieldRng::new(field.seed);
+        let mut spawned = Vec::with_capacity(field.count);
+        for _ in 0..field.count {
+            let Some(offset) = field.sample_position(&mut rng) else {
+                continue;
+            };
+            let rock = &meshes[rng.next_u64() as usize % meshes.len()];
+            let size = rng.range(field.min_size, field.max_size);
+            //Slightly squashed so rocks of the same variant don't look alike
+            let scale = Vec3::new(
+                rng.range(0.8, 1.2),
+                rng.range(0.8, 1.2),
+                rng.range(0.8, 1.2),
+            ) * size
+                * 2.0;
+            let rotation = rng.rotation();
+            let tumbling = rng.next_f32() < field.tumbling_fraction;
+            let angular_velocity = rng.in_unit_sphere() * field.max_tumble_speed;
+
+            let transform = Transform {
+                position: field.center + offset,
+                rotation,
+                scale,
+            };
+            let collider =
+                ColliderShape::convex_hull(rock.points.iter().map(|p| *p * scale).collect()).ok();
+            let model = Some((rock.mesh, material));
+
+            let id = if tumbling {
+                self.add_entity(
+                    DynamicEntity::new(transform, model, collider)
+                        .with_velocity(Vec3::ZERO, angular_velocity),
+                )
+            } else {
+                //The part carries the scale for the model, the collider is already scaled
+                let part = StaticPart {
+                    transform: Transform {
+                        scale,
+                        ..Default::default()
+                    },
+                    model,
+                    collider,
+                };
+                self.add_entity(StaticEntity::new(
+                    Transform {
+                        scale: Vec3::ONE,
+                        ..transform
+                    },
+                    vec![part],
+                ))
+            };
+            self.set_asteroid_draw_distance(id, size * DRAW_DISTANCE_PER_SIZE);
+            spawned.push(id);
+        }
+        spawned
+    }
+
+    fn set_asteroid_draw_distance(&mut self, id: EntityId, distance: f32) {
+        let instances = if let Some(entity) = self.get_entity::<DynamicEntity>(id) {
+            entity.model_instance().into_iter().collect()
+        } else if let Some(entity) = self.get_entity::<StaticEntity>(id) {
+            entity.model_instances().to_vec()
+        } else {
+            Vec::new()
+        };
+        for instance in instances {
+            self.world_info
+                .rendering
+                .set_instance_draw_distance(instance, distance);
+        }
+    }
+}
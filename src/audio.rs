@@ -0,0 +1,365 @@
+use crate::transform::Transform;
+use crate::world::EntityId;
+use glam::Vec3;
+#[cfg(feature = "audio")]
+use log::{info, warn};
+use slotmap::SlotMap;
+use std::path::Path;
+#[cfg(feature = "audio")]
+use std::sync::atomic::{AtomicU32, Ordering};
+#[cfg(feature = "audio")]
+use std::sync::Arc;
+
+slotmap::new_key_type! {
+    pub struct SoundHandle;
+    pub struct EmitterHandle;
+}
+
+/// Sounds closer than this play at full volume, further away they fall off with the inverse distance
+const REFERENCE_DISTANCE: f32 = 10.0;
+/// Sounds further away than this aren't heard at all
+const MAX_DISTANCE: f32 = 2000.0;
+
+#[derive(Debug, thiserror::Error)]
+pub enum AudioError {
+    #[error("failed to open sound file: {0}")]
+    Io(#[from] std::io::Error),
+    #[cfg(feature = "audio")]
+    #[error("failed to decode sound: {0}")]
+    Decode(#[from] rodio::decoder::DecoderError),
+    #[cfg(feature = "audio")]
+    #[error("failed to play sound: {0}")]
+    Play(#[from] rodio::PlayError),
+    #[error("no audio output device")]
+    NoOutput,
+    #[error("sound handle doesn't exist")]
+    UnknownSound,
+    #[cfg(not(feature = "audio"))]
+    #[error("built without the audio feature")]
+    Disabled,
+}
+
+/// Left and right gains for a sound at `position`, with the listener facing +Z and +X to its right
+fn spatial_gains(listener: &Transform, position: Vec3) -> (f32, f32) {
+    let offset = listener.rotation.inverse() * (position - listener.position);
+    let distance = offset.length();
+    if distance > MAX_DISTANCE {
+        return (0.0, 0.0);
+    }
+    let attenuation = REFERENCE_DISTANCE / distance.max(REFERENCE_DISTANCE);
+    //Sounds right on top of the listener are centered
+    let pan = offset.normalize_or_zero().x;
+    (
+        (1.0 - pan).sqrt().min(1.0) * attenuation,
+        (1.0 + pan).sqrt().min(1.0) * attenuation,
+    )
+}
+
+#[cfg(feature = "audio")]
+type SoundData = rodio::source::Buffered<rodio::Decoder<std::io::BufReader<std::fs::File>>>;
+
+#[cfg(feature = "audio")]
+struct Sound {
+    data: SoundData,
+}
+
+/// Looping sound that follows an entity
+struct Emitter {
+    entity: EntityId,
+    volume: f32,
+    pitch: f32,
+    #[cfg(feature = "audio")]
+    sink: rodio::Sink,
+    #[cfg(feature = "audio")]
+    gains: Arc<PanGains>,
+}
+
+/// Left and right gains shared with a playing sound, so they can change while it plays
+#[cfg(feature = "audio")]
+struct PanGains {
+    left: AtomicU32,
+    right: AtomicU32,
+}
+
+#[cfg(feature = "audio")]
+impl PanGains {
+    fn new((left, right): (f32, f32)) -> Self {
+        Self {
+            left: AtomicU32::new(left.to_bits()),
+            right: AtomicU32::new(right.to_bits()),
+        }
+    }
+
+    fn set(&self, (left, right): (f32, f32)) {
+        self.left.store(left.to_bits(), Ordering::Relaxed);
+        self.right.store(right.to_bits(), Ordering::Relaxed);
+    }
+
+    fn get(&self) -> (f32, f32) {
+        (
+            f32::from_bits(self.left.load(Ordering::Relaxed)),
+            f32::from_bits(self.right.load(Ordering::Relaxed)),
+        )
+    }
+}
+
+/// Mixes a source down to mono and plays it in stereo with the shared gains
+#[cfg(feature = "audio")]
+struct Panner<S> {
+    input: S,
+    input_channels: u16,
+    gains: Arc<PanGains>,
+    /// Right sample of the current frame, the left one was already returned
+    right_sample: Option<f32>,
+}
+
+#[cfg(feature = "audio")]
+impl<S: rodio::Source<Item = f32>> Panner<S> {
+    fn new(input: S, gains: Arc<PanGains>) -> Self {
+        Self {
+            input_channels: input.channels().max(1),
+            input,
+            gains,
+            right_sample: None,
+        }
+    }
+}
+
+#[cfg(feature = "audio")]
+impl<S: rodio::Source<Item = f32>> Iterator for Panner<S> {
+    type Item = f32;
+
+    fn next(&mut self) -> Option<f32> {
+        if let Some(sample) = self.right_sample.take() {
+            return Some(sample);
+        }
+
+        let mut mono = 0.0;
+        for _ in 0..self.input_channels {
+            mono += self.input.next()?;
+        }
+        mono /= self.input_channels as f32;
+
+        let (left, right) = self.gains.get();
+        self.right_sample = Some(mono * right);
+        Some(mono * left)
+    }
+}
+
+#[cfg(feature = "audio")]
+impl<S: rodio::Source<Item = f32>> rodio::Source for Panner<S> {
+    fn current_frame_len(&self) -> Option<usize> {
+        self.input
+            .current_frame_len()
+            .map(|len| len / self.input_channels as usize * 2)
+    }
+
+    fn channels(&self) -> u16 {
+        2
+    }
+
+    fn sample_rate(&self) -> u32 {
+        self.input.sample_rate()
+    }
+
+    fn total_duration(&self) -> Option<std::time::Duration> {
+        self.input.total_duration()
+    }
+}
+
+/// Sound effects heard from the listener, which the app moves to the player camera every frame.
+/// Without an output device everything still works, it's just silent
+pub struct AudioSystem {
+    /// Scales everything including ui sounds, 0 to 1
+    pub master_volume: f32,
+    /// Scales sounds in the world, 0 to 1
+    pub sfx_volume: f32,
+    listener: Transform,
+    #[cfg(feature = "audio")]
+    sounds: SlotMap<SoundHandle, Sound>,
+    emitters: SlotMap<EmitterHandle, Emitter>,
+    /// The stream has to be kept alive for the handle to play anything
+    #[cfg(feature = "audio")]
+    output: Option<(rodio::OutputStream, rodio::OutputStreamHandle)>,
+}
+
+impl AudioSystem {
+    /// Headless runs pass `open_output` false so they don't grab the audio device
+    #[cfg(feature = "audio")]
+    pub fn new(master_volume: f32, sfx_volume: f32, open_output: bool) -> Self {
+        let output = if open_output {
+            match rodio::OutputStream::try_default() {
+                Ok(output) => Some(output),
+                Err(e) => {
+                    warn!("No audio output, sounds are disabled: {}", e);
+                    None
+                }
+            }
+        } else {
+            None
+        };
+
+        Self {
+            master_volume,
+            sfx_volume,
+            listener: Transform::default(),
+            sounds: SlotMap::with_key(),
+            emitters: SlotMap::with_key(),
+            output,
+        }
+    }
+
+    #[cfg(not(feature = "audio"))]
+    pub fn new(master_volume: f32, sfx_volume: f32, _open_output: bool) -> Self {
+        log::info!("Built without the audio feature, sounds are disabled");
+        Self {
+            master_volume,
+            sfx_volume,
+            listener: Transform::default(),
+            emitters: SlotMap::with_key(),
+        }
+    }
+
+    /// Kept in memory once it's decoded the first time it plays, wav, ogg, flac and mp3 all work
+    #[cfg(feature = "audio")]
+    pub fn load_sound<P: AsRef<Path>>(&mut self, path: P) -> Result<SoundHandle, AudioError> {
+        use rodio::Source;
+
+        let file = std::fs::File::open(path.as_ref())?;
+        let data = rodio::Decoder::new(std::io::BufReader::new(file))?.buffered();
+        info!("Loaded sound {:?}", path.as_ref());
+        Ok(self.sounds.insert(Sound { data }))
+    }
+
+    #[cfg(not(feature = "audio"))]
+    pub fn load_sound<P: AsRef<Path>>(&mut self, _path: P) -> Result<SoundHandle, AudioError> {
+        Err(AudioError::Disabled)
+    }
+
+    /// False without an output device, nothing can be played then
+    pub fn is_enabled(&self) -> bool {
+        #[cfg(feature = "audio")]
+        return self.output.is_some();
+        #[cfg(not(feature = "audio"))]
+        false
+    }
+
+    /// Camera the sounds are heard from
+    pub fn set_listener(&mut self, transform: &Transform) {
+        self.listener = transform.clone();
+    }
+
+    /// One shot in the world, `volume` is on top of the distance falloff
+    pub fn play_3d(
+        &self,
+        sound: SoundHandle,
+        position: Vec3,
+        volume: f32,
+    ) -> Result<(), AudioError> {
+        let (left, right) = spatial_gains(&self.listener, position);
+        let volume = volume * self.sfx_volume * self.master_volume;
+        self.play(sound, (left * volume, right * volume))
+    }
+
+    /// Centered and without falloff
+    pub fn play_ui(&self, sound: SoundHandle) -> Result<(), AudioError> {
+        self.play(sound, (self.master_volume, self.master_volume))
+    }
+
+    #[cfg(feature = "audio")]
+    fn play(&self, sound: SoundHandle, gains: (f32, f32)) -> Result<(), AudioError> {
+        use rodio::Source;
+
+        let sound = self.sounds.get(sound).ok_or(AudioError::UnknownSound)?;
+        let (_, handle) = self.output.as_ref().ok_or(AudioError::NoOutput)?;
+        handle.play_raw(Panner::new(
+            sound.data.clone().convert_samples::<f32>(),
+            Arc::new(PanGains::new(gains)),
+        ))?;
+        Ok(())
+    }
+
+    #[cfg(not(feature = "audio"))]
+    fn play(&self, _sound: SoundHandle, _gains: (f32, f32)) -> Result<(), AudioError> {
+        Err(AudioError::Disabled)
+    }
+
+    /// Loops `sound` at the entity's position until it's removed or the entity is gone
+    #[cfg(feature = "audio")]
+    pub fn add_emitter(
+        &mut self,
+        sound: SoundHandle,
+        entity: EntityId,
+    ) -> Result<EmitterHandle, AudioError> {
+        use rodio::Source;
+
+        let sound = self.sounds.get(sound).ok_or(AudioError::UnknownSound)?;
+        let (_, handle) = self.output.as_ref().ok_or(AudioError::NoOutput)?;
+        let sink = rodio::Sink::try_new(handle)?;
+        let gains = Arc::new(PanGains::new((0.0, 0.0)));
+        sink.append(Panner::new(
+            sound
+                .data
+                .clone()
+                .repeat_infinite()
+                .convert_samples::<f32>(),
+            gains.clone(),
+        ));
+
+        Ok(self.emitters.insert(Emitter {
+            entity,
+            volume: 1.0,
+            pitch: 1.0,
+            sink,
+            gains,
+        }))
+    }
+
+    #[cfg(not(feature = "audio"))]
+    pub fn add_emitter(
+        &mut self,
+        _sound: SoundHandle,
+        _entity: EntityId,
+    ) -> Result<EmitterHandle, AudioError> {
+        Err(AudioError::Disabled)
+    }
+
+    /// Playback speed doubles as pitch, 1 is the sound as recorded
+    pub fn set_emitter(&mut self, emitter: EmitterHandle, volume: f32, pitch: f32) {
+        if let Some(emitter) = self.emitters.get_mut(emitter) {
+            emitter.volume = volume;
+            emitter.pitch = pitch;
+        }
+    }
+
+    pub fn remove_emitter(&mut self, emitter: EmitterHandle) {
+        //Dropping the sink stops it
+        self.emitters.remove(emitter);
+    }
+
+    pub fn emitter_entity(&self, emitter: EmitterHandle) -> Option<EntityId> {
+        self.emitters.get(emitter).map(|emitter| emitter.entity)
+    }
+
+    /// Moves the emitters to their entities, `entity_position` returns None for entities that are gone
+    pub fn update(&mut self, entity_position: impl Fn(EntityId) -> Option<Vec3>) {
+        let volume = self.sfx_volume * self.master_volume;
+        let listener = &self.listener;
+        self.emitters
+            .retain(|_, emitter| match entity_position(emitter.entity) {
+                Some(position) => {
+                    #[cfg(feature = "audio")]
+                    {
+                        let (left, right) = spatial_gains(listener, position);
+                        let volume = volume * emitter.volume;
+                        emitter.gains.set((left * volume, right * volume));
+                        emitter.sink.set_speed(emitter.pitch.max(0.01));
+                    }
+                    #[cfg(not(feature = "audio"))]
+                    let _ = (listener, position, volume);
+                    true
+                }
+                None => false,
+            });
+    }
+}
@@ -0,0 +1,336 @@
+use crate::renderer::{
+    DebugLines, InstanceHandle, MaterialHandle, MeshHandle, PbrMaterialDefinition, Renderer,
+    ShadingModel,
+};
+use crate::space_craft::{ModuleTable, MODULE_SIZE};
+use crate::transform::Transform;
+use crate::world::{EntityId, SpaceCraftEntity, World};
+use glam::{IVec3, Vec3};
+use log::{info, warn};
+
+const VALID_TINT: [f32; 4] = [0.3, 1.0, 0.3, 1.0];
+const INVALID_TINT: [f32; 4] = [1.0, 0.3, 0.3, 1.0];
+const GHOST_ALPHA: f32 = 0.35;
+const HOVER_COLOR: [f32; 4] = [1.0, 1.0, 1.0, 0.8];
+
+/// Cell of a craft under the cursor
+#[derive(Clone, Copy, Debug)]
+struct BuildTarget {
+    craft: EntityId,
+    /// Occupied cell that was hit, right click removes it
+    hit_cell: IVec3,
+    /// Empty cell against the hit face, left click places the selected module here
+    place_cell: IVec3,
+    valid: bool,
+}
+
+/// Adds and removes modules of a craft with the mouse. The selected module is previewed as a
+/// translucent ghost that only exists in the scene, it never gets a collider.
+/// Modules don't have a rotation in the grid yet, so the ghost always has the craft's orientation
+#[derive(Default)]
+pub struct BuildMode {
+    enabled: bool,
+    /// Names in palette order, grouped by category so cycling is stable
+    modules: Vec<String>,
+    selected: usize,
+    /// Created the first time build mode is used
+    ghost_material: Option<MaterialHandle>,
+    ghost: Option<(InstanceHandle, MeshHandle)>,
+    hovered: Option<BuildTarget>,
+}
+
+impl BuildMode {
+    pub fn new(module_table: &ModuleTable) -> Self {
+        let mut modules: Vec<String> = Vec::new();
+        for module in module_table.by_category().into_values().flatten() {
+            if !modules.contains(&module.name) {
+                modules.push(module.name.clone());
+            }
+        }
+        Self {
+            modules,
+            ..Default::default()
+        }
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.enabled
+    }
+
+    pub fn toggle(&mut self, world: &mut World) {
+        if self.enabled {
+            self.disable(world);
+        } else {
+            self.enabled = true;
+            info!(
+                "Build mode on, placing {}",
+                self.selected_module().unwrap_or("nothing")
+            );
+        }
+    }
+
+    /// Removes the ghost from `world`, has to be called before the world is switched or replaced
+    pub fn disable(&mut self, world: &mut World) {
+        if !self.enabled {
+            return;
+        }
+        self.enabled = false;
+        self.hovered = None;
+        self.remove_ghost(world);
+        info!("Build mode off");
+    }
+
+    pub fn selected_module(&self) -> Option<&str> {
+        self.modules.get(self.selected).map(String::as_str)
+    }
+
+    /// Picks a module from the palette, unknown names are ignored
+    pub fn select(&mut self, module_name: &str) {
+        if let Some(index) = self.modules.iter().position(|name| name == module_name) {
+            self.selected = index;
+            info!("Placing {}", module_name);
+        }
+    }
+
+    /// Steps through the module table, wrapping around at both ends
+    pub fn cycle(&mut self, step: i32) {
+        if self.modules.is_empty() {
+            return;
+        }
+        let count = self.modules.len() as i32;
+        self.selected = (self.selected as i32 + step).rem_euclid(count) as usize;
+        info!("Placing {}", self.modules[self.selected]);
+    }
+
+    /// Finds the cell under the cursor and moves the ghost there, the controlled craft can be built on too
+    pub fn update(
+        &mut self,
+        world: &mut World,
+        module_table: &ModuleTable,
+        renderer: &mut Renderer,
+        cursor: Option<[f32; 2]>,
+        surface_size: [u32; 2],
+    ) {
+        if !self.enabled {
+            return;
+        }
+
+        let module_name = self.selected_module().map(str::to_string);
+        self.hovered = cursor.and_then(|cursor| {
+            find_target(
+                world,
+                module_table,
+                module_name.as_deref()?,
+                cursor,
+                surface_size,
+            )
+        });
+
+        let ghost = self
+            .hovered
+            .zip(module_name)
+            .and_then(|(target, module_name)| {
+                let model = module_table.get(&module_name)?.exterior_model.as_ref()?;
+                let (mesh, _) = model.resolve(renderer)?;
+                let craft_transform = world.get_world_transform(target.craft)?;
+                let transform = craft_transform
+                    .transform_by(&Transform::new_pos(
+                        target.place_cell.as_vec3() * MODULE_SIZE,
+                    ))
+                    .transform_by(&model.offset.to_transform());
+                Some((mesh, transform, target.valid))
+            });
+
+        match ghost {
+            Some((mesh, transform, valid)) => {
+                let tint = if valid { VALID_TINT } else { INVALID_TINT };
+                let material = self.ghost_material(renderer);
+                self.show_ghost(world, mesh, material, &transform, tint);
+            }
+            None => self.remove_ghost(world),
+        }
+    }
+
+    /// Adds the selected module at the ghost
+    pub fn place(
+        &mut self,
+        world: &mut World,
+        module_table: &ModuleTable,
+        renderer: &mut Renderer,
+    ) {
+        let (Some(target), Some(module_name)) = (self.hovered, self.selected_module()) else {
+            return;
+        };
+
+        let World {
+            entities,
+            world_info,
+            ..
+        } = world;
+        let Some(craft) = entities.get_mut(target.craft).and_then(|entity| {
+            entity
+                .as_mut()
+                .as_any_mut()
+                .downcast_mut::<SpaceCraftEntity>()
+        }) else {
+            return;
+        };
+        match craft.add_module(
+            target.place_cell,
+            module_name,
+            module_table,
+            renderer,
+            world_info,
+        ) {
+            Ok(()) => info!("Placed {} at {}", module_name, target.place_cell),
+            Err(e) => warn!("Can't place {}: {}", module_name, e),
+        }
+    }
+
+    /// Removes the module under the cursor
+    pub fn remove_hovered(&mut self, world: &mut World) {
+        let Some(target) = self.hovered else {
+            return;
+        };
+
+        let World {
+            entities,
+            world_info,
+            ..
+        } = world;
+        let Some(craft) = entities.get_mut(target.craft).and_then(|entity| {
+            entity
+                .as_mut()
+                .as_any_mut()
+                .downcast_mut::<SpaceCraftEntity>()
+        }) else {
+            return;
+        };
+        match craft.remove_module(target.hit_cell, world_info) {
+            Ok(()) => info!("Removed the module at {}", target.hit_cell),
+            Err(e) => warn!("Can't remove the module: {}", e),
+        }
+    }
+
+    /// Outlines the hovered module
+    pub fn draw(&self, world: &World, lines: &mut DebugLines) {
+        let Some(target) = self.hovered else {
+            return;
+        };
+        let Some(craft_transform) = world.get_world_transform(target.craft) else {
+            return;
+        };
+
+        let center = target.hit_cell.as_vec3() * MODULE_SIZE;
+        let half = MODULE_SIZE * 0.5;
+        let corner = |x: f32, y: f32, z: f32| {
+            craft_transform.position
+                + craft_transform.rotation * (center + Vec3::new(x, y, z) * half)
+        };
+        for a in [-1.0, 1.0] {
+            for b in [-1.0, 1.0] {
+                lines.line(corner(-1.0, a, b), corner(1.0, a, b), HOVER_COLOR, false);
+                lines.line(corner(a, -1.0, b), corner(a, 1.0, b), HOVER_COLOR, false);
+                lines.line(corner(a, b, -1.0), corner(a, b, 1.0), HOVER_COLOR, false);
+            }
+        }
+    }
+
+    fn ghost_material(&mut self, renderer: &mut Renderer) -> Option<MaterialHandle> {
+        if self.ghost_material.is_none() {
+            self.ghost_material = renderer.create_pbr_material(PbrMaterialDefinition {
+                color: [1.0, 1.0, 1.0, GHOST_ALPHA],
+                metallic: 0.0,
+                roughness: 0.8,
+                albedo_texture: None,
+                normal_texture: None,
+                emissive: [0.0; 3],
+                transparent: true,
+                shading_model: ShadingModel::Lit,
+                cull_mode: None,
+            });
+        }
+        self.ghost_material
+    }
+
+    fn show_ghost(
+        &mut self,
+        world: &mut World,
+        mesh: MeshHandle,
+        material: Option<MaterialHandle>,
+        transform: &Transform,
+        tint: [f32; 4],
+    ) {
+        //Instances can't change mesh, so a different module needs a new one
+        if matches!(self.ghost, Some((_, ghost_mesh)) if ghost_mesh != mesh) {
+            self.remove_ghost(world);
+        }
+
+        let rendering = &mut world.world_info.rendering;
+        match self.ghost {
+            Some((instance, _)) => {
+                rendering.update_instance(instance, transform);
+                rendering.set_instance_tint(instance, tint);
+            }
+            None => {
+                self.ghost = material
+                    .and_then(|material| {
+                        rendering.create_tinted_instance(mesh, material, transform, tint)
+                    })
+                    .map(|instance| (instance, mesh));
+            }
+        }
+    }
+
+    fn remove_ghost(&mut self, world: &mut World) {
+        if let Some((instance, _)) = self.ghost.take() {
+            world.world_info.rendering.remove_instance(instance);
+        }
+    }
+}
+
+/// The hit point is moved into the craft's grid, the face that was hit is the axis the point is furthest out along
+fn find_target(
+    world: &World,
+    module_table: &ModuleTable,
+    module_name: &str,
+    cursor: [f32; 2],
+    surface_size: [u32; 2],
+) -> Option<BuildTarget> {
+    let (origin, direction) = world.pick_ray(cursor, surface_size);
+    //The player's own craft is a valid target while building, only the free player is skipped
+    let (craft_id, hit_point) = world.pick_along(origin, direction, Some(world.player_entity))?;
+    let craft = world.get_entity::<SpaceCraftEntity>(craft_id)?;
+    let craft_transform = world.get_world_transform(craft_id)?;
+
+    let inverse_rotation = craft_transform.rotation.inverse();
+    let local_point = inverse_rotation * (hit_point - craft_transform.position);
+    let local_direction = inverse_rotation * direction;
+
+    //Nudged into the surface so hits on a face land in the cell behind it
+    let hit_cell = ((local_point + local_direction * 0.01) / MODULE_SIZE)
+        .round()
+        .as_ivec3();
+    craft.module_at(hit_cell)?;
+
+    let offset = local_point / MODULE_SIZE - hit_cell.as_vec3();
+    let abs = offset.abs();
+    let normal = if abs.x >= abs.y && abs.x >= abs.z {
+        IVec3::new(offset.x.signum() as i32, 0, 0)
+    } else if abs.y >= abs.z {
+        IVec3::new(0, offset.y.signum() as i32, 0)
+    } else {
+        IVec3::new(0, 0, offset.z.signum() as i32)
+    };
+
+    let place_cell = hit_cell + normal;
+    let valid = craft
+        .check_placement(place_cell, module_name, module_table)
+        .is_ok();
+    Some(BuildTarget {
+        craft: craft_id,
+        hit_cell,
+        place_cell,
+        valid,
+    })
+}
@@ -1,25 +1,60 @@
+use crate::physics::PhysicsScene;
+use crate::transform::Transform;
+use glam::{EulerRot, Quat, Vec3, Vec4};
+use rapier3d::prelude::RigidBodyHandle;
+
 #[derive(Clone, Debug)]
 pub struct PerspectiveCamera {
     x_fov_deg: f32,
+    /// Added to the fov for effects, without changing the configured value
+    fov_offset_deg: f32,
     z_near: f32,
+    /// None for an infinite far plane
+    z_far: Option<f32>,
 }
 
 impl Default for PerspectiveCamera {
     fn default() -> Self {
         Self {
             x_fov_deg: 75.0,
+            fov_offset_deg: 0.0,
             z_near: 0.1,
+            z_far: None,
         }
     }
 }
 
 impl PerspectiveCamera {
     pub fn new(x_fov_deg: f32, z_near: f32) -> Self {
-        Self { x_fov_deg, z_near }
+        Self {
+            x_fov_deg,
+            fov_offset_deg: 0.0,
+            z_near,
+            z_far: None,
+        }
+    }
+
+    /// Horizontal fov in degrees
+    pub fn set_fov(&mut self, x_fov_deg: f32) {
+        self.x_fov_deg = x_fov_deg;
+    }
+
+    pub fn set_z_near(&mut self, z_near: f32) {
+        self.z_near = z_near;
+    }
+
+    /// Far planes closer than the near plane are ignored
+    pub fn set_z_far(&mut self, z_far: Option<f32>) {
+        self.z_far = z_far.filter(|z_far| *z_far > self.z_near);
+    }
+
+    pub fn set_fov_offset_deg(&mut self, fov_offset_deg: f32) {
+        self.fov_offset_deg = fov_offset_deg;
     }
 
     pub fn get_fov_y_rad(&self, aspect_ratio: f32) -> f32 {
-        f32::atan(f32::tan(self.x_fov_deg.to_radians() / 2.0) / aspect_ratio) * 2.0
+        let x_fov_deg = (self.x_fov_deg + self.fov_offset_deg).clamp(10.0, 170.0);
+        f32::atan(f32::tan(x_fov_deg.to_radians() / 2.0) / aspect_ratio) * 2.0
     }
 
     pub fn as_infinite_perspective_matrix(&self, size: [u32; 2]) -> glam::Mat4 {
@@ -31,6 +66,23 @@ impl PerspectiveCamera {
         )
     }
 
+    /// Finite when the camera has a far plane, depth is 1.0 at `z_near` and 0.0 at `z_far`
+    pub fn as_reverse_perspective_matrix(&self, size: [u32; 2]) -> glam::Mat4 {
+        match self.z_far {
+            Some(z_far) => {
+                let aspect_ratio = size[0] as f32 / size[1] as f32;
+                //Swapping the planes reverses the depth range
+                glam::Mat4::perspective_lh(
+                    self.get_fov_y_rad(aspect_ratio),
+                    aspect_ratio,
+                    z_far,
+                    self.z_near,
+                )
+            }
+            None => self.as_infinite_reverse_perspective_matrix(size),
+        }
+    }
+
     pub fn as_infinite_reverse_perspective_matrix(&self, size: [u32; 2]) -> glam::Mat4 {
         let aspect_ratio = size[0] as f32 / size[1] as f32;
         glam::Mat4::perspective_infinite_reverse_lh(
@@ -40,3 +92,416 @@ impl PerspectiveCamera {
         )
     }
 }
+
+/// Parallel projection with a far plane, same reverse depth as the perspective camera
+#[derive(Clone, Debug)]
+pub struct OrthographicCamera {
+    /// Half of the visible height in meters, the width follows the aspect ratio
+    half_height: f32,
+    z_near: f32,
+    z_far: f32,
+}
+
+impl OrthographicCamera {
+    pub fn new(half_height: f32, z_near: f32, z_far: f32) -> Self {
+        Self {
+            half_height,
+            z_near,
+            z_far,
+        }
+    }
+
+    /// Depth is 1.0 at `z_near` and 0.0 at `z_far`
+    pub fn as_reverse_orthographic_matrix(&self, size: [u32; 2]) -> glam::Mat4 {
+        let aspect_ratio = size[0] as f32 / size[1] as f32;
+        let half_width = self.half_height * aspect_ratio;
+        //Swapping the planes flips the depth range the same way the reverse perspective does
+        glam::Mat4::orthographic_lh(
+            -half_width,
+            half_width,
+            -self.half_height,
+            self.half_height,
+            self.z_far,
+            self.z_near,
+        )
+    }
+}
+
+#[derive(Clone, Debug)]
+pub enum Camera {
+    Perspective(PerspectiveCamera),
+    Orthographic(OrthographicCamera),
+}
+
+impl Camera {
+    /// Reverse depth projection for the renderer
+    pub fn as_projection_matrix(&self, size: [u32; 2]) -> glam::Mat4 {
+        match self {
+            Camera::Perspective(camera) => camera.as_reverse_perspective_matrix(size),
+            Camera::Orthographic(camera) => camera.as_reverse_orthographic_matrix(size),
+        }
+    }
+
+    /// World space ray through a pixel, `screen_position` is in pixels from the top left of the surface.
+    /// Returns the point on the near plane and the normalized direction
+    pub fn screen_ray(
+        &self,
+        transform: &Transform,
+        screen_position: [f32; 2],
+        size: [u32; 2],
+    ) -> (Vec3, Vec3) {
+        let ndc_x = screen_position[0] / size[0].max(1) as f32 * 2.0 - 1.0;
+        let ndc_y = 1.0 - screen_position[1] / size[1].max(1) as f32 * 2.0;
+        let inverse_view_projection =
+            (self.as_projection_matrix(size) * transform.as_view_matrix()).inverse();
+
+        //Depth is reversed, 1 is the near plane and 0 is at infinity for the infinite projection,
+        //so the second point is taken halfway to keep it finite
+        let near = inverse_view_projection.project_point3(Vec3::new(ndc_x, ndc_y, 1.0));
+        let far = inverse_view_projection.project_point3(Vec3::new(ndc_x, ndc_y, 0.5));
+        let direction = (far - near).normalize_or_zero();
+        (near, direction)
+    }
+}
+
+/// Planes of a view projection, normals point inward
+#[derive(Clone, Debug)]
+pub struct Frustum {
+    planes: [Vec4; 6],
+}
+
+impl Frustum {
+    /// Works with the reverse depth projections, an infinite far plane never culls anything
+    pub fn from_view_projection(view_projection: &glam::Mat4) -> Self {
+        let row_x = view_projection.row(0);
+        let row_y = view_projection.row(1);
+        let row_z = view_projection.row(2);
+        let row_w = view_projection.row(3);
+        let planes = [
+            row_w + row_x,
+            row_w - row_x,
+            row_w + row_y,
+            row_w - row_y,
+            row_z,
+            row_w - row_z,
+        ]
+        .map(|plane| {
+            let length = plane.truncate().length();
+            if length > f32::EPSILON {
+                plane / length
+            } else {
+                plane
+            }
+        });
+        Self { planes }
+    }
+
+    pub fn intersects_sphere(&self, center: Vec3, radius: f32) -> bool {
+        self.planes
+            .iter()
+            .all(|plane| plane.truncate().dot(center) + plane.w >= -radius)
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum CameraMode {
+    /// The view the controlled entity provides
+    FirstPerson,
+    /// Behind the controlled entity, looking at `pivot_offset` from `distance` meters away
+    ThirdPerson { distance: f32, pivot_offset: Vec3 },
+    /// Orthographic view straight down onto the controlled entity, `half_height` meters tall
+    Overhead { half_height: f32 },
+}
+
+impl CameraMode {
+    pub const MIN_DISTANCE: f32 = 1.0;
+    pub const MAX_DISTANCE: f32 = 100.0;
+
+    pub fn third_person() -> Self {
+        Self::ThirdPerson {
+            distance: 8.0,
+            pivot_offset: Vec3::new(0.0, 1.0, 0.0),
+        }
+    }
+
+    pub fn overhead() -> Self {
+        Self::Overhead { half_height: 10.0 }
+    }
+
+    /// Cycles the perspective modes, overhead goes back to first person
+    pub fn next(self) -> Self {
+        match self {
+            Self::FirstPerson => Self::third_person(),
+            Self::ThirdPerson { .. } | Self::Overhead { .. } => Self::FirstPerson,
+        }
+    }
+}
+
+/// Third person camera that trails the entity it follows, smoothing out jitter from the physics body
+pub struct ChaseCamera {
+    /// Smoothed pivot, None until the first update or after a reset
+    pivot: Option<Transform>,
+    transform: Transform,
+    /// How quickly the pivot catches up with the target, in 1/s
+    position_sharpness: f32,
+    rotation_sharpness: f32,
+}
+
+impl Default for ChaseCamera {
+    fn default() -> Self {
+        Self {
+            pivot: None,
+            transform: Transform::default(),
+            position_sharpness: 12.0,
+            rotation_sharpness: 8.0,
+        }
+    }
+}
+
+/// Kept between the camera and whatever the spring arm hits
+const SPRING_ARM_MARGIN: f32 = 0.2;
+
+impl ChaseCamera {
+    /// The next update snaps to the target instead of flying over from the old one
+    pub fn reset(&mut self) {
+        self.pivot = None;
+    }
+
+    pub fn transform(&self) -> &Transform {
+        &self.transform
+    }
+
+    pub fn shift_origin(&mut self, offset: Vec3) {
+        if let Some(pivot) = &mut self.pivot {
+            pivot.position += offset;
+        }
+        self.transform.position += offset;
+    }
+
+    /// `target` is the first person view of the followed entity, the arm ignores `exclude_rigid_body`
+    pub fn update(
+        &mut self,
+        target: &Transform,
+        distance: f32,
+        pivot_offset: Vec3,
+        delta_time: f32,
+        physics: &PhysicsScene,
+        exclude_rigid_body: Option<RigidBodyHandle>,
+    ) {
+        let target_pivot = target.position + target.rotation * pivot_offset;
+        let pivot = self.pivot.get_or_insert_with(|| Transform {
+            position: target_pivot,
+            rotation: target.rotation,
+            ..Default::default()
+        });
+
+        //Exponential smoothing, the same result no matter how the frame time is split up
+        pivot.position = pivot.position.lerp(
+            target_pivot,
+            1.0 - (-self.position_sharpness * delta_time).exp(),
+        );
+        pivot.rotation = pivot
+            .rotation
+            .slerp(
+                target.rotation,
+                1.0 - (-self.rotation_sharpness * delta_time).exp(),
+            )
+            .normalize();
+
+        //Spring arm, pulled in so the camera doesn't end up inside whatever is behind the entity
+        let backward = pivot.rotation * -Vec3::Z;
+        let arm_length = physics
+            .cast_ray(pivot.position, backward, distance, exclude_rigid_body)
+            .map(|(_, hit_distance)| (hit_distance - SPRING_ARM_MARGIN).max(0.0))
+            .unwrap_or(distance);
+
+        self.transform = Transform {
+            position: pivot.position + backward * arm_length,
+            rotation: pivot.rotation,
+            ..Default::default()
+        };
+    }
+}
+
+/// Impacts with less impulse than this don't shake the camera, in N*s
+const IMPACT_TRAUMA_THRESHOLD: f32 = 500.0;
+/// Impulse above the threshold that adds full trauma, in N*s
+const IMPACT_TRAUMA_RANGE: f32 = 20000.0;
+/// Sustained acceleration above this rattles the camera, in m/s^2
+const ACCELERATION_TRAUMA_THRESHOLD: f32 = 10.0;
+/// Trauma per second for every m/s^2 above the threshold
+const ACCELERATION_TRAUMA_RATE: f32 = 0.02;
+/// Trauma lost per second
+const TRAUMA_DECAY: f32 = 1.0;
+/// Rotation at full trauma in radians, for yaw, pitch and roll
+const MAX_SHAKE_ANGLES: Vec3 = Vec3::new(0.04, 0.04, 0.06);
+/// Noise samples per second
+const SHAKE_FREQUENCY: f32 = 20.0;
+/// Degrees of extra fov for every m/s^2 of forward acceleration
+const FOV_KICK_PER_ACCELERATION: f32 = 0.5;
+const MAX_FOV_OFFSET_DEG: f32 = 15.0;
+/// How quickly the fov follows the acceleration, in 1/s
+const FOV_SHARPNESS: f32 = 4.0;
+
+/// Screen shake and fov kick for the player camera
+#[derive(Default)]
+pub struct CameraEffects {
+    /// 0 to 1, the shake is trauma squared so small knocks stay subtle
+    trauma: f32,
+    time: f32,
+    fov_offset_deg: f32,
+}
+
+impl CameraEffects {
+    pub fn add_trauma(&mut self, amount: f32) {
+        self.trauma = (self.trauma + amount.max(0.0)).min(1.0);
+    }
+
+    pub fn add_impact(&mut self, impulse: f32) {
+        if impulse > IMPACT_TRAUMA_THRESHOLD {
+            self.add_trauma((impulse - IMPACT_TRAUMA_THRESHOLD) / IMPACT_TRAUMA_RANGE);
+        }
+    }
+
+    /// `acceleration` is in camera space, +z pushes the camera forward
+    pub fn update(&mut self, delta_time: f32, acceleration: Vec3) {
+        self.time += delta_time;
+
+        let excess_acceleration = acceleration.length() - ACCELERATION_TRAUMA_THRESHOLD;
+        if excess_acceleration > 0.0 {
+            self.add_trauma(excess_acceleration * ACCELERATION_TRAUMA_RATE * delta_time);
+        }
+        self.trauma = (self.trauma - TRAUMA_DECAY * delta_time).max(0.0);
+
+        let target_fov_offset = (acceleration.z * FOV_KICK_PER_ACCELERATION)
+            .clamp(-MAX_FOV_OFFSET_DEG, MAX_FOV_OFFSET_DEG);
+        self.fov_offset_deg +=
+            (target_fov_offset - self.fov_offset_deg) * (1.0 - (-FOV_SHARPNESS * delta_time).exp());
+    }
+
+    pub fn fov_offset_deg(&self) -> f32 {
+        self.fov_offset_deg
+    }
+
+    /// Applied on top of the camera's rotation
+    pub fn shake_rotation(&self) -> Quat {
+        let shake = self.trauma * self.trauma;
+        if shake <= 0.0 {
+            return Quat::IDENTITY;
+        }
+
+        let sample = self.time * SHAKE_FREQUENCY;
+        let angles = MAX_SHAKE_ANGLES
+            * shake
+            * Vec3::new(
+                perlin_1d(sample, 0),
+                perlin_1d(sample, 1),
+                perlin_1d(sample, 2),
+            );
+        Quat::from_euler(EulerRot::YXZ, angles.x, angles.y, angles.z)
+    }
+}
+
+/// Gradient noise in -1 to 1, each seed is a different curve
+fn perlin_1d(x: f32, seed: u32) -> f32 {
+    fn gradient(cell: i32, seed: u32) -> f32 {
+        let mut hash = (cell as u32).wrapping_mul(0x9E37_79B9) ^ seed.wrapping_mul(0x85EB_CA6B);
+        hash ^= hash >> 16;
+        hash = hash.wrapping_mul(0x7FEB_352D);
+        hash ^= hash >> 15;
+        (hash as f32 / u32::MAX as f32) * 2.0 - 1.0
+    }
+
+    let cell = x.floor();
+    let t = x - cell;
+    let cell = cell as i32;
+    let fade = t * t * t * (t * (t * 6.0 - 15.0) + 10.0);
+
+    let a = gradient(cell, seed) * t;
+    let b = gradient(cell.wrapping_add(1), seed) * (t - 1.0);
+    //1D gradient noise peaks at 0.5
+    (a + (b - a) * fade) * 2.0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use glam::Mat4;
+
+    /// Widescreen, ultrawide and portrait
+    const SIZES: [[u32; 2]; 3] = [[1920, 1080], [3440, 1440], [1080, 1920]];
+
+    fn assert_matrix_eq(actual: Mat4, expected: Mat4) {
+        assert!(
+            actual.abs_diff_eq(expected, 1e-6),
+            "{actual} isn't {expected}"
+        );
+    }
+
+    #[test]
+    fn orthographic_matches_glam() {
+        let camera = OrthographicCamera::new(10.0, 0.5, 200.0);
+        for size in SIZES {
+            let aspect_ratio = size[0] as f32 / size[1] as f32;
+            let half_width = 10.0 * aspect_ratio;
+            let expected = Mat4::orthographic_lh(-half_width, half_width, -10.0, 10.0, 200.0, 0.5);
+            let matrix = camera.as_reverse_orthographic_matrix(size);
+            assert_matrix_eq(matrix, expected);
+            assert_matrix_eq(
+                Camera::Orthographic(camera.clone()).as_projection_matrix(size),
+                expected,
+            );
+
+            //The width follows the aspect ratio, the height is always the same
+            let corner = matrix.project_point3(Vec3::new(half_width, 10.0, 0.5));
+            assert!(
+                corner.abs_diff_eq(Vec3::new(1.0, 1.0, 1.0), 1e-5),
+                "{corner}"
+            );
+            let far = matrix.project_point3(Vec3::new(0.0, 0.0, 200.0));
+            assert!(far.z.abs() < 1e-5, "{far}");
+        }
+    }
+
+    #[test]
+    fn perspective_keeps_the_horizontal_fov() {
+        let camera = PerspectiveCamera::new(90.0, 0.1);
+        for size in SIZES {
+            let matrix = Camera::Perspective(camera.clone()).as_projection_matrix(size);
+            assert_matrix_eq(matrix, camera.as_reverse_perspective_matrix(size));
+
+            //With a 90 degree horizontal fov the edge of the screen is as far to the side as it is ahead
+            let edge = matrix.project_point3(Vec3::new(5.0, 0.0, 5.0));
+            assert!((edge.x - 1.0).abs() < 1e-5, "{size:?} edge is {edge}");
+        }
+    }
+
+    #[test]
+    fn finite_reverse_perspective_swaps_the_planes() {
+        let mut camera = PerspectiveCamera::new(75.0, 0.1);
+        camera.set_z_far(Some(1000.0));
+        for size in SIZES {
+            let aspect_ratio = size[0] as f32 / size[1] as f32;
+            let fov_y = camera.get_fov_y_rad(aspect_ratio);
+            //Left handed like the view matrix, with the planes swapped for reverse depth
+            let expected = Mat4::perspective_lh(fov_y, aspect_ratio, 1000.0, 0.1);
+            let matrix = camera.as_reverse_perspective_matrix(size);
+            assert_matrix_eq(matrix, expected);
+
+            let near = matrix.project_point3(Vec3::new(0.0, 0.0, 0.1));
+            let far = matrix.project_point3(Vec3::new(0.0, 0.0, 1000.0));
+            assert!((near.z - 1.0).abs() < 1e-5, "{near}");
+            assert!(far.z.abs() < 1e-5, "{far}");
+        }
+
+        //Without a far plane it goes back to infinite, far planes in front of the near plane are ignored too
+        for z_far in [None, Some(0.05)] {
+            camera.set_z_far(z_far);
+            let size = [1920, 1080];
+            assert_matrix_eq(
+                camera.as_reverse_perspective_matrix(size),
+                camera.as_infinite_reverse_perspective_matrix(size),
+            );
+        }
+    }
+}
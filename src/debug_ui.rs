@@ -0,0 +1,471 @@
+#[cfg(feature = "debug_ui")]
+mod inspector;
+#[cfg(feature = "debug_ui")]
+mod palette;
+
+use crate::app::FrameProfiler;
+use crate::build_mode::BuildMode;
+use crate::renderer::{FrameContext, MaterialHandle, MeshHandle, RenderStats};
+use crate::space_craft::ModuleTable;
+use crate::thumbnails::ModuleThumbnails;
+use crate::world::World;
+use log::info;
+use std::collections::VecDeque;
+use winit::event::WindowEvent;
+
+/// Frames shown in the frame time graph
+const FRAME_HISTORY: usize = 240;
+#[cfg(feature = "debug_ui")]
+const FRAME_GRAPH_SIZE: [f32; 2] = [240.0, 60.0];
+/// Matches egui-winit, a line of mouse wheel scrolling moves this many points
+#[cfg(feature = "debug_ui")]
+const POINTS_PER_SCROLL_LINE: f32 = 50.0;
+
+/// What the build palette picks from, only passed while build mode is on
+pub struct PaletteSource<'a> {
+    pub build_mode: &'a mut BuildMode,
+    pub module_table: &'a ModuleTable,
+    pub thumbnails: &'a ModuleThumbnails,
+}
+
+/// Egui overlay drawn on top of the scene, hidden until toggled.
+/// The build palette is shown whenever build mode is on, even while the debug windows are hidden
+pub struct DebugUi {
+    visible: bool,
+    /// Seconds, newest last
+    frame_times: VecDeque<f32>,
+
+    #[cfg(feature = "debug_ui")]
+    context: egui::Context,
+    #[cfg(feature = "debug_ui")]
+    renderer: egui_wgpu::Renderer,
+    //Egui-winit needs a newer winit, so window events are translated here
+    #[cfg(feature = "debug_ui")]
+    events: Vec<egui::Event>,
+    #[cfg(feature = "debug_ui")]
+    pointer_position: egui::Pos2,
+    #[cfg(feature = "debug_ui")]
+    modifiers: egui::Modifiers,
+    #[cfg(feature = "debug_ui")]
+    pixels_per_point: f32,
+    #[cfg(feature = "debug_ui")]
+    start_time: std::time::Instant,
+    /// Built by `update`, drawn by `render`
+    #[cfg(feature = "debug_ui")]
+    output: Option<egui::FullOutput>,
+    #[cfg(feature = "debug_ui")]
+    inspector: inspector::Inspector,
+    #[cfg(feature = "debug_ui")]
+    palette: palette::BuildPalette,
+    /// Whether the last update showed the palette
+    #[cfg(feature = "debug_ui")]
+    palette_shown: bool,
+}
+
+impl DebugUi {
+    #[cfg(feature = "debug_ui")]
+    pub fn new(device: &wgpu::Device, format: wgpu::TextureFormat, scale_factor: f32) -> Self {
+        Self {
+            visible: false,
+            frame_times: VecDeque::with_capacity(FRAME_HISTORY),
+            context: egui::Context::default(),
+            renderer: egui_wgpu::Renderer::new(device, format, None, 1),
+            events: Vec::new(),
+            pointer_position: egui::Pos2::ZERO,
+            modifiers: egui::Modifiers::default(),
+            pixels_per_point: scale_factor,
+            start_time: std::time::Instant::now(),
+            output: None,
+            inspector: inspector::Inspector::default(),
+            palette: palette::BuildPalette::default(),
+            palette_shown: false,
+        }
+    }
+
+    #[cfg(not(feature = "debug_ui"))]
+    pub fn new(_device: &wgpu::Device, _format: wgpu::TextureFormat, _scale_factor: f32) -> Self {
+        info!("Built without the debug_ui feature, the debug overlay is unavailable");
+        Self {
+            visible: false,
+            frame_times: VecDeque::with_capacity(FRAME_HISTORY),
+        }
+    }
+
+    /// A new context sends the font atlas again, the old renderer's textures went with the lost device
+    #[cfg(feature = "debug_ui")]
+    pub fn recreate_device(&mut self, device: &wgpu::Device, format: wgpu::TextureFormat) {
+        self.context = egui::Context::default();
+        self.renderer = egui_wgpu::Renderer::new(device, format, None, 1);
+        self.output = None;
+    }
+
+    #[cfg(not(feature = "debug_ui"))]
+    pub fn recreate_device(&mut self, _device: &wgpu::Device, _format: wgpu::TextureFormat) {}
+
+    pub fn toggle(&mut self) {
+        self.visible = !self.visible;
+        info!("Debug ui {}", if self.visible { "shown" } else { "hidden" });
+    }
+
+    pub fn record_frame(&mut self, delta_time: f32) {
+        if self.frame_times.len() == FRAME_HISTORY {
+            self.frame_times.pop_front();
+        }
+        self.frame_times.push_back(delta_time);
+    }
+
+    /// Average over the frame history, in seconds
+    pub fn average_frame_time(&self) -> f32 {
+        if self.frame_times.is_empty() {
+            return 0.0;
+        }
+        self.frame_times.iter().sum::<f32>() / self.frame_times.len() as f32
+    }
+
+    /// True while the pointer is over a debug window or dragging one, the game should ignore the mouse then
+    #[cfg(feature = "debug_ui")]
+    pub fn wants_pointer_input(&self) -> bool {
+        (self.visible || self.palette_shown) && self.context.wants_pointer_input()
+    }
+
+    #[cfg(not(feature = "debug_ui"))]
+    pub fn wants_pointer_input(&self) -> bool {
+        false
+    }
+
+    /// True while a debug widget has keyboard focus
+    #[cfg(feature = "debug_ui")]
+    pub fn wants_keyboard_input(&self) -> bool {
+        (self.visible || self.palette_shown) && self.context.wants_keyboard_input()
+    }
+
+    #[cfg(not(feature = "debug_ui"))]
+    pub fn wants_keyboard_input(&self) -> bool {
+        false
+    }
+
+    #[cfg(feature = "debug_ui")]
+    pub fn window_event(&mut self, event: &WindowEvent) {
+        use winit::event::{ElementState, MouseButton, MouseScrollDelta};
+
+        match event {
+            WindowEvent::ScaleFactorChanged { scale_factor, .. } => {
+                self.pixels_per_point = *scale_factor as f32;
+            }
+            WindowEvent::ModifiersChanged(state) => {
+                self.modifiers = egui::Modifiers {
+                    alt: state.alt(),
+                    ctrl: state.ctrl(),
+                    shift: state.shift(),
+                    mac_cmd: false,
+                    command: state.ctrl(),
+                };
+            }
+            _ => {}
+        }
+
+        //Input isn't queued while hidden, otherwise it would all arrive at once when shown
+        if !self.visible {
+            return;
+        }
+
+        match event {
+            WindowEvent::CursorMoved { position, .. } => {
+                self.pointer_position = egui::pos2(
+                    position.x as f32 / self.pixels_per_point,
+                    position.y as f32 / self.pixels_per_point,
+                );
+                self.events
+                    .push(egui::Event::PointerMoved(self.pointer_position));
+            }
+            WindowEvent::CursorLeft { .. } => self.events.push(egui::Event::PointerGone),
+            WindowEvent::MouseInput { state, button, .. } => {
+                let button = match button {
+                    MouseButton::Left => egui::PointerButton::Primary,
+                    MouseButton::Right => egui::PointerButton::Secondary,
+                    MouseButton::Middle => egui::PointerButton::Middle,
+                    MouseButton::Other(_) => return,
+                };
+                self.events.push(egui::Event::PointerButton {
+                    pos: self.pointer_position,
+                    button,
+                    pressed: *state == ElementState::Pressed,
+                    modifiers: self.modifiers,
+                });
+            }
+            WindowEvent::MouseWheel { delta, .. } => {
+                let delta = match delta {
+                    MouseScrollDelta::LineDelta(x, y) => {
+                        egui::vec2(*x, *y) * POINTS_PER_SCROLL_LINE
+                    }
+                    MouseScrollDelta::PixelDelta(delta) => {
+                        egui::vec2(delta.x as f32, delta.y as f32) / self.pixels_per_point
+                    }
+                };
+                self.events.push(egui::Event::Scroll(delta));
+            }
+            WindowEvent::ReceivedCharacter(character) if !character.is_control() => {
+                self.events.push(egui::Event::Text(character.to_string()));
+            }
+            _ => {}
+        }
+    }
+
+    #[cfg(not(feature = "debug_ui"))]
+    pub fn window_event(&mut self, _event: &WindowEvent) {}
+
+    /// Builds this frame's windows, call after the world has updated so they show its current state
+    #[cfg(feature = "debug_ui")]
+    pub fn update(
+        &mut self,
+        surface_size: [u32; 2],
+        world: &mut World,
+        render_stats: &RenderStats,
+        profiler: &FrameProfiler,
+        cube_model: Option<(MeshHandle, MaterialHandle)>,
+        palette_source: Option<PaletteSource>,
+    ) {
+        self.palette_shown = palette_source.is_some();
+        if !self.visible && !self.palette_shown {
+            self.output = None;
+            return;
+        }
+
+        let pixels_per_point = self.pixels_per_point;
+        let raw_input = egui::RawInput {
+            screen_rect: Some(egui::Rect::from_min_size(
+                egui::Pos2::ZERO,
+                egui::vec2(surface_size[0] as f32, surface_size[1] as f32) / pixels_per_point,
+            )),
+            pixels_per_point: Some(pixels_per_point),
+            time: Some(self.start_time.elapsed().as_secs_f64()),
+            modifiers: self.modifiers,
+            events: std::mem::take(&mut self.events),
+            ..Default::default()
+        };
+
+        let average_frame_time = self.average_frame_time();
+        let frame_times = &self.frame_times;
+        let visible = self.visible;
+        let inspector = &mut self.inspector;
+        let palette = &mut self.palette;
+        let full_output = self.context.run(raw_input, |context| {
+            if let Some(palette_source) = palette_source {
+                palette.show(context, palette_source);
+            }
+            if !visible {
+                return;
+            }
+            egui::Window::new("Debug")
+                .default_pos([10.0, 10.0])
+                .resizable(false)
+                .show(context, |ui| {
+                    stats_ui(
+                        ui,
+                        average_frame_time,
+                        frame_times,
+                        world,
+                        render_stats,
+                        profiler,
+                    )
+                });
+            inspector.show(context, world, cube_model);
+        });
+        self.output = Some(full_output);
+    }
+
+    #[cfg(not(feature = "debug_ui"))]
+    pub fn update(
+        &mut self,
+        _surface_size: [u32; 2],
+        _world: &mut World,
+        _render_stats: &RenderStats,
+        _profiler: &FrameProfiler,
+        _cube_model: Option<(MeshHandle, MaterialHandle)>,
+        _palette_source: Option<PaletteSource>,
+    ) {
+    }
+
+    /// Records the windows built by the last update over whatever is already in the frame's output
+    #[cfg(feature = "debug_ui")]
+    pub fn render(
+        &mut self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        frame: &mut FrameContext,
+        surface_size: [u32; 2],
+    ) {
+        let Some(full_output) = self.output.take() else {
+            return;
+        };
+
+        let paint_jobs = self.context.tessellate(full_output.shapes);
+        let screen_descriptor = egui_wgpu::renderer::ScreenDescriptor {
+            size_in_pixels: surface_size,
+            pixels_per_point: self.pixels_per_point,
+        };
+
+        for (id, image_delta) in full_output.textures_delta.set.iter() {
+            self.renderer
+                .update_texture(device, queue, *id, image_delta);
+        }
+
+        let user_command_buffers = self.renderer.update_buffers(
+            device,
+            queue,
+            &mut frame.encoder,
+            &paint_jobs,
+            &screen_descriptor,
+        );
+        frame.add_command_buffers(user_command_buffers);
+        {
+            let mut render_pass = frame
+                .encoder
+                .begin_render_pass(&wgpu::RenderPassDescriptor {
+                    label: Some("Debug Ui Pass"),
+                    color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                        view: &frame.output_view,
+                        resolve_target: None,
+                        ops: wgpu::Operations {
+                            load: wgpu::LoadOp::Load,
+                            store: true,
+                        },
+                    })],
+                    depth_stencil_attachment: None,
+                });
+            self.renderer
+                .render(&mut render_pass, &paint_jobs, &screen_descriptor);
+        }
+
+        for id in full_output.textures_delta.free.iter() {
+            self.renderer.free_texture(id);
+        }
+    }
+
+    #[cfg(not(feature = "debug_ui"))]
+    pub fn render(
+        &mut self,
+        _device: &wgpu::Device,
+        _queue: &wgpu::Queue,
+        _frame: &mut FrameContext,
+        _surface_size: [u32; 2],
+    ) {
+    }
+}
+
+#[cfg(feature = "debug_ui")]
+fn stats_ui(
+    ui: &mut egui::Ui,
+    average_frame_time: f32,
+    frame_times: &VecDeque<f32>,
+    world: &World,
+    render_stats: &RenderStats,
+    profiler: &FrameProfiler,
+) {
+    let fps = if average_frame_time > 0.0 {
+        1.0 / average_frame_time
+    } else {
+        0.0
+    };
+    ui.label(format!(
+        "FPS: {:.0} ({:.2}ms)",
+        fps,
+        average_frame_time * 1000.0
+    ));
+    frame_time_graph(ui, frame_times);
+
+    ui.separator();
+    let physics = &world.world_info.physics;
+    ui.label(format!("Entities: {}", world.entities.len()));
+    ui.label(format!(
+        "Rigid bodies: {} | colliders: {}",
+        physics.rigid_body_count(),
+        physics.collider_count()
+    ));
+
+    ui.separator();
+    ui.label(format!("Draw calls: {}", render_stats.draw_calls));
+    ui.label(format!(
+        "Instances: {} in {} sets, {} skipped",
+        render_stats.instances_drawn, render_stats.instance_sets, render_stats.instances_skipped
+    ));
+    ui.label(format!(
+        "Instances per LOD: {}",
+        render_stats
+            .instances_per_lod
+            .map(|count| count.to_string())
+            .join(" / ")
+    ));
+    ui.label(format!("Triangles: {}", render_stats.triangles));
+    ui.label(format!("Instance writes: {}", render_stats.instance_writes));
+    ui.label(format!("Encode: {:.2}ms", render_stats.encode_cpu_ms));
+    ui.label(format!(
+        "Gpu: {}",
+        render_stats
+            .gpu_ms
+            .map_or("n/a".to_string(), |gpu_ms| format!("{:.2}ms", gpu_ms))
+    ));
+
+    ui.separator();
+    let (fps, uncapped_fps) = profiler.fps();
+    ui.label(format!(
+        "Profile: {:.0} fps ({:.0} uncapped)",
+        fps, uncapped_fps
+    ));
+    egui::Grid::new("profile").striped(true).show(ui, |ui| {
+        for header in ["Scope", "Avg", "p50", "p95", "p99"] {
+            ui.label(header);
+        }
+        ui.end_row();
+        for summary in profiler.summaries() {
+            ui.label(summary.name);
+            for ms in [
+                summary.average_ms,
+                summary.p50_ms,
+                summary.p95_ms,
+                summary.p99_ms,
+            ] {
+                ui.label(format!("{:.2}", ms));
+            }
+            ui.end_row();
+        }
+    });
+}
+
+/// Line graph of the frame history with a 60fps reference line
+#[cfg(feature = "debug_ui")]
+fn frame_time_graph(ui: &mut egui::Ui, frame_times: &VecDeque<f32>) {
+    let (rect, _response) = ui.allocate_exact_size(
+        egui::vec2(FRAME_GRAPH_SIZE[0], FRAME_GRAPH_SIZE[1]),
+        egui::Sense::hover(),
+    );
+    let painter = ui.painter_at(rect);
+    painter.rect_filled(rect, 0.0, egui::Color32::from_black_alpha(160));
+
+    //Scaled so the slowest recent frame fits, but never zoomed in past 30fps
+    let max_frame_time = frame_times.iter().copied().fold(1.0 / 30.0, f32::max);
+    let frame_time_y =
+        |frame_time: f32| rect.bottom() - frame_time / max_frame_time * rect.height();
+
+    let target_y = frame_time_y(1.0 / 60.0);
+    painter.line_segment(
+        [
+            egui::pos2(rect.left(), target_y),
+            egui::pos2(rect.right(), target_y),
+        ],
+        egui::Stroke::new(1.0, egui::Color32::DARK_GREEN),
+    );
+
+    let step = rect.width() / (FRAME_HISTORY - 1) as f32;
+    let points: Vec<egui::Pos2> = frame_times
+        .iter()
+        .enumerate()
+        .map(|(i, frame_time)| egui::pos2(rect.left() + i as f32 * step, frame_time_y(*frame_time)))
+        .collect();
+    painter.add(egui::Shape::line(
+        points,
+        egui::Stroke::new(1.0, egui::Color32::LIGHT_GREEN),
+    ));
+
+    ui.label(format!("Graph max: {:.2}ms", max_frame_time * 1000.0));
+}
@@ -0,0 +1,225 @@
+use crate::physics::{ColliderShape, PhysicsScene};
+use crate::renderer::{MaterialHandle, MeshHandle};
+use crate::transform::Transform;
+use crate::world::{DynamicEntity, Entity, EntityId, SpaceCraftEntity, World};
+use glam::{EulerRot, Quat, Vec3};
+
+/// How far in front of the camera "spawn cube here" puts the cube
+const SPAWN_DISTANCE: f32 = 5.0;
+const ENTITY_LIST_HEIGHT: f32 = 240.0;
+
+/// Lists the world's entities and edits the selected one
+#[derive(Default)]
+pub struct Inspector {
+    selected: Option<EntityId>,
+}
+
+impl Inspector {
+    pub fn show(
+        &mut self,
+        context: &egui::Context,
+        world: &mut World,
+        cube_model: Option<(MeshHandle, MaterialHandle)>,
+    ) {
+        if self
+            .selected
+            .is_some_and(|selected| !world.entities.contains_key(selected))
+        {
+            self.selected = None;
+        }
+
+        egui::Window::new("Inspector")
+            .default_pos([10.0, 320.0])
+            .show(context, |ui| {
+                if ui
+                    .add_enabled(cube_model.is_some(), egui::Button::new("Spawn cube here"))
+                    .clicked()
+                {
+                    if let Some(cube_model) = cube_model {
+                        spawn_cube(world, cube_model);
+                    }
+                }
+                ui.separator();
+
+                //Despawning the entity being controlled would leave nothing to control
+                let input_entity = world.input_entity();
+                let mut despawned = None;
+                egui::ScrollArea::vertical()
+                    .max_height(ENTITY_LIST_HEIGHT)
+                    .show(ui, |ui| {
+                        for (entity_id, entity) in world.entities.iter() {
+                            let name = world
+                                .get_label(entity_id)
+                                .map_or("", |label| label.name.as_str());
+                            ui.horizontal(|ui| {
+                                if ui
+                                    .add_enabled(
+                                        entity_id != input_entity,
+                                        egui::Button::new("Despawn").small(),
+                                    )
+                                    .clicked()
+                                {
+                                    despawned = Some(entity_id);
+                                }
+                                let text = format!(
+                                    "{:?} {} ({})",
+                                    entity_id,
+                                    name,
+                                    short_type_name(entity.as_ref().type_name())
+                                );
+                                if ui
+                                    .selectable_label(self.selected == Some(entity_id), text)
+                                    .clicked()
+                                {
+                                    self.selected = Some(entity_id);
+                                }
+                            });
+                        }
+                    });
+
+                //Goes through the command queue so it's removed the same way gameplay would remove it
+                if let Some(entity_id) = despawned {
+                    world.world_info.commands.despawn(entity_id);
+                    if self.selected == Some(entity_id) {
+                        self.selected = None;
+                    }
+                }
+
+                if let Some(entity_id) = self.selected {
+                    ui.separator();
+                    entity_details(ui, world, entity_id);
+                }
+            });
+    }
+}
+
+/// `untitled_space_game::world::DynamicEntity` -> `DynamicEntity`
+fn short_type_name(type_name: &str) -> &str {
+    type_name.rsplit("::").next().unwrap_or(type_name)
+}
+
+fn spawn_cube(world: &mut World, (mesh, material): (MeshHandle, MaterialHandle)) {
+    let (_, camera_transform) = world.get_player_camera();
+    let forward = camera_transform.rotation * Vec3::Z;
+    world.world_info.spawn(DynamicEntity::new(
+        Transform {
+            position: camera_transform.position + forward * SPAWN_DISTANCE,
+            rotation: camera_transform.rotation,
+            ..Default::default()
+        },
+        Some((mesh, material)),
+        Some(ColliderShape::Box(Vec3::splat(0.5))),
+    ));
+}
+
+fn entity_details(ui: &mut egui::Ui, world: &mut World, entity_id: EntityId) {
+    let Some(entity) = world
+        .entities
+        .get_mut(entity_id)
+        .map(|entity| entity.as_mut())
+    else {
+        return;
+    };
+
+    ui.label(format!("{:?}: {}", entity_id, entity.type_name()));
+    match entity.get_world_transform() {
+        Some(mut transform) => {
+            if transform_editor(ui, &mut transform) {
+                entity.set_transform(&transform, &mut world.world_info);
+            }
+        }
+        None => {
+            ui.label("No transform");
+        }
+    }
+
+    let physics = &world.world_info.physics;
+    let entity = entity.as_any();
+    if let Some(dynamic) = entity.downcast_ref::<DynamicEntity>() {
+        ui.separator();
+        body_details(ui, physics, dynamic);
+        ui.label(format!("Model instance: {:?}", dynamic.model_instance()));
+        ui.label(format!("Collider: {:?}", dynamic.collider_instance()));
+    } else if let Some(craft) = entity.downcast_ref::<SpaceCraftEntity>() {
+        ui.separator();
+        body_details(ui, physics, craft);
+        let model_instances = craft.model_instances();
+        ui.collapsing(
+            format!("Model instances ({})", model_instances.len()),
+            |ui| {
+                for model_instance in model_instances {
+                    ui.label(format!("{:?}", model_instance));
+                }
+            },
+        );
+    }
+}
+
+fn body_details(ui: &mut egui::Ui, physics: &PhysicsScene, entity: &dyn Entity) {
+    let Some(rigid_body) = entity.get_rigid_body() else {
+        ui.label("No rigid body");
+        return;
+    };
+    ui.label(format!("Rigid body: {:?}", rigid_body));
+    ui.label(format!(
+        "Linear velocity: {}",
+        format_vec3(physics.get_rigid_body_linear_velocity(rigid_body))
+    ));
+    ui.label(format!(
+        "Angular velocity: {}",
+        format_vec3(physics.get_rigid_body_angular_velocity(rigid_body))
+    ));
+    ui.label(format!("Mass: {:.1}kg", physics.get_mass(rigid_body)));
+}
+
+fn format_vec3(value: Vec3) -> String {
+    format!("[{:.2}, {:.2}, {:.2}]", value.x, value.y, value.z)
+}
+
+/// Drag widgets for each component, returns true if anything was changed
+fn transform_editor(ui: &mut egui::Ui, transform: &mut Transform) -> bool {
+    let mut changed = false;
+    egui::Grid::new("transform_editor")
+        .num_columns(4)
+        .show(ui, |ui| {
+            ui.label("Position");
+            for value in transform.position.as_mut() {
+                changed |= ui.add(egui::DragValue::new(value).speed(0.1)).changed();
+            }
+            ui.end_row();
+
+            //Only written back when dragged, going through euler angles every frame would snap near the poles
+            let (yaw, pitch, roll) = transform.rotation.to_euler(EulerRot::YXZ);
+            let mut angles = [yaw.to_degrees(), pitch.to_degrees(), roll.to_degrees()];
+            let mut rotation_changed = false;
+            ui.label("Yaw/pitch/roll");
+            for value in angles.iter_mut() {
+                rotation_changed |= ui
+                    .add(egui::DragValue::new(value).speed(1.0).suffix("°"))
+                    .changed();
+            }
+            if rotation_changed {
+                transform.rotation = Quat::from_euler(
+                    EulerRot::YXZ,
+                    angles[0].to_radians(),
+                    angles[1].to_radians(),
+                    angles[2].to_radians(),
+                );
+                changed = true;
+            }
+            ui.end_row();
+
+            ui.label("Scale");
+            for value in transform.scale.as_mut() {
+                changed |= ui
+                    .add(
+                        egui::DragValue::new(value)
+                            .speed(0.01)
+                            .clamp_range(0.01..=1000.0),
+                    )
+                    .changed();
+            }
+            ui.end_row();
+        });
+    changed
+}
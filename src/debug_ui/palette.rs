@@ -0,0 +1,81 @@
+use super::PaletteSource;
+use crate::thumbnails::ModuleThumbnails;
+use std::collections::HashMap;
+
+const THUMBNAIL_BUTTON_SIZE: f32 = 48.0;
+
+/// Module picker for build mode, grouped by category with the module thumbnails as buttons
+#[derive(Default)]
+pub struct BuildPalette {
+    /// Thumbnails uploaded to egui, by module name
+    textures: HashMap<String, egui::TextureHandle>,
+}
+
+impl BuildPalette {
+    pub fn show(&mut self, context: &egui::Context, source: PaletteSource) {
+        egui::Window::new("Build Palette")
+            .default_pos([10.0, 620.0])
+            .resizable(false)
+            .show(context, |ui| {
+                let selected = source.build_mode.selected_module().map(str::to_string);
+                for (category, modules) in source.module_table.by_category() {
+                    egui::CollapsingHeader::new(category.as_str())
+                        .default_open(true)
+                        .show(ui, |ui| {
+                            ui.horizontal_wrapped(|ui| {
+                                for module in modules {
+                                    let is_selected =
+                                        selected.as_deref() == Some(module.name.as_str());
+                                    let texture =
+                                        self.texture(context, source.thumbnails, &module.name);
+
+                                    //Modules without an exterior model only get their name
+                                    let response = match texture {
+                                        Some(texture) => ui
+                                            .add(
+                                                egui::ImageButton::new(
+                                                    texture.id(),
+                                                    [THUMBNAIL_BUTTON_SIZE; 2],
+                                                )
+                                                .selected(is_selected),
+                                            )
+                                            .on_hover_text(module.name.as_str()),
+                                        None => {
+                                            ui.selectable_label(is_selected, module.name.as_str())
+                                        }
+                                    };
+                                    if response.clicked() {
+                                        source.build_mode.select(&module.name);
+                                    }
+                                }
+                            });
+                        });
+                }
+            });
+    }
+
+    /// Uploads the thumbnail the first time it's shown
+    fn texture(
+        &mut self,
+        context: &egui::Context,
+        thumbnails: &ModuleThumbnails,
+        module_name: &str,
+    ) -> Option<egui::TextureHandle> {
+        if let Some(texture) = self.textures.get(module_name) {
+            return Some(texture.clone());
+        }
+
+        let image = thumbnails.get(module_name)?;
+        let texture = context.load_texture(
+            module_name,
+            egui::ColorImage::from_rgba_unmultiplied(
+                [image.width() as usize, image.height() as usize],
+                image.as_raw(),
+            ),
+            egui::TextureOptions::LINEAR,
+        );
+        self.textures
+            .insert(module_name.to_string(), texture.clone());
+        Some(texture)
+    }
+}
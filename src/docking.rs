@@ -0,0 +1,180 @@
+use crate::physics::PhysicsScene;
+use crate::transform::Transform;
+use crate::world::EntityId;
+use glam::Vec3;
+use rapier3d::prelude::{ImpulseJointHandle, RigidBodyHandle};
+use std::collections::HashMap;
+
+/// Ports closer than this in meters can dock
+const DOCKING_DISTANCE: f32 = 0.5;
+/// Largest angle in radians between one port's direction and the other's reversed direction
+const DOCKING_ANGLE: f32 = 10.0 * std::f32::consts::PI / 180.0;
+/// Largest relative speed between the ports in m/s
+const DOCKING_SPEED: f32 = 1.0;
+/// Pushes the crafts apart when undocking, in Newton seconds
+const SEPARATION_IMPULSE: f32 = 2000.0;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct DockingPortId {
+    pub entity: EntityId,
+    pub index: usize,
+}
+
+/// Where a port is this frame, published by the entity that owns it
+#[derive(Clone, Copy, Debug)]
+pub struct DockingPortState {
+    pub rigid_body: RigidBodyHandle,
+    /// Relative to the rigid body
+    pub local_position: Vec3,
+    pub position: Vec3,
+    /// Outward facing direction
+    pub direction: Vec3,
+    /// Velocity of the point the port is at
+    pub velocity: Vec3,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum DockingStatus {
+    Undocked,
+    /// A free port of another entity is aligned with one of this entity's ports
+    Available {
+        port: DockingPortId,
+        other: DockingPortId,
+    },
+    Docked {
+        port: DockingPortId,
+        other: DockingPortId,
+    },
+}
+
+struct DockingJoint {
+    ports: [DockingPortId; 2],
+    joint: ImpulseJointHandle,
+}
+
+/// Docked crafts are held together by a fixed joint between their bodies at the port frames,
+/// the grids stay separate so undocking only has to remove the joint
+#[derive(Default)]
+pub struct DockingRegistry {
+    ports: HashMap<DockingPortId, DockingPortState>,
+    joints: Vec<DockingJoint>,
+}
+
+impl DockingRegistry {
+    /// Replaces every port the entity published before
+    pub fn set_ports(&mut self, entity: EntityId, ports: impl Iterator<Item = DockingPortState>) {
+        self.remove_ports(entity);
+        for (index, port) in ports.enumerate() {
+            self.ports.insert(DockingPortId { entity, index }, port);
+        }
+    }
+
+    pub fn remove_ports(&mut self, entity: EntityId) {
+        self.ports.retain(|id, _| id.entity != entity);
+    }
+
+    fn joint_for(&self, entity: EntityId) -> Option<&DockingJoint> {
+        self.joints
+            .iter()
+            .find(|joint| joint.ports.iter().any(|port| port.entity == entity))
+    }
+
+    fn is_port_docked(&self, port: DockingPortId) -> bool {
+        self.joints.iter().any(|joint| joint.ports.contains(&port))
+    }
+
+    /// Closest pair of free, aligned and slow moving ports between `entity` and any other entity
+    pub fn find_candidate(&self, entity: EntityId) -> Option<(DockingPortId, DockingPortId)> {
+        let min_alignment = DOCKING_ANGLE.cos();
+
+        let free_ports = || {
+            self.ports
+                .iter()
+                .filter(|(id, _)| !self.is_port_docked(**id))
+        };
+
+        free_ports()
+            .filter(|(id, _)| id.entity == entity)
+            .flat_map(|(id, port)| {
+                free_ports()
+                    .filter(|(other_id, _)| other_id.entity != entity)
+                    .map(move |(other_id, other)| (*id, port, *other_id, other))
+            })
+            .filter(|(_, port, _, other)| {
+                port.position.distance(other.position) <= DOCKING_DISTANCE
+                    && port.direction.dot(-other.direction) >= min_alignment
+                    && port.velocity.distance(other.velocity) <= DOCKING_SPEED
+            })
+            .min_by(|(_, a_port, _, a_other), (_, b_port, _, b_other)| {
+                a_port
+                    .position
+                    .distance_squared(a_other.position)
+                    .total_cmp(&b_port.position.distance_squared(b_other.position))
+            })
+            .map(|(id, _, other_id, _)| (id, other_id))
+    }
+
+    pub fn status(&self, entity: EntityId) -> DockingStatus {
+        if let Some(joint) = self.joint_for(entity) {
+            let (port, other) = if joint.ports[0].entity == entity {
+                (joint.ports[0], joint.ports[1])
+            } else {
+                (joint.ports[1], joint.ports[0])
+            };
+            return DockingStatus::Docked { port, other };
+        }
+
+        match self.find_candidate(entity) {
+            Some((port, other)) => DockingStatus::Available { port, other },
+            None => DockingStatus::Undocked,
+        }
+    }
+
+    /// Joins the best candidate pair with a fixed joint, keeping the current relative rotation so the crafts don't
+    /// snap around the port axis. Returns the entity that was docked with
+    pub fn dock(&mut self, physics: &mut PhysicsScene, entity: EntityId) -> Option<EntityId> {
+        if self.joint_for(entity).is_some() {
+            return None;
+        }
+        let (port_id, other_id) = self.find_candidate(entity)?;
+        let port = self.ports[&port_id];
+        let other = self.ports[&other_id];
+
+        let (_, rotation) = physics.get_rigid_body_transform(port.rigid_body);
+        let (_, other_rotation) = physics.get_rigid_body_transform(other.rigid_body);
+        let joint = physics.create_fixed_joint(
+            port.rigid_body,
+            other.rigid_body,
+            &Transform::new_pos(port.local_position),
+            &Transform {
+                position: other.local_position,
+                rotation: other_rotation.inverse() * rotation,
+                ..Default::default()
+            },
+        );
+
+        self.joints.push(DockingJoint {
+            ports: [port_id, other_id],
+            joint,
+        });
+        Some(other_id.entity)
+    }
+
+    /// Removes the joint and pushes both bodies away from their ports, returns false if the entity wasn't docked
+    pub fn undock(&mut self, physics: &mut PhysicsScene, entity: EntityId) -> bool {
+        let Some(index) = self
+            .joints
+            .iter()
+            .position(|joint| joint.ports.iter().any(|port| port.entity == entity))
+        else {
+            return false;
+        };
+
+        let joint = self.joints.remove(index);
+        physics.remove_joint(joint.joint);
+        for port in joint.ports.iter().filter_map(|id| self.ports.get(id)) {
+            physics.apply_impulse(port.rigid_body, -port.direction * SEPARATION_IMPULSE);
+        }
+        true
+    }
+}
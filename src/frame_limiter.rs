@@ -0,0 +1,42 @@
+use std::time::{Duration, Instant};
+
+/// Sleeping is only accurate to a millisecond or two on most platforms, the rest is spun out
+const SPIN_TIME: Duration = Duration::from_micros(1500);
+
+/// Keeps frames from starting faster than a target rate
+pub struct FrameLimiter {
+    next_frame: Instant,
+}
+
+impl Default for FrameLimiter {
+    fn default() -> Self {
+        Self {
+            next_frame: Instant::now(),
+        }
+    }
+}
+
+impl FrameLimiter {
+    /// Waits until the next frame is due, None returns right away
+    pub fn wait(&mut self, target_fps: Option<f32>) {
+        let now = Instant::now();
+        let Some(target_fps) = target_fps.filter(|fps| *fps > 0.0) else {
+            self.next_frame = now;
+            return;
+        };
+
+        //A frame that ran long starts the schedule over instead of rushing the following ones to catch up
+        self.next_frame = (self.next_frame + Duration::from_secs_f32(1.0 / target_fps)).max(now);
+
+        if let Some(sleep_time) = self
+            .next_frame
+            .saturating_duration_since(now)
+            .checked_sub(SPIN_TIME)
+        {
+            std::thread::sleep(sleep_time);
+        }
+        while Instant::now() < self.next_frame {
+            std::thread::yield_now();
+        }
+    }
+}
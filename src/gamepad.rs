@@ -0,0 +1,269 @@
+use log::info;
+#[cfg(feature = "gamepad")]
+use log::warn;
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum GamepadAxis {
+    LeftStickX,
+    /// Up is positive
+    LeftStickY,
+    RightStickX,
+    RightStickY,
+    /// Triggers go from 0 to 1
+    LeftTrigger,
+    RightTrigger,
+}
+
+impl GamepadAxis {
+    pub const ALL: [Self; 6] = [
+        Self::LeftStickX,
+        Self::LeftStickY,
+        Self::RightStickX,
+        Self::RightStickY,
+        Self::LeftTrigger,
+        Self::RightTrigger,
+    ];
+}
+
+/// Face buttons are named by position since the labels differ between controllers
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum GamepadButton {
+    South,
+    East,
+    North,
+    West,
+    LeftBumper,
+    RightBumper,
+    Select,
+    Start,
+    LeftStick,
+    RightStick,
+    DPadUp,
+    DPadDown,
+    DPadLeft,
+    DPadRight,
+}
+
+impl GamepadButton {
+    pub const ALL: [Self; 14] = [
+        Self::South,
+        Self::East,
+        Self::North,
+        Self::West,
+        Self::LeftBumper,
+        Self::RightBumper,
+        Self::Select,
+        Self::Start,
+        Self::LeftStick,
+        Self::RightStick,
+        Self::DPadUp,
+        Self::DPadDown,
+        Self::DPadLeft,
+        Self::DPadRight,
+    ];
+}
+
+/// Deadzone and response curve for one input axis
+#[derive(Clone, Copy, Debug)]
+pub struct AxisSettings {
+    /// Stick travel below this is ignored, the rest is rescaled to start at zero
+    pub deadzone: f32,
+    /// Exponent of the response curve, above 1 gives finer control near the center
+    pub exponent: f32,
+}
+
+impl Default for AxisSettings {
+    fn default() -> Self {
+        Self {
+            deadzone: 0.0,
+            exponent: 1.0,
+        }
+    }
+}
+
+impl AxisSettings {
+    pub fn apply(&self, value: f32) -> f32 {
+        let magnitude = value.abs().min(1.0);
+        if magnitude <= self.deadzone {
+            return 0.0;
+        }
+        let scaled = (magnitude - self.deadzone) / (1.0 - self.deadzone);
+        scaled.powf(self.exponent) * value.signum()
+    }
+}
+
+/// Axes without settings are used as they come from the pad
+#[derive(Clone, Debug)]
+pub struct GamepadSettings {
+    pub axes: HashMap<GamepadAxis, AxisSettings>,
+}
+
+impl Default for GamepadSettings {
+    fn default() -> Self {
+        let stick = AxisSettings {
+            deadzone: 0.15,
+            exponent: 2.0,
+        };
+        let trigger = AxisSettings {
+            deadzone: 0.05,
+            exponent: 1.0,
+        };
+        Self {
+            axes: HashMap::from([
+                (GamepadAxis::LeftStickX, stick),
+                (GamepadAxis::LeftStickY, stick),
+                (GamepadAxis::RightStickX, stick),
+                (GamepadAxis::RightStickY, stick),
+                (GamepadAxis::LeftTrigger, trigger),
+                (GamepadAxis::RightTrigger, trigger),
+            ]),
+        }
+    }
+}
+
+/// State of the active gamepad for one frame, axes already have their deadzone and curve applied
+#[derive(Clone, Debug, Default)]
+pub struct GamepadFrame {
+    pub axes: HashMap<GamepadAxis, f32>,
+    pub held_buttons: HashSet<GamepadButton>,
+}
+
+impl GamepadFrame {
+    pub fn axis(&self, axis: GamepadAxis) -> f32 {
+        self.axes.get(&axis).copied().unwrap_or(0.0)
+    }
+
+    pub fn button_held(&self, button: GamepadButton) -> bool {
+        self.held_buttons.contains(&button)
+    }
+}
+
+/// Follows the most recently used gamepad, pads can be plugged in and out at any time
+pub struct GamepadInput {
+    pub settings: GamepadSettings,
+    #[cfg(feature = "gamepad")]
+    gilrs: Option<gilrs::Gilrs>,
+    #[cfg(feature = "gamepad")]
+    active_gamepad: Option<gilrs::GamepadId>,
+}
+
+#[cfg(feature = "gamepad")]
+fn to_gilrs_axis(axis: GamepadAxis) -> Result<gilrs::Axis, gilrs::Button> {
+    //Most pads report their triggers as analog buttons
+    match axis {
+        GamepadAxis::LeftStickX => Ok(gilrs::Axis::LeftStickX),
+        GamepadAxis::LeftStickY => Ok(gilrs::Axis::LeftStickY),
+        GamepadAxis::RightStickX => Ok(gilrs::Axis::RightStickX),
+        GamepadAxis::RightStickY => Ok(gilrs::Axis::RightStickY),
+        GamepadAxis::LeftTrigger => Err(gilrs::Button::LeftTrigger2),
+        GamepadAxis::RightTrigger => Err(gilrs::Button::RightTrigger2),
+    }
+}
+
+#[cfg(feature = "gamepad")]
+fn to_gilrs_button(button: GamepadButton) -> gilrs::Button {
+    match button {
+        GamepadButton::South => gilrs::Button::South,
+        GamepadButton::East => gilrs::Button::East,
+        GamepadButton::North => gilrs::Button::North,
+        GamepadButton::West => gilrs::Button::West,
+        GamepadButton::LeftBumper => gilrs::Button::LeftTrigger,
+        GamepadButton::RightBumper => gilrs::Button::RightTrigger,
+        GamepadButton::Select => gilrs::Button::Select,
+        GamepadButton::Start => gilrs::Button::Start,
+        GamepadButton::LeftStick => gilrs::Button::LeftThumb,
+        GamepadButton::RightStick => gilrs::Button::RightThumb,
+        GamepadButton::DPadUp => gilrs::Button::DPadUp,
+        GamepadButton::DPadDown => gilrs::Button::DPadDown,
+        GamepadButton::DPadLeft => gilrs::Button::DPadLeft,
+        GamepadButton::DPadRight => gilrs::Button::DPadRight,
+    }
+}
+
+impl GamepadInput {
+    #[cfg(feature = "gamepad")]
+    pub fn new(settings: GamepadSettings) -> Self {
+        let gilrs = match gilrs::Gilrs::new() {
+            Ok(gilrs) => Some(gilrs),
+            Err(e) => {
+                warn!("Gamepad support unavailable: {}", e);
+                None
+            }
+        };
+        let active_gamepad = gilrs.as_ref().and_then(|gilrs| {
+            gilrs.gamepads().next().map(|(id, gamepad)| {
+                info!("Using gamepad {}", gamepad.name());
+                id
+            })
+        });
+
+        Self {
+            settings,
+            gilrs,
+            active_gamepad,
+        }
+    }
+
+    #[cfg(not(feature = "gamepad"))]
+    pub fn new(settings: GamepadSettings) -> Self {
+        info!("Built without the gamepad feature, only keyboard and mouse input is available");
+        Self { settings }
+    }
+
+    /// Returns None while no gamepad is connected
+    #[cfg(feature = "gamepad")]
+    pub fn poll(&mut self) -> Option<GamepadFrame> {
+        use gilrs::EventType;
+
+        let gilrs = self.gilrs.as_mut()?;
+
+        while let Some(gilrs::Event { id, event, .. }) = gilrs.next_event() {
+            match event {
+                EventType::Connected => {
+                    info!("Gamepad connected: {}", gilrs.gamepad(id).name());
+                    self.active_gamepad.get_or_insert(id);
+                }
+                EventType::Disconnected => {
+                    info!("Gamepad disconnected: {}", gilrs.gamepad(id).name());
+                    if self.active_gamepad == Some(id) {
+                        self.active_gamepad = gilrs
+                            .gamepads()
+                            .map(|(other_id, _)| other_id)
+                            .find(|other_id| *other_id != id);
+                    }
+                }
+                EventType::AxisChanged(..)
+                | EventType::ButtonChanged(..)
+                | EventType::ButtonPressed(..) => self.active_gamepad = Some(id),
+                _ => {}
+            }
+        }
+
+        let gamepad = gilrs.connected_gamepad(self.active_gamepad?)?;
+        let axes = GamepadAxis::ALL
+            .iter()
+            .map(|axis| {
+                let value = match to_gilrs_axis(*axis) {
+                    Ok(gilrs_axis) => gamepad.value(gilrs_axis),
+                    Err(button) => gamepad.button_data(button).map_or(0.0, |data| data.value()),
+                };
+                let settings = self.settings.axes.get(axis).copied().unwrap_or_default();
+                (*axis, settings.apply(value))
+            })
+            .collect();
+        let held_buttons = GamepadButton::ALL
+            .iter()
+            .copied()
+            .filter(|button| gamepad.is_pressed(to_gilrs_button(*button)))
+            .collect();
+
+        Some(GamepadFrame { axes, held_buttons })
+    }
+
+    #[cfg(not(feature = "gamepad"))]
+    pub fn poll(&mut self) -> Option<GamepadFrame> {
+        None
+    }
+}
@@ -0,0 +1,264 @@
+use crate::renderer::{Hud, Renderer};
+use crate::world::{Autopilot, World};
+use glam::{Mat4, Vec2, Vec3};
+
+const LINE_THICKNESS: f32 = 2.0;
+const CROSSHAIR_SIZE: f32 = 8.0;
+/// Empty space in the middle of the crosshair
+const CROSSHAIR_GAP: f32 = 3.0;
+const MARKER_RADIUS: f32 = 10.0;
+const TARGET_BRACKET_SIZE: f32 = 16.0;
+const ARROW_SIZE: f32 = 14.0;
+/// Off screen markers are kept this far in from the screen edge
+const EDGE_MARGIN: f32 = 30.0;
+const TEXT_SCALE: f32 = 2.0;
+
+/// Below this speed the velocity markers are hidden since their direction is mostly noise
+const MIN_MARKER_SPEED: f32 = 0.1;
+/// The velocity markers are projected from this far along the velocity so the camera's offset from the body doesn't matter
+const VELOCITY_MARKER_DISTANCE: f32 = 10_000.0;
+
+const CROSSHAIR_COLOR: [f32; 4] = [1.0, 1.0, 1.0, 0.8];
+const PROGRADE_COLOR: [f32; 4] = [0.2, 1.0, 0.2, 0.9];
+const RETROGRADE_COLOR: [f32; 4] = [1.0, 0.6, 0.1, 0.9];
+const TARGET_COLOR: [f32; 4] = [0.2, 0.8, 1.0, 0.9];
+const NAME_LABEL_COLOR: [f32; 4] = [0.9, 0.9, 0.9, 0.9];
+const AUTOPILOT_COLOR: [f32; 4] = [1.0, 0.85, 0.2, 0.9];
+
+/// Where a world space point ends up on screen, in pixels from the top left corner
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ScreenPoint {
+    OnScreen(Vec2),
+    /// Clamped to the screen edge, `direction` points from the screen center towards the point
+    OffScreen {
+        position: Vec2,
+        direction: Vec2,
+    },
+}
+
+/// Points behind the camera are pushed to the edge on the side they're on instead of being mirrored across the screen
+pub fn project_to_screen(view_projection: &Mat4, point: Vec3, size: Vec2) -> ScreenPoint {
+    let clip = *view_projection * point.extend(1.0);
+    let center = size * 0.5;
+    //Clip space y is up, screen y is down
+    let to_pixels = |ndc: Vec2| Vec2::new(ndc.x, -ndc.y) * center;
+
+    if clip.w > f32::EPSILON {
+        let ndc = clip.truncate().truncate() / clip.w;
+        if ndc.x.abs() <= 1.0 && ndc.y.abs() <= 1.0 {
+            return ScreenPoint::OnScreen(center + to_pixels(ndc));
+        }
+        return clamp_to_edge(to_pixels(ndc), size);
+    }
+
+    //Dividing by a negative w is what flips points behind the camera, the undivided position still points the right way
+    let offset = to_pixels(clip.truncate().truncate());
+    if offset.length_squared() > f32::EPSILON {
+        clamp_to_edge(offset, size)
+    } else {
+        //Straight behind, any direction is as good as another
+        clamp_to_edge(Vec2::Y, size)
+    }
+}
+
+/// `offset` is from the screen center and can't be zero
+fn clamp_to_edge(offset: Vec2, size: Vec2) -> ScreenPoint {
+    let center = size * 0.5;
+    let extent = (center - Vec2::splat(EDGE_MARGIN)).max(Vec2::ONE);
+    //Dividing by a zero component gives infinity, so the other axis decides
+    let scale = (extent.x / offset.x.abs()).min(extent.y / offset.y.abs());
+    ScreenPoint::OffScreen {
+        position: center + offset * scale,
+        direction: offset.normalize(),
+    }
+}
+
+/// Crosshair, velocity markers of the controlled body, the autopilot mode and the selected target
+pub fn draw_hud(
+    hud: &mut Hud,
+    world: &World,
+    view_projection: &Mat4,
+    camera_position: Vec3,
+    surface_size: [u32; 2],
+) {
+    let size = Vec2::new(surface_size[0] as f32, surface_size[1] as f32);
+    draw_crosshair(hud, size * 0.5);
+
+    let velocity = world
+        .entities
+        .get(world.input_entity())
+        .and_then(|entity| entity.get_rigid_body())
+        .map(|rigid_body| {
+            world
+                .world_info
+                .physics
+                .get_rigid_body_linear_velocity(rigid_body)
+        });
+    if let Some(direction) = velocity
+        .filter(|velocity| velocity.length() >= MIN_MARKER_SPEED)
+        .map(Vec3::normalize)
+    {
+        for (direction, retrograde) in [(direction, false), (-direction, true)] {
+            let point = camera_position + direction * VELOCITY_MARKER_DISTANCE;
+            draw_velocity_marker(
+                hud,
+                project_to_screen(view_projection, point, size),
+                retrograde,
+            );
+        }
+    }
+
+    if let Some(autopilot) = world
+        .controlled_autopilot()
+        .filter(|autopilot| *autopilot != Autopilot::Off)
+    {
+        let label = format!("Autopilot: {}", autopilot.name());
+        let text_size = Hud::text_size(&label, TEXT_SCALE);
+        let top_left = Vec2::new(
+            (size.x - text_size.x) * 0.5,
+            size.y - EDGE_MARGIN - text_size.y,
+        );
+        hud.text(top_left, &label, TEXT_SCALE, AUTOPILOT_COLOR);
+    }
+
+    if let Some(target) = world.get_selected_target() {
+        if let Some(transform) = world.get_world_transform(target) {
+            let label = format!(
+                "{} {}",
+                world.display_name(target).unwrap_or_default(),
+                format_distance(transform.position.distance(camera_position))
+            );
+            draw_target_marker(
+                hud,
+                project_to_screen(view_projection, transform.position, size),
+                &label,
+                size,
+            );
+        }
+    }
+}
+
+/// Floating names over every named entity except players and whatever is being controlled, hidden behind geometry
+pub fn draw_name_labels(renderer: &mut Renderer, world: &World, camera_position: Vec3) {
+    let input_entity = world.input_entity();
+    for entity_id in world.entities.keys() {
+        let Some(label) = world.get_label(entity_id) else {
+            continue;
+        };
+        if entity_id == input_entity || label.tags.contains("player") {
+            continue;
+        }
+        let Some(transform) = world.get_world_transform(entity_id) else {
+            continue;
+        };
+
+        //Anchored on the side of the bounds facing the camera, so the entity doesn't hide its own label
+        let bounds = world.entities[entity_id]
+            .get_rigid_body()
+            .and_then(|rigid_body| world.world_info.physics.get_rigid_body_bounds(rigid_body));
+        let anchor = match bounds {
+            Some((min, max)) => {
+                let center = (min + max) * 0.5;
+                let to_camera = (camera_position - center).normalize_or_zero();
+                center + to_camera * (max - min).length() * 0.5
+            }
+            None => transform.position,
+        };
+        renderer.draw_world_label(anchor, &label.name, NAME_LABEL_COLOR, true);
+    }
+}
+
+fn draw_crosshair(hud: &mut Hud, center: Vec2) {
+    for direction in [Vec2::X, -Vec2::X, Vec2::Y, -Vec2::Y] {
+        hud.line(
+            center + direction * CROSSHAIR_GAP,
+            center + direction * (CROSSHAIR_GAP + CROSSHAIR_SIZE),
+            LINE_THICKNESS,
+            CROSSHAIR_COLOR,
+        );
+    }
+}
+
+/// Prograde is a circle with three ticks, retrograde a crossed out circle
+fn draw_velocity_marker(hud: &mut Hud, point: ScreenPoint, retrograde: bool) {
+    let color = if retrograde {
+        RETROGRADE_COLOR
+    } else {
+        PROGRADE_COLOR
+    };
+
+    match point {
+        ScreenPoint::OnScreen(center) => {
+            hud.circle(center, MARKER_RADIUS, LINE_THICKNESS, color);
+            if retrograde {
+                let corner = Vec2::splat(MARKER_RADIUS * std::f32::consts::FRAC_1_SQRT_2);
+                let flipped = Vec2::new(corner.x, -corner.y);
+                hud.line(center - corner, center + corner, LINE_THICKNESS, color);
+                hud.line(center - flipped, center + flipped, LINE_THICKNESS, color);
+            } else {
+                for direction in [-Vec2::Y, Vec2::X, -Vec2::X] {
+                    hud.line(
+                        center + direction * MARKER_RADIUS,
+                        center + direction * (MARKER_RADIUS * 1.8),
+                        LINE_THICKNESS,
+                        color,
+                    );
+                }
+            }
+        }
+        ScreenPoint::OffScreen {
+            position,
+            direction,
+        } => hud.arrow(position, direction, ARROW_SIZE, color),
+    }
+}
+
+fn draw_target_marker(hud: &mut Hud, point: ScreenPoint, label: &str, size: Vec2) {
+    let text_size = Hud::text_size(label, TEXT_SCALE);
+    let label_center = match point {
+        ScreenPoint::OnScreen(center) => {
+            //Corner brackets around the target
+            for corner in [
+                Vec2::new(-1.0, -1.0),
+                Vec2::new(1.0, -1.0),
+                Vec2::new(1.0, 1.0),
+                Vec2::new(-1.0, 1.0),
+            ] {
+                let point = center + corner * TARGET_BRACKET_SIZE;
+                let length = TARGET_BRACKET_SIZE * 0.5;
+                hud.line(
+                    point,
+                    point - Vec2::new(corner.x * length, 0.0),
+                    LINE_THICKNESS,
+                    TARGET_COLOR,
+                );
+                hud.line(
+                    point,
+                    point - Vec2::new(0.0, corner.y * length),
+                    LINE_THICKNESS,
+                    TARGET_COLOR,
+                );
+            }
+            center + Vec2::new(0.0, TARGET_BRACKET_SIZE + text_size.y)
+        }
+        ScreenPoint::OffScreen {
+            position,
+            direction,
+        } => {
+            hud.arrow(position, direction, ARROW_SIZE, TARGET_COLOR);
+            position - direction * (ARROW_SIZE + text_size.y * 2.0)
+        }
+    };
+
+    let top_left =
+        (label_center - text_size * 0.5).clamp(Vec2::ZERO, (size - text_size).max(Vec2::ZERO));
+    hud.text(top_left, label, TEXT_SCALE, TARGET_COLOR);
+}
+
+fn format_distance(meters: f32) -> String {
+    if meters < 1000.0 {
+        format!("{:.0}m", meters)
+    } else {
+        format!("{:.1}km", meters / 1000.0)
+    }
+}
@@ -0,0 +1,379 @@
+use crate::gamepad::{GamepadAxis, GamepadButton, GamepadFrame};
+use log::{info, warn};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::path::Path;
+use winit::event::VirtualKeyCode;
+use winit_input_helper::WinitInputHelper;
+
+/// Gamepad values past this count as a press for actions
+const GAMEPAD_PRESS_THRESHOLD: f32 = 0.5;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum AxisDirection {
+    Positive,
+    Negative,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum InputBinding {
+    Key(VirtualKeyCode),
+    /// Only triggers while control is held
+    ControlKey(VirtualKeyCode),
+    /// 0 is the left button, 1 the right and 2 the middle
+    Mouse(usize),
+    /// One half of an axis, giving 0 to 1 as it moves that way
+    GamepadAxis(GamepadAxis, AxisDirection),
+    GamepadButton(GamepadButton),
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum InputMapError {
+    #[error("failed to access bindings file: {0}")]
+    Io(#[from] std::io::Error),
+    #[cfg(feature = "ron")]
+    #[error("failed to parse bindings file: {0}")]
+    Parse(#[from] ron::error::SpannedError),
+    #[cfg(feature = "ron")]
+    #[error("failed to write bindings: {0}")]
+    Serialize(#[from] ron::Error),
+    #[cfg(not(feature = "ron"))]
+    #[error("bindings are stored as ron but ron support isn't enabled")]
+    RonDisabled,
+}
+
+/// Axes are the positive action minus the negative one
+const AXES: [(&str, &str, &str); 6] = [
+    ("move_x", "move_right", "move_left"),
+    ("move_y", "move_up", "move_down"),
+    ("move_z", "move_forward", "move_back"),
+    ("yaw", "yaw_right", "yaw_left"),
+    ("pitch", "pitch_up", "pitch_down"),
+    ("roll", "roll_right", "roll_left"),
+];
+
+fn default_bindings() -> BTreeMap<String, Vec<InputBinding>> {
+    use AxisDirection::{Negative, Positive};
+    use InputBinding::{ControlKey, GamepadAxis as Axis, GamepadButton as Button, Key, Mouse};
+    use VirtualKeyCode as K;
+
+    let bindings: [(&str, Vec<InputBinding>); 51] = [
+        (
+            "move_right",
+            vec![Key(K::D), Axis(GamepadAxis::LeftStickX, Positive)],
+        ),
+        (
+            "move_left",
+            vec![Key(K::A), Axis(GamepadAxis::LeftStickX, Negative)],
+        ),
+        (
+            "move_up",
+            vec![Key(K::Space), Axis(GamepadAxis::RightTrigger, Positive)],
+        ),
+        (
+            "move_down",
+            vec![Key(K::LShift), Axis(GamepadAxis::LeftTrigger, Positive)],
+        ),
+        (
+            "move_forward",
+            vec![Key(K::W), Axis(GamepadAxis::LeftStickY, Positive)],
+        ),
+        (
+            "move_back",
+            vec![Key(K::S), Axis(GamepadAxis::LeftStickY, Negative)],
+        ),
+        (
+            "yaw_right",
+            vec![Key(K::Right), Axis(GamepadAxis::RightStickX, Positive)],
+        ),
+        (
+            "yaw_left",
+            vec![Key(K::Left), Axis(GamepadAxis::RightStickX, Negative)],
+        ),
+        (
+            "pitch_up",
+            vec![Key(K::Up), Axis(GamepadAxis::RightStickY, Positive)],
+        ),
+        (
+            "pitch_down",
+            vec![Key(K::Down), Axis(GamepadAxis::RightStickY, Negative)],
+        ),
+        (
+            "roll_right",
+            vec![Key(K::E), Button(GamepadButton::RightBumper)],
+        ),
+        (
+            "roll_left",
+            vec![Key(K::Q), Button(GamepadButton::LeftBumper)],
+        ),
+        ("fire", vec![Mouse(0), Button(GamepadButton::South)]),
+        //Shares the button with fire, clicking only selects while the cursor is free
+        ("select", vec![Mouse(0)]),
+        ("build_remove", vec![Mouse(1)]),
+        (
+            "switch_weapon_mode",
+            vec![Key(K::R), Button(GamepadButton::DPadDown)],
+        ),
+        (
+            "toggle_flight_assist",
+            vec![Key(K::B), Button(GamepadButton::North)],
+        ),
+        (
+            "toggle_dampeners",
+            vec![Key(K::Z), Button(GamepadButton::West)],
+        ),
+        ("toggle_noclip", vec![Key(K::N)]),
+        (
+            "toggle_docking",
+            vec![Key(K::G), Button(GamepadButton::East)],
+        ),
+        (
+            "interact",
+            vec![Key(K::F), Button(GamepadButton::DPadRight)],
+        ),
+        (
+            "toggle_craft_control",
+            vec![Key(K::C), Button(GamepadButton::Select)],
+        ),
+        (
+            "cycle_target",
+            vec![Key(K::T), Button(GamepadButton::DPadLeft)],
+        ),
+        (
+            "toggle_interior",
+            vec![Key(K::I), Button(GamepadButton::DPadUp)],
+        ),
+        (
+            "cycle_camera_mode",
+            vec![Key(K::V), Button(GamepadButton::RightStick)],
+        ),
+        (
+            "toggle_build_view",
+            vec![Key(K::O), Button(GamepadButton::LeftStick)],
+        ),
+        ("toggle_build_mode", vec![Key(K::X)]),
+        ("toggle_menu", vec![Key(K::M), Button(GamepadButton::Start)]),
+        ("toggle_cursor_grab", vec![Key(K::Tab), Key(K::Escape)]),
+        ("save_craft", vec![ControlKey(K::S)]),
+        ("autopilot_off", vec![ControlKey(K::Key0)]),
+        ("autopilot_kill_rotation", vec![ControlKey(K::Key1)]),
+        ("autopilot_prograde", vec![ControlKey(K::Key2)]),
+        ("autopilot_retrograde", vec![ControlKey(K::Key3)]),
+        ("autopilot_face_target", vec![ControlKey(K::Key4)]),
+        ("autopilot_approach", vec![ControlKey(K::Key5)]),
+        ("toggle_trajectory", vec![Key(K::J)]),
+        ("toggle_trajectory_thrust", vec![ControlKey(K::J)]),
+        ("pause", vec![Key(K::P)]),
+        ("slow_down_time", vec![Key(K::LBracket)]),
+        ("speed_up_time", vec![Key(K::RBracket)]),
+        ("toggle_draw_mode", vec![Key(K::F1)]),
+        ("toggle_physics_debug", vec![Key(K::F2)]),
+        ("toggle_debug_ui", vec![Key(K::F3)]),
+        ("cycle_present_mode", vec![Key(K::F4)]),
+        ("reload_assets", vec![Key(K::F5)]),
+        ("quick_save", vec![Key(K::F6)]),
+        ("quick_load", vec![Key(K::F7)]),
+        ("teleport_far_test", vec![Key(K::F8)]),
+        ("simulate_device_lost", vec![Key(K::F9)]),
+        ("toggle_fullscreen", vec![Key(K::F11)]),
+    ];
+    bindings
+        .into_iter()
+        .map(|(action, bindings)| (action.to_string(), bindings))
+        .collect()
+}
+
+/// Named actions bound to keys, mouse buttons and gamepad inputs, an action can have any number of bindings
+pub struct InputMap {
+    bindings: BTreeMap<String, Vec<InputBinding>>,
+
+    /// Strongest binding of each action this frame, from 0 to 1
+    values: HashMap<String, f32>,
+    pressed: HashSet<String>,
+    /// Gamepad part of the values last frame, gamepads don't report presses so they're found by crossing a threshold
+    gamepad_values: HashMap<String, f32>,
+    /// Set while the debug ui is using the keyboard or mouse, gamepads are never blocked
+    keyboard_blocked: bool,
+    mouse_blocked: bool,
+}
+
+impl Default for InputMap {
+    fn default() -> Self {
+        Self::new(default_bindings())
+    }
+}
+
+impl InputMap {
+    fn new(bindings: BTreeMap<String, Vec<InputBinding>>) -> Self {
+        Self {
+            bindings,
+            values: HashMap::new(),
+            pressed: HashSet::new(),
+            gamepad_values: HashMap::new(),
+            keyboard_blocked: false,
+            mouse_blocked: false,
+        }
+    }
+
+    /// Falls back to the default bindings if the file can't be read, actions the file doesn't have keep their defaults
+    pub fn load_or_default<P: AsRef<Path>>(path: P) -> Self {
+        let path = path.as_ref();
+        if !path.exists() {
+            info!("No bindings file at {:?}, using the default bindings", path);
+            return Self::default();
+        }
+
+        let loaded = match std::fs::read_to_string(path)
+            .map_err(InputMapError::from)
+            .and_then(|contents| parse_bindings(&contents))
+        {
+            Ok(loaded) => loaded,
+            Err(e) => {
+                warn!("Failed to load bindings from {:?}: {}", path, e);
+                return Self::default();
+            }
+        };
+
+        let mut bindings = default_bindings();
+        for (action, action_bindings) in loaded {
+            match bindings.get_mut(&action) {
+                Some(existing) => *existing = action_bindings,
+                None => warn!("Bindings file {:?} has unknown action {:?}", path, action),
+            }
+        }
+        Self::new(bindings)
+    }
+
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> Result<(), InputMapError> {
+        std::fs::write(path, write_bindings(&self.bindings)?)?;
+        Ok(())
+    }
+
+    pub fn get_bindings(&self, action: &str) -> &[InputBinding] {
+        self.bindings.get(action).map_or(&[], |bindings| bindings)
+    }
+
+    pub fn set_bindings(&mut self, action: &str, bindings: Vec<InputBinding>) {
+        if !self.bindings.contains_key(action) {
+            warn!("Binding unknown action {:?}", action);
+        }
+        self.bindings.insert(action.to_string(), bindings);
+    }
+
+    /// Blocked bindings read as released until unblocked
+    pub fn set_blocked(&mut self, keyboard: bool, mouse: bool) {
+        self.keyboard_blocked = keyboard;
+        self.mouse_blocked = mouse;
+    }
+
+    /// Reads every binding once, call once per frame before querying actions
+    pub fn update(&mut self, input: &WinitInputHelper, gamepad: Option<&GamepadFrame>) {
+        self.values.clear();
+        self.pressed.clear();
+
+        for (action, bindings) in self.bindings.iter() {
+            let mut value: f32 = 0.0;
+            let mut gamepad_value: f32 = 0.0;
+            let mut pressed = false;
+
+            for binding in bindings {
+                match *binding {
+                    InputBinding::Key(_) | InputBinding::ControlKey(_) if self.keyboard_blocked => {
+                    }
+                    InputBinding::Mouse(_) if self.mouse_blocked => {}
+                    InputBinding::Key(key) => {
+                        if input.key_held(key) {
+                            value = 1.0;
+                        }
+                        pressed |= input.key_pressed(key);
+                    }
+                    InputBinding::ControlKey(key) => {
+                        if input.held_control() && input.key_held(key) {
+                            value = 1.0;
+                        }
+                        pressed |= input.held_control() && input.key_pressed(key);
+                    }
+                    InputBinding::Mouse(button) => {
+                        if input.mouse_held(button) {
+                            value = 1.0;
+                        }
+                        pressed |= input.mouse_pressed(button);
+                    }
+                    InputBinding::GamepadAxis(axis, direction) => {
+                        let axis_value = gamepad.map_or(0.0, |gamepad| gamepad.axis(axis));
+                        let axis_value = match direction {
+                            AxisDirection::Positive => axis_value,
+                            AxisDirection::Negative => -axis_value,
+                        };
+                        gamepad_value = gamepad_value.max(axis_value);
+                    }
+                    InputBinding::GamepadButton(button) => {
+                        if gamepad.is_some_and(|gamepad| gamepad.button_held(button)) {
+                            gamepad_value = 1.0;
+                        }
+                    }
+                }
+            }
+
+            let last_gamepad_value = self.gamepad_values.get(action).copied().unwrap_or(0.0);
+            pressed |= gamepad_value >= GAMEPAD_PRESS_THRESHOLD
+                && last_gamepad_value < GAMEPAD_PRESS_THRESHOLD;
+            self.gamepad_values.insert(action.clone(), gamepad_value);
+
+            self.values.insert(action.clone(), value.max(gamepad_value));
+            if pressed {
+                self.pressed.insert(action.clone());
+            }
+        }
+    }
+
+    /// From 0 to 1, 0 for unknown actions
+    pub fn value(&self, action: &str) -> f32 {
+        self.values.get(action).copied().unwrap_or(0.0)
+    }
+
+    /// True only on the frame the action was pressed
+    pub fn action_pressed(&self, action: &str) -> bool {
+        self.pressed.contains(action)
+    }
+
+    pub fn action_held(&self, action: &str) -> bool {
+        self.value(action) > 0.0
+    }
+
+    /// From -1 to 1, one of move_x, move_y, move_z, yaw, pitch or roll
+    pub fn axis(&self, axis: &str) -> f32 {
+        match AXES.iter().find(|(name, _, _)| *name == axis) {
+            Some((_, positive, negative)) => self.value(positive) - self.value(negative),
+            None => {
+                warn!("Unknown input axis {:?}", axis);
+                0.0
+            }
+        }
+    }
+}
+
+#[cfg(feature = "ron")]
+fn parse_bindings(contents: &str) -> Result<BTreeMap<String, Vec<InputBinding>>, InputMapError> {
+    Ok(ron::from_str(contents)?)
+}
+
+#[cfg(not(feature = "ron"))]
+fn parse_bindings(_contents: &str) -> Result<BTreeMap<String, Vec<InputBinding>>, InputMapError> {
+    Err(InputMapError::RonDisabled)
+}
+
+#[cfg(feature = "ron")]
+fn write_bindings(bindings: &BTreeMap<String, Vec<InputBinding>>) -> Result<String, InputMapError> {
+    Ok(ron::ser::to_string_pretty(
+        bindings,
+        ron::ser::PrettyConfig::default(),
+    )?)
+}
+
+#[cfg(not(feature = "ron"))]
+fn write_bindings(
+    _bindings: &BTreeMap<String, Vec<InputBinding>>,
+) -> Result<String, InputMapError> {
+    Err(InputMapError::RonDisabled)
+}
@@ -0,0 +1,26 @@
+pub use crate::renderer::Renderer;
+
+pub mod app;
+pub mod asteroid_field;
+pub mod audio;
+pub mod build_mode;
+pub mod camera;
+pub mod debug_ui;
+pub mod docking;
+pub mod frame_limiter;
+pub mod gamepad;
+pub mod hud;
+pub mod input_map;
+pub mod module;
+pub mod orbit;
+pub mod physics;
+pub mod player;
+pub mod projectile;
+pub mod renderer;
+pub mod sector;
+pub mod settings;
+pub mod space_craft;
+pub mod thumbnails;
+pub mod trajectory;
+pub mod transform;
+pub mod world;
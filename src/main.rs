@@ -1,46 +1,96 @@
-use crate::app::App;
-use crate::renderer::Renderer;
+use untitled_space_game::app::{self, App};
+use untitled_space_game::frame_limiter::FrameLimiter;
+use untitled_space_game::renderer;
+use untitled_space_game::settings::Settings;
 
 use log::*;
 
-mod app;
-mod camera;
-mod module;
-mod physics;
-mod player;
-mod renderer;
-mod space_craft;
-mod transform;
-mod world;
+/// Headless frames are stepped at a fixed rate so runs are comparable
+const HEADLESS_DELTA_TIME: f32 = 1.0 / 60.0;
+const DEFAULT_HEADLESS_FRAMES: u32 = 600;
+/// Frame rate while the window is in the background
+const BACKGROUND_FPS: f32 = 10.0;
 
 fn main() {
     pretty_env_logger::init_timed();
 
+    //--headless [frames] [--capture path.png]
+    let args: Vec<String> = std::env::args().collect();
+    let arg_value = |name: &str| {
+        args.iter()
+            .position(|arg| arg == name)
+            .and_then(|index| args.get(index + 1))
+    };
+    if args.iter().any(|arg| arg == "--headless") {
+        let frame_count = arg_value("--headless")
+            .and_then(|value| value.parse().ok())
+            .unwrap_or(DEFAULT_HEADLESS_FRAMES);
+        run_headless(
+            app::load_settings(),
+            frame_count,
+            arg_value("--capture").map(String::as_str),
+        );
+        return;
+    }
+
+    let settings = app::load_settings();
+
     let event_loop = winit::event_loop::EventLoop::new();
     let window = winit::window::WindowBuilder::new()
         .with_title("untitled_space_game")
         .with_resizable(true)
+        .with_inner_size(winit::dpi::PhysicalSize::new(
+            settings.window_size[0],
+            settings.window_size[1],
+        ))
+        .with_maximized(settings.maximized)
         .build(&event_loop)
         .unwrap();
 
-    window.set_maximized(true);
-
-    let mut app = App::new(&window);
+    //Monitors can only be picked once the window exists
+    let fullscreen = settings.fullscreen;
+    let max_fps = settings.max_fps.map(|fps| fps as f32);
+    let mut app = App::new(&window, settings);
+    if fullscreen {
+        app.set_fullscreen(&window, true);
+    }
 
     let mut frame_time = std::time::Instant::now();
-
-    let mut fps_frame_count: u16 = 0;
-    let mut fps_frame_time: f32 = 0.0;
+    let mut frame_limiter = FrameLimiter::default();
+    let mut focused = true;
 
     event_loop.run(move |event, _, control_flow| {
         *control_flow = winit::event_loop::ControlFlow::Poll;
 
         app.input.update(&event);
+        if let winit::event::Event::WindowEvent {
+            event: window_event,
+            window_id,
+        } = &event
+        {
+            if *window_id == window.id() {
+                app.window_event(window_event);
+            }
+        }
         match event {
             winit::event::Event::WindowEvent {
                 event: winit::event::WindowEvent::CloseRequested,
                 window_id,
-            } if window_id == window.id() => control_flow.set_exit(),
+            } if window_id == window.id() => {
+                app.save_settings();
+                control_flow.set_exit();
+            }
+            winit::event::Event::WindowEvent {
+                event: winit::event::WindowEvent::Focused(is_focused),
+                window_id,
+            } if window_id == window.id() => {
+                focused = is_focused;
+                app.focus_changed(&window, is_focused);
+            }
+            winit::event::Event::DeviceEvent {
+                event: winit::event::DeviceEvent::MouseMotion { delta },
+                ..
+            } => app.mouse_motion(delta),
             winit::event::Event::WindowEvent {
                 event: winit::event::WindowEvent::Resized(new_size),
                 window_id,
@@ -57,19 +107,63 @@ fn main() {
                 let delta_time = frame_time.elapsed().as_secs_f32();
                 frame_time = std::time::Instant::now();
 
-                app.update(delta_time);
+                app.update(Some(&window), delta_time);
                 app.render();
 
-                fps_frame_count += 1;
-                fps_frame_time += delta_time;
-
-                if fps_frame_time >= 1.0 {
-                    warn!("FPS: {fps_frame_count}");
-                    fps_frame_count = 0;
-                    fps_frame_time = 0.0;
-                }
+                //Timed so the profiler can tell the capped and uncapped frame rates apart
+                app.profiler.begin("wait");
+                frame_limiter.wait(if focused {
+                    max_fps
+                } else {
+                    Some(BACKGROUND_FPS)
+                });
+                app.profiler.end("wait");
             }
             _ => (),
         }
     });
 }
+
+/// Steps the game without a window and logs the average frame cost, optionally saving the last frame.
+/// Exits with an error if there were gpu errors or the capture couldn't be saved
+fn run_headless(settings: Settings, frame_count: u32, capture_path: Option<&str>) {
+    let mut app = App::new_headless(settings);
+
+    let start = std::time::Instant::now();
+    let mut encode_ms = 0.0;
+    let mut draw_calls = 0;
+    for _ in 0..frame_count {
+        app.update(None, HEADLESS_DELTA_TIME);
+        let render_stats = app.render();
+        encode_ms += render_stats.encode_cpu_ms;
+        draw_calls = render_stats.draw_calls;
+    }
+
+    let frames = frame_count.max(1) as f32;
+    info!(
+        "Ran {} headless frames in {:.2}s | frame: {:.2}ms | encode: {:.2}ms | draw calls: {}",
+        frame_count,
+        start.elapsed().as_secs_f32(),
+        start.elapsed().as_secs_f32() * 1000.0 / frames,
+        encode_ms / frames,
+        draw_calls,
+    );
+
+    if let Some(path) = capture_path {
+        let pixels = app.capture_frame();
+        let [width, height] = app.surface_size();
+        match image::save_buffer(path, &pixels, width, height, image::ColorType::Rgba8) {
+            Ok(()) => info!("Saved the last frame to {}", path),
+            Err(e) => {
+                error!("Failed to save the last frame to {}: {}", path, e);
+                std::process::exit(1);
+            }
+        }
+    }
+
+    let gpu_errors = renderer::gpu_error_count();
+    if gpu_errors > 0 {
+        error!("{} gpu errors during the headless run", gpu_errors);
+        std::process::exit(1);
+    }
+}
@@ -0,0 +1,454 @@
+use crate::physics::{ColliderMass, ColliderShape};
+use crate::renderer::{InstanceHandle, MaterialHandle, MeshHandle, Renderer};
+use crate::transform::Transform;
+use crate::world::{model_names, Entity, EntityId, EntitySaveData, UpdateStage, WorldInfo};
+use glam::{DQuat, DVec3, Vec3};
+use rapier3d::dynamics::RigidBodyType;
+use rapier3d::prelude::{ColliderHandle, RigidBodyHandle};
+use serde::{Deserialize, Serialize};
+use std::f64::consts::{PI, TAU};
+
+/// Below this eccentricity or sin(inclination) the orbit counts as circular or equatorial, the angles they'd define are zero
+const DEGENERATE_EPSILON: f64 = 1e-9;
+const KEPLER_TOLERANCE: f64 = 1e-12;
+const KEPLER_MAX_ITERATIONS: usize = 32;
+
+/// Keplerian elements of a closed orbit, angles in radians and distances in meters.
+/// The reference plane is the world's XZ plane with +Y as its north, longitudes are measured from +X.
+/// Everything is f64 since planet sized orbits lose too much in f32 before the result is turned back into world space
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub struct OrbitalElements {
+    pub semi_major_axis: f64,
+    /// 0 for circular, has to stay below 1
+    pub eccentricity: f64,
+    pub inclination: f64,
+    /// Longitude of the ascending node
+    pub raan: f64,
+    pub argument_of_periapsis: f64,
+    /// Mean anomaly at time 0
+    pub mean_anomaly_at_epoch: f64,
+    /// Gravitational parameter of the central body in m^3/s^2
+    pub mu: f64,
+}
+
+impl OrbitalElements {
+    /// Circular orbit in the reference plane, starting on +X
+    pub fn circular(radius: f64, mu: f64) -> Self {
+        Self {
+            semi_major_axis: radius,
+            eccentricity: 0.0,
+            inclination: 0.0,
+            raan: 0.0,
+            argument_of_periapsis: 0.0,
+            mean_anomaly_at_epoch: 0.0,
+            mu,
+        }
+    }
+
+    /// Radians per second
+    pub fn mean_motion(&self) -> f64 {
+        (self.mu / self.semi_major_axis.powi(3)).sqrt()
+    }
+
+    pub fn period(&self) -> f64 {
+        TAU / self.mean_motion()
+    }
+
+    pub fn position_at(&self, time: f64) -> Vec3 {
+        self.state_at(time).0
+    }
+
+    /// Position and velocity relative to the central body
+    pub fn state_at(&self, time: f64) -> (Vec3, Vec3) {
+        let e = self.eccentricity;
+        let a = self.semi_major_axis;
+        let mean_anomaly = self.mean_anomaly_at_epoch + self.mean_motion() * time;
+        let eccentric_anomaly = solve_kepler(mean_anomaly, e);
+        let (sin_e, cos_e) = eccentric_anomaly.sin_cos();
+
+        //In the orbital plane with periapsis along +x
+        let minor = (1.0 - e * e).sqrt();
+        let position = DVec3::new(a * (cos_e - e), a * minor * sin_e, 0.0);
+        let radius = a * (1.0 - e * cos_e);
+        let velocity = DVec3::new(-sin_e, minor * cos_e, 0.0) * ((self.mu * a).sqrt() / radius);
+
+        let rotation = self.plane_rotation();
+        (to_world(rotation * position), to_world(rotation * velocity))
+    }
+
+    /// Elements of the orbit passing through `position` with `velocity` at time 0, both relative to the central body.
+    /// None for escape trajectories and for states that don't make an orbit, like falling straight down
+    pub fn from_position_velocity(position: Vec3, velocity: Vec3, mu: f64) -> Option<Self> {
+        let r = from_world(position);
+        let v = from_world(velocity);
+        let radius = r.length();
+        let h = r.cross(v);
+        if radius < DEGENERATE_EPSILON || h.length() < DEGENERATE_EPSILON || mu <= 0.0 {
+            return None;
+        }
+        let h_hat = h.normalize();
+
+        let energy = v.length_squared() * 0.5 - mu / radius;
+        if energy >= 0.0 {
+            return None;
+        }
+        let semi_major_axis = -mu / (2.0 * energy);
+
+        let eccentricity_vector = ((v.length_squared() - mu / radius) * r - r.dot(v) * v) / mu;
+        let eccentricity = eccentricity_vector.length();
+        let inclination = h_hat.z.clamp(-1.0, 1.0).acos();
+
+        //Angle from `from` to `to` around the orbit normal
+        let angle = |from: DVec3, to: DVec3| from.cross(to).dot(h_hat).atan2(from.dot(to));
+
+        let node = DVec3::Z.cross(h);
+        let (raan, reference) = if node.length() > DEGENERATE_EPSILON * h.length() {
+            (node.y.atan2(node.x), node.normalize())
+        } else {
+            (0.0, DVec3::X)
+        };
+
+        let (argument_of_periapsis, true_anomaly) = if eccentricity > DEGENERATE_EPSILON {
+            (
+                angle(reference, eccentricity_vector),
+                angle(eccentricity_vector, r),
+            )
+        } else {
+            (0.0, angle(reference, r))
+        };
+
+        let eccentric_anomaly = 2.0
+            * ((1.0 - eccentricity).sqrt() * (true_anomaly * 0.5).sin())
+                .atan2((1.0 + eccentricity).sqrt() * (true_anomaly * 0.5).cos());
+        let mean_anomaly = eccentric_anomaly - eccentricity * eccentric_anomaly.sin();
+
+        Some(Self {
+            semi_major_axis,
+            eccentricity,
+            inclination,
+            raan: raan.rem_euclid(TAU),
+            argument_of_periapsis: argument_of_periapsis.rem_euclid(TAU),
+            mean_anomaly_at_epoch: mean_anomaly.rem_euclid(TAU),
+            mu,
+        })
+    }
+
+    /// From the perifocal frame to the reference frame
+    fn plane_rotation(&self) -> DQuat {
+        DQuat::from_rotation_z(self.raan)
+            * DQuat::from_rotation_x(self.inclination)
+            * DQuat::from_rotation_z(self.argument_of_periapsis)
+    }
+}
+
+/// Eccentric anomaly for a mean anomaly with Newton's method
+fn solve_kepler(mean_anomaly: f64, eccentricity: f64) -> f64 {
+    let mean_anomaly = (mean_anomaly + PI).rem_euclid(TAU) - PI;
+    //Starting from pi converges for every eccentricity, the mean anomaly is closer for nearly circular orbits
+    let mut eccentric_anomaly = if eccentricity < 0.8 {
+        mean_anomaly
+    } else {
+        PI.copysign(mean_anomaly)
+    };
+    for _ in 0..KEPLER_MAX_ITERATIONS {
+        let step = (eccentric_anomaly - eccentricity * eccentric_anomaly.sin() - mean_anomaly)
+            / (1.0 - eccentricity * eccentric_anomaly.cos());
+        eccentric_anomaly -= step;
+        if step.abs() < KEPLER_TOLERANCE {
+            break;
+        }
+    }
+    eccentric_anomaly
+}
+
+//The math is done with z as north, the world uses y
+fn to_world(v: DVec3) -> Vec3 {
+    Vec3::new(v.x as f32, v.z as f32, v.y as f32)
+}
+
+fn from_world(v: Vec3) -> DVec3 {
+    DVec3::new(v.x as f64, v.z as f64, v.y as f64)
+}
+
+/// Planets and stations that follow their orbit exactly instead of being simulated, colliders get a kinematic body
+pub struct OrbitalEntity {
+    id: EntityId,
+    elements: OrbitalElements,
+    /// World position of the central body
+    center: Vec3,
+    /// Seconds since the elements' epoch, advances with the scaled world time
+    time: f64,
+    transform: Transform,
+    model: Option<(MeshHandle, MaterialHandle)>,
+    collider: Option<ColliderShape>,
+
+    model_instance: Option<InstanceHandle>,
+    rigid_body_instance: Option<RigidBodyHandle>,
+    collider_instance: Option<ColliderHandle>,
+}
+
+impl OrbitalEntity {
+    /// `rotation` and `scale` of the transform are kept, the position comes from the orbit
+    pub fn new(elements: OrbitalElements, center: Vec3, transform: Transform) -> Self {
+        let mut entity = Self {
+            id: Default::default(),
+            elements,
+            center,
+            time: 0.0,
+            transform,
+            model: None,
+            collider: None,
+            model_instance: None,
+            rigid_body_instance: None,
+            collider_instance: None,
+        };
+        entity.transform.position = entity.center + elements.position_at(0.0);
+        entity
+    }
+
+    pub fn with_time(mut self, time: f64) -> Self {
+        self.time = time;
+        self.transform.position = self.center + self.elements.position_at(time);
+        self
+    }
+
+    pub fn with_model(mut self, mesh: MeshHandle, material: MaterialHandle) -> Self {
+        self.model = Some((mesh, material));
+        self
+    }
+
+    pub fn with_collider(mut self, collider: ColliderShape) -> Self {
+        self.collider = Some(collider);
+        self
+    }
+
+    pub fn elements(&self) -> &OrbitalElements {
+        &self.elements
+    }
+
+    /// World position and velocity right now, for releasing something from the rails at matching speed
+    pub fn world_state(&self) -> (Vec3, Vec3) {
+        let (position, velocity) = self.elements.state_at(self.time);
+        (self.center + position, velocity)
+    }
+}
+
+impl Entity for OrbitalEntity {
+    fn set_id(&mut self, id: EntityId) {
+        self.id = id;
+    }
+
+    fn add_to_world(&mut self, world: &mut WorldInfo) {
+        if let Some((mesh, material)) = &self.model {
+            self.model_instance =
+                world
+                    .rendering
+                    .create_instance(*mesh, *material, &self.transform);
+        }
+
+        if let Some(shape) = &self.collider {
+            let rigid_body = world.physics.create_rigid_body(
+                self.transform.position,
+                self.transform.rotation,
+                RigidBodyType::KinematicPositionBased,
+            );
+            self.rigid_body_instance = Some(rigid_body);
+            self.collider_instance = world.physics.create_collider(
+                rigid_body,
+                &Transform::default(),
+                shape,
+                ColliderMass::Mass(0.0),
+                false,
+                self.id,
+            );
+        }
+    }
+
+    fn remove_from_world(&mut self, world: &mut WorldInfo) {
+        if let Some(model) = self.model_instance.take() {
+            world.rendering.remove_instance(model);
+        }
+
+        if let Some(collider) = self.collider_instance.take() {
+            world.physics.remove_collider(collider);
+        }
+
+        if let Some(rigid_body) = self.rigid_body_instance.take() {
+            world.physics.remove_rigid_body(rigid_body);
+        }
+    }
+
+    fn update_stages(&self) -> &'static [UpdateStage] {
+        &[UpdateStage::PrePhysics]
+    }
+
+    /// Before the step so the kinematic body reaches the same point the model is drawn at
+    fn pre_physics_update(&mut self, world: &mut WorldInfo, delta_time: f32) {
+        self.time += delta_time as f64;
+        self.transform.position = self.center + self.elements.position_at(self.time);
+
+        //Rapier works out the body's velocity from the move, so things resting on it get carried along
+        if let Some(rigid_body) = self.rigid_body_instance {
+            world
+                .physics
+                .set_next_kinematic_transform(rigid_body, &self.transform);
+        }
+        if let Some(model) = self.model_instance {
+            world.rendering.update_instance(model, &self.transform);
+        }
+    }
+
+    fn update(&mut self, _world: &mut WorldInfo, _delta_time: f32) {}
+
+    fn update_player_input(&mut self, _linear_input: Vec3, _angular_input: Vec3) {}
+
+    fn get_camera_transform(&self) -> Option<Transform> {
+        None
+    }
+
+    fn get_rigid_body(&self) -> Option<RigidBodyHandle> {
+        self.rigid_body_instance
+    }
+
+    fn get_world_transform(&self) -> Option<Transform> {
+        Some(self.transform.clone())
+    }
+
+    fn shift_origin(&mut self, offset: Vec3, _world: &mut WorldInfo) {
+        self.center += offset;
+        self.transform.position += offset;
+    }
+
+    fn save(&self, _world: &WorldInfo, renderer: &Renderer) -> Option<EntitySaveData> {
+        Some(EntitySaveData::Orbital {
+            elements: self.elements,
+            center: self.center,
+            time: self.time,
+            transform: self.transform.clone(),
+            model: self
+                .model
+                .and_then(|model| model_names(renderer, self.id, model)),
+            collider: self.collider.clone(),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MU: f64 = 3.986e5;
+
+    fn assert_close(actual: Vec3, expected: Vec3, tolerance: f32) {
+        assert!(
+            actual.distance(expected) <= tolerance * expected.length().max(1.0),
+            "{actual} isn't {expected}"
+        );
+    }
+
+    fn assert_round_trip(position: Vec3, velocity: Vec3, mu: f64) -> OrbitalElements {
+        let elements = OrbitalElements::from_position_velocity(position, velocity, mu).unwrap();
+        let (round_trip_position, round_trip_velocity) = elements.state_at(0.0);
+        assert_close(round_trip_position, position, 1e-5);
+        assert_close(round_trip_velocity, velocity, 1e-5);
+        elements
+    }
+
+    #[test]
+    fn circular_orbit_keeps_radius_and_speed() {
+        let radius = 7000.0;
+        let elements = OrbitalElements::circular(radius, MU);
+        let speed = (MU / radius).sqrt() as f32;
+        for step in 0..16 {
+            let (position, velocity) = elements.state_at(step as f64 * 300.0);
+            assert!(
+                (position.length() - radius as f32).abs() < 1e-2,
+                "{position}"
+            );
+            assert!((velocity.length() - speed).abs() < 1e-5, "{velocity}");
+            assert!(position.normalize().dot(velocity.normalize()).abs() < 1e-5);
+            //Stays in the reference plane
+            assert!(position.y.abs() < 1e-3);
+        }
+        assert_close(elements.position_at(0.0), Vec3::new(7000.0, 0.0, 0.0), 1e-6);
+    }
+
+    #[test]
+    fn period_matches_keplers_third_law() {
+        let elements = OrbitalElements::circular(7000.0, MU);
+        let expected = TAU * (7000.0f64.powi(3) / MU).sqrt();
+        assert!((elements.period() - expected).abs() < 1e-9);
+
+        let (position, velocity) = elements.state_at(elements.period());
+        let (start_position, start_velocity) = elements.state_at(0.0);
+        assert_close(position, start_position, 1e-5);
+        assert_close(velocity, start_velocity, 1e-5);
+    }
+
+    #[test]
+    fn elliptical_orbit_at_periapsis_and_apoapsis() {
+        let (a, e) = (10000.0, 0.5);
+        let elements = OrbitalElements {
+            semi_major_axis: a,
+            eccentricity: e,
+            ..OrbitalElements::circular(a, MU)
+        };
+
+        let periapsis_speed = (MU / a * (1.0 + e) / (1.0 - e)).sqrt() as f32;
+        let (position, velocity) = elements.state_at(0.0);
+        assert_close(position, Vec3::new(5000.0, 0.0, 0.0), 1e-6);
+        assert_close(velocity, Vec3::new(0.0, 0.0, periapsis_speed), 1e-6);
+
+        let apoapsis_speed = (MU / a * (1.0 - e) / (1.0 + e)).sqrt() as f32;
+        let (position, velocity) = elements.state_at(elements.period() * 0.5);
+        assert_close(position, Vec3::new(-15000.0, 0.0, 0.0), 1e-6);
+        assert_close(velocity, Vec3::new(0.0, 0.0, -apoapsis_speed), 1e-6);
+    }
+
+    #[test]
+    fn elements_round_trip_through_state() {
+        let elements = OrbitalElements {
+            semi_major_axis: 12000.0,
+            eccentricity: 0.3,
+            inclination: 0.7,
+            raan: 1.2,
+            argument_of_periapsis: 2.5,
+            mean_anomaly_at_epoch: 0.4,
+            mu: MU,
+        };
+        let (position, velocity) = elements.state_at(0.0);
+        let round_trip = assert_round_trip(position, velocity, MU);
+        assert!((round_trip.semi_major_axis - 12000.0).abs() < 1e-2);
+        assert!((round_trip.eccentricity - 0.3).abs() < 1e-5);
+        assert!((round_trip.inclination - 0.7).abs() < 1e-5);
+        assert!((round_trip.raan - 1.2).abs() < 1e-5);
+    }
+
+    #[test]
+    fn equatorial_orbit_measures_from_x() {
+        //Faster than circular, so +X is the periapsis
+        let elements = assert_round_trip(
+            Vec3::new(100.0, 0.0, 0.0),
+            Vec3::new(0.0, 0.0, 12.0),
+            10000.0,
+        );
+        assert!(elements.inclination.abs() < 1e-9);
+        assert_eq!(elements.raan, 0.0);
+        assert!(elements.argument_of_periapsis.abs() < 1e-9);
+        assert!(elements.eccentricity > 0.4);
+    }
+
+    #[test]
+    fn circular_orbit_measures_from_the_node() {
+        //Exactly circular, sqrt(mu / r) is 10 and the velocity has no radial part
+        let elements = assert_round_trip(
+            Vec3::new(100.0, 0.0, 0.0),
+            Vec3::new(0.0, 6.0, 8.0),
+            10000.0,
+        );
+        assert_eq!(elements.eccentricity, 0.0);
+        assert_eq!(elements.argument_of_periapsis, 0.0);
+        assert!((elements.inclination - 0.8f64.acos()).abs() < 1e-9);
+        assert!(elements.mean_anomaly_at_epoch.abs() < 1e-9);
+    }
+}
@@ -1,28 +1,371 @@
+use crate::renderer::DebugLines;
+use crate::transform::Transform;
+use crate::world::EntityId;
 use glam::{Quat, Vec3};
+use log::error;
+use rapier3d::control::KinematicCharacterController;
+use rapier3d::crossbeam::channel::{unbounded, Receiver, Sender};
+use rapier3d::parry::bounding_volume::BoundingVolume;
 use rapier3d::prelude::*;
+use serde::{Deserialize, Serialize};
+use slotmap::{Key, KeyData, SlotMap};
+use std::collections::HashSet;
 
+slotmap::new_key_type! {
+    pub struct GravitySourceHandle;
+}
+
+/// Point mass that pulls dynamic bodies with inverse square gravity, `mu` is the standard gravitational parameter (G * M)
+#[derive(Clone, Copy, Debug)]
+pub struct GravitySource {
+    pub position: Vec3,
+    pub mu: f32,
+    /// Below this distance the pull falls off linearly to zero at the center, like it would inside a uniform planet
+    pub radius: f32,
+}
+
+impl GravitySource {
+    fn acceleration_at(&self, position: Vec3) -> Vec3 {
+        let offset = self.position - position;
+        let distance = offset.length();
+        if distance <= f32::EPSILON {
+            return Vec3::ZERO;
+        }
+
+        let effective_distance_cubed = if distance < self.radius {
+            self.radius.powi(3)
+        } else {
+            distance.powi(3)
+        };
+        offset * (self.mu / effective_distance_cubed)
+    }
+}
+
+/// Copy of the gravity acting on one body, for sampling it away from the physics scene like when predicting a trajectory
+#[derive(Clone, Debug)]
+pub struct GravityField {
+    uniform: Vec3,
+    sources: Vec<GravitySource>,
+    scale: f32,
+}
+
+impl GravityField {
+    pub fn acceleration_at(&self, position: Vec3) -> Vec3 {
+        let sources: Vec3 = self
+            .sources
+            .iter()
+            .map(|gravity_source| gravity_source.acceleration_at(position))
+            .sum();
+        (self.uniform + sources) * self.scale
+    }
+
+    pub fn is_zero(&self) -> bool {
+        self.scale == 0.0 || (self.uniform == Vec3::ZERO && self.sources.is_empty())
+    }
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
 pub enum ColliderShape {
     Sphere(f32),
     Box(glam::Vec3),
     Capsule(f32, f32),
     Cylinder(f32, f32),
-    Mesh,
+    ConvexHull(Vec<Vec3>),
+    TriMesh {
+        vertices: Vec<Vec3>,
+        indices: Vec<[u32; 3]>,
+    },
+    /// Child shapes with their offset from the collider, mass is split between them by volume
+    Compound(Vec<(Vec3, Quat, ColliderShape)>),
+}
+
+/// Interiors get their own collision layer so they can sit inside exterior hulls without touching them,
+/// a collider only collides with colliders in the same layer
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CollisionLayer {
+    Exterior,
+    Interior,
+}
+
+impl CollisionLayer {
+    const INTERIOR_GROUP: Group = Group::GROUP_32;
+
+    fn interaction_groups(self) -> InteractionGroups {
+        let group = match self {
+            Self::Exterior => Group::ALL.difference(Self::INTERIOR_GROUP),
+            Self::Interior => Self::INTERIOR_GROUP,
+        };
+        InteractionGroups::new(group, group)
+    }
+}
+
+/// How a collider contributes to its body's mass, density is in kg/m^3
+#[derive(Clone, Copy, Debug)]
+pub enum ColliderMass {
+    Density(f32),
+    Mass(f32),
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum ColliderShapeError {
+    #[error("convex hull needs at least 4 non-coplanar points")]
+    DegenerateConvexHull,
+    #[error("triangle mesh has no triangles")]
+    EmptyTriMesh,
+    #[error("triangle mesh index {index} is out of range for {vertex_count} vertices")]
+    TriMeshIndexOutOfRange { index: u32, vertex_count: usize },
+    #[error("compound shape has no children")]
+    EmptyCompound,
+    #[error("triangle meshes can't be part of a compound shape")]
+    TriMeshInCompound,
+    #[error("failed to load obj file: {0}")]
+    ObjLoad(#[from] tobj::LoadError),
 }
 
 impl ColliderShape {
-    fn create_shared_shape(&self) -> SharedShape {
-        match self {
+    /// Checks the points up front so a bad hull is reported when it's created rather than when it's added to a body
+    pub fn convex_hull(points: Vec<Vec3>) -> Result<Self, ColliderShapeError> {
+        if !points_span_volume(&points) {
+            return Err(ColliderShapeError::DegenerateConvexHull);
+        }
+        Ok(Self::ConvexHull(points))
+    }
+
+    pub fn tri_mesh(
+        vertices: Vec<Vec3>,
+        indices: Vec<[u32; 3]>,
+    ) -> Result<Self, ColliderShapeError> {
+        validate_tri_mesh(&vertices, &indices)?;
+        Ok(Self::TriMesh { vertices, indices })
+    }
+
+    fn create_shared_shape(&self) -> Result<SharedShape, ColliderShapeError> {
+        Ok(match self {
             Self::Sphere(radius) => SharedShape::ball(*radius),
             Self::Box(half_extent) => {
                 SharedShape::cuboid(half_extent.x, half_extent.y, half_extent.z)
             }
             Self::Capsule(radius, y) => SharedShape::capsule_y(*y, *radius),
             Self::Cylinder(radius, y) => SharedShape::cylinder(*y, *radius),
-            _ => unimplemented!(),
+            //The variants can be built directly, so they're validated again here
+            Self::ConvexHull(points) => {
+                if !points_span_volume(points) {
+                    return Err(ColliderShapeError::DegenerateConvexHull);
+                }
+
+                let points: Vec<Point<Real>> = points.iter().map(|point| (*point).into()).collect();
+                SharedShape::convex_hull(&points).ok_or(ColliderShapeError::DegenerateConvexHull)?
+            }
+            Self::TriMesh { vertices, indices } => {
+                validate_tri_mesh(vertices, indices)?;
+                SharedShape::trimesh(
+                    vertices.iter().map(|vertex| (*vertex).into()).collect(),
+                    indices.clone(),
+                )
+            }
+            Self::Compound(_) => {
+                let mut shapes = Vec::new();
+                self.append_compound_shapes(Isometry::identity(), &mut shapes)?;
+                if shapes.is_empty() {
+                    return Err(ColliderShapeError::EmptyCompound);
+                }
+                SharedShape::compound(shapes)
+            }
+        })
+    }
+
+    /// Rapier doesn't allow nested compounds, so child compounds are flattened into the parent
+    fn append_compound_shapes(
+        &self,
+        position: Isometry<Real>,
+        shapes: &mut Vec<(Isometry<Real>, SharedShape)>,
+    ) -> Result<(), ColliderShapeError> {
+        match self {
+            Self::Compound(children) => {
+                for (translation, rotation, child) in children {
+                    let child_position = Isometry::from_parts(
+                        (*translation).into(),
+                        nalgebra::UnitQuaternion::from(*rotation),
+                    );
+                    child.append_compound_shapes(position * child_position, shapes)?;
+                }
+            }
+            Self::TriMesh { .. } => return Err(ColliderShapeError::TriMeshInCompound),
+            _ => shapes.push((position, self.create_shared_shape()?)),
         }
+        Ok(())
     }
 }
 
+fn transform_to_isometry(transform: &Transform) -> Isometry<Real> {
+    Isometry::from_parts(
+        transform.position.into(),
+        nalgebra::UnitQuaternion::from(transform.rotation),
+    )
+}
+
+fn to_unit_vector(vector: Vec3) -> UnitVector<Real> {
+    UnitVector::new_normalize(vector.into())
+}
+
+fn validate_tri_mesh(vertices: &[Vec3], indices: &[[u32; 3]]) -> Result<(), ColliderShapeError> {
+    if indices.is_empty() {
+        return Err(ColliderShapeError::EmptyTriMesh);
+    }
+
+    match indices
+        .iter()
+        .flatten()
+        .find(|index| **index as usize >= vertices.len())
+    {
+        Some(index) => Err(ColliderShapeError::TriMeshIndexOutOfRange {
+            index: *index,
+            vertex_count: vertices.len(),
+        }),
+        None => Ok(()),
+    }
+}
+
+/// True if at least 4 of the points are not coplanar
+fn points_span_volume(points: &[Vec3]) -> bool {
+    const EPSILON: f32 = 1e-6;
+
+    let Some(first) = points.first() else {
+        return false;
+    };
+    let Some(second) = points
+        .iter()
+        .find(|point| point.distance_squared(*first) > EPSILON)
+    else {
+        return false;
+    };
+    let edge = *second - *first;
+    let Some(normal) = points
+        .iter()
+        .map(|point| edge.cross(*point - *first))
+        .find(|normal| normal.length_squared() > EPSILON)
+    else {
+        return false;
+    };
+
+    points
+        .iter()
+        .any(|point| normal.dot(*point - *first).abs() > EPSILON)
+}
+
+/// Builds a convex hull from the vertex positions of every model in the obj file
+pub fn load_convex_hull_from_obj<P: AsRef<std::path::Path>>(
+    path: P,
+) -> Result<ColliderShape, ColliderShapeError> {
+    const LOAD_OPTIONS: tobj::LoadOptions = tobj::LoadOptions {
+        single_index: true,
+        triangulate: true,
+        ignore_points: false,
+        ignore_lines: true,
+    };
+
+    let (models, _materials) = tobj::load_obj(path.as_ref(), &LOAD_OPTIONS)?;
+
+    let points = models
+        .iter()
+        .flat_map(|model| model.mesh.positions.chunks_exact(3))
+        .map(Vec3::from_slice)
+        .collect();
+
+    ColliderShape::convex_hull(points)
+}
+
+/// A contact or sensor intersection, from the point of view of `collider`
+#[derive(Clone, Copy, Debug)]
+pub enum CollisionEvent {
+    Started {
+        collider: ColliderHandle,
+        other_collider: ColliderHandle,
+        sensor: bool,
+    },
+    Stopped {
+        collider: ColliderHandle,
+        other_collider: ColliderHandle,
+        sensor: bool,
+    },
+    /// Contact impulse over a step, only for colliders with impact events enabled
+    Impact {
+        collider: ColliderHandle,
+        other_collider: ColliderHandle,
+        impulse: f32,
+        /// Where the colliders touch in world space
+        point: Vec3,
+    },
+}
+
+impl CollisionEvent {
+    /// The same event from the point of view of the other collider
+    pub fn swapped(self) -> Self {
+        match self {
+            Self::Started {
+                collider,
+                other_collider,
+                sensor,
+            } => Self::Started {
+                collider: other_collider,
+                other_collider: collider,
+                sensor,
+            },
+            Self::Stopped {
+                collider,
+                other_collider,
+                sensor,
+            } => Self::Stopped {
+                collider: other_collider,
+                other_collider: collider,
+                sensor,
+            },
+            Self::Impact {
+                collider,
+                other_collider,
+                impulse,
+                point,
+            } => Self::Impact {
+                collider: other_collider,
+                other_collider: collider,
+                impulse,
+                point,
+            },
+        }
+    }
+}
+
+fn entity_to_user_data(entity_id: EntityId) -> u128 {
+    entity_id.data().as_ffi() as u128
+}
+
+fn user_data_to_entity(user_data: u128) -> EntityId {
+    EntityId::from(KeyData::from_ffi(user_data as u64))
+}
+
+/// The movement a kinematic body actually made after sliding along obstacles
+#[derive(Clone, Copy, Debug)]
+pub struct KinematicMovement {
+    pub translation: Vec3,
+    pub grounded: bool,
+    pub touching: bool,
+}
+
+/// Full copy of the simulation state, restoring it and stepping with the same inputs gives bit identical results on the same build.
+/// Gravity sources and per body gravity flags are treated as inputs and aren't included
+#[derive(Clone, serde::Serialize, serde::Deserialize)]
+pub struct PhysicsSnapshot {
+    rigid_body_set: RigidBodySet,
+    collider_set: ColliderSet,
+    gravity: Vector<Real>,
+    integration_parameters: IntegrationParameters,
+    island_manager: IslandManager,
+    broad_phase: BroadPhase,
+    narrow_phase: NarrowPhase,
+    impulse_joint_set: ImpulseJointSet,
+    multibody_joint_set: MultibodyJointSet,
+}
+
 pub struct PhysicsScene {
     rigid_body_set: RigidBodySet,
     collider_set: ColliderSet,
@@ -36,6 +379,21 @@ pub struct PhysicsScene {
     impulse_joint_set: ImpulseJointSet,
     multibody_joint_set: MultibodyJointSet,
     ccd_solver: CCDSolver,
+    query_pipeline: QueryPipeline,
+    gravity_sources: SlotMap<GravitySourceHandle, GravitySource>,
+    ignore_gravity_bodies: HashSet<RigidBodyHandle>,
+    character_controller: KinematicCharacterController,
+
+    collision_event_sender: Sender<rapier3d::geometry::CollisionEvent>,
+    collision_event_receiver: Receiver<rapier3d::geometry::CollisionEvent>,
+    contact_force_event_sender: Sender<ContactForceEvent>,
+    contact_force_event_receiver: Receiver<ContactForceEvent>,
+}
+
+impl Default for PhysicsScene {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl PhysicsScene {
@@ -52,6 +410,16 @@ impl PhysicsScene {
         let impulse_joint_set = ImpulseJointSet::new();
         let multibody_joint_set = MultibodyJointSet::new();
         let ccd_solver = CCDSolver::new();
+        let query_pipeline = QueryPipeline::new();
+
+        //Free flying, so there is no ground to snap to or steps to climb
+        let character_controller = KinematicCharacterController {
+            autostep: None,
+            snap_to_ground: None,
+            ..Default::default()
+        };
+        let (collision_event_sender, collision_event_receiver) = unbounded();
+        let (contact_force_event_sender, contact_force_event_receiver) = unbounded();
 
         Self {
             rigid_body_set,
@@ -65,14 +433,28 @@ impl PhysicsScene {
             impulse_joint_set,
             multibody_joint_set,
             ccd_solver,
+            query_pipeline,
+            gravity_sources: SlotMap::with_key(),
+            ignore_gravity_bodies: HashSet::new(),
+            character_controller,
+            collision_event_sender,
+            collision_event_receiver,
+            contact_force_event_sender,
+            contact_force_event_receiver,
         }
     }
 
-    pub fn step_physics(&mut self, delta_time: f32) {
+    /// Returns the collision events from this step as (entity, other entity, event) from the first entity's point of view,
+    /// events involving colliders that were removed during the step are dropped
+    pub fn step_physics(&mut self, delta_time: f32) -> Vec<(EntityId, EntityId, CollisionEvent)> {
         self.integration_parameters.dt = delta_time;
+        self.apply_gravity_sources();
 
         let physics_hooks = ();
-        let event_handler = ();
+        let event_handler = ChannelEventCollector::new(
+            self.collision_event_sender.clone(),
+            self.contact_force_event_sender.clone(),
+        );
 
         self.physics_pipeline.step(
             &self.gravity,
@@ -85,10 +467,215 @@ impl PhysicsScene {
             &mut self.impulse_joint_set,
             &mut self.multibody_joint_set,
             &mut self.ccd_solver,
-            None,
+            Some(&mut self.query_pipeline),
             &physics_hooks,
             &event_handler,
         );
+
+        //Forces only apply to the step after they were added
+        for (_handle, rigid_body) in self.rigid_body_set.iter_mut() {
+            rigid_body.reset_forces(false);
+            rigid_body.reset_torques(false);
+        }
+
+        let mut events = Vec::new();
+        while let Ok(event) = self.contact_force_event_receiver.try_recv() {
+            let (collider, other_collider) = (event.collider1, event.collider2);
+            if let (Some(entity), Some(other_entity)) = (
+                self.get_collider_entity(collider),
+                self.get_collider_entity(other_collider),
+            ) {
+                events.push((
+                    entity,
+                    other_entity,
+                    CollisionEvent::Impact {
+                        collider,
+                        other_collider,
+                        impulse: event.total_force_magnitude * delta_time,
+                        point: self.contact_point(collider, other_collider),
+                    },
+                ));
+            }
+        }
+
+        while let Ok(event) = self.collision_event_receiver.try_recv() {
+            let (collider, other_collider) = (event.collider1(), event.collider2());
+            let sensor = event.sensor();
+            let event = if event.started() {
+                CollisionEvent::Started {
+                    collider,
+                    other_collider,
+                    sensor,
+                }
+            } else {
+                CollisionEvent::Stopped {
+                    collider,
+                    other_collider,
+                    sensor,
+                }
+            };
+
+            if let (Some(entity), Some(other_entity)) = (
+                self.get_collider_entity(collider),
+                self.get_collider_entity(other_collider),
+            ) {
+                events.push((entity, other_entity, event));
+            }
+        }
+        events
+    }
+
+    /// Average of the pair's contact points from the narrow phase, the middle of the colliders if it has none
+    fn contact_point(&self, collider: ColliderHandle, other_collider: ColliderHandle) -> Vec3 {
+        let (sum, count) = self
+            .narrow_phase
+            .contact_pair(collider, other_collider)
+            .into_iter()
+            .flat_map(|contact_pair| contact_pair.manifolds.iter())
+            .flat_map(|manifold| manifold.data.solver_contacts.iter())
+            .fold((Vec3::ZERO, 0), |(sum, count), contact| {
+                (sum + Vec3::from(contact.point), count + 1)
+            });
+        if count > 0 {
+            return sum / count as f32;
+        }
+
+        let position = |handle| {
+            self.collider_set
+                .get(handle)
+                .map_or(Vec3::ZERO, |collider| Vec3::from(*collider.translation()))
+        };
+        (position(collider) + position(other_collider)) * 0.5
+    }
+
+    pub fn snapshot(&self) -> PhysicsSnapshot {
+        PhysicsSnapshot {
+            rigid_body_set: self.rigid_body_set.clone(),
+            collider_set: self.collider_set.clone(),
+            gravity: self.gravity,
+            integration_parameters: self.integration_parameters,
+            island_manager: self.island_manager.clone(),
+            broad_phase: self.broad_phase.clone(),
+            narrow_phase: self.narrow_phase.clone(),
+            impulse_joint_set: self.impulse_joint_set.clone(),
+            multibody_joint_set: self.multibody_joint_set.clone(),
+        }
+    }
+
+    pub fn restore(&mut self, snapshot: &PhysicsSnapshot) {
+        let snapshot = snapshot.clone();
+        self.rigid_body_set = snapshot.rigid_body_set;
+        self.collider_set = snapshot.collider_set;
+        self.gravity = snapshot.gravity;
+        self.integration_parameters = snapshot.integration_parameters;
+        self.island_manager = snapshot.island_manager;
+        self.broad_phase = snapshot.broad_phase;
+        self.narrow_phase = snapshot.narrow_phase;
+        self.impulse_joint_set = snapshot.impulse_joint_set;
+        self.multibody_joint_set = snapshot.multibody_joint_set;
+
+        //Nothing in flight should carry over from the old state
+        self.ccd_solver = CCDSolver::new();
+        self.query_pipeline = QueryPipeline::new();
+        self.query_pipeline
+            .update(&self.rigid_body_set, &self.collider_set);
+        while self.collision_event_receiver.try_recv().is_ok() {}
+        while self.contact_force_event_receiver.try_recv().is_ok() {}
+        self.ignore_gravity_bodies
+            .retain(|handle| self.rigid_body_set.contains(*handle));
+    }
+
+    pub fn add_gravity_source(&mut self, gravity_source: GravitySource) -> GravitySourceHandle {
+        self.gravity_sources.insert(gravity_source)
+    }
+
+    pub fn remove_gravity_source(&mut self, handle: GravitySourceHandle) {
+        self.gravity_sources.remove(handle);
+    }
+
+    pub fn get_gravity_source_mut(
+        &mut self,
+        handle: GravitySourceHandle,
+    ) -> Option<&mut GravitySource> {
+        self.gravity_sources.get_mut(handle)
+    }
+
+    /// Translates every body, free collider and gravity source, used to keep the simulation near the origin.
+    /// Attached colliders follow their bodies on the next step
+    pub fn shift_origin(&mut self, offset: Vec3) {
+        let offset: Vector<Real> = offset.into();
+        for (_, rigid_body) in self.rigid_body_set.iter_mut() {
+            let translation = rigid_body.translation() + offset;
+            rigid_body.set_translation(translation, false);
+        }
+        for (_, collider) in self.collider_set.iter_mut() {
+            if collider.parent().is_none() {
+                let translation = collider.translation() + offset;
+                collider.set_translation(translation);
+            }
+        }
+        for gravity_source in self.gravity_sources.values_mut() {
+            gravity_source.position += Vec3::from(offset);
+        }
+    }
+
+    /// Scales both the global gravity and gravity sources for this body
+    pub fn set_body_gravity_scale(&mut self, handle: RigidBodyHandle, gravity_scale: f32) {
+        if let Some(rigid_body) = self.rigid_body_set.get_mut(handle) {
+            rigid_body.set_gravity_scale(gravity_scale, true);
+        }
+    }
+
+    /// Bodies that ignore gravity aren't pulled by gravity sources
+    pub fn set_body_ignore_gravity(&mut self, handle: RigidBodyHandle, ignore_gravity: bool) {
+        if ignore_gravity {
+            self.ignore_gravity_bodies.insert(handle);
+        } else {
+            self.ignore_gravity_bodies.remove(&handle);
+        }
+    }
+
+    /// Same gravity the body gets during a step, zero for bodies that aren't dynamic
+    pub fn gravity_field(&self, handle: RigidBodyHandle) -> GravityField {
+        let scale = self
+            .rigid_body_set
+            .get(handle)
+            .filter(|rigid_body| rigid_body.is_dynamic())
+            .map_or(0.0, |rigid_body| rigid_body.gravity_scale());
+        let sources = if self.ignore_gravity_bodies.contains(&handle) {
+            Vec::new()
+        } else {
+            self.gravity_sources.values().copied().collect()
+        };
+        GravityField {
+            uniform: self.gravity.into(),
+            sources,
+            scale,
+        }
+    }
+
+    fn apply_gravity_sources(&mut self) {
+        if self.gravity_sources.is_empty() {
+            return;
+        }
+
+        for (handle, rigid_body) in self.rigid_body_set.iter_mut() {
+            if !rigid_body.is_dynamic() || self.ignore_gravity_bodies.contains(&handle) {
+                continue;
+            }
+
+            let position: Vec3 = (*rigid_body.center_of_mass()).into();
+            let acceleration: Vec3 = self
+                .gravity_sources
+                .values()
+                .map(|gravity_source| gravity_source.acceleration_at(position))
+                .sum();
+
+            let force = acceleration * rigid_body.mass() * rigid_body.gravity_scale();
+            if force != Vec3::ZERO {
+                rigid_body.add_force(force.into(), true);
+            }
+        }
     }
 
     pub fn create_rigid_body(
@@ -104,7 +691,200 @@ impl PhysicsScene {
         self.rigid_body_set.insert(rigid_body)
     }
 
+    /// Moves a `KinematicPositionBased` body using the shape of its first collider, sliding along anything in the way.
+    /// The move starts from the body's next position so it still works while physics isn't being stepped
+    pub fn move_kinematic(
+        &mut self,
+        handle: RigidBodyHandle,
+        desired_translation: Vec3,
+        delta_time: f32,
+    ) -> Option<KinematicMovement> {
+        self.move_kinematic_with_up(handle, desired_translation, Vec3::Y, delta_time)
+    }
+
+    /// Same as `move_kinematic` with `up` deciding what counts as ground, for walking on floors that aren't level with the world
+    pub fn move_kinematic_with_up(
+        &mut self,
+        handle: RigidBodyHandle,
+        desired_translation: Vec3,
+        up: Vec3,
+        delta_time: f32,
+    ) -> Option<KinematicMovement> {
+        let mut character_controller = self.character_controller;
+        character_controller.up = to_unit_vector(up);
+
+        let rigid_body = self.rigid_body_set.get(handle)?;
+        let start_position = *rigid_body.next_position();
+
+        let collider = rigid_body
+            .colliders()
+            .first()
+            .and_then(|collider_handle| self.collider_set.get(*collider_handle));
+
+        let (translation, grounded, touching) = match collider {
+            Some(collider) => {
+                let mut touching = false;
+                let movement = character_controller.move_shape(
+                    delta_time,
+                    &self.rigid_body_set,
+                    &self.collider_set,
+                    &self.query_pipeline,
+                    collider.shape(),
+                    &(start_position * collider.position_wrt_parent().copied().unwrap_or_default()),
+                    desired_translation.into(),
+                    QueryFilter::default()
+                        .exclude_rigid_body(handle)
+                        .exclude_sensors()
+                        .groups(collider.collision_groups()),
+                    |_collision| touching = true,
+                );
+                (movement.translation.into(), movement.grounded, touching)
+            }
+            None => (desired_translation, false, false),
+        };
+
+        if let Some(rigid_body) = self.rigid_body_set.get_mut(handle) {
+            rigid_body.set_next_kinematic_translation(
+                start_position.translation.vector + nalgebra::Vector3::from(translation),
+            );
+        }
+
+        Some(KinematicMovement {
+            translation,
+            grounded,
+            touching,
+        })
+    }
+
+    /// Local frames are relative to each body, their scale is ignored
+    pub fn create_fixed_joint(
+        &mut self,
+        body_a: RigidBodyHandle,
+        body_b: RigidBodyHandle,
+        local_frame_a: &Transform,
+        local_frame_b: &Transform,
+    ) -> ImpulseJointHandle {
+        let joint = FixedJointBuilder::new()
+            .local_frame1(transform_to_isometry(local_frame_a))
+            .local_frame2(transform_to_isometry(local_frame_b));
+        self.impulse_joint_set.insert(body_a, body_b, joint, true)
+    }
+
+    /// `axis` is in the space of the local frames
+    pub fn create_revolute_joint(
+        &mut self,
+        body_a: RigidBodyHandle,
+        body_b: RigidBodyHandle,
+        local_frame_a: &Transform,
+        local_frame_b: &Transform,
+        axis: Vec3,
+    ) -> ImpulseJointHandle {
+        let mut joint: GenericJoint =
+            RevoluteJointBuilder::new(to_unit_vector(local_frame_a.rotation * axis))
+                .local_anchor1(local_frame_a.position.into())
+                .local_anchor2(local_frame_b.position.into())
+                .build()
+                .into();
+        joint.set_local_axis2(to_unit_vector(local_frame_b.rotation * axis));
+        self.impulse_joint_set.insert(body_a, body_b, joint, true)
+    }
+
+    /// `axis` is in the space of the local frames, `limits` is the [min, max] distance along it
+    pub fn create_prismatic_joint(
+        &mut self,
+        body_a: RigidBodyHandle,
+        body_b: RigidBodyHandle,
+        local_frame_a: &Transform,
+        local_frame_b: &Transform,
+        axis: Vec3,
+        limits: Option<[f32; 2]>,
+    ) -> ImpulseJointHandle {
+        let mut joint = PrismaticJointBuilder::new(to_unit_vector(axis))
+            .local_anchor1(local_frame_a.position.into())
+            .local_anchor2(local_frame_b.position.into())
+            .local_axis1(to_unit_vector(local_frame_a.rotation * axis))
+            .local_axis2(to_unit_vector(local_frame_b.rotation * axis));
+        if let Some(limits) = limits {
+            joint = joint.limits(limits);
+        }
+        self.impulse_joint_set.insert(body_a, body_b, joint, true)
+    }
+
+    pub fn remove_joint(&mut self, handle: ImpulseJointHandle) {
+        self.impulse_joint_set.remove(handle, true);
+    }
+
+    /// Drives a revolute joint towards `target_velocity` in radians per second
+    pub fn set_joint_motor(
+        &mut self,
+        handle: ImpulseJointHandle,
+        target_velocity: f32,
+        max_force: f32,
+    ) {
+        const MOTOR_DAMPING_FACTOR: f32 = 1.0;
+
+        if let Some(joint) = self.impulse_joint_set.get_mut(handle) {
+            joint
+                .data
+                .set_motor_velocity(JointAxis::AngX, target_velocity, MOTOR_DAMPING_FACTOR)
+                .set_motor_max_force(JointAxis::AngX, max_force);
+        }
+    }
+
+    /// Total mass in kg, including the collider contributions
+    pub fn get_mass(&self, handle: RigidBodyHandle) -> f32 {
+        let rigid_body = self.rigid_body_set.get(handle).unwrap();
+        rigid_body.mass()
+    }
+
+    /// Angular inertia around the principal axes, good enough as a per axis inertia for near symmetric bodies
+    pub fn get_principal_angular_inertia(&self, handle: RigidBodyHandle) -> Vec3 {
+        let rigid_body = self.rigid_body_set.get(handle).unwrap();
+        rigid_body
+            .mass_properties()
+            .local_mprops
+            .principal_inertia()
+            .into()
+    }
+
+    pub fn get_center_of_mass(&self, handle: RigidBodyHandle) -> Vec3 {
+        let rigid_body = self.rigid_body_set.get(handle).unwrap();
+        (*rigid_body.center_of_mass()).into()
+    }
+
+    /// Center of mass relative to the body
+    pub fn get_local_center_of_mass(&self, handle: RigidBodyHandle) -> Vec3 {
+        let rigid_body = self.rigid_body_set.get(handle).unwrap();
+        rigid_body.mass_properties().local_mprops.local_com.into()
+    }
+
+    /// Mass added on top of the colliders, replaces any previous additional mass or mass properties
+    pub fn set_additional_mass(&mut self, handle: RigidBodyHandle, mass: f32) {
+        if let Some(rigid_body) = self.rigid_body_set.get_mut(handle) {
+            rigid_body.set_additional_mass(mass, true);
+            rigid_body.recompute_mass_properties_from_colliders(&self.collider_set);
+        }
+    }
+
+    /// Also added on top of the colliders, give the colliders zero mass to fully control the body's mass
+    pub fn set_mass_properties(
+        &mut self,
+        handle: RigidBodyHandle,
+        mass: f32,
+        local_center_of_mass: Vec3,
+        principal_inertia: Vec3,
+    ) {
+        if let Some(rigid_body) = self.rigid_body_set.get_mut(handle) {
+            rigid_body.set_additional_mass_properties(
+                MassProperties::new(local_center_of_mass.into(), mass, principal_inertia.into()),
+                true,
+            );
+            rigid_body.recompute_mass_properties_from_colliders(&self.collider_set);
+        }
+    }
+
     pub fn remove_rigid_body(&mut self, handle: RigidBodyHandle) {
+        self.ignore_gravity_bodies.remove(&handle);
         self.rigid_body_set.remove(
             handle,
             &mut self.island_manager,
@@ -115,6 +895,10 @@ impl PhysicsScene {
         );
     }
 
+    pub fn contains_rigid_body(&self, handle: RigidBodyHandle) -> bool {
+        self.rigid_body_set.contains(handle)
+    }
+
     pub fn get_rigid_body_transform(&self, handle: RigidBodyHandle) -> (Vec3, Quat) {
         let rigid_body = self.rigid_body_set.get(handle).unwrap();
         (
@@ -123,6 +907,52 @@ impl PhysicsScene {
         )
     }
 
+    /// World space box around every collider attached to the body as (min, max), None without colliders
+    pub fn get_rigid_body_bounds(&self, handle: RigidBodyHandle) -> Option<(Vec3, Vec3)> {
+        let rigid_body = self.rigid_body_set.get(handle)?;
+        rigid_body
+            .colliders()
+            .iter()
+            .filter_map(|collider| self.collider_set.get(*collider))
+            .map(|collider| collider.compute_aabb())
+            .reduce(|a, b| a.merged(&b))
+            .map(|aabb| (aabb.mins.into(), aabb.maxs.into()))
+    }
+
+    /// Continuous collision detection, for small fast bodies that would otherwise pass through thin colliders
+    pub fn set_ccd_enabled(&mut self, handle: RigidBodyHandle, enabled: bool) {
+        if let Some(rigid_body) = self.rigid_body_set.get_mut(handle) {
+            rigid_body.enable_ccd(enabled);
+        }
+    }
+
+    /// Disabled bodies and their colliders are left out of the simulation and scene queries
+    pub fn set_rigid_body_enabled(&mut self, handle: RigidBodyHandle, enabled: bool) {
+        if let Some(rigid_body) = self.rigid_body_set.get_mut(handle) {
+            rigid_body.set_enabled(enabled);
+        }
+    }
+
+    pub fn is_rigid_body_dynamic(&self, handle: RigidBodyHandle) -> bool {
+        self.rigid_body_set
+            .get(handle)
+            .map(|rigid_body| rigid_body.is_dynamic())
+            .unwrap_or(false)
+    }
+
+    pub fn set_rigid_body_type(&mut self, handle: RigidBodyHandle, body_type: RigidBodyType) {
+        if let Some(rigid_body) = self.rigid_body_set.get_mut(handle) {
+            rigid_body.set_body_type(body_type, true);
+        }
+    }
+
+    /// Where a kinematic body ends up after the next step, later calls to `move_kinematic` start from here
+    pub fn set_next_kinematic_transform(&mut self, handle: RigidBodyHandle, transform: &Transform) {
+        if let Some(rigid_body) = self.rigid_body_set.get_mut(handle) {
+            rigid_body.set_next_kinematic_position(transform_to_isometry(transform));
+        }
+    }
+
     pub fn set_rigid_body_transform(
         &mut self,
         handle: RigidBodyHandle,
@@ -136,31 +966,188 @@ impl PhysicsScene {
         }
     }
 
+    /// The scale of `local_transform` is ignored, shapes carry their own size
     pub fn create_collider(
         &mut self,
         parent_handle: RigidBodyHandle,
-        translation: Vec3,
-        rotation: Quat,
+        local_transform: &Transform,
         shape: &ColliderShape,
-        mass: f32,
-    ) -> ColliderHandle {
-        let collider = ColliderBuilder::new(shape.create_shared_shape())
-            .mass(mass)
-            .translation(translation.into())
-            .rotation(nalgebra::UnitQuaternion::from(rotation).scaled_axis())
+        mass: ColliderMass,
+        is_sensor: bool,
+        entity_id: EntityId,
+    ) -> Option<ColliderHandle> {
+        let shared_shape = match shape.create_shared_shape() {
+            Ok(shared_shape) => shared_shape,
+            Err(e) => {
+                error!("Failed to create collider shape: {}", e);
+                return None;
+            }
+        };
+
+        //Sensors should also pick up kinematic and fixed bodies, like the player or a docked craft
+        let collision_types = if is_sensor {
+            ActiveCollisionTypes::all()
+        } else {
+            ActiveCollisionTypes::default()
+        };
+
+        let collider_builder = ColliderBuilder::new(shared_shape);
+        let collider_builder = match mass {
+            ColliderMass::Density(density) => collider_builder.density(density),
+            ColliderMass::Mass(mass) => collider_builder.mass(mass),
+        };
+
+        let collider = collider_builder
+            .sensor(is_sensor)
+            .active_collision_types(collision_types)
+            .user_data(entity_to_user_data(entity_id))
+            .collision_groups(CollisionLayer::Exterior.interaction_groups())
+            .active_events(ActiveEvents::COLLISION_EVENTS)
+            .translation(local_transform.position.into())
+            .rotation(nalgebra::UnitQuaternion::from(local_transform.rotation).scaled_axis())
             .build();
 
-        self.collider_set
-            .insert_with_parent(collider, parent_handle, &mut self.rigid_body_set)
+        let handle =
+            self.collider_set
+                .insert_with_parent(collider, parent_handle, &mut self.rigid_body_set);
+
+        //Update the mass now instead of waiting for the next step, so it can be read back right away
+        if let Some(rigid_body) = self.rigid_body_set.get_mut(parent_handle) {
+            rigid_body.recompute_mass_properties_from_colliders(&self.collider_set);
+        }
+
+        Some(handle)
     }
 
     pub fn remove_collider(&mut self, handle: ColliderHandle) {
+        let parent_handle = self
+            .collider_set
+            .get(handle)
+            .and_then(|collider| collider.parent());
+
         self.collider_set.remove(
             handle,
             &mut self.island_manager,
             &mut self.rigid_body_set,
             true,
         );
+
+        if let Some(rigid_body) =
+            parent_handle.and_then(|handle| self.rigid_body_set.get_mut(handle))
+        {
+            rigid_body.recompute_mass_properties_from_colliders(&self.collider_set);
+        }
+    }
+
+    /// Moves a collider to another body keeping its offset, both bodies get their mass recomputed
+    pub fn set_collider_parent(&mut self, handle: ColliderHandle, rigid_body: RigidBodyHandle) {
+        let old_parent = self
+            .collider_set
+            .get(handle)
+            .and_then(|collider| collider.parent());
+
+        self.collider_set
+            .set_parent(handle, Some(rigid_body), &mut self.rigid_body_set);
+
+        for parent in old_parent.into_iter().chain(Some(rigid_body)) {
+            if let Some(rigid_body) = self.rigid_body_set.get_mut(parent) {
+                rigid_body.recompute_mass_properties_from_colliders(&self.collider_set);
+            }
+        }
+    }
+
+    /// Reports `CollisionEvent::Impact` for contacts on this collider pushing harder than `force_threshold`
+    pub fn enable_impact_events(&mut self, handle: ColliderHandle, force_threshold: f32) {
+        if let Some(collider) = self.collider_set.get_mut(handle) {
+            collider
+                .set_active_events(collider.active_events() | ActiveEvents::CONTACT_FORCE_EVENTS);
+            collider.set_contact_force_event_threshold(force_threshold);
+        }
+    }
+
+    pub fn set_collider_layer(&mut self, handle: ColliderHandle, layer: CollisionLayer) {
+        if let Some(collider) = self.collider_set.get_mut(handle) {
+            collider.set_collision_groups(layer.interaction_groups());
+        }
+    }
+
+    /// Changes the entity reported for a collider in collision events and queries
+    pub fn set_collider_entity(&mut self, handle: ColliderHandle, entity_id: EntityId) {
+        if let Some(collider) = self.collider_set.get_mut(handle) {
+            collider.user_data = entity_to_user_data(entity_id);
+        }
+    }
+
+    /// Colliders currently touching or, for sensors, overlapping the given collider
+    pub fn intersections_with(&self, handle: ColliderHandle) -> Vec<ColliderHandle> {
+        let other_collider = |collider1: ColliderHandle, collider2: ColliderHandle| {
+            if collider1 == handle {
+                collider2
+            } else {
+                collider1
+            }
+        };
+
+        let intersections = self
+            .narrow_phase
+            .intersections_with(handle)
+            .filter(|(_, _, intersecting)| *intersecting)
+            .map(|(collider1, collider2, _)| other_collider(collider1, collider2));
+        let contacts = self
+            .narrow_phase
+            .contacts_with(handle)
+            .filter(|contact_pair| contact_pair.has_any_active_contact)
+            .map(|contact_pair| other_collider(contact_pair.collider1, contact_pair.collider2));
+
+        intersections.chain(contacts).collect()
+    }
+
+    /// Closest exterior collider hit by the ray within `max_distance`, with the distance along the ray.
+    /// Sensors are skipped, uses the query pipeline from the last step
+    pub fn cast_ray(
+        &self,
+        origin: Vec3,
+        direction: Vec3,
+        max_distance: f32,
+        exclude_rigid_body: Option<RigidBodyHandle>,
+    ) -> Option<(ColliderHandle, f32)> {
+        let mut filter = QueryFilter::default()
+            .exclude_sensors()
+            .groups(CollisionLayer::Exterior.interaction_groups());
+        if let Some(rigid_body) = exclude_rigid_body {
+            filter = filter.exclude_rigid_body(rigid_body);
+        }
+
+        self.query_pipeline.cast_ray(
+            &self.rigid_body_set,
+            &self.collider_set,
+            &Ray::new(origin.into(), direction.normalize_or_zero().into()),
+            max_distance,
+            true,
+            filter,
+        )
+    }
+
+    /// Colliders that contain the world space point, uses the query pipeline from the last step
+    pub fn point_query(&self, point: Vec3) -> Vec<ColliderHandle> {
+        let mut colliders = Vec::new();
+        self.query_pipeline.intersections_with_point(
+            &self.rigid_body_set,
+            &self.collider_set,
+            &point.into(),
+            QueryFilter::default(),
+            |handle| {
+                colliders.push(handle);
+                true
+            },
+        );
+        colliders
+    }
+
+    pub fn get_collider_entity(&self, handle: ColliderHandle) -> Option<EntityId> {
+        self.collider_set
+            .get(handle)
+            .map(|collider| user_data_to_entity(collider.user_data))
     }
 
     pub fn get_collider_transform(&self, handle: ColliderHandle) -> (Vec3, Quat) {
@@ -191,4 +1178,333 @@ impl PhysicsScene {
         let rigid_body = self.rigid_body_set.get_mut(handle).unwrap();
         rigid_body.set_angvel(angular_velocity.into(), true);
     }
+
+    pub fn get_rigid_body_angular_velocity(&self, handle: RigidBodyHandle) -> Vec3 {
+        let rigid_body = self.rigid_body_set.get(handle).unwrap();
+        (*rigid_body.angvel()).into()
+    }
+
+    pub fn set_rigid_body_linear_velocity(
+        &mut self,
+        handle: RigidBodyHandle,
+        linear_velocity: Vec3,
+    ) {
+        let rigid_body = self.rigid_body_set.get_mut(handle).unwrap();
+        rigid_body.set_linvel(linear_velocity.into(), true);
+    }
+
+    pub fn get_rigid_body_linear_velocity(&self, handle: RigidBodyHandle) -> Vec3 {
+        let rigid_body = self.rigid_body_set.get(handle).unwrap();
+        (*rigid_body.linvel()).into()
+    }
+
+    /// Forces and torques are cleared after every step, so they need to be applied each frame
+    pub fn apply_force(&mut self, handle: RigidBodyHandle, force: Vec3) {
+        if let Some(rigid_body) = self.rigid_body_set.get_mut(handle) {
+            rigid_body.add_force(force.into(), true);
+        }
+    }
+
+    pub fn apply_force_at_point(&mut self, handle: RigidBodyHandle, force: Vec3, point: Vec3) {
+        if let Some(rigid_body) = self.rigid_body_set.get_mut(handle) {
+            rigid_body.add_force_at_point(force.into(), point.into(), true);
+        }
+    }
+
+    pub fn apply_torque(&mut self, handle: RigidBodyHandle, torque: Vec3) {
+        if let Some(rigid_body) = self.rigid_body_set.get_mut(handle) {
+            rigid_body.add_torque(torque.into(), true);
+        }
+    }
+
+    pub fn apply_impulse(&mut self, handle: RigidBodyHandle, impulse: Vec3) {
+        if let Some(rigid_body) = self.rigid_body_set.get_mut(handle) {
+            rigid_body.apply_impulse(impulse.into(), true);
+        }
+    }
+
+    pub fn apply_torque_impulse(&mut self, handle: RigidBodyHandle, torque_impulse: Vec3) {
+        if let Some(rigid_body) = self.rigid_body_set.get_mut(handle) {
+            rigid_body.apply_torque_impulse(torque_impulse.into(), true);
+        }
+    }
+
+    /// `point` is in world space
+    pub fn apply_impulse_at_point(&mut self, handle: RigidBodyHandle, impulse: Vec3, point: Vec3) {
+        if let Some(rigid_body) = self.rigid_body_set.get_mut(handle) {
+            rigid_body.apply_impulse_at_point(impulse.into(), point.into(), true);
+        }
+    }
+
+    pub fn rigid_body_count(&self) -> usize {
+        self.rigid_body_set.len()
+    }
+
+    pub fn collider_count(&self) -> usize {
+        self.collider_set.len()
+    }
+
+    /// Outlines every collider in world space, colored by the type of its parent body
+    pub fn debug_render(&self, lines: &mut DebugLines) {
+        for (_handle, collider) in self.collider_set.iter() {
+            let parent = collider
+                .parent()
+                .and_then(|handle| self.rigid_body_set.get(handle));
+
+            //Build the position from the parent so colliders don't lag a frame behind teleported bodies
+            let (position, color) = match (parent, collider.position_wrt_parent()) {
+                (Some(rigid_body), Some(local_position)) => (
+                    rigid_body.position() * local_position,
+                    match rigid_body.body_type() {
+                        _ if rigid_body.is_sleeping() => SLEEPING_COLLIDER_COLOR,
+                        RigidBodyType::Dynamic => DYNAMIC_COLLIDER_COLOR,
+                        RigidBodyType::Fixed => FIXED_COLLIDER_COLOR,
+                        _ => KINEMATIC_COLLIDER_COLOR,
+                    },
+                ),
+                _ => (*collider.position(), FIXED_COLLIDER_COLOR),
+            };
+
+            debug_render_shape(lines, collider.shape(), &position, color);
+        }
+    }
+}
+
+const DYNAMIC_COLLIDER_COLOR: [f32; 4] = [0.0, 1.0, 0.0, 1.0];
+const KINEMATIC_COLLIDER_COLOR: [f32; 4] = [1.0, 1.0, 0.0, 1.0];
+const FIXED_COLLIDER_COLOR: [f32; 4] = [0.0, 0.5, 1.0, 1.0];
+const SLEEPING_COLLIDER_COLOR: [f32; 4] = [0.5, 0.5, 0.5, 1.0];
+
+fn debug_render_shape(
+    lines: &mut DebugLines,
+    shape: &dyn Shape,
+    position: &Isometry<Real>,
+    color: [f32; 4],
+) {
+    let translation: Vec3 = position.translation.vector.into();
+    let rotation: Quat = position.rotation.into();
+    let to_world = |point: &Point<Real>| -> Vec3 { (position * point).into() };
+
+    match shape.as_typed_shape() {
+        TypedShape::Ball(ball) => lines.sphere(translation, ball.radius, color, true),
+        TypedShape::Cuboid(cuboid) => lines.box_outline(
+            &Transform {
+                position: translation,
+                rotation,
+                scale: Vec3::ONE,
+            },
+            cuboid.half_extents.into(),
+            color,
+            true,
+        ),
+        TypedShape::Capsule(capsule) => {
+            let a = to_world(&capsule.segment.a);
+            let b = to_world(&capsule.segment.b);
+            let axis = (b - a).normalize_or_zero();
+            let axis_rotation = Quat::from_rotation_arc(Vec3::Z, axis);
+
+            lines.sphere(a, capsule.radius, color, true);
+            lines.sphere(b, capsule.radius, color, true);
+            for side in [Vec3::X, Vec3::NEG_X, Vec3::Y, Vec3::NEG_Y] {
+                let offset = axis_rotation * side * capsule.radius;
+                lines.line(a + offset, b + offset, color, true);
+            }
+        }
+        TypedShape::Cylinder(cylinder) => {
+            //Circles are drawn in the XY plane, the cylinder axis is Y
+            let circle_rotation = rotation * Quat::from_rotation_x(std::f32::consts::FRAC_PI_2);
+            let top = translation + rotation * Vec3::Y * cylinder.half_height;
+            let bottom = translation - rotation * Vec3::Y * cylinder.half_height;
+
+            lines.circle(top, circle_rotation, cylinder.radius, color, true);
+            lines.circle(bottom, circle_rotation, cylinder.radius, color, true);
+            for side in [Vec3::X, Vec3::NEG_X, Vec3::Z, Vec3::NEG_Z] {
+                let offset = rotation * side * cylinder.radius;
+                lines.line(top + offset, bottom + offset, color, true);
+            }
+        }
+        TypedShape::ConvexPolyhedron(polyhedron) => {
+            let points = polyhedron.points();
+            for edge in polyhedron.edges() {
+                lines.line(
+                    to_world(&points[edge.vertices.x as usize]),
+                    to_world(&points[edge.vertices.y as usize]),
+                    color,
+                    true,
+                );
+            }
+        }
+        TypedShape::TriMesh(trimesh) => {
+            for triangle in trimesh.triangles() {
+                let [a, b, c] = triangle.vertices().map(|point| to_world(&point));
+                lines.line(a, b, color, true);
+                lines.line(b, c, color, true);
+                lines.line(c, a, color, true);
+            }
+        }
+        TypedShape::Compound(compound) => {
+            for (local_position, sub_shape) in compound.shapes() {
+                debug_render_shape(lines, &**sub_shape, &(position * local_position), color);
+            }
+        }
+        _ => {
+            //Fallback to the bounding box for shapes without a dedicated outline
+            let aabb = shape.compute_aabb(position);
+            lines.box_outline(
+                &Transform::new_pos(aabb.center().into()),
+                aabb.half_extents().into(),
+                color,
+                true,
+            );
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const DELTA_TIME: f32 = 1.0 / 60.0;
+
+    fn add_body(
+        physics: &mut PhysicsScene,
+        translation: Vec3,
+        rotation: Quat,
+        body_type: RigidBodyType,
+        shape: ColliderShape,
+    ) -> RigidBodyHandle {
+        let handle = physics.create_rigid_body(translation, rotation, body_type);
+        physics
+            .create_collider(
+                handle,
+                &Transform::default(),
+                &shape,
+                ColliderMass::Density(1.0),
+                false,
+                EntityId::default(),
+            )
+            .unwrap();
+        handle
+    }
+
+    fn step(physics: &mut PhysicsScene, steps: usize) {
+        for _ in 0..steps {
+            physics.step_physics(DELTA_TIME);
+        }
+    }
+
+    #[test]
+    fn restored_snapshot_steps_identically() {
+        let mut physics = PhysicsScene::new();
+        //Roughly earth gravity at the ground from a source far below it
+        physics.add_gravity_source(GravitySource {
+            position: Vec3::new(0.0, -1000.0, 0.0),
+            mu: 9.81 * 1000.0 * 1000.0,
+            radius: 1.0,
+        });
+        add_body(
+            &mut physics,
+            Vec3::ZERO,
+            Quat::IDENTITY,
+            RigidBodyType::Fixed,
+            ColliderShape::Box(Vec3::new(10.0, 0.5, 10.0)),
+        );
+        //A leaning stack that topples over
+        let boxes: Vec<RigidBodyHandle> = (0..5)
+            .map(|i| {
+                let height = 1.0 + i as f32 * 1.05;
+                add_body(
+                    &mut physics,
+                    Vec3::new(i as f32 * 0.2, height, 0.0),
+                    Quat::from_rotation_y(i as f32 * 0.3),
+                    RigidBodyType::Dynamic,
+                    ColliderShape::Box(Vec3::splat(0.5)),
+                )
+            })
+            .collect();
+        physics.set_rigid_body_angular_velocity(boxes[4], Vec3::new(1.0, 0.0, 2.0));
+        step(&mut physics, 10);
+
+        let translations = |physics: &PhysicsScene| -> Vec<Vec3> {
+            boxes
+                .iter()
+                .map(|handle| physics.get_rigid_body_transform(*handle).0)
+                .collect()
+        };
+
+        let snapshot = physics.snapshot();
+        let before = translations(&physics);
+        step(&mut physics, 120);
+        let first_run = translations(&physics);
+        assert_ne!(before, first_run);
+
+        physics.restore(&snapshot);
+        step(&mut physics, 120);
+        assert_eq!(first_run, translations(&physics));
+    }
+
+    #[test]
+    fn impulses_and_forces_change_velocity_by_their_mass() {
+        let mut physics = PhysicsScene::new();
+        let handle = physics.create_rigid_body(Vec3::ZERO, Quat::IDENTITY, RigidBodyType::Dynamic);
+        physics
+            .create_collider(
+                handle,
+                &Transform::default(),
+                &ColliderShape::Sphere(0.5),
+                ColliderMass::Mass(1.0),
+                false,
+                EntityId::default(),
+            )
+            .unwrap();
+        assert!((physics.get_mass(handle) - 1.0).abs() < 1e-6);
+
+        physics.apply_impulse(handle, Vec3::new(2.0, 0.0, -1.0));
+        step(&mut physics, 1);
+        let velocity = physics.get_rigid_body_linear_velocity(handle);
+        assert!(
+            velocity.abs_diff_eq(Vec3::new(2.0, 0.0, -1.0), 1e-5),
+            "{velocity}"
+        );
+
+        //A force only lasts for the step it was applied before
+        physics.apply_force(handle, Vec3::new(0.0, 6.0, 0.0));
+        step(&mut physics, 2);
+        let velocity = physics.get_rigid_body_linear_velocity(handle);
+        let expected = Vec3::new(2.0, 6.0 * DELTA_TIME, -1.0);
+        assert!(velocity.abs_diff_eq(expected, 1e-5), "{velocity}");
+    }
+
+    #[test]
+    fn gravity_source_keeps_a_circular_orbit() {
+        let mut physics = PhysicsScene::new();
+        let mu = 100.0;
+        let radius = 10.0;
+        physics.add_gravity_source(GravitySource {
+            position: Vec3::ZERO,
+            mu,
+            radius: 1.0,
+        });
+        let handle = add_body(
+            &mut physics,
+            Vec3::new(radius, 0.0, 0.0),
+            Quat::IDENTITY,
+            RigidBodyType::Dynamic,
+            ColliderShape::Sphere(0.5),
+        );
+        let speed = (mu / radius).sqrt();
+        physics.set_rigid_body_linear_velocity(handle, Vec3::new(0.0, 0.0, speed));
+
+        //About a third of an orbit
+        for _ in 0..400 {
+            physics.step_physics(DELTA_TIME);
+            let distance = physics.get_rigid_body_transform(handle).0.length();
+            assert!(
+                (distance - radius).abs() < radius * 0.01,
+                "drifted to {distance}"
+            );
+        }
+        let position = physics.get_rigid_body_transform(handle).0;
+        assert!(position.z > radius * 0.5, "{position} didn't go around");
+    }
 }
@@ -1,23 +1,222 @@
+use crate::physics::{ColliderMass, ColliderShape, CollisionLayer};
 use crate::transform::Transform;
-use crate::world::{Entity, EntityId, WorldInfo};
+use crate::world::{Entity, EntityId, EntitySaveData, PlayerAction, UpdateStage, WorldInfo};
+use crate::Renderer;
 use glam::{Quat, Vec3};
+use log::info;
+use rapier3d::dynamics::RigidBodyType;
+use rapier3d::prelude::{ColliderHandle, RigidBodyHandle};
 
+const CAMERA_MOVE_SPEED: f32 = 5.0;
+const CAMERA_ROTATION_SPEED: f32 = 1.0;
+
+const PLAYER_MASS: f32 = 100.0;
+/// In Newtons, a bit under half a g for the default mass
+const PLAYER_THRUST: f32 = 400.0;
+/// In rad/s^2
+const PLAYER_ANGULAR_ACCELERATION: f32 = 3.0;
+/// Braking acceleration per unit of velocity in 1/s, capped by the thrust
+const DAMPENER_GAIN: f32 = 2.0;
+
+const INTERIOR_WALK_SPEED: f32 = 2.0;
+const INTERIOR_JUMP_SPEED: f32 = 3.0;
+/// Artificial gravity inside crafts, towards the craft's down
+const INTERIOR_GRAVITY: f32 = 9.81;
+const INTERIOR_PITCH_LIMIT: f32 = 89.0 * std::f32::consts::PI / 180.0;
+
+fn rotate_by_input(rotation: Quat, angular_input: Vec3, delta_time: f32) -> Quat {
+    let rotation = rotation
+        * Quat::from_rotation_y(angular_input.x * CAMERA_ROTATION_SPEED * delta_time)
+        * Quat::from_rotation_x(angular_input.y * CAMERA_ROTATION_SPEED * delta_time)
+        * Quat::from_rotation_z(-angular_input.z * CAMERA_ROTATION_SPEED * delta_time);
+    rotation.normalize()
+}
+
+/// Yaw about the camera's local Y, then pitch about its local X
+fn rotate_by_mouse(rotation: Quat, yaw: f32, pitch: f32) -> Quat {
+    (rotation * Quat::from_rotation_y(yaw) * Quat::from_rotation_x(pitch)).normalize()
+}
+
+/// Flies with thrust and momentum, or teleports around when noclip is on
 pub struct Player {
     id: EntityId,
     transform: Transform,
     linear_input: Vec3,
     angular_input: Vec3,
+
+    /// Collider radius, None for a player that only ever flies in noclip
+    radius: Option<f32>,
+    noclip: bool,
+    /// Brakes every axis without input
+    dampeners: bool,
+    /// Mouse-look gathered since the last update, applied to the body since there's no world to do it right away
+    pending_look: (f32, f32),
+
+    rigid_body_instance: Option<RigidBodyHandle>,
+    collider_instance: Option<ColliderHandle>,
 }
 
 impl Player {
+    /// Noclip only player without a body
     pub fn new(transform: Transform) -> Self {
         Self {
             id: Default::default(),
             transform,
             linear_input: Vec3::ZERO,
             angular_input: Vec3::ZERO,
+            radius: None,
+            noclip: true,
+            dampeners: true,
+            pending_look: (0.0, 0.0),
+            rigid_body_instance: None,
+            collider_instance: None,
+        }
+    }
+
+    /// Player with a dynamic sphere body driven by forces, noclip can still be toggled on
+    pub fn new_inertial(transform: Transform, radius: f32) -> Self {
+        Self {
+            radius: Some(radius),
+            noclip: false,
+            ..Self::new(transform)
+        }
+    }
+
+    /// Moves the player and its body, the body continues with `linear_velocity`
+    pub fn teleport(
+        &mut self,
+        transform: &Transform,
+        linear_velocity: Vec3,
+        world: &mut WorldInfo,
+    ) {
+        self.transform.position = transform.position;
+        self.transform.rotation = transform.rotation;
+        if let Some(rigid_body) = self.rigid_body_instance {
+            world.physics.set_rigid_body_transform(
+                rigid_body,
+                transform.position,
+                transform.rotation,
+                true,
+            );
+            world
+                .physics
+                .set_rigid_body_linear_velocity(rigid_body, linear_velocity);
+            world
+                .physics
+                .set_rigid_body_angular_velocity(rigid_body, Vec3::ZERO);
+        }
+    }
+
+    fn create_body(&mut self, world: &mut WorldInfo) {
+        let Some(radius) = self.radius else {
+            return;
+        };
+        let rigid_body = world.physics.create_rigid_body(
+            self.transform.position,
+            self.transform.rotation,
+            RigidBodyType::Dynamic,
+        );
+        self.rigid_body_instance = Some(rigid_body);
+        self.collider_instance = world.physics.create_collider(
+            rigid_body,
+            &Transform::default(),
+            &ColliderShape::Sphere(radius),
+            ColliderMass::Mass(PLAYER_MASS),
+            false,
+            self.id,
+        );
+    }
+
+    fn remove_body(&mut self, world: &mut WorldInfo) {
+        if let Some(collider) = self.collider_instance.take() {
+            world.physics.remove_collider(collider);
+        }
+
+        if let Some(rigid_body) = self.rigid_body_instance.take() {
+            world.physics.remove_rigid_body(rigid_body);
         }
     }
+
+    fn update_noclip(&mut self, delta_time: f32) {
+        let (yaw, pitch) = std::mem::take(&mut self.pending_look);
+        self.transform.rotation = rotate_by_mouse(self.transform.rotation, yaw, pitch);
+
+        let input_vector = self.linear_input.normalize_or_zero();
+        self.transform.position +=
+            self.transform.rotation * (input_vector * CAMERA_MOVE_SPEED * delta_time);
+        self.transform.rotation =
+            rotate_by_input(self.transform.rotation, self.angular_input, delta_time);
+    }
+
+    /// Mouse look and thrust, before the physics step
+    fn apply_inertial_input(&mut self, world: &mut WorldInfo, rigid_body: RigidBodyHandle) {
+        let (position, rotation) = world.physics.get_rigid_body_transform(rigid_body);
+        let (yaw, pitch) = std::mem::take(&mut self.pending_look);
+        let rotation = if yaw != 0.0 || pitch != 0.0 {
+            let rotation = rotate_by_mouse(rotation, yaw, pitch);
+            world
+                .physics
+                .set_rigid_body_transform(rigid_body, position, rotation, true);
+            rotation
+        } else {
+            rotation
+        };
+
+        let mass = world.physics.get_mass(rigid_body);
+        let local_velocity =
+            rotation.inverse() * world.physics.get_rigid_body_linear_velocity(rigid_body);
+        let thrust = thrust_or_brake(
+            self.linear_input.normalize_or_zero(),
+            local_velocity,
+            Vec3::splat(mass),
+            PLAYER_THRUST / mass,
+            self.dampeners,
+        );
+        world.physics.apply_force(rigid_body, rotation * thrust);
+
+        //Same axes as rotate_by_input
+        let angular_input = Vec3::new(
+            self.angular_input.y,
+            self.angular_input.x,
+            -self.angular_input.z,
+        );
+        let local_angular_velocity =
+            rotation.inverse() * world.physics.get_rigid_body_angular_velocity(rigid_body);
+        let torque = thrust_or_brake(
+            angular_input,
+            local_angular_velocity,
+            world.physics.get_principal_angular_inertia(rigid_body),
+            PLAYER_ANGULAR_ACCELERATION,
+            self.dampeners,
+        );
+        world.physics.apply_torque(rigid_body, rotation * torque);
+    }
+}
+
+/// Per axis force (or torque) in the body's frame: full acceleration along the input, or braking against the
+/// velocity on axes without input when the dampeners are on. `inertia` is the mass or moment of inertia per axis
+fn thrust_or_brake(
+    input: Vec3,
+    velocity: Vec3,
+    inertia: Vec3,
+    max_acceleration: f32,
+    dampeners: bool,
+) -> Vec3 {
+    let axis = |input: f32, velocity: f32, inertia: f32| {
+        let acceleration = if input != 0.0 {
+            input * max_acceleration
+        } else if dampeners {
+            (-velocity * DAMPENER_GAIN).clamp(-max_acceleration, max_acceleration)
+        } else {
+            0.0
+        };
+        acceleration * inertia
+    };
+    Vec3::new(
+        axis(input.x, velocity.x, inertia.x),
+        axis(input.y, velocity.y, inertia.y),
+        axis(input.z, velocity.z, inertia.z),
+    )
 }
 
 impl Entity for Player {
@@ -25,33 +224,429 @@ impl Entity for Player {
         self.id = id;
     }
 
-    fn add_to_world(&mut self, world: &mut WorldInfo) {}
+    fn add_to_world(&mut self, world: &mut WorldInfo) {
+        if !self.noclip {
+            self.create_body(world);
+        }
+    }
 
-    fn remove_from_world(&mut self, world: &mut WorldInfo) {}
+    fn remove_from_world(&mut self, world: &mut WorldInfo) {
+        self.remove_body(world);
+    }
 
+    fn update_stages(&self) -> &'static [UpdateStage] {
+        &[UpdateStage::PrePhysics, UpdateStage::PostPhysics]
+    }
+
+    /// Forces added while paused would pile up until the next step, so the body only gets input while running
+    fn pre_physics_update(&mut self, world: &mut WorldInfo, _delta_time: f32) {
+        if let Some(rigid_body) = self.rigid_body_instance {
+            if world.physics_delta_time > 0.0 {
+                self.apply_inertial_input(world, rigid_body);
+            }
+        }
+    }
+
+    /// While paused the camera flies freely like noclip, it's back on the body once the game runs again
     fn update(&mut self, world: &mut WorldInfo, delta_time: f32) {
-        const CAMERA_MOVE_SPEED: f32 = 5.0;
+        match self.rigid_body_instance {
+            Some(_) if world.physics_delta_time == 0.0 => self.update_noclip(delta_time),
+            Some(rigid_body) => {
+                let (position, rotation) = world.physics.get_rigid_body_transform(rigid_body);
+                self.transform.position = position;
+                self.transform.rotation = rotation;
+            }
+            None => self.update_noclip(delta_time),
+        }
+    }
+
+    fn update_player_input(&mut self, linear_input: Vec3, angular_input: Vec3) {
+        self.linear_input = linear_input;
+        self.angular_input = angular_input;
+    }
+    fn get_camera_transform(&self) -> Option<Transform> {
+        Some(self.transform.clone())
+    }
+
+    fn apply_mouse_look(&mut self, yaw: f32, pitch: f32) {
+        self.pending_look.0 += yaw;
+        self.pending_look.1 += pitch;
+    }
+
+    fn on_player_action(&mut self, action: PlayerAction, world: &mut WorldInfo) {
+        match action {
+            PlayerAction::ToggleDampeners => {
+                self.dampeners = !self.dampeners;
+                info!("Dampeners {}", if self.dampeners { "on" } else { "off" });
+            }
+            PlayerAction::ToggleNoclip if self.radius.is_some() => {
+                self.noclip = !self.noclip;
+                //Leaving noclip starts at rest wherever the player flew to
+                if self.noclip {
+                    self.remove_body(world);
+                } else {
+                    self.create_body(world);
+                }
+                info!("Noclip {}", if self.noclip { "on" } else { "off" });
+            }
+            _ => {}
+        }
+    }
+
+    fn get_rigid_body(&self) -> Option<RigidBodyHandle> {
+        self.rigid_body_instance
+    }
+
+    fn get_world_transform(&self) -> Option<Transform> {
+        Some(self.transform.clone())
+    }
+
+    fn shift_origin(&mut self, offset: Vec3, _world: &mut WorldInfo) {
+        self.transform.position += offset;
+    }
+
+    fn save(&self, _world: &WorldInfo, _renderer: &Renderer) -> Option<EntitySaveData> {
+        Some(EntitySaveData::Player {
+            transform: self.transform.clone(),
+            radius: self.radius,
+        })
+    }
+}
+
+/// Player camera with a kinematic body, so it slides along colliders instead of flying through them
+pub struct PhysicsPlayer {
+    id: EntityId,
+    transform: Transform,
+    radius: f32,
+    linear_input: Vec3,
+    angular_input: Vec3,
+
+    rigid_body_instance: Option<RigidBodyHandle>,
+    collider_instance: Option<ColliderHandle>,
+}
+
+impl PhysicsPlayer {
+    pub fn new(transform: Transform, radius: f32) -> Self {
+        Self {
+            id: Default::default(),
+            transform,
+            radius,
+            linear_input: Vec3::ZERO,
+            angular_input: Vec3::ZERO,
+            rigid_body_instance: None,
+            collider_instance: None,
+        }
+    }
+}
+
+impl Entity for PhysicsPlayer {
+    fn set_id(&mut self, id: EntityId) {
+        self.id = id;
+    }
+
+    fn add_to_world(&mut self, world: &mut WorldInfo) {
+        let rigid_body = world.physics.create_rigid_body(
+            self.transform.position,
+            Quat::IDENTITY,
+            RigidBodyType::KinematicPositionBased,
+        );
+        self.rigid_body_instance = Some(rigid_body);
+        self.collider_instance = world.physics.create_collider(
+            rigid_body,
+            &Transform::default(),
+            &ColliderShape::Sphere(self.radius),
+            ColliderMass::Mass(1.0),
+            false,
+            self.id,
+        );
+    }
+
+    fn remove_from_world(&mut self, world: &mut WorldInfo) {
+        if let Some(collider) = self.collider_instance.take() {
+            world.physics.remove_collider(collider);
+        }
+
+        if let Some(rigid_body) = self.rigid_body_instance.take() {
+            world.physics.remove_rigid_body(rigid_body);
+        }
+    }
+
+    fn update_stages(&self) -> &'static [UpdateStage] {
+        &[UpdateStage::PrePhysics]
+    }
+
+    /// The kinematic move is queued before the step so the body and camera end up in the same place this frame
+    fn pre_physics_update(&mut self, world: &mut WorldInfo, delta_time: f32) {
+        //The body only carries the position, rotation stays on the camera since the collider is a sphere
+        self.transform.rotation =
+            rotate_by_input(self.transform.rotation, self.angular_input, delta_time);
 
         let input_vector = self.linear_input.normalize_or_zero();
-        self.transform.position +=
+        let desired_translation =
             self.transform.rotation * (input_vector * CAMERA_MOVE_SPEED * delta_time);
 
-        const CAMERA_ROTATION_SPEED: f32 = 1.0;
-        self.transform.rotation *=
-            Quat::from_rotation_y(self.angular_input.x * CAMERA_ROTATION_SPEED * delta_time);
-        self.transform.rotation *=
-            Quat::from_rotation_x(self.angular_input.y * CAMERA_ROTATION_SPEED * delta_time);
-        self.transform.rotation *=
-            Quat::from_rotation_z(-self.angular_input.z * CAMERA_ROTATION_SPEED * delta_time);
+        if let Some(movement) = self.rigid_body_instance.and_then(|rigid_body| {
+            world
+                .physics
+                .move_kinematic(rigid_body, desired_translation, delta_time)
+        }) {
+            self.transform.position += movement.translation;
+        }
+    }
+
+    fn update(&mut self, _world: &mut WorldInfo, _delta_time: f32) {}
+
+    fn update_player_input(&mut self, linear_input: Vec3, angular_input: Vec3) {
+        self.linear_input = linear_input;
+        self.angular_input = angular_input;
+    }
+
+    fn get_camera_transform(&self) -> Option<Transform> {
+        Some(self.transform.clone())
+    }
+
+    fn apply_mouse_look(&mut self, yaw: f32, pitch: f32) {
+        self.transform.rotation = rotate_by_mouse(self.transform.rotation, yaw, pitch);
+    }
+
+    fn get_rigid_body(&self) -> Option<RigidBodyHandle> {
+        self.rigid_body_instance
+    }
 
-        self.transform.rotation = self.transform.rotation.normalize();
+    fn get_world_transform(&self) -> Option<Transform> {
+        Some(self.transform.clone())
+    }
+
+    fn shift_origin(&mut self, offset: Vec3, _world: &mut WorldInfo) {
+        self.transform.position += offset;
+    }
+
+    fn save(&self, _world: &WorldInfo, _renderer: &Renderer) -> Option<EntitySaveData> {
+        Some(EntitySaveData::PhysicsPlayer {
+            transform: self.transform.clone(),
+            radius: self.radius,
+        })
+    }
+}
+
+/// Walks around the interior of a craft, the position is kept relative to the craft's body so the player
+/// rides along while it flies. Only collides with the interior collision layer
+pub struct InteriorPlayer {
+    id: EntityId,
+    craft_rigid_body: RigidBodyHandle,
+    /// Relative to the craft's body
+    local_position: Vec3,
+    yaw: f32,
+    pitch: f32,
+    radius: f32,
+    vertical_speed: f32,
+    grounded: bool,
+    linear_input: Vec3,
+    angular_input: Vec3,
+
+    /// World space camera transform, updated every frame
+    transform: Transform,
+    rigid_body_instance: Option<RigidBodyHandle>,
+    collider_instance: Option<ColliderHandle>,
+}
+
+impl InteriorPlayer {
+    pub fn new(craft_rigid_body: RigidBodyHandle, local_position: Vec3, radius: f32) -> Self {
+        Self {
+            id: Default::default(),
+            craft_rigid_body,
+            local_position,
+            yaw: 0.0,
+            pitch: 0.0,
+            radius,
+            vertical_speed: 0.0,
+            grounded: false,
+            linear_input: Vec3::ZERO,
+            angular_input: Vec3::ZERO,
+            transform: Transform::default(),
+            rigid_body_instance: None,
+            collider_instance: None,
+        }
+    }
+
+    fn craft_transform(&self, world: &WorldInfo) -> Option<(Vec3, Quat)> {
+        world
+            .physics
+            .contains_rigid_body(self.craft_rigid_body)
+            .then(|| {
+                world
+                    .physics
+                    .get_rigid_body_transform(self.craft_rigid_body)
+            })
+    }
+}
+
+impl Entity for InteriorPlayer {
+    fn set_id(&mut self, id: EntityId) {
+        self.id = id;
+    }
+
+    fn add_to_world(&mut self, world: &mut WorldInfo) {
+        let (craft_position, craft_rotation) = self
+            .craft_transform(world)
+            .unwrap_or((Vec3::ZERO, Quat::IDENTITY));
+        self.transform.position = craft_position + craft_rotation * self.local_position;
+
+        let rigid_body = world.physics.create_rigid_body(
+            self.transform.position,
+            Quat::IDENTITY,
+            RigidBodyType::KinematicPositionBased,
+        );
+        self.rigid_body_instance = Some(rigid_body);
+
+        //A sensor so the kinematic body can't push the craft it's standing in
+        self.collider_instance = world.physics.create_collider(
+            rigid_body,
+            &Transform::default(),
+            &ColliderShape::Sphere(self.radius),
+            ColliderMass::Mass(1.0),
+            true,
+            self.id,
+        );
+        if let Some(collider) = self.collider_instance {
+            world
+                .physics
+                .set_collider_layer(collider, CollisionLayer::Interior);
+        }
+    }
+
+    fn remove_from_world(&mut self, world: &mut WorldInfo) {
+        if let Some(collider) = self.collider_instance.take() {
+            world.physics.remove_collider(collider);
+        }
+
+        if let Some(rigid_body) = self.rigid_body_instance.take() {
+            world.physics.remove_rigid_body(rigid_body);
+        }
+    }
+
+    /// Stays in PostPhysics even though it's driven by input, it has to start from where the step left the craft
+    fn update(&mut self, world: &mut WorldInfo, delta_time: f32) {
+        self.yaw += self.angular_input.x * CAMERA_ROTATION_SPEED * delta_time;
+        self.pitch = (self.pitch + self.angular_input.y * CAMERA_ROTATION_SPEED * delta_time)
+            .clamp(-INTERIOR_PITCH_LIMIT, INTERIOR_PITCH_LIMIT);
+
+        let (Some(rigid_body), Some((craft_position, craft_rotation))) =
+            (self.rigid_body_instance, self.craft_transform(world))
+        else {
+            return;
+        };
+
+        //Carry the player to where the craft moved it before walking
+        let start_position = craft_position + craft_rotation * self.local_position;
+        world
+            .physics
+            .set_next_kinematic_transform(rigid_body, &Transform::new_pos(start_position));
+
+        if self.grounded {
+            self.vertical_speed = if self.linear_input.y > 0.0 {
+                INTERIOR_JUMP_SPEED
+            } else {
+                0.0
+            };
+        }
+        self.vertical_speed -= INTERIOR_GRAVITY * delta_time;
+
+        let up = craft_rotation * Vec3::Y;
+        let heading = craft_rotation * Quat::from_rotation_y(self.yaw);
+        let walk_input =
+            Vec3::new(self.linear_input.x, 0.0, self.linear_input.z).normalize_or_zero();
+        let desired_translation =
+            (heading * walk_input * INTERIOR_WALK_SPEED + up * self.vertical_speed) * delta_time;
+
+        let translation = match world.physics.move_kinematic_with_up(
+            rigid_body,
+            desired_translation,
+            up,
+            delta_time,
+        ) {
+            Some(movement) => {
+                self.grounded = movement.grounded;
+                movement.translation
+            }
+            None => Vec3::ZERO,
+        };
+
+        self.transform.position = start_position + translation;
+        self.transform.rotation = heading * Quat::from_rotation_x(self.pitch);
+        self.local_position = craft_rotation.inverse() * (self.transform.position - craft_position);
     }
 
     fn update_player_input(&mut self, linear_input: Vec3, angular_input: Vec3) {
         self.linear_input = linear_input;
         self.angular_input = angular_input;
     }
+
     fn get_camera_transform(&self) -> Option<Transform> {
         Some(self.transform.clone())
     }
+
+    fn apply_mouse_look(&mut self, yaw: f32, pitch: f32) {
+        self.yaw += yaw;
+        self.pitch = (self.pitch + pitch).clamp(-INTERIOR_PITCH_LIMIT, INTERIOR_PITCH_LIMIT);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::renderer::test_renderer;
+    use crate::world::World;
+
+    const DELTA_TIME: f32 = 1.0 / 60.0;
+
+    #[test]
+    fn thrust_held_while_paused_doesnt_build_up() {
+        let Some(mut renderer) = test_renderer() else {
+            eprintln!("No adapter, skipping");
+            return;
+        };
+        let mut world = World::new(&mut renderer);
+        let player_id = world.add_entity(Player::new_inertial(Transform::default(), 0.5));
+        world.set_player(player_id);
+        let rigid_body = world.entities[player_id].get_rigid_body().unwrap();
+
+        world.set_paused(true);
+        world.entities[player_id].update_player_input(Vec3::Z, Vec3::ZERO);
+        for _ in 0..60 {
+            world.update(DELTA_TIME);
+        }
+        let physics = &world.world_info.physics;
+        assert_eq!(
+            physics.get_rigid_body_linear_velocity(rigid_body),
+            Vec3::ZERO
+        );
+        assert_eq!(physics.get_rigid_body_transform(rigid_body).0, Vec3::ZERO);
+
+        //The camera still flew while the body stayed put
+        let camera = world.entities[player_id].get_camera_transform().unwrap();
+        assert!(camera.position.z > 0.0);
+
+        //One step of thrust, not one for every paused frame
+        world.set_paused(false);
+        world.update(DELTA_TIME);
+        let velocity = world
+            .world_info
+            .physics
+            .get_rigid_body_linear_velocity(rigid_body);
+        let expected = PLAYER_THRUST / PLAYER_MASS * DELTA_TIME;
+        assert!(
+            (velocity - Vec3::Z * expected).length() < expected * 0.01,
+            "{} != {}",
+            velocity,
+            Vec3::Z * expected
+        );
+
+        let camera = world.entities[player_id].get_camera_transform().unwrap();
+        let (position, _) = world
+            .world_info
+            .physics
+            .get_rigid_body_transform(rigid_body);
+        assert_eq!(camera.position, position);
+    }
 }
@@ -0,0 +1,258 @@
+use crate::physics::{ColliderMass, ColliderShape, CollisionEvent};
+use crate::renderer::{InstanceHandle, MaterialHandle, MeshHandle, TrailHandle, TrailSettings};
+use crate::transform::Transform;
+use crate::world::{Entity, EntityId, EntityMessage, WorldInfo};
+use glam::Vec3;
+use log::info;
+use rapier3d::dynamics::RigidBodyType;
+use rapier3d::prelude::{ColliderHandle, RigidBodyHandle};
+
+/// Density of physical projectiles in kg/m^3, roughly steel
+const PROJECTILE_DENSITY: f32 = 7800.0;
+
+/// Tracer behind physical projectiles
+fn tracer_settings() -> TrailSettings {
+    TrailSettings {
+        max_points: 16,
+        segment_length: 4.0,
+        lifetime: 0.3,
+        width: 0.15,
+        color: [1.0, 0.8, 0.4, 0.9],
+        break_distance: 200.0,
+        fade_time: 0.2,
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ProjectileMode {
+    /// A small fast body that travels and can be dodged
+    Physical,
+    /// Hits the first collider along the firing direction on its first update, out to speed * lifetime
+    Hitscan,
+}
+
+/// Sends the damage to the entity owning the collider, crafts turn it into module damage
+fn deliver_hit(world: &mut WorldInfo, collider: ColliderHandle, damage: f32) {
+    if let Some(target) = world.physics.get_collider_entity(collider) {
+        world.commands.send(
+            target,
+            EntityMessage::Damage {
+                collider,
+                amount: damage,
+            },
+        );
+    }
+}
+
+pub struct ProjectileEntity {
+    id: EntityId,
+    mode: ProjectileMode,
+    transform: Transform,
+    velocity: Vec3,
+    radius: f32,
+    damage: f32,
+    /// Seconds left before the projectile despawns on its own
+    lifetime: f32,
+    model: Option<(MeshHandle, MaterialHandle)>,
+    /// Never hit, so shots don't hit the craft or player that fired them
+    shooter: Option<EntityId>,
+    shooter_rigid_body: Option<RigidBodyHandle>,
+    /// Set once the despawn is queued so a second contact in the same step doesn't hit again
+    spent: bool,
+
+    model_instance: Option<InstanceHandle>,
+    trail_instance: Option<TrailHandle>,
+    rigid_body_instance: Option<RigidBodyHandle>,
+    collider_instance: Option<ColliderHandle>,
+}
+
+impl ProjectileEntity {
+    /// Travels along the transform's forward (+Z) at `velocity`, which already includes the shooter's velocity
+    pub fn new(
+        mode: ProjectileMode,
+        transform: Transform,
+        velocity: Vec3,
+        damage: f32,
+        lifetime: f32,
+    ) -> Self {
+        Self {
+            id: Default::default(),
+            mode,
+            transform,
+            velocity,
+            radius: 0.05,
+            damage,
+            lifetime,
+            model: None,
+            shooter: None,
+            shooter_rigid_body: None,
+            spent: false,
+            model_instance: None,
+            trail_instance: None,
+            rigid_body_instance: None,
+            collider_instance: None,
+        }
+    }
+
+    /// Only used by physical projectiles, the mesh is scaled to the collider so it should be a sphere one unit across
+    pub fn with_model(mut self, mesh: MeshHandle, material: MaterialHandle, radius: f32) -> Self {
+        self.model = Some((mesh, material));
+        self.radius = radius;
+        self
+    }
+
+    pub fn with_shooter(mut self, shooter: EntityId, rigid_body: Option<RigidBodyHandle>) -> Self {
+        self.shooter = Some(shooter);
+        self.shooter_rigid_body = rigid_body;
+        self
+    }
+
+    fn despawn(&mut self, world: &mut WorldInfo) {
+        if !self.spent {
+            self.spent = true;
+            world.commands.despawn(self.id);
+        }
+    }
+
+    fn fire_hitscan(&mut self, world: &mut WorldInfo) {
+        let range = self.velocity.length() * self.lifetime;
+        if let Some((collider, distance)) = world.physics.cast_ray(
+            self.transform.position,
+            self.velocity,
+            range,
+            self.shooter_rigid_body,
+        ) {
+            info!("Hitscan hit {:?} at {:.1}m", collider, distance);
+            deliver_hit(world, collider, self.damage);
+        }
+        self.despawn(world);
+    }
+}
+
+impl Entity for ProjectileEntity {
+    fn set_id(&mut self, id: EntityId) {
+        self.id = id;
+    }
+
+    fn add_to_world(&mut self, world: &mut WorldInfo) {
+        if self.mode == ProjectileMode::Hitscan {
+            return;
+        }
+
+        if let Some((mesh, material)) = &self.model {
+            let transform = Transform {
+                scale: Vec3::splat(self.radius * 2.0),
+                ..self.transform.clone()
+            };
+            self.model_instance = world
+                .rendering
+                .create_instance(*mesh, *material, &transform);
+        }
+        let trail = world.rendering.create_trail(tracer_settings());
+        world.rendering.update_trail(trail, self.transform.position);
+        self.trail_instance = Some(trail);
+
+        let rigid_body = world.physics.create_rigid_body(
+            self.transform.position,
+            self.transform.rotation,
+            RigidBodyType::Dynamic,
+        );
+        world.physics.set_ccd_enabled(rigid_body, true);
+        world
+            .physics
+            .set_rigid_body_linear_velocity(rigid_body, self.velocity);
+        self.rigid_body_instance = Some(rigid_body);
+        self.collider_instance = world.physics.create_collider(
+            rigid_body,
+            &Transform::default(),
+            &ColliderShape::Sphere(self.radius),
+            ColliderMass::Density(PROJECTILE_DENSITY),
+            false,
+            self.id,
+        );
+    }
+
+    fn remove_from_world(&mut self, world: &mut WorldInfo) {
+        if let Some(model) = self.model_instance.take() {
+            world.rendering.remove_instance(model);
+        }
+        if let Some(trail) = self.trail_instance.take() {
+            world.rendering.remove_trail(trail);
+        }
+
+        if let Some(collider) = self.collider_instance.take() {
+            world.physics.remove_collider(collider);
+        }
+
+        if let Some(rigid_body) = self.rigid_body_instance.take() {
+            world.physics.remove_rigid_body(rigid_body);
+        }
+    }
+
+    fn update(&mut self, world: &mut WorldInfo, delta_time: f32) {
+        if self.spent {
+            return;
+        }
+
+        if self.mode == ProjectileMode::Hitscan {
+            self.fire_hitscan(world);
+            return;
+        }
+
+        if let Some(rigid_body) = self.rigid_body_instance {
+            let (position, rotation) = world.physics.get_rigid_body_transform(rigid_body);
+            self.transform.position = position;
+            self.transform.rotation = rotation;
+        }
+
+        if let Some(model) = self.model_instance {
+            let transform = Transform {
+                scale: Vec3::splat(self.radius * 2.0),
+                ..self.transform.clone()
+            };
+            world.rendering.update_instance(model, &transform);
+        }
+        if let Some(trail) = self.trail_instance {
+            world.rendering.update_trail(trail, self.transform.position);
+        }
+
+        self.lifetime -= delta_time;
+        if self.lifetime <= 0.0 {
+            self.despawn(world);
+        }
+    }
+
+    fn update_player_input(&mut self, _linear_input: Vec3, _angular_input: Vec3) {}
+
+    fn get_camera_transform(&self) -> Option<Transform> {
+        None
+    }
+
+    fn get_rigid_body(&self) -> Option<RigidBodyHandle> {
+        self.rigid_body_instance
+    }
+
+    fn get_world_transform(&self) -> Option<Transform> {
+        Some(self.transform.clone())
+    }
+
+    fn shift_origin(&mut self, offset: Vec3, _world: &mut WorldInfo) {
+        self.transform.position += offset;
+    }
+
+    fn on_collision(&mut self, other: EntityId, event: CollisionEvent, world: &mut WorldInfo) {
+        if self.spent || Some(other) == self.shooter {
+            return;
+        }
+
+        if let CollisionEvent::Started {
+            other_collider,
+            sensor: false,
+            ..
+        } = event
+        {
+            deliver_hit(world, other_collider, self.damage);
+            self.despawn(world);
+        }
+    }
+}
@@ -1,16 +1,51 @@
+mod asset_registry;
+mod bitmap_font;
+mod bloom;
+mod debug_lines;
+mod frame;
+mod gpu_timer;
+mod hud;
+mod lod;
+mod particles;
+mod skybox;
+mod trails;
+mod world_labels;
+
 use bytemuck::{Pod, Zeroable};
 
 use crate::transform::Transform;
+use glam::Vec3;
 
-use log::error;
+use log::{error, warn};
 use slotmap::SlotMap;
 use std::borrow::Cow;
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use std::fmt::Debug;
 use std::ops::Range;
-use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, AtomicU32, Ordering};
+use std::sync::{Arc, Mutex};
 use wgpu::util::DeviceExt;
 
+pub use asset_registry::AssetRegistry;
+use bloom::Bloom;
+pub use bloom::BloomSettings;
+use debug_lines::DebugLinePass;
+pub use debug_lines::DebugLines;
+pub use frame::FrameContext;
+use frame::{FrameResources, Pass, PassContext, FRAME_PASSES};
+use gpu_timer::GpuTimer;
+pub use hud::Hud;
+use hud::HudPass;
+pub use lod::{cluster_vertices, LodLevel, MAX_LOD_LEVELS};
+pub use particles::{EmitterHandle, EmitterSettings, ParticleCurve};
+use particles::{ParticlePass, ParticleSystem};
+use skybox::Skybox;
+pub use skybox::SkyboxSource;
+pub use trails::{TrailHandle, TrailSettings};
+use trails::{TrailPass, TrailSystem};
+pub use world_labels::WorldLabelSettings;
+use world_labels::{WorldLabelPass, WorldLabels};
+
 #[repr(C)]
 #[derive(Pod, Zeroable, Copy, Clone, Debug)]
 pub struct SceneData {
@@ -18,6 +53,57 @@ pub struct SceneData {
     pub(crate) ambient_light_color: [f32; 4],
     pub(crate) sun_light_direction_intensity: [f32; 4],
     pub(crate) sun_light_color: [f32; 4],
+    /// w is unused, also used on the cpu to sort transparent instances
+    pub(crate) camera_position: [f32; 4],
+}
+
+/// Maximum number of point/spot lights evaluated per scene, matches the array length in the shader
+pub const MAX_PUNCTUAL_LIGHTS: usize = 32;
+
+#[derive(Clone, Debug)]
+pub struct PunctualLight {
+    pub position: glam::Vec3,
+    /// Direction the light points, only used by spot lights
+    pub direction: glam::Vec3,
+    pub color: [f32; 3],
+    pub intensity: f32,
+    /// Distance at which the light has faded out completely
+    pub radius: f32,
+    /// Inner and outer cone angles in radians, None for a point light
+    pub cone_angles: Option<[f32; 2]>,
+}
+
+impl PunctualLight {
+    fn as_light_data(&self) -> PunctualLightData {
+        let (spot, cone_cos) = match self.cone_angles {
+            Some([inner, outer]) => (1.0, [inner.cos(), outer.cos(), 0.0, 0.0]),
+            None => (0.0, [0.0; 4]),
+        };
+        let direction = self.direction.normalize_or_zero();
+
+        PunctualLightData {
+            position_radius: self.position.extend(self.radius).to_array(),
+            color_intensity: [self.color[0], self.color[1], self.color[2], self.intensity],
+            direction_spot: direction.extend(spot).to_array(),
+            cone_cos,
+        }
+    }
+}
+
+#[repr(C)]
+#[derive(Pod, Zeroable, Copy, Clone, Debug)]
+struct PunctualLightData {
+    position_radius: [f32; 4],
+    color_intensity: [f32; 4],
+    direction_spot: [f32; 4],
+    cone_cos: [f32; 4],
+}
+
+#[repr(C)]
+#[derive(Pod, Zeroable, Copy, Clone, Debug)]
+struct LightData {
+    count: [u32; 4],
+    lights: [PunctualLightData; MAX_PUNCTUAL_LIGHTS],
 }
 
 #[repr(C)]
@@ -26,6 +112,8 @@ pub struct Vertex {
     position: [f32; 3],
     normal: [f32; 3],
     uv: [f32; 2],
+    /// xyz is the tangent direction, w is the bitangent sign
+    tangent: [f32; 4],
 }
 
 impl Vertex {
@@ -34,48 +122,191 @@ impl Vertex {
             position,
             normal,
             uv,
+            tangent: [1.0, 0.0, 0.0, 1.0],
         }
     }
 }
 
 impl Vertex {
+    /// Attributes in field order, offsets are packed so they must match the #[repr(C)] layout
+    const ATTRIBUTES: [wgpu::VertexAttribute; 4] = wgpu::vertex_attr_array![
+        0 => Float32x3,
+        1 => Float32x3,
+        2 => Float32x2,
+        3 => Float32x4,
+    ];
+
     fn desc<'a>() -> wgpu::VertexBufferLayout<'a> {
         wgpu::VertexBufferLayout {
             array_stride: std::mem::size_of::<Vertex>() as wgpu::BufferAddress,
             step_mode: wgpu::VertexStepMode::Vertex,
-            attributes: &[
-                wgpu::VertexAttribute {
-                    format: wgpu::VertexFormat::Float32x3,
-                    offset: 0,
-                    shader_location: 0,
-                },
-                wgpu::VertexAttribute {
-                    format: wgpu::VertexFormat::Float32x3,
-                    offset: std::mem::size_of::<[f32; 3]>() as wgpu::BufferAddress,
-                    shader_location: 1,
-                },
-                wgpu::VertexAttribute {
-                    format: wgpu::VertexFormat::Float32x3,
-                    offset: std::mem::size_of::<[f32; 3]>() as wgpu::BufferAddress * 2,
-                    shader_location: 2,
-                },
-            ],
+            attributes: &Self::ATTRIBUTES,
+        }
+    }
+}
+
+//Each attribute must start where the matching field does and the last one must end at the stride
+const _: () = {
+    let field_offsets = [
+        std::mem::offset_of!(Vertex, position),
+        std::mem::offset_of!(Vertex, normal),
+        std::mem::offset_of!(Vertex, uv),
+        std::mem::offset_of!(Vertex, tangent),
+    ];
+
+    let mut i = 0;
+    while i < Vertex::ATTRIBUTES.len() {
+        assert!(Vertex::ATTRIBUTES[i].offset == field_offsets[i] as u64);
+        i += 1;
+    }
+
+    let last = &Vertex::ATTRIBUTES[Vertex::ATTRIBUTES.len() - 1];
+    assert!(last.offset + last.format.size() == std::mem::size_of::<Vertex>() as u64);
+};
+
+/// On disk form of a pbr material, textures are referenced by path
+#[derive(serde::Deserialize)]
+struct PbrMaterialFile {
+    color: [f32; 4],
+    metallic: f32,
+    roughness: f32,
+    #[serde(default)]
+    albedo_texture: Option<String>,
+    #[serde(default)]
+    normal_texture: Option<String>,
+    #[serde(default)]
+    emissive: [f32; 3],
+    #[serde(default)]
+    transparent: bool,
+    #[serde(default)]
+    shading_model: ShadingModel,
+    /// Double sided unless set
+    #[serde(default)]
+    cull_mode: Option<CullFace>,
+}
+
+/// `wgpu::Face` for material files
+#[derive(Clone, Copy, Debug, serde::Deserialize)]
+enum CullFace {
+    Front,
+    Back,
+}
+
+impl From<CullFace> for wgpu::Face {
+    fn from(face: CullFace) -> Self {
+        match face {
+            CullFace::Front => wgpu::Face::Front,
+            CullFace::Back => wgpu::Face::Back,
         }
     }
 }
 
+/// How the pbr shader lights a material, packed into the material uniform next to metallic and roughness
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq, serde::Deserialize)]
+pub enum ShadingModel {
+    #[default]
+    Lit,
+    /// Color and textures without any lighting, the emissive term isn't added
+    Unlit,
+    /// Color at full brightness plus the emissive term regardless of sun, ambient or lights.
+    /// For running lights and windows, a strong emissive term pushes it past the bloom threshold
+    Emissive,
+}
+
+#[derive(Clone, Copy)]
 pub struct PbrMaterialDefinition {
     pub color: [f32; 4],
     pub metallic: f32,
     pub roughness: f32,
+    /// Multiplied with color, untextured materials use a 1x1 white texture
+    pub albedo_texture: Option<TextureHandle>,
+    /// Tangent space normal map, should be created with srgb disabled
+    pub normal_texture: Option<TextureHandle>,
+    /// Added after lighting, values above the bloom threshold will glow
+    pub emissive: [f32; 3],
+    /// Blends using the alpha of color, drawn after all opaque geometry sorted back to front
+    pub transparent: bool,
+    pub shading_model: ShadingModel,
+    /// Faces that aren't drawn, None for double sided materials like interior walls and thin panels.
+    /// Which side is the front comes from the renderer's front face winding
+    pub cull_mode: Option<wgpu::Face>,
+}
+
+/// Flat color that ignores all lighting, for markers and gizmos
+#[derive(Clone, Copy)]
+pub struct UnlitMaterialDefinition {
+    pub color: [f32; 4],
+}
+
+const PBR_MATERIAL_STATIC_MESH_SHADER: &str = include_str!("shader/pbr_material_static_mesh.wgsl");
+const UNLIT_MATERIAL_STATIC_MESH_SHADER: &str =
+    include_str!("shader/unlit_material_static_mesh.wgsl");
+
+/// Every cull mode a material can have, `cull_mode_index` gives the position of one
+const CULL_MODES: [Option<wgpu::Face>; 3] = [None, Some(wgpu::Face::Back), Some(wgpu::Face::Front)];
+
+fn cull_mode_index(cull_mode: Option<wgpu::Face>) -> usize {
+    match cull_mode {
+        None => 0,
+        Some(wgpu::Face::Back) => 1,
+        Some(wgpu::Face::Front) => 2,
+    }
+}
+
+/// One variant of a static mesh pipeline for each of `CULL_MODES`
+struct CullModePipelines([wgpu::RenderPipeline; 3]);
+
+impl CullModePipelines {
+    fn new(
+        device: &Arc<wgpu::Device>,
+        pipeline_layout: &wgpu::PipelineLayout,
+        code: &str,
+        key: StaticMeshPipelineKey,
+        front_face: wgpu::FrontFace,
+    ) -> Self {
+        Self(CULL_MODES.map(|cull_mode| {
+            create_static_mesh_pipeline(
+                device,
+                pipeline_layout,
+                code,
+                key,
+                wgpu::PrimitiveState {
+                    front_face,
+                    cull_mode,
+                    ..Default::default()
+                },
+            )
+        }))
+    }
+
+    fn get(&self, cull_mode: Option<wgpu::Face>) -> &wgpu::RenderPipeline {
+        &self.0[cull_mode_index(cull_mode)]
+    }
+}
+
+/// The targets a static mesh pipeline renders into, shared by every pipeline of a pass
+#[derive(Clone, Copy)]
+struct StaticMeshPipelineKey {
+    color_format: wgpu::TextureFormat,
+    depth_stencil_format: Option<wgpu::TextureFormat>,
+    sample_count: u32,
+    /// None for opaque geometry, which also writes depth
+    blend: Option<wgpu::BlendState>,
 }
 
-fn create_pbr_material_static_mesh_pipeline(
+fn create_static_mesh_pipeline(
     device: &Arc<wgpu::Device>,
     pipeline_layout: &wgpu::PipelineLayout,
-    depth_stencil_format: Option<wgpu::TextureFormat>,
+    code: &str,
+    key: StaticMeshPipelineKey,
+    primitive: wgpu::PrimitiveState,
 ) -> wgpu::RenderPipeline {
-    let code = include_str!("shader/pbr_material_static_mesh.wgsl");
+    let StaticMeshPipelineKey {
+        color_format,
+        depth_stencil_format,
+        sample_count,
+        blend,
+    } = key;
     let shader_module = device.create_shader_module(wgpu::ShaderModuleDescriptor {
         label: None,
         source: wgpu::ShaderSource::Wgsl(Cow::from(code)),
@@ -88,21 +319,29 @@ fn create_pbr_material_static_mesh_pipeline(
             entry_point: "vs_main",
             buffers: &[Vertex::desc()],
         },
-        primitive: Default::default(),
+        primitive,
         depth_stencil: depth_stencil_format.map(|format| wgpu::DepthStencilState {
             format,
-            depth_write_enabled: true,
+            //Blended geometry is still depth tested but shouldn't hide what's behind it
+            depth_write_enabled: blend.is_none(),
             depth_compare: wgpu::CompareFunction::Greater,
             stencil: Default::default(),
             bias: Default::default(),
         }),
-        multisample: Default::default(),
+        multisample: wgpu::MultisampleState {
+            count: sample_count,
+            ..Default::default()
+        },
         fragment: Some(wgpu::FragmentState {
             module: &shader_module,
-            entry_point: "fs_main",
+            entry_point: if needs_srgb_encode(color_format) {
+                "fs_main_srgb_encode"
+            } else {
+                "fs_main"
+            },
             targets: &[Some(wgpu::ColorTargetState {
-                format: wgpu::TextureFormat::Bgra8Unorm,
-                blend: None,
+                format: color_format,
+                blend,
                 write_mask: wgpu::ColorWrites::COLOR,
             })],
         }),
@@ -110,38 +349,431 @@ fn create_pbr_material_static_mesh_pipeline(
     })
 }
 
+fn load_obj_models<P: AsRef<std::path::Path> + Debug>(path: P) -> Option<Vec<tobj::Model>> {
+    const LOAD_OPTIONS: tobj::LoadOptions = tobj::LoadOptions {
+        single_index: true,
+        triangulate: true,
+        ignore_points: true,
+        ignore_lines: true,
+    };
+
+    let (models, _materials) = match tobj::load_obj(&path, &LOAD_OPTIONS) {
+        Ok(values) => values,
+        Err(e) => {
+            error!("Failed to load obj file: {}", e);
+            return None;
+        }
+    };
+
+    if models.is_empty() {
+        error!("Obj file {:?} contains no models", path);
+        return None;
+    }
+
+    Some(models)
+}
+
+/// Loads every model in the obj file merged into one set of vertices and indices
+fn load_obj_merged<P: AsRef<std::path::Path> + Debug>(path: P) -> Option<(Vec<Vertex>, Vec<u32>)> {
+    let models = load_obj_models(path)?;
+
+    let mut vertices = Vec::new();
+    let mut indices = Vec::new();
+    for model in models.iter() {
+        let (model_vertices, model_indices) = obj_mesh_to_vertices(&model.mesh);
+        let index_offset = vertices.len() as u32;
+        vertices.extend(model_vertices);
+        indices.extend(model_indices.iter().map(|index| index + index_offset));
+    }
+    Some((vertices, indices))
+}
+
+/// Converts an obj mesh into vertices and indices
+fn obj_mesh_to_vertices(mesh: &tobj::Mesh) -> (Vec<Vertex>, Vec<u32>) {
+    let positions: Vec<[f32; 3]> = mesh
+        .positions
+        .chunks_exact(3)
+        .map(|p| [p[0], p[1], p[2]])
+        .collect();
+    let normals: Vec<[f32; 3]> = mesh
+        .normals
+        .chunks_exact(3)
+        .map(|n| [n[0], n[1], n[2]])
+        .collect();
+    let uvs: Vec<[f32; 2]> = mesh
+        .texcoords
+        .chunks_exact(2)
+        .map(|uv| [uv[0], uv[1]])
+        .collect();
+
+    build_vertices(&positions, &normals, &uvs, &mesh.indices)
+}
+
+/// Builds vertices from separate attribute streams, missing uvs are zeroed and
+/// missing normals are replaced with flat per-triangle normals
+fn build_vertices(
+    positions: &[[f32; 3]],
+    normals: &[[f32; 3]],
+    uvs: &[[f32; 2]],
+    indices: &[u32],
+) -> (Vec<Vertex>, Vec<u32>) {
+    let has_normals = normals.len() == positions.len();
+    let has_uvs = uvs.len() == positions.len();
+
+    let uv = |i: usize| -> [f32; 2] {
+        if has_uvs {
+            uvs[i]
+        } else {
+            [0.0; 2]
+        }
+    };
+
+    let (mut vertices, indices) = if has_normals {
+        let vertices = (0..positions.len())
+            .map(|i| Vertex::new(positions[i], normals[i], uv(i)))
+            .collect();
+        (vertices, indices.to_vec())
+    } else {
+        //Flat normals can't be shared between faces, so every triangle gets its own vertices
+        let mut vertices = Vec::with_capacity(indices.len());
+        for triangle in indices.chunks_exact(3) {
+            let corners = [0, 1, 2].map(|i| glam::Vec3::from(positions[triangle[i] as usize]));
+            let normal = (corners[1] - corners[0])
+                .cross(corners[2] - corners[0])
+                .normalize_or_zero();
+
+            for index in triangle {
+                vertices.push(Vertex::new(
+                    positions[*index as usize],
+                    normal.to_array(),
+                    uv(*index as usize),
+                ));
+            }
+        }
+        let indices = (0..vertices.len() as u32).collect();
+        (vertices, indices)
+    };
+
+    compute_tangents(&mut vertices, &indices);
+    (vertices, indices)
+}
+
+/// Accumulates per-triangle tangents onto each vertex, then orthogonalizes them against the normal
+fn compute_tangents(vertices: &mut [Vertex], indices: &[u32]) {
+    use glam::{Vec2, Vec3};
+
+    let mut tangents = vec![Vec3::ZERO; vertices.len()];
+    let mut bitangents = vec![Vec3::ZERO; vertices.len()];
+
+    for triangle in indices.chunks_exact(3) {
+        let [i0, i1, i2] = [0, 1, 2].map(|i| triangle[i] as usize);
+
+        let edge1 = Vec3::from(vertices[i1].position) - Vec3::from(vertices[i0].position);
+        let edge2 = Vec3::from(vertices[i2].position) - Vec3::from(vertices[i0].position);
+        let delta_uv1 = Vec2::from(vertices[i1].uv) - Vec2::from(vertices[i0].uv);
+        let delta_uv2 = Vec2::from(vertices[i2].uv) - Vec2::from(vertices[i0].uv);
+
+        let determinant = delta_uv1.x * delta_uv2.y - delta_uv2.x * delta_uv1.y;
+        if determinant.abs() <= f32::EPSILON {
+            continue;
+        }
+        let r = 1.0 / determinant;
+
+        let tangent = (edge1 * delta_uv2.y - edge2 * delta_uv1.y) * r;
+        let bitangent = (edge2 * delta_uv1.x - edge1 * delta_uv2.x) * r;
+
+        for i in [i0, i1, i2] {
+            tangents[i] += tangent;
+            bitangents[i] += bitangent;
+        }
+    }
+
+    for (i, vertex) in vertices.iter_mut().enumerate() {
+        let normal = Vec3::from(vertex.normal);
+        let mut tangent = (tangents[i] - normal * normal.dot(tangents[i])).normalize_or_zero();
+
+        //No usable uvs, so pick any direction perpendicular to the normal
+        if tangent == Vec3::ZERO {
+            tangent = normal.any_orthonormal_vector();
+        }
+
+        let handedness = if normal.cross(tangent).dot(bitangents[i]) < 0.0 {
+            -1.0
+        } else {
+            1.0
+        };
+
+        vertex.tangent = tangent.extend(handedness).to_array();
+    }
+}
+
+/// A single drawable part of a glTF mesh
+pub struct GltfPrimitive {
+    pub mesh: MeshHandle,
+    pub material: PbrMaterialDefinition,
+}
+
+/// All the primitives of one glTF mesh, which together make up a complete object
+pub struct GltfMesh {
+    pub name: Option<String>,
+    pub primitives: Vec<GltfPrimitive>,
+}
+
+/// Line mode is only available when the device was created with `Features::POLYGON_MODE_LINE`.
+/// Nothing is culled so the back of every mesh shows up too
+fn create_static_mesh_wireframe_pipeline(
+    device: &Arc<wgpu::Device>,
+    pipeline_layout: &wgpu::PipelineLayout,
+    code: &str,
+    key: StaticMeshPipelineKey,
+) -> Option<wgpu::RenderPipeline> {
+    if device
+        .features()
+        .contains(wgpu::Features::POLYGON_MODE_LINE)
+    {
+        Some(create_static_mesh_pipeline(
+            device,
+            pipeline_layout,
+            code,
+            key,
+            wgpu::PrimitiveState {
+                polygon_mode: wgpu::PolygonMode::Line,
+                ..Default::default()
+            },
+        ))
+    } else {
+        None
+    }
+}
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum DrawMode {
+    Filled,
+    Wireframe,
+}
+
+#[derive(Clone, Copy, Debug, Default)]
+pub struct RenderStats {
+    pub draw_calls: u32,
+    pub instances_drawn: u32,
+    /// Instances past their draw distance or the last LOD level, not part of `instances_drawn`
+    pub instances_skipped: u32,
+    /// Non-empty instance sets, each one is at least one draw call
+    pub instance_sets: u32,
+    pub triangles: u64,
+    /// Instance buffer uploads this frame, consecutive changed instances share one
+    pub instance_writes: u32,
+    /// Cpu time spent uploading the instances written since the last frame
+    pub instance_flush_ms: f32,
+    /// Cpu time from beginning the frame to submitting its commands
+    pub encode_cpu_ms: f32,
+    /// Only available with TIMESTAMP_QUERY, this is from an earlier frame since readback is asynchronous
+    pub gpu_ms: Option<f32>,
+    /// Instances drawn with each LOD level, meshes without LODs count as level 0
+    pub instances_per_lod: [u32; MAX_LOD_LEVELS],
+}
+
+impl RenderStats {
+    fn add_mesh_draw(&mut self, mesh: &Mesh, level: usize, instances: u32) {
+        self.draw_calls += 1;
+        self.instances_drawn += instances;
+        self.instances_per_lod[level] += instances;
+        self.triangles += (mesh.levels[level].index_count / 3) as u64 * instances as u64;
+    }
+}
+
+const DEPTH_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Depth24Plus;
+
+/// Scene color format while bloom is enabled, composited into the output afterwards
+const HDR_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Rgba16Float;
+
+/// Output format of renderers without a surface, also what `render_to_texture` reads back
+pub const HEADLESS_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Rgba8UnormSrgb;
+
+/// Falls back to a software adapter when there is no hardware one, like on most CI machines
+pub fn request_adapter(
+    instance: &wgpu::Instance,
+    compatible_surface: Option<&wgpu::Surface>,
+    power_preference: wgpu::PowerPreference,
+) -> Option<wgpu::Adapter> {
+    let request = |force_fallback_adapter| {
+        pollster::block_on(instance.request_adapter(&wgpu::RequestAdapterOptions {
+            power_preference,
+            compatible_surface,
+            force_fallback_adapter,
+        }))
+    };
+
+    request(false).or_else(|| {
+        warn!("No hardware adapter found, trying a fallback adapter");
+        request(true)
+    })
+}
+
+/// Requests the optional features the renderer can use, panics if the adapter can't run the renderer at all
+pub fn request_device(adapter: &wgpu::Adapter) -> (wgpu::Device, wgpu::Queue) {
+    let info: wgpu::AdapterInfo = adapter.get_info();
+
+    //Instance transforms are read from storage buffers in the vertex shader
+    if !adapter
+        .get_downlevel_capabilities()
+        .flags
+        .contains(wgpu::DownlevelFlags::VERTEX_STORAGE)
+    {
+        panic!(
+            "Adapter {} doesn't support storage buffers in vertex shaders",
+            info.name
+        );
+    }
+
+    pollster::block_on(adapter.request_device(
+        &wgpu::DeviceDescriptor {
+            label: None,
+            //Optional features, only requested when the adapter has them
+            features: adapter.features()
+                & (wgpu::Features::TEXTURE_ADAPTER_SPECIFIC_FORMAT_FEATURES
+                    | wgpu::Features::POLYGON_MODE_LINE
+                    | wgpu::Features::TIMESTAMP_QUERY),
+            limits: wgpu::Limits::default(),
+        },
+        None,
+    ))
+    .unwrap()
+}
+
+/// Uncaptured errors on every device since startup, headless runs fail if there were any
+static GPU_ERROR_COUNT: AtomicU32 = AtomicU32::new(0);
+
+pub fn gpu_error_count() -> u32 {
+    GPU_ERROR_COUNT.load(Ordering::Relaxed)
+}
+
+/// Uncaptured errors are logged and counted instead of panicking, the returned flag is set once one of them says the device is lost
+pub fn watch_device_errors(device: &wgpu::Device) -> Arc<AtomicBool> {
+    let lost = Arc::new(AtomicBool::new(false));
+    let flag = lost.clone();
+    device.on_uncaptured_error(Box::new(move |e: wgpu::Error| {
+        error!("Uncaptured gpu error: {}", e);
+        GPU_ERROR_COUNT.fetch_add(1, Ordering::Relaxed);
+        //wgpu 0.15 has no device lost callback, a lost device shows up as errors on everything that uses it
+        let device_lost = match &e {
+            wgpu::Error::OutOfMemory { .. } => true,
+            wgpu::Error::Validation { description, .. } => {
+                description.to_lowercase().contains("lost")
+            }
+        };
+        if device_lost {
+            flag.store(true, Ordering::Relaxed);
+        }
+    }));
+    lost
+}
+
+/// A headless renderer for tests, None when there is no adapter at all so gpu tests can skip.
+/// Any gpu error panics so it fails the test that caused it
+#[cfg(test)]
+pub(crate) fn test_renderer() -> Option<Renderer> {
+    let instance = wgpu::Instance::new(wgpu::InstanceDescriptor::default());
+    let adapter = request_adapter(&instance, None, wgpu::PowerPreference::default())?;
+    let (device, queue) = request_device(&adapter);
+    device.on_uncaptured_error(Box::new(|e: wgpu::Error| panic!("Gpu error: {}", e)));
+    Some(Renderer::new(
+        Arc::new(adapter),
+        Arc::new(device),
+        Arc::new(queue),
+        HEADLESS_FORMAT,
+    ))
+}
+
+/// Linear unorm targets don't convert on write, so the shader has to encode to srgb itself
+fn needs_srgb_encode(color_format: wgpu::TextureFormat) -> bool {
+    !color_format.describe().srgb && color_format != HDR_FORMAT
+}
+
 pub struct Renderer {
+    adapter: Arc<wgpu::Adapter>,
     device: Arc<wgpu::Device>,
     queue: Arc<wgpu::Queue>,
+    output_format: wgpu::TextureFormat,
+    sample_count: u32,
 
-    scene_bind_group_layout: Arc<wgpu::BindGroupLayout>,
     instance_set_bind_group_layout: Arc<wgpu::BindGroupLayout>,
     material_bind_group_layout: Arc<wgpu::BindGroupLayout>,
+    unlit_material_bind_group_layout: wgpu::BindGroupLayout,
 
     pbr_material_pipeline_layout: wgpu::PipelineLayout,
-    pbr_material_static_mesh_pipeline: wgpu::RenderPipeline,
-
-    scene_data: (wgpu::Buffer, wgpu::BindGroup),
+    pbr_material_static_mesh_pipelines: CullModePipelines,
+    pbr_material_static_mesh_wireframe_pipeline: Option<wgpu::RenderPipeline>,
+    pbr_material_static_mesh_transparent_pipelines: CullModePipelines,
+    unlit_material_pipeline_layout: wgpu::PipelineLayout,
+    /// Unlit materials are always double sided
+    unlit_material_static_mesh_pipeline: wgpu::RenderPipeline,
+    unlit_material_static_mesh_wireframe_pipeline: Option<wgpu::RenderPipeline>,
+    draw_mode: DrawMode,
+    /// Winding on screen of the front faces of every static mesh, some obj exports come in flipped
+    front_face: wgpu::FrontFace,
+
+    frame: FrameResources,
+    skybox: Option<Skybox>,
+    /// Kept to create the skybox again if the device is lost
+    skybox_source: Option<SkyboxSource>,
+    debug_lines: DebugLines,
+    debug_line_pass: DebugLinePass,
+    particle_pass: ParticlePass,
+    trail_pass: TrailPass,
+    hud: Hud,
+    hud_pass: HudPass,
+    world_labels: WorldLabels,
+    world_label_pass: WorldLabelPass,
+    bloom: Bloom,
+    gpu_timer: Option<GpuTimer>,
+
+    linear_sampler: wgpu::Sampler,
+    white_texture: TextureHandle,
+    flat_normal_texture: TextureHandle,
 
     meshes: SlotMap<MeshHandle, Mesh>,
     materials: SlotMap<MaterialHandle, Material>,
+    textures: SlotMap<TextureHandle, Texture>,
+
+    asset_registry: AssetRegistry,
+
+    resource_usage: Arc<Mutex<ResourceUsage>>,
+    invalid_instance_types: HashSet<InstanceType>,
 }
 
 impl Renderer {
-    pub fn new(device: Arc<wgpu::Device>, queue: Arc<wgpu::Queue>) -> Self {
+    pub fn new(
+        adapter: Arc<wgpu::Adapter>,
+        device: Arc<wgpu::Device>,
+        queue: Arc<wgpu::Queue>,
+        output_format: wgpu::TextureFormat,
+    ) -> Self {
         let scene_bind_group_layout = Arc::new(device.create_bind_group_layout(
             &wgpu::BindGroupLayoutDescriptor {
                 label: None,
-                entries: &[wgpu::BindGroupLayoutEntry {
-                    binding: 0,
-                    visibility: wgpu::ShaderStages::VERTEX_FRAGMENT,
-                    ty: wgpu::BindingType::Buffer {
-                        ty: wgpu::BufferBindingType::Uniform,
-                        min_binding_size: None,
-                        has_dynamic_offset: false,
+                entries: &[
+                    wgpu::BindGroupLayoutEntry {
+                        binding: 0,
+                        visibility: wgpu::ShaderStages::VERTEX_FRAGMENT,
+                        ty: wgpu::BindingType::Buffer {
+                            ty: wgpu::BufferBindingType::Uniform,
+                            min_binding_size: None,
+                            has_dynamic_offset: false,
+                        },
+                        count: None,
                     },
-                    count: None,
-                }],
+                    wgpu::BindGroupLayoutEntry {
+                        binding: 1,
+                        visibility: wgpu::ShaderStages::FRAGMENT,
+                        ty: wgpu::BindingType::Buffer {
+                            ty: wgpu::BufferBindingType::Uniform,
+                            min_binding_size: None,
+                            has_dynamic_offset: false,
+                        },
+                        count: None,
+                    },
+                ],
             },
         ));
 
@@ -150,9 +782,9 @@ impl Renderer {
                 label: None,
                 entries: &[wgpu::BindGroupLayoutEntry {
                     binding: 0,
-                    visibility: wgpu::ShaderStages::VERTEX_FRAGMENT,
+                    visibility: wgpu::ShaderStages::VERTEX,
                     ty: wgpu::BindingType::Buffer {
-                        ty: wgpu::BufferBindingType::Uniform,
+                        ty: wgpu::BufferBindingType::Storage { read_only: true },
                         min_binding_size: None,
                         has_dynamic_offset: false,
                     },
@@ -163,6 +795,50 @@ impl Renderer {
 
         let material_bind_group_layout = Arc::new(device.create_bind_group_layout(
             &wgpu::BindGroupLayoutDescriptor {
+                label: None,
+                entries: &[
+                    wgpu::BindGroupLayoutEntry {
+                        binding: 0,
+                        visibility: wgpu::ShaderStages::FRAGMENT,
+                        ty: wgpu::BindingType::Buffer {
+                            ty: wgpu::BufferBindingType::Uniform,
+                            min_binding_size: None,
+                            has_dynamic_offset: false,
+                        },
+                        count: None,
+                    },
+                    wgpu::BindGroupLayoutEntry {
+                        binding: 1,
+                        visibility: wgpu::ShaderStages::FRAGMENT,
+                        ty: wgpu::BindingType::Texture {
+                            sample_type: wgpu::TextureSampleType::Float { filterable: true },
+                            view_dimension: wgpu::TextureViewDimension::D2,
+                            multisampled: false,
+                        },
+                        count: None,
+                    },
+                    wgpu::BindGroupLayoutEntry {
+                        binding: 2,
+                        visibility: wgpu::ShaderStages::FRAGMENT,
+                        ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+                        count: None,
+                    },
+                    wgpu::BindGroupLayoutEntry {
+                        binding: 3,
+                        visibility: wgpu::ShaderStages::FRAGMENT,
+                        ty: wgpu::BindingType::Texture {
+                            sample_type: wgpu::TextureSampleType::Float { filterable: true },
+                            view_dimension: wgpu::TextureViewDimension::D2,
+                            multisampled: false,
+                        },
+                        count: None,
+                    },
+                ],
+            },
+        ));
+
+        let unlit_material_bind_group_layout =
+            device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
                 label: None,
                 entries: &[wgpu::BindGroupLayoutEntry {
                     binding: 0,
@@ -174,8 +850,7 @@ impl Renderer {
                     },
                     count: None,
                 }],
-            },
-        ));
+            });
 
         let pbr_material_pipeline_layout =
             device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
@@ -188,180 +863,1342 @@ impl Renderer {
                 push_constant_ranges: &[],
             });
 
-        let pbr_material_static_mesh_pipeline = create_pbr_material_static_mesh_pipeline(
+        let unlit_material_pipeline_layout =
+            device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+                label: None,
+                bind_group_layouts: &[
+                    &scene_bind_group_layout,
+                    &instance_set_bind_group_layout,
+                    &unlit_material_bind_group_layout,
+                ],
+                push_constant_ranges: &[],
+            });
+
+        let bloom = Bloom::new(&device, BloomSettings::default(), output_format);
+        let scene_color_format = if bloom.settings().enabled {
+            HDR_FORMAT
+        } else {
+            output_format
+        };
+
+        let sample_count = 1;
+        //Meshes are wound counter clockwise in a right handed space, the cameras are left handed so that ends up clockwise on screen
+        let front_face = wgpu::FrontFace::Cw;
+        let opaque_key = StaticMeshPipelineKey {
+            color_format: scene_color_format,
+            depth_stencil_format: Some(DEPTH_FORMAT),
+            sample_count,
+            blend: None,
+        };
+        let transparent_key = StaticMeshPipelineKey {
+            blend: Some(wgpu::BlendState::ALPHA_BLENDING),
+            ..opaque_key
+        };
+        let pbr_material_static_mesh_pipelines = CullModePipelines::new(
+            &device,
+            &pbr_material_pipeline_layout,
+            PBR_MATERIAL_STATIC_MESH_SHADER,
+            opaque_key,
+            front_face,
+        );
+        let pbr_material_static_mesh_wireframe_pipeline = create_static_mesh_wireframe_pipeline(
+            &device,
+            &pbr_material_pipeline_layout,
+            PBR_MATERIAL_STATIC_MESH_SHADER,
+            opaque_key,
+        );
+        let pbr_material_static_mesh_transparent_pipelines = CullModePipelines::new(
             &device,
             &pbr_material_pipeline_layout,
-            Some(wgpu::TextureFormat::Depth24Plus),
+            PBR_MATERIAL_STATIC_MESH_SHADER,
+            transparent_key,
+            front_face,
+        );
+        let unlit_material_static_mesh_pipeline = create_static_mesh_pipeline(
+            &device,
+            &unlit_material_pipeline_layout,
+            UNLIT_MATERIAL_STATIC_MESH_SHADER,
+            opaque_key,
+            wgpu::PrimitiveState {
+                front_face,
+                ..Default::default()
+            },
+        );
+        let unlit_material_static_mesh_wireframe_pipeline = create_static_mesh_wireframe_pipeline(
+            &device,
+            &unlit_material_pipeline_layout,
+            UNLIT_MATERIAL_STATIC_MESH_SHADER,
+            opaque_key,
         );
 
-        let scene_data = {
-            let scene_data = SceneData {
-                view_projection_matrix: [0.0; 16],
-                ambient_light_color: [0.0; 4],
-                sun_light_direction_intensity: [0.0; 4],
-                sun_light_color: [0.0; 4],
-            };
+        let frame = FrameResources::new(&device, &scene_bind_group_layout);
+
+        let linear_sampler = device.create_sampler(&wgpu::SamplerDescriptor {
+            label: Some("Linear Sampler"),
+            address_mode_u: wgpu::AddressMode::Repeat,
+            address_mode_v: wgpu::AddressMode::Repeat,
+            address_mode_w: wgpu::AddressMode::Repeat,
+            mag_filter: wgpu::FilterMode::Linear,
+            min_filter: wgpu::FilterMode::Linear,
+            mipmap_filter: wgpu::FilterMode::Linear,
+            ..Default::default()
+        });
 
-            let scene_data_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
-                label: None,
-                contents: bytemuck::cast_slice(&[scene_data]),
-                usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
-            });
+        let debug_line_pass = DebugLinePass::new(
+            &device,
+            &scene_bind_group_layout,
+            scene_color_format,
+            DEPTH_FORMAT,
+            sample_count,
+        );
 
-            let scene_data_bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
-                label: None,
-                layout: &scene_bind_group_layout,
-                entries: &[wgpu::BindGroupEntry {
-                    binding: 0,
-                    resource: wgpu::BindingResource::Buffer(
-                        scene_data_buffer.as_entire_buffer_binding(),
-                    ),
-                }],
-            });
+        let particle_pass = ParticlePass::new(
+            &device,
+            &scene_bind_group_layout,
+            scene_color_format,
+            DEPTH_FORMAT,
+            sample_count,
+        );
 
-            (scene_data_buffer, scene_data_bind_group)
-        };
+        let trail_pass = TrailPass::new(
+            &device,
+            &scene_bind_group_layout,
+            scene_color_format,
+            DEPTH_FORMAT,
+            sample_count,
+        );
 
-        Self {
+        let hud_pass = HudPass::new(&device, output_format);
+        let world_label_pass =
+            WorldLabelPass::new(&device, scene_color_format, DEPTH_FORMAT, sample_count);
+
+        let gpu_timer = GpuTimer::new(&device, &queue);
+
+        let mut renderer = Self {
+            adapter,
             device,
             queue,
-            scene_bind_group_layout,
+            output_format,
+            sample_count,
             instance_set_bind_group_layout,
             material_bind_group_layout,
+            unlit_material_bind_group_layout,
             pbr_material_pipeline_layout,
-            pbr_material_static_mesh_pipeline,
-            scene_data,
+            pbr_material_static_mesh_pipelines,
+            pbr_material_static_mesh_wireframe_pipeline,
+            pbr_material_static_mesh_transparent_pipelines,
+            unlit_material_pipeline_layout,
+            unlit_material_static_mesh_pipeline,
+            unlit_material_static_mesh_wireframe_pipeline,
+            draw_mode: DrawMode::Filled,
+            front_face,
+            frame,
+            skybox: None,
+            skybox_source: None,
+            debug_lines: DebugLines::default(),
+            debug_line_pass,
+            particle_pass,
+            trail_pass,
+            hud: Hud::default(),
+            hud_pass,
+            world_labels: WorldLabels::default(),
+            world_label_pass,
+            bloom,
+            gpu_timer,
+            linear_sampler,
+            white_texture: TextureHandle::default(),
+            flat_normal_texture: TextureHandle::default(),
             meshes: SlotMap::with_key(),
             materials: SlotMap::with_key(),
-        }
-    }
+            textures: SlotMap::with_key(),
+            asset_registry: AssetRegistry::default(),
+            resource_usage: Arc::new(Mutex::new(ResourceUsage::default())),
+            invalid_instance_types: HashSet::new(),
+        };
 
-    pub fn create_scene(&self) -> SceneRenderData {
-        SceneRenderData::new(
-            self.device.clone(),
-            self.queue.clone(),
-            self.instance_set_bind_group_layout.clone(),
-        )
-    }
+        renderer.white_texture = renderer
+            .create_texture_from_rgba(1, 1, &[255; 4], true)
+            .unwrap();
+        renderer.flat_normal_texture = renderer
+            .create_texture_from_rgba(1, 1, &[128, 128, 255, 255], false)
+            .unwrap();
 
-    pub fn create_mesh(&mut self, vertices: &[Vertex], indices: &[u32]) -> Option<MeshHandle> {
-        Some(
-            self.meshes
-                .insert(Mesh::new(&self.device, vertices, indices)),
-        )
+        renderer
     }
 
-    pub fn create_material(&mut self, material: PbrMaterialDefinition) -> Option<MaterialHandle> {
-        let material_uniform_buffer =
-            self.device
-                .create_buffer_init(&wgpu::util::BufferInitDescriptor {
-                    label: None,
-                    contents: bytemuck::cast_slice(&[
-                        material.color[0],
-                        material.color[1],
-                        material.color[2],
-                        material.color[3],
-                        material.metallic,
-                        material.roughness,
-                        0.0,
-                        0.0,
-                    ]),
-                    usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
-                });
+    pub fn adapter(&self) -> Arc<wgpu::Adapter> {
+        self.adapter.clone()
+    }
 
-        let material_bind_group = self.device.create_bind_group(&wgpu::BindGroupDescriptor {
-            label: None,
-            layout: &self.material_bind_group_layout,
-            entries: &[wgpu::BindGroupEntry {
-                binding: 0,
-                resource: wgpu::BindingResource::Buffer(
-                    material_uniform_buffer.as_entire_buffer_binding(),
-                ),
-            }],
-        });
+    /// Creates every gpu object again on a new device after the old one was lost, handles stay valid.
+    /// The scenes have to follow with `recreate_scene`
+    pub fn recreate_device(&mut self, device: Arc<wgpu::Device>, queue: Arc<wgpu::Queue>) {
+        let mut renderer = Self::new(
+            self.adapter.clone(),
+            device.clone(),
+            queue.clone(),
+            self.output_format,
+        );
 
-        Some(self.materials.insert(Material {
-            material_uniform_buffer,
-            material_bind_group,
-        }))
-    }
+        //Cpu side state carries over as is
+        renderer.draw_mode = self.draw_mode;
+        renderer.debug_lines = std::mem::take(&mut self.debug_lines);
+        renderer.hud = std::mem::take(&mut self.hud);
+        renderer.world_labels = std::mem::take(&mut self.world_labels);
+        renderer.asset_registry = std::mem::take(&mut self.asset_registry);
+        renderer.resource_usage = self.resource_usage.clone();
+        renderer.invalid_instance_types = std::mem::take(&mut self.invalid_instance_types);
+
+        //Uploaded again in place so every handle keeps pointing at the same resource
+        renderer.textures = std::mem::take(&mut self.textures);
+        for texture in renderer.textures.values_mut() {
+            texture.recreate(&device, &queue);
+        }
+        renderer.white_texture = self.white_texture;
+        renderer.flat_normal_texture = self.flat_normal_texture;
 
-    pub fn load_mesh<P: AsRef<std::path::Path> + Debug>(&mut self, path: P) -> Option<MeshHandle> {
-        const LOAD_OPTIONS: tobj::LoadOptions = tobj::LoadOptions {
-            single_index: true,
-            triangulate: true,
-            ignore_points: true,
-            ignore_lines: true,
-        };
+        renderer.meshes = std::mem::take(&mut self.meshes);
+        for mesh in renderer.meshes.values_mut() {
+            mesh.recreate(&device);
+        }
 
-        let (models, _materials) = match tobj::load_obj(path, &LOAD_OPTIONS) {
-            Ok(values) => values,
-            Err(e) => {
-                error!("Failed to load obj file: {}", e);
-                return None;
+        let mut materials = std::mem::take(&mut self.materials);
+        for (handle, material) in materials.iter_mut() {
+            let recreated = renderer.create_material(material.source).or_else(|| {
+                //The texture was destroyed while the material still used it
+                warn!("Material {:?} lost a texture, using the defaults", handle);
+                let mut source = material.source;
+                if let MaterialSource::Pbr(definition) = &mut source {
+                    definition.albedo_texture = None;
+                    definition.normal_texture = None;
+                }
+                renderer.create_material(source)
+            });
+            if let Some(recreated) = recreated {
+                *material = recreated;
             }
-        };
+        }
+        renderer.materials = materials;
 
-        //TODO: support more then one model
-        let model = &models[0];
-        let mesh = &model.mesh;
+        renderer.set_bloom_settings(self.bloom.settings());
+        renderer.set_sample_count(self.sample_count);
+        if let Some(source) = self.skybox_source.take() {
+            renderer.set_skybox(source);
+        }
 
-        let mut vertices = Vec::with_capacity(model.mesh.positions.len());
+        *self = renderer;
+    }
 
-        for i in 0..(mesh.positions.len() / 3) {
-            let i2 = i * 2;
-            let i3 = i * 3;
+    /// Moves a scene created before `recreate_device` to the new device, its instances are uploaded again on the next frame
+    pub fn recreate_scene(&self, scene: &mut SceneRenderData) {
+        scene.device = self.device.clone();
+        scene.queue = self.queue.clone();
+        scene.instance_set_bind_group_layout = self.instance_set_bind_group_layout.clone();
+        for set in scene.instance_set_map.values_mut() {
+            set.recreate(
+                self.device.clone(),
+                self.queue.clone(),
+                self.instance_set_bind_group_layout.clone(),
+            );
+        }
+    }
 
-            vertices.push(Vertex::new(
-                [
-                    mesh.positions[i3],
-                    mesh.positions[i3 + 1],
-                    mesh.positions[i3 + 2],
-                ],
-                [mesh.normals[i3], mesh.normals[i3 + 1], mesh.normals[i3 + 2]],
-                [mesh.texcoords[i2], mesh.texcoords[i2 + 1]],
-            ))
+    /// Rebuilds the pipelines if the format of the render target changed
+    pub fn set_output_format(&mut self, output_format: wgpu::TextureFormat) {
+        if self.output_format == output_format {
+            return;
         }
 
-        self.create_mesh(&vertices, &model.mesh.indices)
+        self.output_format = output_format;
+        self.check_sample_count();
+        self.rebuild_pipelines();
     }
 
-    pub fn render_scene(
-        &mut self,
-        size: [u32; 2],
-        render_target: &wgpu::TextureView,
-        scene_data: &SceneData,
-        scene_render_data: &SceneRenderData,
+    /// Format the scene is drawn into before any post processing
+    fn scene_color_format(&self) -> wgpu::TextureFormat {
+        if self.bloom.settings().enabled {
+            HDR_FORMAT
+        } else {
+            self.output_format
+        }
+    }
+
+    /// A new scene format may not support the current sample count
+    fn check_sample_count(&mut self) {
+        if !self.supported_sample_counts().contains(&self.sample_count) {
+            warn!(
+                "{}x MSAA is not supported for {:?}, disabling MSAA",
+                self.sample_count,
+                self.scene_color_format()
+            );
+            self.sample_count = 1;
+        }
+    }
+
+    fn rebuild_pipelines(&mut self) {
+        let scene_color_format = self.scene_color_format();
+        let opaque_key = StaticMeshPipelineKey {
+            color_format: scene_color_format,
+            depth_stencil_format: Some(DEPTH_FORMAT),
+            sample_count: self.sample_count,
+            blend: None,
+        };
+        let transparent_key = StaticMeshPipelineKey {
+            blend: Some(wgpu::BlendState::ALPHA_BLENDING),
+            ..opaque_key
+        };
+        self.pbr_material_static_mesh_pipelines = CullModePipelines::new(
+            &self.device,
+            &self.pbr_material_pipeline_layout,
+            PBR_MATERIAL_STATIC_MESH_SHADER,
+            opaque_key,
+            self.front_face,
+        );
+        self.pbr_material_static_mesh_wireframe_pipeline = create_static_mesh_wireframe_pipeline(
+            &self.device,
+            &self.pbr_material_pipeline_layout,
+            PBR_MATERIAL_STATIC_MESH_SHADER,
+            opaque_key,
+        );
+        self.pbr_material_static_mesh_transparent_pipelines = CullModePipelines::new(
+            &self.device,
+            &self.pbr_material_pipeline_layout,
+            PBR_MATERIAL_STATIC_MESH_SHADER,
+            transparent_key,
+            self.front_face,
+        );
+        self.unlit_material_static_mesh_pipeline = create_static_mesh_pipeline(
+            &self.device,
+            &self.unlit_material_pipeline_layout,
+            UNLIT_MATERIAL_STATIC_MESH_SHADER,
+            opaque_key,
+            wgpu::PrimitiveState {
+                front_face: self.front_face,
+                ..Default::default()
+            },
+        );
+        self.unlit_material_static_mesh_wireframe_pipeline = create_static_mesh_wireframe_pipeline(
+            &self.device,
+            &self.unlit_material_pipeline_layout,
+            UNLIT_MATERIAL_STATIC_MESH_SHADER,
+            opaque_key,
+        );
+        if let Some(skybox) = &mut self.skybox {
+            skybox.rebuild_pipeline(
+                &self.device,
+                scene_color_format,
+                DEPTH_FORMAT,
+                self.sample_count,
+            );
+        }
+        self.debug_line_pass.rebuild_pipelines(
+            &self.device,
+            scene_color_format,
+            DEPTH_FORMAT,
+            self.sample_count,
+        );
+        self.world_label_pass.rebuild_pipelines(
+            &self.device,
+            scene_color_format,
+            DEPTH_FORMAT,
+            self.sample_count,
+        );
+        self.particle_pass.rebuild_pipelines(
+            &self.device,
+            scene_color_format,
+            DEPTH_FORMAT,
+            self.sample_count,
+        );
+        self.trail_pass.rebuild_pipelines(
+            &self.device,
+            scene_color_format,
+            DEPTH_FORMAT,
+            self.sample_count,
+        );
+        self.bloom
+            .rebuild_pipelines(&self.device, self.output_format);
+        self.hud_pass
+            .rebuild_pipelines(&self.device, self.output_format);
+        self.frame.invalidate_targets();
+    }
+
+    pub fn get_bloom_settings(&self) -> BloomSettings {
+        self.bloom.settings()
+    }
+
+    /// Threshold and intensity apply next frame, toggling bloom rebuilds the pipelines for the new scene format
+    pub fn set_bloom_settings(&mut self, settings: BloomSettings) {
+        let format_changed = settings.enabled != self.bloom.settings().enabled;
+        self.bloom.set_settings(&self.queue, settings);
+
+        if format_changed {
+            self.check_sample_count();
+            self.rebuild_pipelines();
+        }
+    }
+
+    pub fn get_front_face(&self) -> wgpu::FrontFace {
+        self.front_face
+    }
+
+    /// Applies to every static mesh, rebuilds the pipelines if it changed
+    pub fn set_front_face(&mut self, front_face: wgpu::FrontFace) {
+        if front_face != self.front_face {
+            self.front_face = front_face;
+            self.rebuild_pipelines();
+        }
+    }
+
+    pub fn get_draw_mode(&self) -> DrawMode {
+        self.draw_mode
+    }
+
+    pub fn set_draw_mode(&mut self, draw_mode: DrawMode) {
+        if draw_mode == DrawMode::Wireframe
+            && self.pbr_material_static_mesh_wireframe_pipeline.is_none()
+        {
+            warn!("Wireframe mode requires the POLYGON_MODE_LINE feature, staying in filled mode");
+            return;
+        }
+
+        self.draw_mode = draw_mode;
+    }
+
+    /// Replaces the background, the previous skybox is kept if the new one fails to load
+    pub fn set_skybox(&mut self, source: SkyboxSource) {
+        if let Some(skybox) = Skybox::new(
+            &self.device,
+            &self.queue,
+            &source,
+            self.scene_color_format(),
+            DEPTH_FORMAT,
+            self.sample_count,
+        ) {
+            self.skybox = Some(skybox);
+            self.skybox_source = Some(source);
+        }
+    }
+
+    pub fn remove_skybox(&mut self) {
+        self.skybox = None;
+        self.skybox_source = None;
+    }
+
+    /// Lines drawn by the next render_scene call, cleared once they are rendered
+    pub fn debug_lines(&mut self) -> &mut DebugLines {
+        &mut self.debug_lines
+    }
+
+    /// Screen space shapes drawn over everything by the next render_scene call, cleared once they are rendered
+    pub fn hud(&mut self) -> &mut Hud {
+        &mut self.hud
+    }
+
+    /// Text over a point in the world for the next render_scene call, scaled down and faded out with distance.
+    /// Depth tested labels are hidden behind geometry, the others draw on top of it
+    pub fn draw_world_label(
+        &mut self,
+        position: Vec3,
+        text: &str,
+        color: [f32; 4],
+        depth_test: bool,
     ) {
-        self.queue
-            .write_buffer(&self.scene_data.0, 0, bytemuck::cast_slice(&[*scene_data]));
+        self.world_labels.push(position, text, color, depth_test);
+    }
+
+    pub fn get_world_label_settings(&self) -> WorldLabelSettings {
+        self.world_labels.settings
+    }
+
+    pub fn set_world_label_settings(&mut self, settings: WorldLabelSettings) {
+        self.world_labels.settings = settings;
+    }
+
+    pub fn debug_line(&mut self, from: Vec3, to: Vec3, color: [f32; 4], depth_test: bool) {
+        self.debug_lines.line(from, to, color, depth_test);
+    }
+
+    pub fn debug_box(
+        &mut self,
+        transform: &Transform,
+        half_extents: Vec3,
+        color: [f32; 4],
+        depth_test: bool,
+    ) {
+        self.debug_lines
+            .box_outline(transform, half_extents, color, depth_test);
+    }
+
+    pub fn debug_sphere(&mut self, center: Vec3, radius: f32, color: [f32; 4], depth_test: bool) {
+        self.debug_lines.sphere(center, radius, color, depth_test);
+    }
+
+    /// Sample counts usable by both the scene color and depth formats
+    pub fn supported_sample_counts(&self) -> Vec<u32> {
+        let adapter_specific = self
+            .device
+            .features()
+            .contains(wgpu::Features::TEXTURE_ADAPTER_SPECIFIC_FORMAT_FEATURES);
+
+        let format_flags = |format: wgpu::TextureFormat| {
+            if adapter_specific {
+                self.adapter.get_texture_format_features(format).flags
+            } else {
+                format.describe().guaranteed_format_features.flags
+            }
+        };
+        let color_flags = format_flags(self.scene_color_format());
+        let depth_flags = format_flags(DEPTH_FORMAT);
+
+        [1, 2, 4, 8]
+            .into_iter()
+            .filter(|count| {
+                color_flags.sample_count_supported(*count)
+                    && depth_flags.sample_count_supported(*count)
+            })
+            .collect()
+    }
+
+    pub fn get_sample_count(&self) -> u32 {
+        self.sample_count
+    }
+
+    /// Sets the MSAA sample count, unsupported counts fall back to the highest supported count below it
+    pub fn set_sample_count(&mut self, sample_count: u32) {
+        let supported_sample_counts = self.supported_sample_counts();
+        let new_sample_count = supported_sample_counts
+            .iter()
+            .copied()
+            .filter(|count| *count <= sample_count)
+            .max()
+            .unwrap_or(1);
+
+        if new_sample_count != sample_count {
+            warn!(
+                "{}x MSAA is not supported (supported: {:?}), using {}x",
+                sample_count, supported_sample_counts, new_sample_count
+            );
+        }
+
+        if new_sample_count != self.sample_count {
+            self.sample_count = new_sample_count;
+            self.rebuild_pipelines();
+        }
+    }
+
+    pub fn create_scene(&self) -> SceneRenderData {
+        SceneRenderData::new(
+            self.device.clone(),
+            self.queue.clone(),
+            self.instance_set_bind_group_layout.clone(),
+            self.resource_usage.clone(),
+        )
+    }
+
+    /// Every triangle gets its own vertices with the face normal, for generated meshes without uvs
+    pub fn create_flat_mesh(
+        &mut self,
+        positions: &[[f32; 3]],
+        indices: &[u32],
+    ) -> Option<MeshHandle> {
+        let (vertices, indices) = build_vertices(positions, &[], &[], indices);
+        self.create_mesh(&vertices, &indices)
+    }
+
+    pub fn create_mesh(&mut self, vertices: &[Vertex], indices: &[u32]) -> Option<MeshHandle> {
+        Some(
+            self.meshes
+                .insert(Mesh::new(&self.device, vertices, indices)),
+        )
+    }
+
+    /// Levels go from the most detailed to the least, each is drawn up to its max distance from the camera.
+    /// Distances are for an instance at scale 1, bigger instances switch further away.
+    /// Instances past the last level aren't drawn, give it f32::INFINITY to always draw them
+    pub fn create_mesh_with_lods(&mut self, levels: &[LodLevel]) -> Option<MeshHandle> {
+        if levels.is_empty() {
+            error!("A mesh needs at least one LOD level");
+            return None;
+        }
+        if levels.len() > MAX_LOD_LEVELS {
+            warn!(
+                "Mesh has {} LOD levels, only the first {} are used",
+                levels.len(),
+                MAX_LOD_LEVELS
+            );
+        }
+        let levels = &levels[..levels.len().min(MAX_LOD_LEVELS)];
+        if levels
+            .windows(2)
+            .any(|pair| pair[0].max_distance >= pair[1].max_distance)
+        {
+            warn!("LOD distances should increase with each level, later levels may never be used");
+        }
+        Some(self.meshes.insert(Mesh::with_lods(&self.device, levels)))
+    }
+
+    /// `create_flat_mesh` for every level of `create_mesh_with_lods`
+    pub fn create_flat_mesh_with_lods(
+        &mut self,
+        levels: &[LodLevel<[f32; 3]>],
+    ) -> Option<MeshHandle> {
+        let built: Vec<(Vec<Vertex>, Vec<u32>)> = levels
+            .iter()
+            .map(|level| build_vertices(level.vertices, &[], &[], level.indices))
+            .collect();
+        let levels: Vec<LodLevel> = built
+            .iter()
+            .zip(levels)
+            .map(|((vertices, indices), level)| LodLevel {
+                vertices,
+                indices,
+                max_distance: level.max_distance,
+            })
+            .collect();
+        self.create_mesh_with_lods(&levels)
+    }
+
+    pub fn destroy_mesh(&mut self, handle: MeshHandle) {
+        let instance_count = self.resource_usage.lock().unwrap().mesh_count(handle);
+        if instance_count != 0 {
+            warn!(
+                "Destroying mesh {:?} that is still used by {} instances",
+                handle, instance_count
+            );
+        }
+        debug_assert_eq!(instance_count, 0, "Destroyed mesh still in use");
+
+        self.asset_registry.unregister_mesh(handle);
+        if let Some(mesh) = self.meshes.remove(handle) {
+            mesh.destroy();
+        }
+    }
+
+    /// Creates a texture from tightly packed 8-bit RGBA pixels,
+    /// colors should be srgb while data textures like normal maps should be linear
+    pub fn create_texture_from_rgba(
+        &mut self,
+        width: u32,
+        height: u32,
+        data: &[u8],
+        srgb: bool,
+    ) -> Option<TextureHandle> {
+        if width == 0 || height == 0 || data.len() != (width * height * 4) as usize {
+            error!(
+                "Invalid texture data: {}x{} with {} bytes",
+                width,
+                height,
+                data.len()
+            );
+            return None;
+        }
+
+        Some(self.textures.insert(Texture::new(
+            &self.device,
+            &self.queue,
+            width,
+            height,
+            data.to_vec(),
+            srgb,
+        )))
+    }
+
+    pub fn load_texture<P: AsRef<std::path::Path> + Debug>(
+        &mut self,
+        path: P,
+        srgb: bool,
+    ) -> Option<TextureHandle> {
+        let image = match image::open(&path) {
+            Ok(image) => image.to_rgba8(),
+            Err(e) => {
+                error!("Failed to load texture {:?}: {}", path, e);
+                return None;
+            }
+        };
+
+        self.create_texture_from_rgba(image.width(), image.height(), image.as_raw(), srgb)
+    }
+
+    pub fn create_pbr_material(
+        &mut self,
+        material: PbrMaterialDefinition,
+    ) -> Option<MaterialHandle> {
+        let material = self.create_material(MaterialSource::Pbr(material))?;
+        Some(self.materials.insert(material))
+    }
+
+    pub fn create_unlit_material(
+        &mut self,
+        material: UnlitMaterialDefinition,
+    ) -> Option<MaterialHandle> {
+        let material = self.create_material(MaterialSource::Unlit(material))?;
+        Some(self.materials.insert(material))
+    }
+
+    /// Gpu side of a material, None if it uses a texture that doesn't exist
+    fn create_material(&self, source: MaterialSource) -> Option<Material> {
+        let (kind, transparent, material_uniform_buffer, material_bind_group) = match &source {
+            MaterialSource::Pbr(material) => {
+                let albedo_texture = match material.albedo_texture {
+                    Some(handle) => match self.textures.get(handle) {
+                        Some(texture) => texture,
+                        None => {
+                            error!("Invalid albedo texture handle: {:?}", handle);
+                            return None;
+                        }
+                    },
+                    None => self.textures.get(self.white_texture).unwrap(),
+                };
+
+                let normal_texture = match material.normal_texture {
+                    Some(handle) => match self.textures.get(handle) {
+                        Some(texture) => texture,
+                        None => {
+                            error!("Invalid normal texture handle: {:?}", handle);
+                            return None;
+                        }
+                    },
+                    None => self.textures.get(self.flat_normal_texture).unwrap(),
+                };
+
+                let material_uniform_buffer =
+                    self.device
+                        .create_buffer_init(&wgpu::util::BufferInitDescriptor {
+                            label: None,
+                            contents: bytemuck::cast_slice(&[
+                                material.color[0],
+                                material.color[1],
+                                material.color[2],
+                                material.color[3],
+                                material.metallic,
+                                material.roughness,
+                                material.shading_model as u32 as f32,
+                                0.0,
+                                material.emissive[0],
+                                material.emissive[1],
+                                material.emissive[2],
+                                0.0,
+                            ]),
+                            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+                        });
+
+                let material_bind_group =
+                    self.device.create_bind_group(&wgpu::BindGroupDescriptor {
+                        label: None,
+                        layout: &self.material_bind_group_layout,
+                        entries: &[
+                            wgpu::BindGroupEntry {
+                                binding: 0,
+                                resource: wgpu::BindingResource::Buffer(
+                                    material_uniform_buffer.as_entire_buffer_binding(),
+                                ),
+                            },
+                            wgpu::BindGroupEntry {
+                                binding: 1,
+                                resource: wgpu::BindingResource::TextureView(&albedo_texture.view),
+                            },
+                            wgpu::BindGroupEntry {
+                                binding: 2,
+                                resource: wgpu::BindingResource::Sampler(&self.linear_sampler),
+                            },
+                            wgpu::BindGroupEntry {
+                                binding: 3,
+                                resource: wgpu::BindingResource::TextureView(&normal_texture.view),
+                            },
+                        ],
+                    });
+
+                (
+                    MaterialKind::Pbr,
+                    material.transparent,
+                    material_uniform_buffer,
+                    material_bind_group,
+                )
+            }
+            MaterialSource::Unlit(material) => {
+                let material_uniform_buffer =
+                    self.device
+                        .create_buffer_init(&wgpu::util::BufferInitDescriptor {
+                            label: None,
+                            contents: bytemuck::cast_slice(&material.color),
+                            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+                        });
+
+                let material_bind_group =
+                    self.device.create_bind_group(&wgpu::BindGroupDescriptor {
+                        label: None,
+                        layout: &self.unlit_material_bind_group_layout,
+                        entries: &[wgpu::BindGroupEntry {
+                            binding: 0,
+                            resource: wgpu::BindingResource::Buffer(
+                                material_uniform_buffer.as_entire_buffer_binding(),
+                            ),
+                        }],
+                    });
+
+                (
+                    MaterialKind::Unlit,
+                    false,
+                    material_uniform_buffer,
+                    material_bind_group,
+                )
+            }
+        };
+
+        let cull_mode = match &source {
+            MaterialSource::Pbr(material) => material.cull_mode,
+            MaterialSource::Unlit(_) => None,
+        };
+
+        Some(Material {
+            kind,
+            transparent,
+            cull_mode,
+            material_uniform_buffer,
+            material_bind_group,
+            source,
+        })
+    }
+
+    pub fn destroy_material(&mut self, handle: MaterialHandle) {
+        let instance_count = self.resource_usage.lock().unwrap().material_count(handle);
+        if instance_count != 0 {
+            warn!(
+                "Destroying material {:?} that is still used by {} instances",
+                handle, instance_count
+            );
+        }
+        debug_assert_eq!(instance_count, 0, "Destroyed material still in use");
+
+        self.asset_registry.unregister_material(handle);
+        if let Some(material) = self.materials.remove(handle) {
+            material.material_uniform_buffer.destroy();
+        }
+    }
+
+    /// Materials keep their own reference to the texture, so existing materials are unaffected
+    pub fn destroy_texture(&mut self, handle: TextureHandle) {
+        if handle == self.white_texture || handle == self.flat_normal_texture {
+            error!("Can't destroy the default textures");
+            return;
+        }
+
+        self.textures.remove(handle);
+    }
+
+    /// Loads every model in the obj file and merges them into a single mesh
+    /// The mesh is registered under its path, unless that name is already taken
+    pub fn load_mesh<P: AsRef<std::path::Path> + Debug>(&mut self, path: P) -> Option<MeshHandle> {
+        let path_name = path.as_ref().to_string_lossy().to_string();
+        let (vertices, indices) = load_obj_merged(path)?;
+
+        let handle = self.create_mesh(&vertices, &indices)?;
+        if self.asset_registry.get_mesh(&path_name).is_none() {
+            self.asset_registry.register_mesh(&path_name, handle);
+        }
+        Some(handle)
+    }
+
+    /// Reloads every registered mesh from its file and swaps the data behind the existing handle,
+    /// meshes that fail to load keep their old data. Returns the number of meshes reloaded
+    pub fn reload_meshes(&mut self) -> usize {
+        let meshes: Vec<(MeshHandle, String)> = self
+            .asset_registry
+            .meshes()
+            .map(|(name, handle)| (handle, name.to_string()))
+            .collect();
+
+        let mut reloaded = 0;
+        for (handle, name) in meshes {
+            if !std::path::Path::new(&name).is_file() {
+                continue;
+            }
+
+            if let Some((vertices, indices)) = load_obj_merged(&name) {
+                if let Some(mesh) = self.meshes.get_mut(handle) {
+                    let old_mesh =
+                        std::mem::replace(mesh, Mesh::new(&self.device, &vertices, &indices));
+                    old_mesh.destroy();
+                    reloaded += 1;
+                }
+            }
+        }
+        reloaded
+    }
+
+    pub fn assets(&self) -> &AssetRegistry {
+        &self.asset_registry
+    }
+
+    /// Names an existing mesh so it can be found through `assets`
+    pub fn register_mesh(&mut self, name: &str, handle: MeshHandle) {
+        self.asset_registry.register_mesh(name, handle);
+    }
+
+    /// Names an existing material so it can be found through `assets`
+    pub fn register_material(&mut self, name: &str, handle: MaterialHandle) {
+        self.asset_registry.register_material(name, handle);
+    }
+
+    /// Mesh names are obj paths, the file is only loaded the first time the name is used
+    pub fn get_or_load_mesh(&mut self, name: &str) -> Option<MeshHandle> {
+        match self.asset_registry.get_mesh(name) {
+            Some(handle) => Some(handle),
+            None => self.load_mesh(name),
+        }
+    }
+
+    /// Material names are json file paths, the file is only loaded the first time the name is used
+    pub fn get_or_load_material(&mut self, name: &str) -> Option<MaterialHandle> {
+        match self.asset_registry.get_material(name) {
+            Some(handle) => Some(handle),
+            None => self.load_material(name),
+        }
+    }
+
+    /// Loads a pbr material from a json file and registers it under its path
+    pub fn load_material<P: AsRef<std::path::Path> + Debug>(
+        &mut self,
+        path: P,
+    ) -> Option<MaterialHandle> {
+        let material_file: PbrMaterialFile = match std::fs::read_to_string(&path)
+            .map_err(|e| e.to_string())
+            .and_then(|contents| serde_json::from_str(&contents).map_err(|e| e.to_string()))
+        {
+            Ok(material_file) => material_file,
+            Err(e) => {
+                error!("Failed to load material file {:?}: {}", path, e);
+                return None;
+            }
+        };
+
+        let albedo_texture = match &material_file.albedo_texture {
+            Some(texture_path) => Some(self.load_texture(texture_path, true)?),
+            None => None,
+        };
+        let normal_texture = match &material_file.normal_texture {
+            Some(texture_path) => Some(self.load_texture(texture_path, false)?),
+            None => None,
+        };
+
+        let handle = self.create_pbr_material(PbrMaterialDefinition {
+            color: material_file.color,
+            metallic: material_file.metallic,
+            roughness: material_file.roughness,
+            albedo_texture,
+            normal_texture,
+            emissive: material_file.emissive,
+            transparent: material_file.transparent,
+            shading_model: material_file.shading_model,
+            cull_mode: material_file.cull_mode.map(wgpu::Face::from),
+        })?;
+        self.asset_registry
+            .register_material(&path.as_ref().to_string_lossy(), handle);
+        Some(handle)
+    }
+
+    /// The definition is ignored if a material already exists with the name
+    pub fn get_or_create_material(
+        &mut self,
+        name: &str,
+        material: PbrMaterialDefinition,
+    ) -> Option<MaterialHandle> {
+        if let Some(handle) = self.asset_registry.get_material(name) {
+            return Some(handle);
+        }
+
+        let handle = self.create_pbr_material(material)?;
+        self.asset_registry.register_material(name, handle);
+        Some(handle)
+    }
+
+    /// Loads every model in the obj file as its own mesh, paired with the model name
+    pub fn load_meshes<P: AsRef<std::path::Path> + Debug>(
+        &mut self,
+        path: P,
+    ) -> Option<Vec<(String, MeshHandle)>> {
+        let models = load_obj_models(path)?;
+
+        let mut meshes = Vec::with_capacity(models.len());
+        for model in models.iter() {
+            let (vertices, indices) = obj_mesh_to_vertices(&model.mesh);
+            meshes.push((model.name.clone(), self.create_mesh(&vertices, &indices)?));
+        }
+        Some(meshes)
+    }
 
+    /// Loads every mesh in a .gltf/.glb file, one MeshHandle per primitive. Textures are ignored
+    pub fn load_gltf<P: AsRef<std::path::Path> + Debug>(
+        &mut self,
+        path: P,
+    ) -> Option<Vec<GltfMesh>> {
+        let (document, buffers, _images) = match gltf::import(&path) {
+            Ok(values) => values,
+            Err(e) => {
+                error!("Failed to load gltf file {:?}: {}", path, e);
+                return None;
+            }
+        };
+
+        let mut meshes = Vec::with_capacity(document.meshes().len());
+        for gltf_mesh in document.meshes() {
+            let mut primitives = Vec::new();
+            for primitive in gltf_mesh.primitives() {
+                if primitive.mode() != gltf::mesh::Mode::Triangles {
+                    error!(
+                        "Skipping non-triangle primitive in mesh {:?} of {:?}",
+                        gltf_mesh.name(),
+                        path
+                    );
+                    continue;
+                }
+
+                let reader = primitive.reader(|buffer| Some(&buffers[buffer.index()]));
+
+                let positions: Vec<[f32; 3]> = match reader.read_positions() {
+                    Some(positions) => positions.collect(),
+                    None => {
+                        error!(
+                            "Skipping primitive without positions in mesh {:?} of {:?}",
+                            gltf_mesh.name(),
+                            path
+                        );
+                        continue;
+                    }
+                };
+                let normals: Vec<[f32; 3]> = reader
+                    .read_normals()
+                    .map(|normals| normals.collect())
+                    .unwrap_or_default();
+                let uvs: Vec<[f32; 2]> = reader
+                    .read_tex_coords(0)
+                    .map(|uvs| uvs.into_f32().collect())
+                    .unwrap_or_default();
+                let indices: Vec<u32> = reader
+                    .read_indices()
+                    .map(|indices| indices.into_u32().collect())
+                    .unwrap_or_else(|| (0..positions.len() as u32).collect());
+
+                let (vertices, indices) = build_vertices(&positions, &normals, &uvs, &indices);
+
+                let material = primitive.material();
+                let pbr = material.pbr_metallic_roughness();
+                primitives.push(GltfPrimitive {
+                    mesh: self.create_mesh(&vertices, &indices)?,
+                    material: PbrMaterialDefinition {
+                        color: pbr.base_color_factor(),
+                        metallic: pbr.metallic_factor(),
+                        roughness: pbr.roughness_factor(),
+                        albedo_texture: None,
+                        normal_texture: None,
+                        emissive: material.emissive_factor(),
+                        transparent: material.alpha_mode() == gltf::material::AlphaMode::Blend,
+                        shading_model: ShadingModel::Lit,
+                        cull_mode: if material.double_sided() {
+                            None
+                        } else {
+                            Some(wgpu::Face::Back)
+                        },
+                    },
+                });
+            }
+
+            meshes.push(GltfMesh {
+                name: gltf_mesh.name().map(String::from),
+                primitives,
+            });
+        }
+
+        Some(meshes)
+    }
+
+    /// Recreates the render targets if the size changed, zero sized targets are released until a valid size arrives
+    pub fn resize(&mut self, size: [u32; 2]) {
+        let scene_color_format = self.scene_color_format();
+        self.frame.resize(
+            &self.device,
+            size,
+            self.sample_count,
+            scene_color_format,
+            &self.bloom,
+        );
+    }
+
+    /// Starts recording a frame that ends up in `output_texture`
+    pub fn begin_frame(&mut self, output_texture: wgpu::SurfaceTexture) -> FrameContext {
+        let output_view = output_texture
+            .texture
+            .create_view(&wgpu::TextureViewDescriptor::default());
+        self.begin_frame_into(output_view, Some(output_texture))
+    }
+
+    /// Starts recording a frame into a texture of the output format, nothing is presented at the end
+    pub fn begin_offscreen_frame(&mut self, output_view: wgpu::TextureView) -> FrameContext {
+        self.begin_frame_into(output_view, None)
+    }
+
+    fn begin_frame_into(
+        &mut self,
+        output_view: wgpu::TextureView,
+        output_texture: Option<wgpu::SurfaceTexture>,
+    ) -> FrameContext {
         let mut encoder = self
             .device
             .create_command_encoder(&wgpu::CommandEncoderDescriptor { label: None });
 
-        let depth_texture = self.device.create_texture(&wgpu::TextureDescriptor {
-            label: Some("Depth Texture"),
-            size: wgpu::Extent3d {
-                width: size[0],
-                height: size[1],
-                depth_or_array_layers: 1,
-            },
+        if let Some(gpu_timer) = &mut self.gpu_timer {
+            gpu_timer.begin(&self.device, &mut encoder);
+        }
+
+        FrameContext {
+            encoder,
+            output_view,
+            output_texture,
+            command_buffers: Vec::new(),
+            stats: RenderStats::default(),
+            start: std::time::Instant::now(),
+        }
+    }
+
+    /// Submits everything recorded into the frame with a single submit and presents it
+    pub fn end_frame(&mut self, frame: FrameContext) -> RenderStats {
+        let FrameContext {
+            mut encoder,
+            output_view,
+            output_texture,
+            command_buffers,
+            mut stats,
+            start,
+        } = frame;
+
+        if let Some(gpu_timer) = &mut self.gpu_timer {
+            gpu_timer.end(&mut encoder);
+        }
+
+        self.queue.submit(
+            command_buffers
+                .into_iter()
+                .chain(std::iter::once(encoder.finish())),
+        );
+
+        if let Some(gpu_timer) = &mut self.gpu_timer {
+            gpu_timer.after_submit();
+            stats.gpu_ms = gpu_timer.last_ms();
+        }
+
+        drop(output_view);
+        if let Some(output_texture) = output_texture {
+            output_texture.present();
+        }
+
+        stats.encode_cpu_ms = start.elapsed().as_secs_f32() * 1000.0;
+        stats
+    }
+
+    /// Renders a single frame into an offscreen texture and reads it back as tightly packed rgba8 rows.
+    /// Blocks until the gpu is done, meant for tests and captures rather than every frame
+    pub fn render_to_texture(
+        &mut self,
+        size: [u32; 2],
+        scene_data: &SceneData,
+        scene_render_data: &mut SceneRenderData,
+    ) -> Vec<u8> {
+        let bytes_per_pixel = self.output_format.describe().block_size as u32;
+        if size[0] == 0 || size[1] == 0 || bytes_per_pixel != 4 {
+            warn!(
+                "Can't read back a {}x{} {:?} frame",
+                size[0], size[1], self.output_format
+            );
+            return Vec::new();
+        }
+
+        let extent = wgpu::Extent3d {
+            width: size[0],
+            height: size[1],
+            depth_or_array_layers: 1,
+        };
+        let texture = self.device.create_texture(&wgpu::TextureDescriptor {
+            label: Some("Offscreen Output Texture"),
+            size: extent,
             mip_level_count: 1,
             sample_count: 1,
             dimension: wgpu::TextureDimension::D2,
-            format: wgpu::TextureFormat::Depth24Plus,
-            usage: wgpu::TextureUsages::RENDER_ATTACHMENT,
+            format: self.output_format,
+            usage: wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::COPY_SRC,
             view_formats: &[],
         });
-        let depth_view = depth_texture.create_view(&wgpu::TextureViewDescriptor::default());
 
+        //Copied rows have to be aligned, the padding is stripped after reading back
+        let unpadded_bytes_per_row = size[0] * bytes_per_pixel;
+        let padded_bytes_per_row = unpadded_bytes_per_row
+            .div_ceil(wgpu::COPY_BYTES_PER_ROW_ALIGNMENT)
+            * wgpu::COPY_BYTES_PER_ROW_ALIGNMENT;
+        let readback_buffer = self.device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Offscreen Readback Buffer"),
+            size: (padded_bytes_per_row * size[1]) as wgpu::BufferAddress,
+            usage: wgpu::BufferUsages::COPY_DST | wgpu::BufferUsages::MAP_READ,
+            mapped_at_creation: false,
+        });
+
+        let mut frame = self
+            .begin_offscreen_frame(texture.create_view(&wgpu::TextureViewDescriptor::default()));
+        self.render_scene(&mut frame, size, scene_data, scene_render_data);
+        frame.encoder.copy_texture_to_buffer(
+            texture.as_image_copy(),
+            wgpu::ImageCopyBuffer {
+                buffer: &readback_buffer,
+                layout: wgpu::ImageDataLayout {
+                    offset: 0,
+                    bytes_per_row: std::num::NonZeroU32::new(padded_bytes_per_row),
+                    rows_per_image: None,
+                },
+            },
+            extent,
+        );
+        self.end_frame(frame);
+
+        let slice = readback_buffer.slice(..);
+        slice.map_async(wgpu::MapMode::Read, |result| {
+            if let Err(e) = result {
+                error!("Failed to map the readback buffer: {}", e);
+            }
+        });
+        self.device.poll(wgpu::Maintain::Wait);
+
+        let mut pixels = Vec::with_capacity((unpadded_bytes_per_row * size[1]) as usize);
         {
-            let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            let data = slice.get_mapped_range();
+            for row in data.chunks(padded_bytes_per_row as usize) {
+                pixels.extend_from_slice(&row[..unpadded_bytes_per_row as usize]);
+            }
+        }
+        readback_buffer.unmap();
+
+        //Surfaces are often bgra
+        if matches!(
+            self.output_format,
+            wgpu::TextureFormat::Bgra8Unorm | wgpu::TextureFormat::Bgra8UnormSrgb
+        ) {
+            for pixel in pixels.chunks_mut(4) {
+                pixel.swap(0, 2);
+            }
+        }
+        pixels
+    }
+
+    /// Records the scene into the frame, the stats are added to the frame's
+    pub fn render_scene(
+        &mut self,
+        frame: &mut FrameContext,
+        size: [u32; 2],
+        scene_data: &SceneData,
+        scene_render_data: &mut SceneRenderData,
+    ) {
+        self.resize(size);
+        if self.frame.targets().is_none() {
+            self.debug_lines.clear();
+            self.hud.clear();
+            self.world_labels.clear();
+            return;
+        }
+
+        let flush_start = std::time::Instant::now();
+        frame.stats.instance_writes += scene_render_data.flush_instances();
+        frame.stats.instance_flush_ms += flush_start.elapsed().as_secs_f32() * 1000.0;
+        self.upload_frame_data(size, scene_data, scene_render_data);
+
+        let camera_position = Vec3::from_slice(&scene_data.camera_position[0..3]);
+        scene_render_data.build_visible_lists(&self.meshes, camera_position);
+        let draws = SceneDraws::collect(
+            &self.meshes,
+            &self.materials,
+            &mut self.invalid_instance_types,
+            scene_render_data,
+            camera_position,
+            &mut frame.stats,
+        );
+
+        if let Some(targets) = self.frame.targets() {
+            let mut context = PassContext {
+                encoder: &mut frame.encoder,
+                resources: &self.frame,
+                targets,
+                output: &frame.output_view,
+                stats: &mut frame.stats,
+            };
+            for pass in FRAME_PASSES {
+                self.execute_pass(pass, &mut context, &draws);
+            }
+        }
+    }
+
+    /// Writes the uniforms and uploads everything queued this frame, the queues are cleared for the next frame
+    fn upload_frame_data(
+        &mut self,
+        size: [u32; 2],
+        scene_data: &SceneData,
+        scene_render_data: &SceneRenderData,
+    ) {
+        self.frame
+            .write_uniforms(&self.queue, scene_data, &scene_render_data.light_data());
+        if let Some(skybox) = &self.skybox {
+            skybox.update(&self.queue, &scene_data.view_projection_matrix);
+        }
+        self.debug_line_pass
+            .update(&self.device, &self.queue, &self.debug_lines);
+        self.debug_lines.clear();
+        self.particle_pass
+            .update(&self.device, &self.queue, &scene_render_data.particles);
+        self.trail_pass.update(
+            &self.device,
+            &self.queue,
+            &scene_render_data.trails,
+            Vec3::from_slice(&scene_data.camera_position[0..3]),
+        );
+        self.hud_pass
+            .update(&self.device, &self.queue, &self.hud, size);
+        self.hud.clear();
+        self.world_label_pass.update(
+            &self.device,
+            &self.queue,
+            &self.world_labels,
+            scene_data,
+            size,
+        );
+        self.world_labels.clear();
+    }
+
+    fn execute_pass<'a>(
+        &'a self,
+        pass: Pass,
+        context: &mut PassContext<'a>,
+        draws: &SceneDraws<'a>,
+    ) {
+        match pass {
+            Pass::Scene => self.scene_pass(context, draws),
+            Pass::Bloom => {
+                if let Some(hdr) = &context.targets.hdr {
+                    context.stats.draw_calls +=
+                        self.bloom
+                            .render(context.encoder, &hdr.bloom, context.output);
+                }
+            }
+            Pass::Hud => {
+                context.stats.draw_calls += self.hud_pass.draw(context.encoder, context.output);
+            }
+        }
+    }
+
+    fn scene_pass<'a>(&'a self, context: &mut PassContext<'a>, draws: &SceneDraws<'a>) {
+        let (color_view, resolve_target) = context.targets.scene_attachment(context.output);
+        let stats = &mut *context.stats;
+        let mut render_pass = context
+            .encoder
+            .begin_render_pass(&wgpu::RenderPassDescriptor {
                 label: None,
                 color_attachments: &[Some(wgpu::RenderPassColorAttachment {
-                    view: render_target,
-                    resolve_target: None,
+                    view: color_view,
+                    resolve_target,
                     ops: wgpu::Operations {
                         load: wgpu::LoadOp::Clear(wgpu::Color {
                             r: 0.0,
@@ -373,7 +2210,7 @@ impl Renderer {
                     },
                 })],
                 depth_stencil_attachment: Some(wgpu::RenderPassDepthStencilAttachment {
-                    view: &depth_view,
+                    view: &context.targets.depth.1,
                     depth_ops: Some(wgpu::Operations {
                         load: wgpu::LoadOp::Clear(0.0),
                         store: true,
@@ -382,43 +2219,221 @@ impl Renderer {
                 }),
             });
 
-            render_pass.set_pipeline(&self.pbr_material_static_mesh_pipeline);
-            render_pass.set_bind_group(0, &self.scene_data.1, &[]);
-
-            for (key, set) in scene_render_data.instance_set_map.iter() {
-                if !set.is_empty() {
-                    render_pass.set_bind_group(1, &set.bind_group, &[]);
-
-                    render_pass.set_bind_group(
-                        2,
-                        &self
-                            .materials
-                            .get(key.material)
-                            .unwrap()
-                            .material_bind_group,
-                        &[],
-                    );
+        if let Some(skybox) = &self.skybox {
+            skybox.draw(&mut render_pass);
+            stats.draw_calls += 1;
+        }
+
+        render_pass.set_bind_group(0, context.resources.scene_bind_group(), &[]);
+
+        let mut current_pipeline = None;
+        for (kind, draw) in draws.opaque.iter() {
+            let pipeline = (*kind, draw.material.cull_mode);
+            if current_pipeline != Some(pipeline) {
+                render_pass.set_pipeline(self.opaque_pipeline(*kind, draw.material.cull_mode));
+                current_pipeline = Some(pipeline);
+            }
+            draw.record(&mut render_pass, stats);
+        }
+
+        //Sorted by distance, so the cull mode can change between any two draws
+        let mut current_cull_mode = None;
+        for draw in draws.transparent.iter() {
+            if current_cull_mode != Some(draw.material.cull_mode) {
+                render_pass.set_pipeline(
+                    match (
+                        self.draw_mode,
+                        &self.pbr_material_static_mesh_wireframe_pipeline,
+                    ) {
+                        (DrawMode::Wireframe, Some(wireframe_pipeline)) => wireframe_pipeline,
+                        _ => self
+                            .pbr_material_static_mesh_transparent_pipelines
+                            .get(draw.material.cull_mode),
+                    },
+                );
+                current_cull_mode = Some(draw.material.cull_mode);
+            }
+            draw.record(&mut render_pass, stats);
+        }
+
+        //Scene bind group is still bound at group 0
+        stats.draw_calls += self.trail_pass.draw(&mut render_pass);
+        stats.draw_calls += self.particle_pass.draw(&mut render_pass);
+        stats.draw_calls += self.debug_line_pass.draw(&mut render_pass);
+        stats.draw_calls += self.world_label_pass.draw(&mut render_pass);
+    }
+
+    fn opaque_pipeline(
+        &self,
+        kind: MaterialKind,
+        cull_mode: Option<wgpu::Face>,
+    ) -> &wgpu::RenderPipeline {
+        let (pipeline, wireframe_pipeline) = match kind {
+            MaterialKind::Pbr => (
+                self.pbr_material_static_mesh_pipelines.get(cull_mode),
+                &self.pbr_material_static_mesh_wireframe_pipeline,
+            ),
+            MaterialKind::Unlit => (
+                &self.unlit_material_static_mesh_pipeline,
+                &self.unlit_material_static_mesh_wireframe_pipeline,
+            ),
+        };
+        match (self.draw_mode, wireframe_pipeline) {
+            (DrawMode::Wireframe, Some(wireframe_pipeline)) => wireframe_pipeline,
+            _ => pipeline,
+        }
+    }
+}
+
+/// One draw call of a range of instances, all with the same mesh level and material
+struct MeshDraw<'a> {
+    mesh: &'a Mesh,
+    level: usize,
+    material: &'a Material,
+    /// The set's own buffer, or its visible list
+    instance_bind_group: &'a wgpu::BindGroup,
+    instances: Range<u32>,
+}
+
+impl<'a> MeshDraw<'a> {
+    fn record(&self, render_pass: &mut wgpu::RenderPass<'a>, stats: &mut RenderStats) {
+        render_pass.set_bind_group(2, &self.material.material_bind_group, &[]);
+        render_pass.set_bind_group(1, self.instance_bind_group, &[]);
+        self.mesh
+            .draw(render_pass, self.level, self.instances.clone());
+        stats.add_mesh_draw(self.mesh, self.level, self.instances.len() as u32);
+    }
+}
+
+/// Instance sets resolved against the live meshes and materials, gathered before any pass is recorded
+struct SceneDraws<'a> {
+    /// Sorted by material kind and cull mode so the pipeline only changes between them
+    opaque: Vec<(MaterialKind, MeshDraw<'a>)>,
+    /// Single instances, farthest from the camera first
+    transparent: Vec<MeshDraw<'a>>,
+}
+
+impl<'a> SceneDraws<'a> {
+    fn collect(
+        meshes: &'a SlotMap<MeshHandle, Mesh>,
+        materials: &'a SlotMap<MaterialHandle, Material>,
+        invalid_instance_types: &mut HashSet<InstanceType>,
+        scene_render_data: &'a SceneRenderData,
+        camera_position: Vec3,
+        stats: &mut RenderStats,
+    ) -> Self {
+        let mut opaque = Vec::new();
+        let mut transparent = Vec::new();
 
-                    self.meshes
-                        .get(key.mesh)
-                        .unwrap()
-                        .draw(&mut render_pass, 0..(set.len() as u32));
+        for (key, set) in scene_render_data.instance_set_map.iter() {
+            if set.is_empty() {
+                continue;
+            }
+
+            let (mesh, material) = match (meshes.get(key.mesh), materials.get(key.material)) {
+                (Some(mesh), Some(material)) => (mesh, material),
+                _ => {
+                    if invalid_instance_types.insert(key.clone()) {
+                        error!("Skipping instances with destroyed resources: {:?}", key);
+                    }
+                    continue;
+                }
+            };
+
+            stats.instance_sets += 1;
+            if material.transparent {
+                //Transparent instances are drawn one at a time
+                for (index, instance) in set.instances.instance_map.values() {
+                    let Some(level) = set.visible_level(
+                        mesh,
+                        *index,
+                        camera_position,
+                        scene_render_data.distance_scale,
+                    ) else {
+                        stats.instances_skipped += 1;
+                        continue;
+                    };
+                    let position = Vec3::from_slice(&instance.model_matrix[12..15]);
+                    transparent.push((
+                        position.distance_squared(camera_position),
+                        MeshDraw {
+                            mesh,
+                            level,
+                            material,
+                            instance_bind_group: &set.bind_group,
+                            instances: *index as u32..(*index as u32 + 1),
+                        },
+                    ));
+                }
+            } else if let Some(visible) = &set.visible {
+                stats.instances_skipped += visible.skipped;
+                for (level, instances) in visible.ranges.iter().enumerate() {
+                    if !instances.is_empty() {
+                        opaque.push((
+                            material.kind,
+                            MeshDraw {
+                                mesh,
+                                level,
+                                material,
+                                instance_bind_group: &visible.bind_group,
+                                instances: instances.clone(),
+                            },
+                        ));
+                    }
                 }
+            } else {
+                opaque.push((
+                    material.kind,
+                    MeshDraw {
+                        mesh,
+                        level: 0,
+                        material,
+                        instance_bind_group: &set.bind_group,
+                        instances: 0..set.len() as u32,
+                    },
+                ));
             }
         }
 
-        self.queue.submit(Some(encoder.finish()));
+        opaque.sort_by_key(|(kind, draw)| (*kind, cull_mode_index(draw.material.cull_mode)));
+        transparent.sort_by(|a, b| b.0.total_cmp(&a.0));
+
+        Self {
+            opaque,
+            transparent: transparent.into_iter().map(|(_, draw)| draw).collect(),
+        }
     }
 }
 
-struct Mesh {
+struct MeshLevel {
     vertex_buffer: wgpu::Buffer,
     index_buffer: wgpu::Buffer,
     index_count: usize,
+    /// Drawn up to this far from the camera, for an instance at scale 1
+    max_distance: f32,
+    /// Kept to upload the mesh again if the device is lost
+    vertices: Vec<Vertex>,
+    indices: Vec<u32>,
 }
 
-impl Mesh {
-    fn new(device: &wgpu::Device, vertices: &[Vertex], indices: &[u32]) -> Self {
+impl MeshLevel {
+    fn new(device: &wgpu::Device, vertices: &[Vertex], indices: &[u32], max_distance: f32) -> Self {
+        let (vertex_buffer, index_buffer) = Self::create_buffers(device, vertices, indices);
+        Self {
+            vertex_buffer,
+            index_buffer,
+            index_count: indices.len(),
+            max_distance,
+            vertices: vertices.to_vec(),
+            indices: indices.to_vec(),
+        }
+    }
+
+    fn create_buffers(
+        device: &wgpu::Device,
+        vertices: &[Vertex],
+        indices: &[u32],
+    ) -> (wgpu::Buffer, wgpu::Buffer) {
         let vertex_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
             label: Some("Vertex Buffer"),
             contents: bytemuck::cast_slice(vertices),
@@ -431,29 +2446,230 @@ impl Mesh {
             usage: wgpu::BufferUsages::INDEX,
         });
 
+        (vertex_buffer, index_buffer)
+    }
+
+    fn recreate(&mut self, device: &wgpu::Device) {
+        (self.vertex_buffer, self.index_buffer) =
+            Self::create_buffers(device, &self.vertices, &self.indices);
+    }
+}
+
+/// Every LOD level of a mesh, most detailed first. Meshes created without LODs have a single level drawn at any distance
+struct Mesh {
+    levels: Vec<MeshLevel>,
+}
+
+impl Mesh {
+    fn new(device: &wgpu::Device, vertices: &[Vertex], indices: &[u32]) -> Self {
+        Self::with_lods(
+            device,
+            &[LodLevel {
+                vertices,
+                indices,
+                max_distance: f32::INFINITY,
+            }],
+        )
+    }
+
+    fn with_lods(device: &wgpu::Device, levels: &[LodLevel]) -> Self {
         Self {
-            vertex_buffer,
-            index_buffer,
-            index_count: indices.len(),
+            levels: levels
+                .iter()
+                .map(|level| {
+                    MeshLevel::new(device, level.vertices, level.indices, level.max_distance)
+                })
+                .collect(),
+        }
+    }
+
+    /// Whether its instances have to be sorted into levels every frame
+    fn has_lods(&self) -> bool {
+        self.levels.len() > 1 || self.levels[0].max_distance.is_finite()
+    }
+
+    /// None once the distance is past the last level
+    fn level_for(&self, distance: f32) -> Option<usize> {
+        self.levels
+            .iter()
+            .position(|level| distance <= level.max_distance)
+    }
+
+    fn recreate(&mut self, device: &wgpu::Device) {
+        for level in self.levels.iter_mut() {
+            level.recreate(device);
+        }
+    }
+
+    fn destroy(&self) {
+        for level in self.levels.iter() {
+            level.vertex_buffer.destroy();
+            level.index_buffer.destroy();
         }
     }
 
-    fn draw<'a>(&'a self, render_pass: &mut wgpu::RenderPass<'a>, instances: Range<u32>) {
-        render_pass.set_vertex_buffer(0, self.vertex_buffer.slice(..));
-        render_pass.set_index_buffer(self.index_buffer.slice(..), wgpu::IndexFormat::Uint32);
-        render_pass.draw_indexed(0..self.index_count as u32, 0, instances);
+    fn draw<'a>(
+        &'a self,
+        render_pass: &mut wgpu::RenderPass<'a>,
+        level: usize,
+        instances: Range<u32>,
+    ) {
+        let level = &self.levels[level];
+        render_pass.set_vertex_buffer(0, level.vertex_buffer.slice(..));
+        render_pass.set_index_buffer(level.index_buffer.slice(..), wgpu::IndexFormat::Uint32);
+        render_pass.draw_indexed(0..level.index_count as u32, 0, instances);
     }
 }
 
-struct Material {
-    material_uniform_buffer: wgpu::Buffer,
-    material_bind_group: wgpu::BindGroup,
+/// Which static mesh pipeline a material is drawn with
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Ord, PartialOrd)]
+enum MaterialKind {
+    Pbr,
+    Unlit,
+}
+
+/// What a material was created from, so it can be created again on a new device
+#[derive(Clone, Copy)]
+enum MaterialSource {
+    Pbr(PbrMaterialDefinition),
+    Unlit(UnlitMaterialDefinition),
+}
+
+struct Material {
+    kind: MaterialKind,
+    transparent: bool,
+    cull_mode: Option<wgpu::Face>,
+    material_uniform_buffer: wgpu::Buffer,
+    material_bind_group: wgpu::BindGroup,
+    source: MaterialSource,
+}
+
+struct Texture {
+    texture: wgpu::Texture,
+    view: wgpu::TextureView,
+    width: u32,
+    height: u32,
+    /// Tightly packed rgba8 pixels, kept to upload the texture again if the device is lost
+    data: Vec<u8>,
+    srgb: bool,
+}
+
+impl Texture {
+    fn new(
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        width: u32,
+        height: u32,
+        data: Vec<u8>,
+        srgb: bool,
+    ) -> Self {
+        let (texture, view) = Self::create(device, queue, width, height, &data, srgb);
+        Self {
+            texture,
+            view,
+            width,
+            height,
+            data,
+            srgb,
+        }
+    }
+
+    fn create(
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        width: u32,
+        height: u32,
+        data: &[u8],
+        srgb: bool,
+    ) -> (wgpu::Texture, wgpu::TextureView) {
+        let texture = device.create_texture_with_data(
+            queue,
+            &wgpu::TextureDescriptor {
+                label: None,
+                size: wgpu::Extent3d {
+                    width,
+                    height,
+                    depth_or_array_layers: 1,
+                },
+                mip_level_count: 1,
+                sample_count: 1,
+                dimension: wgpu::TextureDimension::D2,
+                format: if srgb {
+                    wgpu::TextureFormat::Rgba8UnormSrgb
+                } else {
+                    wgpu::TextureFormat::Rgba8Unorm
+                },
+                usage: wgpu::TextureUsages::TEXTURE_BINDING,
+                view_formats: &[],
+            },
+            data,
+        );
+        let view = texture.create_view(&wgpu::TextureViewDescriptor::default());
+        (texture, view)
+    }
+
+    fn recreate(&mut self, device: &wgpu::Device, queue: &wgpu::Queue) {
+        (self.texture, self.view) = Self::create(
+            device,
+            queue,
+            self.width,
+            self.height,
+            &self.data,
+            self.srgb,
+        );
+    }
 }
 
 slotmap::new_key_type! {
     pub struct InstanceHandle;
     pub struct MeshHandle;
     pub struct MaterialHandle;
+    pub struct TextureHandle;
+    pub struct LightHandle;
+}
+
+/// Instances are drawn as their material is unless they're given a tint
+pub const NO_TINT: [f32; 4] = [1.0; 4];
+
+/// Per instance storage data, the normal matrix columns are padded to vec4s to match wgsl's mat3x3 layout
+#[repr(C)]
+#[derive(Pod, Zeroable, Copy, Clone, Debug)]
+struct InstanceData {
+    model_matrix: [f32; 16],
+    normal_matrix: [[f32; 4]; 3],
+    /// Multiplied with the material color, alpha included
+    tint: [f32; 4],
+}
+
+impl InstanceData {
+    fn new(transform: &Transform, tint: [f32; 4]) -> Self {
+        let model_matrix = transform.as_model_matrix();
+
+        //Inverse transpose keeps normals perpendicular to surfaces under non-uniform scale
+        let normal_matrix = glam::Mat3::from_mat4(model_matrix).inverse().transpose();
+
+        Self {
+            model_matrix: model_matrix.to_cols_array(),
+            normal_matrix: [
+                normal_matrix.x_axis.extend(0.0).to_array(),
+                normal_matrix.y_axis.extend(0.0).to_array(),
+                normal_matrix.z_axis.extend(0.0).to_array(),
+            ],
+            tint,
+        }
+    }
+
+    /// Distance to the camera divided by the largest scale axis, what the LOD levels are picked with
+    fn lod_distance(&self, camera_position: Vec3) -> f32 {
+        let model_matrix = glam::Mat4::from_cols_array(&self.model_matrix);
+        let scale = model_matrix
+            .x_axis
+            .truncate()
+            .length()
+            .max(model_matrix.y_axis.truncate().length())
+            .max(model_matrix.z_axis.truncate().length());
+        model_matrix.w_axis.truncate().distance(camera_position) / scale.max(f32::EPSILON)
+    }
 }
 
 #[derive(Debug, Clone, Hash, Ord, PartialOrd, Eq, PartialEq)]
@@ -462,27 +2678,78 @@ struct InstanceType {
     material: MaterialHandle,
 }
 
+/// Counts how many live instances reference each mesh and material across all scenes
+#[derive(Default)]
+struct ResourceUsage {
+    meshes: HashMap<MeshHandle, usize>,
+    materials: HashMap<MaterialHandle, usize>,
+}
+
+impl ResourceUsage {
+    fn add(&mut self, instance_type: &InstanceType) {
+        *self.meshes.entry(instance_type.mesh).or_default() += 1;
+        *self.materials.entry(instance_type.material).or_default() += 1;
+    }
+
+    fn remove(&mut self, instance_type: &InstanceType) {
+        if let Some(count) = self.meshes.get_mut(&instance_type.mesh) {
+            *count -= 1;
+            if *count == 0 {
+                self.meshes.remove(&instance_type.mesh);
+            }
+        }
+        if let Some(count) = self.materials.get_mut(&instance_type.material) {
+            *count -= 1;
+            if *count == 0 {
+                self.materials.remove(&instance_type.material);
+            }
+        }
+    }
+
+    fn mesh_count(&self, mesh: MeshHandle) -> usize {
+        self.meshes.get(&mesh).copied().unwrap_or_default()
+    }
+
+    fn material_count(&self, material: MaterialHandle) -> usize {
+        self.materials.get(&material).copied().unwrap_or_default()
+    }
+}
+
 pub struct SceneRenderData {
     device: Arc<wgpu::Device>,
     queue: Arc<wgpu::Queue>,
     instance_set_bind_group_layout: Arc<wgpu::BindGroupLayout>,
+    resource_usage: Arc<Mutex<ResourceUsage>>,
 
     instance_map: SlotMap<InstanceHandle, InstanceType>,
-    instance_set_map: HashMap<InstanceType, InstanceSet<[f32; 16]>>,
+    instance_set_map: HashMap<InstanceType, InstanceSet<InstanceData>>,
+
+    lights: SlotMap<LightHandle, PunctualLight>,
+    particles: ParticleSystem,
+    trails: TrailSystem,
+
+    /// Multiplies every draw distance and LOD distance
+    distance_scale: f32,
 }
 
 impl SceneRenderData {
-    pub fn new(
+    fn new(
         device: Arc<wgpu::Device>,
         queue: Arc<wgpu::Queue>,
         instance_set_bind_group_layout: Arc<wgpu::BindGroupLayout>,
+        resource_usage: Arc<Mutex<ResourceUsage>>,
     ) -> Self {
         Self {
             device,
             queue,
             instance_set_bind_group_layout,
+            resource_usage,
             instance_map: SlotMap::with_key(),
             instance_set_map: HashMap::new(),
+            lights: SlotMap::with_key(),
+            particles: ParticleSystem::default(),
+            trails: TrailSystem::default(),
+            distance_scale: 1.0,
         }
     }
 
@@ -491,10 +2758,22 @@ impl SceneRenderData {
         mesh: MeshHandle,
         material: MaterialHandle,
         transform: &Transform,
+    ) -> Option<InstanceHandle> {
+        self.create_tinted_instance(mesh, material, transform, NO_TINT)
+    }
+
+    /// The tint is multiplied with the material color, it stays until it's changed with `set_instance_tint`
+    pub fn create_tinted_instance(
+        &mut self,
+        mesh: MeshHandle,
+        material: MaterialHandle,
+        transform: &Transform,
+        tint: [f32; 4],
     ) -> Option<InstanceHandle> {
         let instance_type = InstanceType { mesh, material };
 
         let instance_key = self.instance_map.insert(instance_type.clone());
+        self.resource_usage.lock().unwrap().add(&instance_type);
 
         let set = self
             .instance_set_map
@@ -503,128 +2782,719 @@ impl SceneRenderData {
                 InstanceSet::new(
                     self.device.clone(),
                     self.queue.clone(),
-                    self.instance_set_bind_group_layout.as_ref(),
+                    self.instance_set_bind_group_layout.clone(),
                     1024,
                 )
             });
-        set.add(instance_key, transform.as_model_matrix().as_ref());
+        set.add(instance_key, &InstanceData::new(transform, tint));
         Some(instance_key)
     }
 
+    /// Keeps the instance's tint
     pub fn update_instance(&mut self, key: InstanceHandle, transform: &Transform) {
         let instance_type = self.instance_map.get(key).unwrap().clone();
         let set = self.instance_set_map.get_mut(&instance_type).unwrap();
-        set.update(key, transform.as_model_matrix().as_ref());
+        let tint = set.get(key).map_or(NO_TINT, |data| data.tint);
+        set.update(key, &InstanceData::new(transform, tint));
     }
 
-    pub fn remove_instance(&mut self, key: InstanceHandle) {
+    pub fn set_instance_tint(&mut self, key: InstanceHandle, tint: [f32; 4]) {
         let instance_type = self.instance_map.get(key).unwrap().clone();
         let set = self.instance_set_map.get_mut(&instance_type).unwrap();
+        if let Some(mut data) = set.get(key).copied() {
+            data.tint = tint;
+            set.update(key, &data);
+        }
+    }
+
+    /// The instance isn't drawn while it's further than this from the camera, instances start out with no limit.
+    /// Unlike LOD distances this is in meters and doesn't grow with the instance's scale
+    pub fn set_instance_draw_distance(&mut self, key: InstanceHandle, distance: f32) {
+        let instance_type = self.instance_map.get(key).unwrap().clone();
+        let set = self.instance_set_map.get_mut(&instance_type).unwrap();
+        set.set_draw_distance(key, distance);
+    }
+
+    /// Below 1 instances are drawn with lower LODs and stop being drawn sooner, above 1 they keep their detail further out
+    pub fn set_distance_scale(&mut self, scale: f32) {
+        if !(scale.is_finite() && scale > 0.0) {
+            warn!(
+                "Invalid draw distance scale {}, keeping {}",
+                scale, self.distance_scale
+            );
+            return;
+        }
+        self.distance_scale = scale;
+    }
+
+    pub fn remove_instance(&mut self, key: InstanceHandle) {
+        let instance_type = self.instance_map.remove(key).unwrap();
+        self.resource_usage.lock().unwrap().remove(&instance_type);
+        let set = self.instance_set_map.get_mut(&instance_type).unwrap();
         set.remove(key);
     }
+
+    pub fn add_light(&mut self, light: PunctualLight) -> LightHandle {
+        self.lights.insert(light)
+    }
+
+    pub fn update_light(&mut self, handle: LightHandle, light: PunctualLight) {
+        if let Some(existing_light) = self.lights.get_mut(handle) {
+            *existing_light = light;
+        }
+    }
+
+    pub fn remove_light(&mut self, handle: LightHandle) {
+        self.lights.remove(handle);
+    }
+
+    /// Doesn't spawn anything until it's given a rate with `update_emitter`
+    pub fn create_emitter(
+        &mut self,
+        settings: EmitterSettings,
+        transform: &Transform,
+    ) -> EmitterHandle {
+        self.particles.create(settings, transform)
+    }
+
+    /// `velocity` is passed on to new particles, `rate` scales the settings' spawn rate
+    pub fn update_emitter(
+        &mut self,
+        handle: EmitterHandle,
+        transform: &Transform,
+        velocity: Vec3,
+        rate: f32,
+    ) {
+        self.particles
+            .update_emitter(handle, transform, velocity, rate);
+    }
+
+    /// The particles already spawned live out their lifetime
+    pub fn remove_emitter(&mut self, handle: EmitterHandle) {
+        self.particles.remove(handle);
+    }
+
+    /// Spawns `count` particles at once, for one shot effects like impact sparks
+    pub fn burst(
+        &mut self,
+        settings: &EmitterSettings,
+        transform: &Transform,
+        velocity: Vec3,
+        count: usize,
+    ) {
+        self.particles.burst(settings, transform, velocity, count);
+    }
+
+    /// Starts empty, the owner adds its position every frame with `update_trail`
+    pub fn create_trail(&mut self, settings: TrailSettings) -> TrailHandle {
+        self.trails.create(settings)
+    }
+
+    pub fn update_trail(&mut self, handle: TrailHandle, position: Vec3) {
+        self.trails.update_trail(handle, position);
+    }
+
+    /// Fades out over the settings' fade time instead of disappearing at once
+    pub fn remove_trail(&mut self, handle: TrailHandle) {
+        self.trails.remove(handle);
+    }
+
+    /// Moves and spawns the particles and ages the trails, a delta time of 0 freezes them
+    pub fn update_effects(&mut self, delta_time: f32) {
+        self.particles.update(delta_time);
+        self.trails.update(delta_time);
+    }
+
+    /// Particles and trails are in world space, so they have to move with everything else when the origin shifts
+    pub fn shift_origin(&mut self, offset: Vec3) {
+        self.particles.shift_origin(offset);
+        self.trails.shift_origin(offset);
+    }
+
+    /// Uploads the instances written since the last frame, returns the number of buffer writes
+    fn flush_instances(&mut self) -> u32 {
+        self.instance_set_map
+            .values_mut()
+            .map(|set| set.flush())
+            .sum()
+    }
+
+    /// Drops the instances out of draw distance and buckets the rest by LOD level.
+    /// Sets without LODs or draw distances are drawn straight from their buffers
+    fn build_visible_lists(&mut self, meshes: &SlotMap<MeshHandle, Mesh>, camera_position: Vec3) {
+        let distance_scale = self.distance_scale;
+        for (key, set) in self.instance_set_map.iter_mut() {
+            match meshes.get(key.mesh) {
+                Some(mesh) if (mesh.has_lods() || set.has_draw_distances()) && !set.is_empty() => {
+                    set.build_visible_list(mesh, camera_position, distance_scale)
+                }
+                _ => set.visible = None,
+            }
+        }
+    }
+
+    /// Only the first MAX_PUNCTUAL_LIGHTS lights are uploaded
+    fn light_data(&self) -> LightData {
+        let mut light_data = LightData::zeroed();
+        for (i, light) in self.lights.values().take(MAX_PUNCTUAL_LIGHTS).enumerate() {
+            light_data.lights[i] = light.as_light_data();
+            light_data.count[0] = i as u32 + 1;
+        }
+        light_data
+    }
+}
+
+impl Drop for SceneRenderData {
+    fn drop(&mut self) {
+        let mut resource_usage = self.resource_usage.lock().unwrap();
+        for instance_type in self.instance_map.values() {
+            resource_usage.remove(instance_type);
+        }
+    }
 }
 
 pub struct InstanceSet<T: bytemuck::Pod + Clone> {
     device: Arc<wgpu::Device>,
     queue: Arc<wgpu::Queue>,
+    bind_group_layout: Arc<wgpu::BindGroupLayout>,
 
     buffer: wgpu::Buffer,
     bind_group: wgpu::BindGroup,
+    instances: InstanceStaging<T>,
+
+    /// Only kept for meshes with LODs or sets with draw distances, rebuilt every frame
+    visible: Option<VisibleList<T>>,
+}
 
-    count: usize,
+/// Cpu side of an instance set, which slot every instance is in and which slots changed since the last flush.
+/// The buffer follows `capacity`, it has to be recreated whenever that grows
+struct InstanceStaging<T: Copy> {
     capacity: usize,
     instance_map: HashMap<InstanceHandle, (usize, T)>,
+    /// Cpu copy of the buffer by index, so dirty runs can be uploaded with one write each
+    staging: Vec<T>,
+    /// Indices written since the last flush, merged into ranges on flush
+    dirty: Vec<usize>,
+    /// Max distance from the camera of each instance by index, infinite unless it was set
+    draw_distances: Vec<f32>,
+}
+
+impl<T: Copy> InstanceStaging<T> {
+    fn new(capacity: usize) -> Self {
+        Self {
+            capacity,
+            instance_map: HashMap::new(),
+            staging: Vec::with_capacity(capacity),
+            dirty: Vec::new(),
+            draw_distances: Vec::with_capacity(capacity),
+        }
+    }
+
+    /// Returns true if the capacity doubled to fit it, every slot is dirty again in that case
+    fn add(&mut self, key: InstanceHandle, data: &T) -> bool {
+        let next_index = self.staging.len();
+        self.staging.push(*data);
+        self.draw_distances.push(f32::INFINITY);
+        self.dirty.push(next_index);
+        self.instance_map.insert(key, (next_index, *data));
+
+        if self.staging.len() > self.capacity {
+            self.capacity *= 2;
+            self.mark_all_dirty();
+            true
+        } else {
+            false
+        }
+    }
+
+    fn get(&self, key: InstanceHandle) -> Option<&T> {
+        self.instance_map.get(&key).map(|(_, data)| data)
+    }
+
+    fn update(&mut self, key: InstanceHandle, data: &T) {
+        let index = {
+            let instance_entry = self.instance_map.get_mut(&key).unwrap();
+            instance_entry.1 = *data;
+            instance_entry.0
+        };
+
+        self.staging[index] = *data;
+        self.dirty.push(index);
+    }
+
+    fn remove(&mut self, key: InstanceHandle) {
+        let removed_entry = self.instance_map.remove(&key).unwrap();
+        let last_index = self.staging.len() - 1;
+
+        //If the removed entry wasn't the last one, move the last entry into the removed slot
+        self.staging.swap_remove(removed_entry.0);
+        self.draw_distances.swap_remove(removed_entry.0);
+        if removed_entry.0 != last_index {
+            if let Some(last_entry) = self
+                .instance_map
+                .values_mut()
+                .find(|entry| entry.0 == last_index)
+            {
+                last_entry.0 = removed_entry.0;
+                self.dirty.push(removed_entry.0);
+            }
+        }
+    }
+
+    /// For a new buffer, everything has to be uploaded again
+    fn mark_all_dirty(&mut self) {
+        self.dirty.clear();
+        self.dirty.extend(0..self.staging.len());
+    }
+
+    /// Merges the indices written since the last flush into runs that each go out as a single write
+    fn take_dirty_ranges(&mut self) -> Vec<Range<usize>> {
+        if self.dirty.is_empty() {
+            return Vec::new();
+        }
+
+        let mut dirty = std::mem::take(&mut self.dirty);
+        dirty.sort_unstable();
+        dirty.dedup();
+
+        //Indices past the end were removed after being written
+        let count = self.staging.len();
+        dirty.retain(|index| *index < count);
+
+        //Past half dirty one big write is cheaper than picking out the runs
+        let mut ranges = Vec::new();
+        if dirty.len() * 2 > count {
+            ranges.push(0..count);
+        } else {
+            for index in dirty.iter().copied() {
+                match ranges.last_mut() {
+                    Some(range) if range.end == index => range.end += 1,
+                    _ => ranges.push(index..(index + 1)),
+                }
+            }
+        }
+
+        //Keeps the allocation for the next frame
+        dirty.clear();
+        self.dirty = dirty;
+        ranges
+    }
+
+    fn set_draw_distance(&mut self, key: InstanceHandle, distance: f32) {
+        if let Some((index, _)) = self.instance_map.get(&key) {
+            self.draw_distances[*index] = distance;
+        }
+    }
+
+    fn has_draw_distances(&self) -> bool {
+        self.draw_distances
+            .iter()
+            .any(|distance| distance.is_finite())
+    }
+
+    fn len(&self) -> usize {
+        self.staging.len()
+    }
+}
+
+/// Copy of an instance set with only the instances that are drawn, grouped by LOD level so each level is a single draw
+struct VisibleList<T: bytemuck::Pod> {
+    buffer: wgpu::Buffer,
+    bind_group: wgpu::BindGroup,
+    capacity: usize,
+    staging: Vec<T>,
+    /// Instances of each level in the buffer
+    ranges: Vec<Range<u32>>,
+    /// Instances left out of the buffer
+    skipped: u32,
 }
 
 impl<T: bytemuck::Pod> InstanceSet<T> {
     pub fn new(
         device: Arc<wgpu::Device>,
         queue: Arc<wgpu::Queue>,
-        bind_group_layout: &wgpu::BindGroupLayout,
+        bind_group_layout: Arc<wgpu::BindGroupLayout>,
         capacity: usize,
     ) -> Self {
+        let (buffer, bind_group) = Self::create_buffer(&device, &bind_group_layout, capacity);
+
+        Self {
+            device,
+            queue,
+            bind_group_layout,
+            buffer,
+            bind_group,
+            instances: InstanceStaging::new(capacity),
+            visible: None,
+        }
+    }
+
+    fn create_buffer(
+        device: &wgpu::Device,
+        bind_group_layout: &wgpu::BindGroupLayout,
+        capacity: usize,
+    ) -> (wgpu::Buffer, wgpu::BindGroup) {
         let buffer = device.create_buffer(&wgpu::BufferDescriptor {
             label: Some("InstanceSet Buffer"),
-            size: (capacity * std::mem::size_of::<T>()) as wgpu::BufferAddress,
-            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+            size: (capacity.max(1) * std::mem::size_of::<T>()) as wgpu::BufferAddress,
+            usage: wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_DST,
             mapped_at_creation: false,
         });
 
         let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
             label: Some("InstanceSet BindGroup"),
-            layout: &bind_group_layout,
+            layout: bind_group_layout,
             entries: &[wgpu::BindGroupEntry {
                 binding: 0,
                 resource: wgpu::BindingResource::Buffer(buffer.as_entire_buffer_binding()),
             }],
         });
 
-        Self {
-            device,
-            queue,
-            buffer,
-            bind_group,
-            count: 0,
-            capacity,
-            instance_map: HashMap::new(),
-        }
+        (buffer, bind_group)
+    }
+
+    /// Same as growing but on a new device at the same capacity
+    fn recreate(
+        &mut self,
+        device: Arc<wgpu::Device>,
+        queue: Arc<wgpu::Queue>,
+        bind_group_layout: Arc<wgpu::BindGroupLayout>,
+    ) {
+        self.device = device;
+        self.queue = queue;
+        self.bind_group_layout = bind_group_layout;
+        self.visible = None;
+        self.reallocate();
+    }
+
+    /// Every entry is re-uploaded to the same index in the new buffer on the next flush
+    fn reallocate(&mut self) {
+        let (buffer, bind_group) = Self::create_buffer(
+            &self.device,
+            &self.bind_group_layout,
+            self.instances.capacity,
+        );
+        self.buffer = buffer;
+        self.bind_group = bind_group;
+        self.instances.mark_all_dirty();
     }
 
     pub fn add(&mut self, key: InstanceHandle, data: &T) {
-        let next_index = self.count;
-        self.count += 1;
+        if self.instances.add(key, data) {
+            self.reallocate();
+        }
+    }
+    pub fn get(&self, key: InstanceHandle) -> Option<&T> {
+        self.instances.get(key)
+    }
+
+    pub fn update(&mut self, key: InstanceHandle, data: &T) {
+        self.instances.update(key, data);
+    }
+    pub fn remove(&mut self, key: InstanceHandle) {
+        self.instances.remove(key);
+    }
 
-        if self.count > self.capacity {
-            todo!("Resize Instance-Set Buffer");
+    /// Uploads everything written since the last flush, consecutive indices go out as a single write.
+    /// Returns the number of buffer writes
+    pub fn flush(&mut self) -> u32 {
+        let ranges = self.instances.take_dirty_ranges();
+        for range in ranges.iter().cloned() {
+            self.queue.write_buffer(
+                &self.buffer,
+                (range.start * std::mem::size_of::<T>()) as wgpu::BufferAddress,
+                bytemuck::cast_slice(&self.instances.staging[range]),
+            );
         }
+        ranges.len() as u32
+    }
 
-        let new_entry = (next_index, *data);
-        self.write_index(new_entry.0, &new_entry.1);
-        self.instance_map.insert(key, new_entry);
+    fn set_draw_distance(&mut self, key: InstanceHandle, distance: f32) {
+        self.instances.set_draw_distance(key, distance);
     }
-    pub fn update(&mut self, key: InstanceHandle, data: &T) {
-        let index = {
-            let instance_entry = self.instance_map.get_mut(&key).unwrap();
-            instance_entry.1 = *data;
-            instance_entry.0
-        };
 
-        self.write_index(index, data);
+    fn has_draw_distances(&self) -> bool {
+        self.instances.has_draw_distances()
     }
-    pub fn remove(&mut self, key: InstanceHandle) {
-        let removed_entry = self.instance_map.remove(&key).unwrap();
 
-        let last_index = self.count;
+    fn len(&self) -> usize {
+        self.instances.len()
+    }
 
-        //If the last entry still exists, move it too the removed slot
-        if let Some(entry) = self
-            .instance_map
-            .iter_mut()
-            .find(|entry| entry.1 .0 == last_index)
-            .map(|(_id, last_entry)| {
-                last_entry.0 = removed_entry.0;
-                *last_entry
-            })
+    fn is_empty(&self) -> bool {
+        self.instances.len() == 0
+    }
+}
+
+impl InstanceSet<InstanceData> {
+    /// The LOD level the instance at `index` is drawn with, None if it isn't drawn at all
+    fn visible_level(
+        &self,
+        mesh: &Mesh,
+        index: usize,
+        camera_position: Vec3,
+        distance_scale: f32,
+    ) -> Option<usize> {
+        let instance = &self.instances.staging[index];
+        let position = Vec3::from_slice(&instance.model_matrix[12..15]);
+        if position.distance(camera_position)
+            > self.instances.draw_distances[index] * distance_scale
         {
-            self.write_index(entry.0, &entry.1);
+            return None;
+        }
+        mesh.level_for(instance.lod_distance(camera_position) / distance_scale)
+    }
+
+    /// Counting sort of the drawn instances by their level, uploaded to the visible list's buffer
+    fn build_visible_list(&mut self, mesh: &Mesh, camera_position: Vec3, distance_scale: f32) {
+        let capacity = self.instances.capacity;
+        let levels: Vec<Option<usize>> = (0..self.instances.len())
+            .map(|index| self.visible_level(mesh, index, camera_position, distance_scale))
+            .collect();
+
+        let visible = self.visible.get_or_insert_with(|| {
+            let (buffer, bind_group) =
+                Self::create_buffer(&self.device, &self.bind_group_layout, capacity);
+            VisibleList {
+                buffer,
+                bind_group,
+                capacity,
+                staging: Vec::with_capacity(capacity),
+                ranges: Vec::new(),
+                skipped: 0,
+            }
+        });
+        if visible.capacity < capacity {
+            (visible.buffer, visible.bind_group) =
+                Self::create_buffer(&self.device, &self.bind_group_layout, capacity);
+            visible.capacity = capacity;
+        }
+
+        let mut counts = vec![0; mesh.levels.len()];
+        for level in levels.iter().flatten() {
+            counts[*level] += 1;
+        }
+        visible.ranges.clear();
+        let mut start = 0;
+        for count in counts {
+            visible.ranges.push(start..(start + count));
+            start += count;
+        }
+        visible.skipped = (self.instances.len() - start as usize) as u32;
+
+        let mut next: Vec<usize> = visible
+            .ranges
+            .iter()
+            .map(|range| range.start as usize)
+            .collect();
+        visible.staging.clear();
+        visible
+            .staging
+            .resize(start as usize, InstanceData::zeroed());
+        for (instance, level) in self.instances.staging.iter().zip(levels) {
+            if let Some(level) = level {
+                visible.staging[next[level]] = *instance;
+                next[level] += 1;
+            }
+        }
+
+        if !visible.staging.is_empty() {
+            self.queue
+                .write_buffer(&visible.buffer, 0, bytemuck::cast_slice(&visible.staging));
         }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::camera::PerspectiveCamera;
+
+    #[test]
+    fn renders_a_mesh_without_gpu_errors() {
+        let Some(mut renderer) = test_renderer() else {
+            eprintln!("No adapter, skipping");
+            return;
+        };
+
+        let positions: Vec<[f32; 3]> = (0..8)
+            .map(|corner| {
+                let axis = |bit: u32| if corner & bit == 0 { -1.0 } else { 1.0 };
+                [axis(1), axis(2), axis(4)]
+            })
+            .collect();
+        let indices: Vec<u32> = vec![
+            0, 1, 3, 0, 3, 2, 4, 6, 7, 4, 7, 5, 0, 4, 5, 0, 5, 1, 2, 3, 7, 2, 7, 6, 0, 2, 6, 0, 6,
+            4, 1, 5, 7, 1, 7, 3,
+        ];
+        let mesh = renderer.create_flat_mesh(&positions, &indices).unwrap();
+        let material = renderer
+            .create_pbr_material(PbrMaterialDefinition {
+                color: [1.0, 0.5, 0.25, 1.0],
+                metallic: 0.0,
+                roughness: 1.0,
+                albedo_texture: None,
+                normal_texture: None,
+                emissive: [0.0; 3],
+                transparent: false,
+                shading_model: ShadingModel::Unlit,
+                cull_mode: None,
+            })
+            .unwrap();
+
+        let mut scene = renderer.create_scene();
+        scene
+            .create_instance(mesh, material, &Transform::default())
+            .unwrap();
+
+        let size = [64, 64];
+        let camera = Transform::new_pos(Vec3::new(0.0, 0.0, -5.0));
+        let view_projection = PerspectiveCamera::default().as_reverse_perspective_matrix(size)
+            * camera.as_view_matrix();
+        let scene_data = SceneData {
+            view_projection_matrix: *view_projection.as_ref(),
+            ambient_light_color: [0.1; 4],
+            sun_light_direction_intensity: [0.0, -1.0, 0.0, 0.5],
+            sun_light_color: [1.0; 4],
+            camera_position: camera.position.extend(1.0).to_array(),
+        };
+        let pixels = renderer.render_to_texture(size, &scene_data, &mut scene);
+        assert_eq!(pixels.len(), 64 * 64 * 4);
 
-        self.count -= 1;
+        let pixel = |x: usize, y: usize| &pixels[(y * 64 + x) * 4..(y * 64 + x) * 4 + 3];
+        let center = pixel(32, 32);
+        assert!(center[0] > 200 && center[1] > 100, "center is {center:?}");
+        assert_ne!(center, pixel(0, 0), "the cube should only cover the middle");
     }
 
-    fn write_index(&mut self, index: usize, data: &T) {
-        self.queue.write_buffer(
-            &self.buffer,
-            (index * std::mem::size_of::<T>()) as wgpu::BufferAddress,
-            bytemuck::cast_slice(&[*data]),
-        )
+    fn add_instances(
+        instances: &mut InstanceStaging<u32>,
+        keys: &mut SlotMap<InstanceHandle, ()>,
+        count: u32,
+    ) -> Vec<InstanceHandle> {
+        (0..count)
+            .map(|data| {
+                let key = keys.insert(());
+                instances.add(key, &data);
+                key
+            })
+            .collect()
     }
 
-    fn len(&self) -> usize {
-        self.count
+    /// Every instance's slot holds its data and no two instances share a slot
+    fn assert_slots_match(instances: &InstanceStaging<u32>) {
+        let mut slots: Vec<usize> = instances
+            .instance_map
+            .values()
+            .map(|(index, data)| {
+                assert_eq!(instances.staging[*index], *data, "slot {index}");
+                *index
+            })
+            .collect();
+        slots.sort_unstable();
+        assert_eq!(slots, (0..instances.len()).collect::<Vec<_>>());
     }
 
-    fn is_empty(&self) -> bool {
-        self.count == 0
+    #[test]
+    fn instances_keep_their_slots_when_the_set_grows() {
+        let mut keys = SlotMap::with_key();
+        let mut instances = InstanceStaging::new(1024);
+        let mut grew = 0;
+        let handles: Vec<InstanceHandle> = (0..2000)
+            .map(|data| {
+                let key = keys.insert(());
+                grew += instances.add(key, &data) as u32;
+                key
+            })
+            .collect();
+        assert_eq!(grew, 1);
+        assert_eq!(instances.capacity, 2048);
+        assert_eq!(instances.take_dirty_ranges(), vec![0..2000]);
+        assert_slots_match(&instances);
+
+        instances.update(handles[1500], &9999);
+        assert_eq!(instances.get(handles[1500]), Some(&9999));
+        assert_eq!(instances.take_dirty_ranges(), vec![1500..1501]);
+
+        instances.remove(handles[10]);
+        assert_eq!(instances.get(handles[10]), None);
+        assert_eq!(instances.get(handles[1999]), Some(&1999));
+        assert_eq!(instances.instance_map[&handles[1999]].0, 10);
+        assert_slots_match(&instances);
+
+        //Past the new capacity it doubles again
+        add_instances(&mut instances, &mut keys, 100);
+        assert_eq!(instances.capacity, 4096);
+        assert_slots_match(&instances);
+    }
+
+    #[test]
+    fn remove_moves_the_last_instance_into_the_gap() {
+        let mut keys = SlotMap::with_key();
+        let mut instances = InstanceStaging::new(16);
+        let handles = add_instances(&mut instances, &mut keys, 10);
+        instances.take_dirty_ranges();
+
+        instances.remove(handles[4]);
+        assert_eq!(instances.len(), 9);
+        assert_slots_match(&instances);
+        assert_eq!(instances.instance_map[&handles[9]], (4, 9));
+        assert_eq!(instances.take_dirty_ranges(), vec![4..5]);
+
+        //Removing the last one leaves nothing to upload
+        instances.remove(handles[8]);
+        assert_slots_match(&instances);
+        assert!(instances.take_dirty_ranges().is_empty());
+    }
+
+    #[test]
+    fn dirty_ranges_write_less_than_single_instances() {
+        let mut keys = SlotMap::with_key();
+        let mut instances = InstanceStaging::new(4096);
+        let handles = add_instances(&mut instances, &mut keys, 4000);
+        instances.take_dirty_ranges();
+        let mut range_buffer = instances.staging.clone();
+        let mut single_buffer = instances.staging.clone();
+        let mut range_writes = 0;
+        let mut single_writes = 0;
+
+        for frame in 0..32u32 {
+            //A moving group, a few scattered updates and the odd removal
+            let group = (frame as usize * 97) % 3000;
+            for (offset, handle) in handles[group..(group + 200)].iter().enumerate() {
+                if instances.get(*handle).is_some() {
+                    instances.update(*handle, &(frame * 10000 + offset as u32));
+                }
+            }
+            for handle in handles.iter().skip(frame as usize).step_by(450) {
+                if instances.get(*handle).is_some() {
+                    instances.update(*handle, &frame);
+                }
+            }
+            if frame % 4 == 0 {
+                instances.remove(handles[frame as usize * 31]);
+            }
+
+            //What the old flush did, one write per dirty instance
+            let mut dirty = instances.dirty.clone();
+            dirty.sort_unstable();
+            dirty.dedup();
+            dirty.retain(|index| *index < instances.len());
+            for index in dirty {
+                single_buffer[index] = instances.staging[index];
+                single_writes += 1;
+            }
+
+            for range in instances.take_dirty_ranges() {
+                range_buffer[range.clone()].copy_from_slice(&instances.staging[range]);
+                range_writes += 1;
+            }
+            assert_eq!(range_buffer[..instances.len()], instances.staging[..]);
+            assert_eq!(single_buffer[..instances.len()], instances.staging[..]);
+        }
+        assert!(
+            range_writes * 10 < single_writes,
+            "{range_writes} range writes against {single_writes} single writes"
+        );
     }
 }
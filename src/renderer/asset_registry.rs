@@ -0,0 +1,71 @@
+use super::{MaterialHandle, MeshHandle};
+use std::collections::HashMap;
+
+/// Two way mapping between asset names and renderer handles, so every user of a name shares the same handle
+#[derive(Default)]
+pub struct AssetRegistry {
+    meshes: HashMap<String, MeshHandle>,
+    mesh_names: HashMap<MeshHandle, String>,
+    materials: HashMap<String, MaterialHandle>,
+    material_names: HashMap<MaterialHandle, String>,
+}
+
+impl AssetRegistry {
+    pub fn get_mesh(&self, name: &str) -> Option<MeshHandle> {
+        self.meshes.get(name).copied()
+    }
+
+    pub fn get_mesh_name(&self, handle: MeshHandle) -> Option<&str> {
+        self.mesh_names.get(&handle).map(String::as_str)
+    }
+
+    pub fn meshes(&self) -> impl Iterator<Item = (&str, MeshHandle)> {
+        self.meshes
+            .iter()
+            .map(|(name, handle)| (name.as_str(), *handle))
+    }
+
+    pub fn get_material(&self, name: &str) -> Option<MaterialHandle> {
+        self.materials.get(name).copied()
+    }
+
+    pub fn get_material_name(&self, handle: MaterialHandle) -> Option<&str> {
+        self.material_names.get(&handle).map(String::as_str)
+    }
+
+    /// Replaces any handle that was already registered under the name
+    pub(super) fn register_mesh(&mut self, name: &str, handle: MeshHandle) {
+        if let Some(old_handle) = self.meshes.insert(name.to_string(), handle) {
+            self.mesh_names.remove(&old_handle);
+        }
+        if let Some(old_name) = self.mesh_names.insert(handle, name.to_string()) {
+            if old_name != name {
+                self.meshes.remove(&old_name);
+            }
+        }
+    }
+
+    pub(super) fn unregister_mesh(&mut self, handle: MeshHandle) {
+        if let Some(name) = self.mesh_names.remove(&handle) {
+            self.meshes.remove(&name);
+        }
+    }
+
+    /// Replaces any handle that was already registered under the name
+    pub(super) fn register_material(&mut self, name: &str, handle: MaterialHandle) {
+        if let Some(old_handle) = self.materials.insert(name.to_string(), handle) {
+            self.material_names.remove(&old_handle);
+        }
+        if let Some(old_name) = self.material_names.insert(handle, name.to_string()) {
+            if old_name != name {
+                self.materials.remove(&old_name);
+            }
+        }
+    }
+
+    pub(super) fn unregister_material(&mut self, handle: MaterialHandle) {
+        if let Some(name) = self.material_names.remove(&handle) {
+            self.materials.remove(&name);
+        }
+    }
+}
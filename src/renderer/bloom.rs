@@ -0,0 +1,423 @@
+use super::{needs_srgb_encode, HDR_FORMAT};
+use bytemuck::{Pod, Zeroable};
+use std::borrow::Cow;
+use wgpu::util::DeviceExt;
+
+const MAX_BLOOM_MIPS: usize = 6;
+
+#[derive(Clone, Copy, Debug)]
+pub struct BloomSettings {
+    /// Disabling bloom renders straight into the output and skips every bloom pass
+    pub enabled: bool,
+    /// Brightness above which pixels start to bloom, values above 1.0 only catch emissive and bright lights
+    pub threshold: f32,
+    /// Scale of the blurred bloom added back on top of the scene
+    pub intensity: f32,
+}
+
+impl Default for BloomSettings {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            threshold: 1.0,
+            intensity: 0.5,
+        }
+    }
+}
+
+#[repr(C)]
+#[derive(Pod, Zeroable, Copy, Clone, Debug)]
+struct BloomData {
+    /// x: threshold, y: soft knee, z: intensity
+    params: [f32; 4],
+}
+
+impl BloomData {
+    fn new(settings: &BloomSettings) -> Self {
+        Self {
+            params: [
+                settings.threshold,
+                settings.threshold * 0.5,
+                settings.intensity,
+                0.0,
+            ],
+        }
+    }
+}
+
+struct BloomMip {
+    _texture: wgpu::Texture,
+    view: wgpu::TextureView,
+    _blur_texture: wgpu::Texture,
+    blur_view: wgpu::TextureView,
+
+    /// Samples view
+    bind_group: wgpu::BindGroup,
+    /// Samples blur_view
+    blur_bind_group: wgpu::BindGroup,
+}
+
+/// Size dependent bloom textures, recreated with the rest of the render targets
+pub(super) struct BloomTargets {
+    mips: Vec<BloomMip>,
+    /// Samples the hdr scene
+    threshold_bind_group: wgpu::BindGroup,
+    /// Samples the hdr scene and the first bloom mip
+    composite_bind_group: wgpu::BindGroup,
+}
+
+struct BloomPipelines {
+    threshold: wgpu::RenderPipeline,
+    downsample: wgpu::RenderPipeline,
+    blur_horizontal: wgpu::RenderPipeline,
+    blur_vertical: wgpu::RenderPipeline,
+    upsample: wgpu::RenderPipeline,
+    composite: wgpu::RenderPipeline,
+}
+
+pub(super) struct Bloom {
+    settings: BloomSettings,
+    uniform_buffer: wgpu::Buffer,
+    sampler: wgpu::Sampler,
+
+    bind_group_layout: wgpu::BindGroupLayout,
+    pipeline_layout: wgpu::PipelineLayout,
+    pipelines: BloomPipelines,
+}
+
+impl Bloom {
+    pub(super) fn new(
+        device: &wgpu::Device,
+        settings: BloomSettings,
+        output_format: wgpu::TextureFormat,
+    ) -> Self {
+        let uniform_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Bloom Buffer"),
+            contents: bytemuck::cast_slice(&[BloomData::new(&settings)]),
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+        });
+
+        let sampler = device.create_sampler(&wgpu::SamplerDescriptor {
+            label: Some("Bloom Sampler"),
+            address_mode_u: wgpu::AddressMode::ClampToEdge,
+            address_mode_v: wgpu::AddressMode::ClampToEdge,
+            mag_filter: wgpu::FilterMode::Linear,
+            min_filter: wgpu::FilterMode::Linear,
+            ..Default::default()
+        });
+
+        let texture_entry = |binding| wgpu::BindGroupLayoutEntry {
+            binding,
+            visibility: wgpu::ShaderStages::FRAGMENT,
+            ty: wgpu::BindingType::Texture {
+                sample_type: wgpu::TextureSampleType::Float { filterable: true },
+                view_dimension: wgpu::TextureViewDimension::D2,
+                multisampled: false,
+            },
+            count: None,
+        };
+
+        let bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("Bloom BindGroupLayout"),
+            entries: &[
+                texture_entry(0),
+                wgpu::BindGroupLayoutEntry {
+                    binding: 1,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 2,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        min_binding_size: None,
+                        has_dynamic_offset: false,
+                    },
+                    count: None,
+                },
+                texture_entry(3),
+            ],
+        });
+
+        let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Bloom PipelineLayout"),
+            bind_group_layouts: &[&bind_group_layout],
+            push_constant_ranges: &[],
+        });
+
+        let pipelines = create_bloom_pipelines(device, &pipeline_layout, output_format);
+
+        Self {
+            settings,
+            uniform_buffer,
+            sampler,
+            bind_group_layout,
+            pipeline_layout,
+            pipelines,
+        }
+    }
+
+    pub(super) fn rebuild_pipelines(
+        &mut self,
+        device: &wgpu::Device,
+        output_format: wgpu::TextureFormat,
+    ) {
+        self.pipelines = create_bloom_pipelines(device, &self.pipeline_layout, output_format);
+    }
+
+    pub(super) fn settings(&self) -> BloomSettings {
+        self.settings
+    }
+
+    pub(super) fn set_settings(&mut self, queue: &wgpu::Queue, settings: BloomSettings) {
+        self.settings = settings;
+        queue.write_buffer(
+            &self.uniform_buffer,
+            0,
+            bytemuck::cast_slice(&[BloomData::new(&settings)]),
+        );
+    }
+
+    fn create_bind_group(
+        &self,
+        device: &wgpu::Device,
+        source: &wgpu::TextureView,
+        bloom: &wgpu::TextureView,
+    ) -> wgpu::BindGroup {
+        device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("Bloom BindGroup"),
+            layout: &self.bind_group_layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: wgpu::BindingResource::TextureView(source),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: wgpu::BindingResource::Sampler(&self.sampler),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 2,
+                    resource: wgpu::BindingResource::Buffer(
+                        self.uniform_buffer.as_entire_buffer_binding(),
+                    ),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 3,
+                    resource: wgpu::BindingResource::TextureView(bloom),
+                },
+            ],
+        })
+    }
+
+    /// Builds a mip chain starting at half the scene size, stopping before mips get too small to blur
+    pub(super) fn create_targets(
+        &self,
+        device: &wgpu::Device,
+        hdr_view: &wgpu::TextureView,
+        size: [u32; 2],
+    ) -> BloomTargets {
+        let create_texture = |label, size: [u32; 2]| {
+            let texture = device.create_texture(&wgpu::TextureDescriptor {
+                label: Some(label),
+                size: wgpu::Extent3d {
+                    width: size[0],
+                    height: size[1],
+                    depth_or_array_layers: 1,
+                },
+                mip_level_count: 1,
+                sample_count: 1,
+                dimension: wgpu::TextureDimension::D2,
+                format: HDR_FORMAT,
+                usage: wgpu::TextureUsages::RENDER_ATTACHMENT
+                    | wgpu::TextureUsages::TEXTURE_BINDING,
+                view_formats: &[],
+            });
+            let view = texture.create_view(&wgpu::TextureViewDescriptor::default());
+            (texture, view)
+        };
+
+        let mut mips = Vec::new();
+        let mut mip_size = [(size[0] / 2).max(1), (size[1] / 2).max(1)];
+        while mips.len() < MAX_BLOOM_MIPS && (mips.is_empty() || mip_size[0].min(mip_size[1]) >= 8)
+        {
+            let (texture, view) = create_texture("Bloom Texture", mip_size);
+            let (blur_texture, blur_view) = create_texture("Bloom Blur Texture", mip_size);
+            mips.push(BloomMip {
+                bind_group: self.create_bind_group(device, &view, &view),
+                blur_bind_group: self.create_bind_group(device, &blur_view, &blur_view),
+                _texture: texture,
+                view,
+                _blur_texture: blur_texture,
+                blur_view,
+            });
+            mip_size = [(mip_size[0] / 2).max(1), (mip_size[1] / 2).max(1)];
+        }
+
+        BloomTargets {
+            threshold_bind_group: self.create_bind_group(device, hdr_view, hdr_view),
+            composite_bind_group: self.create_bind_group(device, hdr_view, &mips[0].view),
+            mips,
+        }
+    }
+
+    /// Blooms the hdr scene and composites the result into output_view, returns the number of draw calls
+    pub(super) fn render(
+        &self,
+        encoder: &mut wgpu::CommandEncoder,
+        targets: &BloomTargets,
+        output_view: &wgpu::TextureView,
+    ) -> u32 {
+        let draw = |encoder: &mut wgpu::CommandEncoder,
+                    label,
+                    target: &wgpu::TextureView,
+                    load: wgpu::LoadOp<wgpu::Color>,
+                    pipeline: &wgpu::RenderPipeline,
+                    bind_group: &wgpu::BindGroup| {
+            let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+                label: Some(label),
+                color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                    view: target,
+                    resolve_target: None,
+                    ops: wgpu::Operations { load, store: true },
+                })],
+                depth_stencil_attachment: None,
+            });
+            render_pass.set_pipeline(pipeline);
+            render_pass.set_bind_group(0, bind_group, &[]);
+            render_pass.draw(0..3, 0..1);
+        };
+        let clear = wgpu::LoadOp::Clear(wgpu::Color::BLACK);
+
+        for (i, mip) in targets.mips.iter().enumerate() {
+            match i {
+                0 => draw(
+                    encoder,
+                    "Bloom Threshold Pass",
+                    &mip.view,
+                    clear,
+                    &self.pipelines.threshold,
+                    &targets.threshold_bind_group,
+                ),
+                _ => draw(
+                    encoder,
+                    "Bloom Downsample Pass",
+                    &mip.view,
+                    clear,
+                    &self.pipelines.downsample,
+                    &targets.mips[i - 1].bind_group,
+                ),
+            }
+
+            draw(
+                encoder,
+                "Bloom Blur Pass",
+                &mip.blur_view,
+                clear,
+                &self.pipelines.blur_horizontal,
+                &mip.bind_group,
+            );
+            draw(
+                encoder,
+                "Bloom Blur Pass",
+                &mip.view,
+                clear,
+                &self.pipelines.blur_vertical,
+                &mip.blur_bind_group,
+            );
+        }
+
+        //Accumulate the smaller mips back up into the first one
+        for i in (1..targets.mips.len()).rev() {
+            draw(
+                encoder,
+                "Bloom Upsample Pass",
+                &targets.mips[i - 1].view,
+                wgpu::LoadOp::Load,
+                &self.pipelines.upsample,
+                &targets.mips[i].bind_group,
+            );
+        }
+
+        draw(
+            encoder,
+            "Bloom Composite Pass",
+            output_view,
+            clear,
+            &self.pipelines.composite,
+            &targets.composite_bind_group,
+        );
+
+        //Threshold or downsample plus two blurs per mip, the upsamples, and the composite
+        (targets.mips.len() * 4) as u32
+    }
+}
+
+fn create_bloom_pipelines(
+    device: &wgpu::Device,
+    pipeline_layout: &wgpu::PipelineLayout,
+    output_format: wgpu::TextureFormat,
+) -> BloomPipelines {
+    let code = include_str!("../shader/bloom.wgsl");
+    let shader_module = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+        label: Some("Bloom Shader"),
+        source: wgpu::ShaderSource::Wgsl(Cow::from(code)),
+    });
+
+    let create_pipeline = |entry_point, format, blend| {
+        device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+            label: Some("Bloom Pipeline"),
+            layout: Some(pipeline_layout),
+            vertex: wgpu::VertexState {
+                module: &shader_module,
+                entry_point: "vs_main",
+                buffers: &[],
+            },
+            primitive: Default::default(),
+            depth_stencil: None,
+            multisample: Default::default(),
+            fragment: Some(wgpu::FragmentState {
+                module: &shader_module,
+                entry_point,
+                targets: &[Some(wgpu::ColorTargetState {
+                    format,
+                    blend,
+                    write_mask: wgpu::ColorWrites::ALL,
+                })],
+            }),
+            multiview: None,
+        })
+    };
+
+    let additive = wgpu::BlendComponent {
+        src_factor: wgpu::BlendFactor::One,
+        dst_factor: wgpu::BlendFactor::One,
+        operation: wgpu::BlendOperation::Add,
+    };
+
+    BloomPipelines {
+        threshold: create_pipeline("fs_threshold", HDR_FORMAT, None),
+        downsample: create_pipeline("fs_downsample", HDR_FORMAT, None),
+        blur_horizontal: create_pipeline("fs_blur_horizontal", HDR_FORMAT, None),
+        blur_vertical: create_pipeline("fs_blur_vertical", HDR_FORMAT, None),
+        upsample: create_pipeline(
+            "fs_upsample",
+            HDR_FORMAT,
+            Some(wgpu::BlendState {
+                color: additive,
+                alpha: additive,
+            }),
+        ),
+        composite: create_pipeline(
+            if needs_srgb_encode(output_format) {
+                "fs_composite_srgb_encode"
+            } else {
+                "fs_composite"
+            },
+            output_format,
+            None,
+        ),
+    }
+}
@@ -0,0 +1,313 @@
+use super::needs_srgb_encode;
+use crate::transform::Transform;
+use bytemuck::{Pod, Zeroable};
+use glam::{Quat, Vec3};
+use std::borrow::Cow;
+
+const SPHERE_SEGMENTS: usize = 24;
+
+#[repr(C)]
+#[derive(Pod, Zeroable, Copy, Clone, Debug)]
+struct DebugLineVertex {
+    position: [f32; 3],
+    color: [f32; 4],
+}
+
+impl DebugLineVertex {
+    const ATTRIBUTES: [wgpu::VertexAttribute; 2] = wgpu::vertex_attr_array![
+        0 => Float32x3,
+        1 => Float32x4,
+    ];
+
+    fn desc<'a>() -> wgpu::VertexBufferLayout<'a> {
+        wgpu::VertexBufferLayout {
+            array_stride: std::mem::size_of::<DebugLineVertex>() as wgpu::BufferAddress,
+            step_mode: wgpu::VertexStepMode::Vertex,
+            attributes: &Self::ATTRIBUTES,
+        }
+    }
+}
+
+/// World space lines accumulated over a frame, cleared after they are drawn
+#[derive(Default)]
+pub struct DebugLines {
+    depth_tested: Vec<DebugLineVertex>,
+    overlay: Vec<DebugLineVertex>,
+}
+
+impl DebugLines {
+    pub fn line(&mut self, from: Vec3, to: Vec3, color: [f32; 4], depth_test: bool) {
+        let vertices = if depth_test {
+            &mut self.depth_tested
+        } else {
+            &mut self.overlay
+        };
+
+        vertices.push(DebugLineVertex {
+            position: from.to_array(),
+            color,
+        });
+        vertices.push(DebugLineVertex {
+            position: to.to_array(),
+            color,
+        });
+    }
+
+    pub fn box_outline(
+        &mut self,
+        transform: &Transform,
+        half_extents: Vec3,
+        color: [f32; 4],
+        depth_test: bool,
+    ) {
+        let corner = |x: f32, y: f32, z: f32| {
+            transform.position
+                + transform.rotation * (Vec3::new(x, y, z) * half_extents * transform.scale)
+        };
+
+        for (x, y) in [(-1.0, -1.0), (1.0, -1.0), (1.0, 1.0), (-1.0, 1.0)] {
+            self.line(corner(x, y, -1.0), corner(x, y, 1.0), color, depth_test);
+            self.line(corner(x, -1.0, y), corner(x, 1.0, y), color, depth_test);
+            self.line(corner(-1.0, x, y), corner(1.0, x, y), color, depth_test);
+        }
+    }
+
+    pub fn sphere(&mut self, center: Vec3, radius: f32, color: [f32; 4], depth_test: bool) {
+        self.circle(center, Quat::IDENTITY, radius, color, depth_test);
+        self.circle(
+            center,
+            Quat::from_rotation_x(std::f32::consts::FRAC_PI_2),
+            radius,
+            color,
+            depth_test,
+        );
+        self.circle(
+            center,
+            Quat::from_rotation_y(std::f32::consts::FRAC_PI_2),
+            radius,
+            color,
+            depth_test,
+        );
+    }
+
+    /// Circle in the local XY plane of `rotation`
+    pub fn circle(
+        &mut self,
+        center: Vec3,
+        rotation: Quat,
+        radius: f32,
+        color: [f32; 4],
+        depth_test: bool,
+    ) {
+        let point = |i: usize| {
+            let angle = (i as f32 / SPHERE_SEGMENTS as f32) * std::f32::consts::TAU;
+            center + rotation * (Vec3::new(angle.cos(), angle.sin(), 0.0) * radius)
+        };
+
+        for i in 0..SPHERE_SEGMENTS {
+            self.line(point(i), point(i + 1), color, depth_test);
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.depth_tested.is_empty() && self.overlay.is_empty()
+    }
+
+    pub fn clear(&mut self) {
+        self.depth_tested.clear();
+        self.overlay.clear();
+    }
+}
+
+pub(super) struct DebugLinePass {
+    pipeline_layout: wgpu::PipelineLayout,
+    depth_tested_pipeline: wgpu::RenderPipeline,
+    overlay_pipeline: wgpu::RenderPipeline,
+
+    vertex_buffer: wgpu::Buffer,
+    vertex_capacity: usize,
+    depth_tested_count: u32,
+    overlay_count: u32,
+}
+
+impl DebugLinePass {
+    pub(super) fn new(
+        device: &wgpu::Device,
+        scene_bind_group_layout: &wgpu::BindGroupLayout,
+        output_format: wgpu::TextureFormat,
+        depth_format: wgpu::TextureFormat,
+        sample_count: u32,
+    ) -> Self {
+        let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Debug Line PipelineLayout"),
+            bind_group_layouts: &[scene_bind_group_layout],
+            push_constant_ranges: &[],
+        });
+
+        let (depth_tested_pipeline, overlay_pipeline) = create_debug_line_pipelines(
+            device,
+            &pipeline_layout,
+            output_format,
+            depth_format,
+            sample_count,
+        );
+
+        let vertex_capacity = 1024;
+        let vertex_buffer = create_vertex_buffer(device, vertex_capacity);
+
+        Self {
+            pipeline_layout,
+            depth_tested_pipeline,
+            overlay_pipeline,
+            vertex_buffer,
+            vertex_capacity,
+            depth_tested_count: 0,
+            overlay_count: 0,
+        }
+    }
+
+    pub(super) fn rebuild_pipelines(
+        &mut self,
+        device: &wgpu::Device,
+        output_format: wgpu::TextureFormat,
+        depth_format: wgpu::TextureFormat,
+        sample_count: u32,
+    ) {
+        (self.depth_tested_pipeline, self.overlay_pipeline) = create_debug_line_pipelines(
+            device,
+            &self.pipeline_layout,
+            output_format,
+            depth_format,
+            sample_count,
+        );
+    }
+
+    /// Uploads this frame's lines, growing the vertex buffer if needed
+    pub(super) fn update(
+        &mut self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        lines: &DebugLines,
+    ) {
+        let vertex_count = lines.depth_tested.len() + lines.overlay.len();
+        if vertex_count > self.vertex_capacity {
+            self.vertex_capacity = vertex_count.next_power_of_two();
+            self.vertex_buffer = create_vertex_buffer(device, self.vertex_capacity);
+        }
+
+        if !lines.depth_tested.is_empty() {
+            queue.write_buffer(
+                &self.vertex_buffer,
+                0,
+                bytemuck::cast_slice(&lines.depth_tested),
+            );
+        }
+        if !lines.overlay.is_empty() {
+            queue.write_buffer(
+                &self.vertex_buffer,
+                (lines.depth_tested.len() * std::mem::size_of::<DebugLineVertex>())
+                    as wgpu::BufferAddress,
+                bytemuck::cast_slice(&lines.overlay),
+            );
+        }
+
+        self.depth_tested_count = lines.depth_tested.len() as u32;
+        self.overlay_count = lines.overlay.len() as u32;
+    }
+
+    /// Expects the scene bind group to already be bound at group 0, returns the number of draw calls
+    pub(super) fn draw<'a>(&'a self, render_pass: &mut wgpu::RenderPass<'a>) -> u32 {
+        let mut draw_calls = 0;
+        if self.depth_tested_count == 0 && self.overlay_count == 0 {
+            return draw_calls;
+        }
+
+        render_pass.set_vertex_buffer(0, self.vertex_buffer.slice(..));
+
+        if self.depth_tested_count > 0 {
+            render_pass.set_pipeline(&self.depth_tested_pipeline);
+            render_pass.draw(0..self.depth_tested_count, 0..1);
+            draw_calls += 1;
+        }
+
+        if self.overlay_count > 0 {
+            render_pass.set_pipeline(&self.overlay_pipeline);
+            render_pass.draw(
+                self.depth_tested_count..(self.depth_tested_count + self.overlay_count),
+                0..1,
+            );
+            draw_calls += 1;
+        }
+
+        draw_calls
+    }
+}
+
+fn create_vertex_buffer(device: &wgpu::Device, vertex_capacity: usize) -> wgpu::Buffer {
+    device.create_buffer(&wgpu::BufferDescriptor {
+        label: Some("Debug Line Vertex Buffer"),
+        size: (vertex_capacity * std::mem::size_of::<DebugLineVertex>()) as wgpu::BufferAddress,
+        usage: wgpu::BufferUsages::VERTEX | wgpu::BufferUsages::COPY_DST,
+        mapped_at_creation: false,
+    })
+}
+
+fn create_debug_line_pipelines(
+    device: &wgpu::Device,
+    pipeline_layout: &wgpu::PipelineLayout,
+    output_format: wgpu::TextureFormat,
+    depth_format: wgpu::TextureFormat,
+    sample_count: u32,
+) -> (wgpu::RenderPipeline, wgpu::RenderPipeline) {
+    let code = include_str!("../shader/debug_lines.wgsl");
+    let shader_module = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+        label: Some("Debug Line Shader"),
+        source: wgpu::ShaderSource::Wgsl(Cow::from(code)),
+    });
+
+    let create_pipeline = |depth_compare| {
+        device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+            label: Some("Debug Line Pipeline"),
+            layout: Some(pipeline_layout),
+            vertex: wgpu::VertexState {
+                module: &shader_module,
+                entry_point: "vs_main",
+                buffers: &[DebugLineVertex::desc()],
+            },
+            primitive: wgpu::PrimitiveState {
+                topology: wgpu::PrimitiveTopology::LineList,
+                ..Default::default()
+            },
+            depth_stencil: Some(wgpu::DepthStencilState {
+                format: depth_format,
+                depth_write_enabled: false,
+                depth_compare,
+                stencil: Default::default(),
+                bias: Default::default(),
+            }),
+            multisample: wgpu::MultisampleState {
+                count: sample_count,
+                ..Default::default()
+            },
+            fragment: Some(wgpu::FragmentState {
+                module: &shader_module,
+                entry_point: if needs_srgb_encode(output_format) {
+                    "fs_main_srgb_encode"
+                } else {
+                    "fs_main"
+                },
+                targets: &[Some(wgpu::ColorTargetState {
+                    format: output_format,
+                    blend: Some(wgpu::BlendState::ALPHA_BLENDING),
+                    write_mask: wgpu::ColorWrites::ALL,
+                })],
+            }),
+            multiview: None,
+        })
+    };
+
+    (
+        create_pipeline(wgpu::CompareFunction::GreaterEqual),
+        create_pipeline(wgpu::CompareFunction::Always),
+    )
+}
@@ -0,0 +1,283 @@
+use super::bloom::{Bloom, BloomTargets};
+use super::{LightData, RenderStats, SceneData, DEPTH_FORMAT, HDR_FORMAT};
+use bytemuck::Zeroable;
+use wgpu::util::DeviceExt;
+
+/// The passes of a frame, a new pass only needs a variant here, a spot in `FRAME_PASSES` and its arm in `Renderer::execute_pass`
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub(super) enum Pass {
+    /// Skybox, opaque then transparent instances, debug lines and world labels, all sharing the depth buffer
+    Scene,
+    /// Blurs the hdr target and composites it into the output, does nothing without bloom
+    Bloom,
+    /// Screen space shapes over the final image, after bloom so they aren't blurred
+    Hud,
+}
+
+/// Executed in this order every frame
+pub(super) const FRAME_PASSES: [Pass; 3] = [Pass::Scene, Pass::Bloom, Pass::Hud];
+
+/// Size dependent targets, recreated together when the size changes or the pipelines are rebuilt
+pub(super) struct RenderTargets {
+    pub(super) size: [u32; 2],
+    pub(super) depth: (wgpu::Texture, wgpu::TextureView),
+    pub(super) msaa_color: Option<(wgpu::Texture, wgpu::TextureView)>,
+    pub(super) hdr: Option<HdrTargets>,
+}
+
+pub(super) struct HdrTargets {
+    pub(super) color: (wgpu::Texture, wgpu::TextureView),
+    pub(super) bloom: BloomTargets,
+}
+
+impl RenderTargets {
+    /// Color view and resolve target of the scene pass
+    pub(super) fn scene_attachment<'a>(
+        &'a self,
+        output: &'a wgpu::TextureView,
+    ) -> (&'a wgpu::TextureView, Option<&'a wgpu::TextureView>) {
+        //With bloom the scene is drawn into the hdr target and composited into the output at the end
+        let scene_view = match &self.hdr {
+            Some(hdr) => &hdr.color.1,
+            None => output,
+        };
+
+        //With MSAA everything is drawn into the multisampled target and resolved into the scene target
+        match &self.msaa_color {
+            Some((_texture, view)) => (view, Some(scene_view)),
+            None => (scene_view, None),
+        }
+    }
+}
+
+/// Everything the passes share, the uniform buffers live as long as the renderer while the targets follow the size
+pub(super) struct FrameResources {
+    scene_data: (wgpu::Buffer, wgpu::BindGroup),
+    lights_buffer: wgpu::Buffer,
+    targets: Option<RenderTargets>,
+    /// How many times the targets were created, resizes to the same size shouldn't add to it
+    #[cfg(test)]
+    targets_created: u32,
+}
+
+impl FrameResources {
+    pub(super) fn new(
+        device: &wgpu::Device,
+        scene_bind_group_layout: &wgpu::BindGroupLayout,
+    ) -> Self {
+        let lights_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Lights Buffer"),
+            contents: bytemuck::cast_slice(&[LightData::zeroed()]),
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+        });
+
+        let scene_data_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: None,
+            contents: bytemuck::cast_slice(&[SceneData::zeroed()]),
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+        });
+
+        let scene_data_bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: None,
+            layout: scene_bind_group_layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: wgpu::BindingResource::Buffer(
+                        scene_data_buffer.as_entire_buffer_binding(),
+                    ),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: wgpu::BindingResource::Buffer(
+                        lights_buffer.as_entire_buffer_binding(),
+                    ),
+                },
+            ],
+        });
+
+        Self {
+            scene_data: (scene_data_buffer, scene_data_bind_group),
+            lights_buffer,
+            targets: None,
+            #[cfg(test)]
+            targets_created: 0,
+        }
+    }
+
+    pub(super) fn scene_bind_group(&self) -> &wgpu::BindGroup {
+        &self.scene_data.1
+    }
+
+    pub(super) fn targets(&self) -> Option<&RenderTargets> {
+        self.targets.as_ref()
+    }
+
+    pub(super) fn write_uniforms(
+        &self,
+        queue: &wgpu::Queue,
+        scene_data: &SceneData,
+        light_data: &LightData,
+    ) {
+        queue.write_buffer(&self.scene_data.0, 0, bytemuck::cast_slice(&[*scene_data]));
+        queue.write_buffer(&self.lights_buffer, 0, bytemuck::cast_slice(&[*light_data]));
+    }
+
+    /// The targets are recreated on the next resize
+    pub(super) fn invalidate_targets(&mut self) {
+        self.targets = None;
+    }
+
+    /// Recreates the targets if the size changed, zero sized targets are released until a valid size arrives
+    pub(super) fn resize(
+        &mut self,
+        device: &wgpu::Device,
+        size: [u32; 2],
+        sample_count: u32,
+        scene_color_format: wgpu::TextureFormat,
+        bloom: &Bloom,
+    ) {
+        if size[0] == 0 || size[1] == 0 {
+            self.targets = None;
+            return;
+        }
+
+        if self
+            .targets
+            .as_ref()
+            .is_some_and(|targets| targets.size == size)
+        {
+            return;
+        }
+
+        let create_target = |label, format| {
+            let texture = device.create_texture(&wgpu::TextureDescriptor {
+                label: Some(label),
+                size: wgpu::Extent3d {
+                    width: size[0],
+                    height: size[1],
+                    depth_or_array_layers: 1,
+                },
+                mip_level_count: 1,
+                sample_count,
+                dimension: wgpu::TextureDimension::D2,
+                format,
+                usage: wgpu::TextureUsages::RENDER_ATTACHMENT,
+                view_formats: &[],
+            });
+            let view = texture.create_view(&wgpu::TextureViewDescriptor::default());
+            (texture, view)
+        };
+
+        let depth = create_target("Depth Texture", DEPTH_FORMAT);
+        let msaa_color = if sample_count > 1 {
+            Some(create_target("MSAA Color Texture", scene_color_format))
+        } else {
+            None
+        };
+
+        let hdr = if bloom.settings().enabled {
+            let texture = device.create_texture(&wgpu::TextureDescriptor {
+                label: Some("HDR Color Texture"),
+                size: wgpu::Extent3d {
+                    width: size[0],
+                    height: size[1],
+                    depth_or_array_layers: 1,
+                },
+                mip_level_count: 1,
+                sample_count: 1,
+                dimension: wgpu::TextureDimension::D2,
+                format: HDR_FORMAT,
+                usage: wgpu::TextureUsages::RENDER_ATTACHMENT
+                    | wgpu::TextureUsages::TEXTURE_BINDING,
+                view_formats: &[],
+            });
+            let view = texture.create_view(&wgpu::TextureViewDescriptor::default());
+            let bloom = bloom.create_targets(device, &view, size);
+            Some(HdrTargets {
+                color: (texture, view),
+                bloom,
+            })
+        } else {
+            None
+        };
+
+        self.targets = Some(RenderTargets {
+            size,
+            depth,
+            msaa_color,
+            hdr,
+        });
+        #[cfg(test)]
+        {
+            self.targets_created += 1;
+        }
+    }
+}
+
+/// What a pass gets to record with
+pub(super) struct PassContext<'a> {
+    pub(super) encoder: &'a mut wgpu::CommandEncoder,
+    pub(super) resources: &'a FrameResources,
+    pub(super) targets: &'a RenderTargets,
+    /// The surface texture or whatever else the frame ends up in
+    pub(super) output: &'a wgpu::TextureView,
+    pub(super) stats: &'a mut RenderStats,
+}
+
+/// Everything recorded over a frame, submitted together by `Renderer::end_frame`
+pub struct FrameContext {
+    pub(crate) encoder: wgpu::CommandEncoder,
+    pub(crate) output_view: wgpu::TextureView,
+    pub(super) output_texture: Option<wgpu::SurfaceTexture>,
+    /// Recorded outside the frame's encoder, submitted ahead of it
+    pub(super) command_buffers: Vec<wgpu::CommandBuffer>,
+    pub(super) stats: RenderStats,
+    pub(super) start: std::time::Instant,
+}
+
+impl FrameContext {
+    pub fn add_command_buffers(
+        &mut self,
+        command_buffers: impl IntoIterator<Item = wgpu::CommandBuffer>,
+    ) {
+        self.command_buffers.extend(command_buffers);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn pass_order_is_stable() {
+        //Bloom composites the scene, the hud goes on top of the composited image
+        assert_eq!(FRAME_PASSES, [Pass::Scene, Pass::Bloom, Pass::Hud]);
+    }
+
+    #[test]
+    fn targets_are_only_created_when_the_size_changes() {
+        let Some(mut renderer) = crate::renderer::test_renderer() else {
+            eprintln!("No adapter, skipping");
+            return;
+        };
+        let created = |renderer: &crate::renderer::Renderer| renderer.frame.targets_created;
+
+        renderer.resize([64, 64]);
+        assert_eq!(created(&renderer), 1);
+        renderer.resize([64, 64]);
+        assert_eq!(created(&renderer), 1);
+
+        renderer.resize([128, 32]);
+        renderer.resize([32, 128]);
+        assert_eq!(created(&renderer), 3);
+        assert_eq!(renderer.frame.targets().unwrap().size, [32, 128]);
+
+        //Zero sized targets are released, the next valid size creates them again even if it didn't change
+        renderer.resize([0, 128]);
+        assert!(renderer.frame.targets().is_none());
+        assert_eq!(created(&renderer), 3);
+        renderer.resize([32, 128]);
+        assert_eq!(created(&renderer), 4);
+    }
+}
@@ -0,0 +1,118 @@
+use std::sync::atomic::{AtomicU8, Ordering};
+use std::sync::Arc;
+
+const TIMESTAMP_SIZE: wgpu::BufferAddress = std::mem::size_of::<u64>() as wgpu::BufferAddress;
+
+const READBACK_PENDING: u8 = 0;
+const READBACK_MAPPED: u8 = 1;
+const READBACK_FAILED: u8 = 2;
+
+/// Measures the gpu time between two points in a frame with timestamp queries,
+/// results are read back asynchronously so they arrive a frame or more late
+pub(super) struct GpuTimer {
+    query_set: wgpu::QuerySet,
+    readback_buffer: wgpu::Buffer,
+    timestamp_period: f32,
+
+    /// Set while the readback buffer is mapped or waiting to be mapped
+    in_flight: bool,
+    readback_state: Arc<AtomicU8>,
+    recording: bool,
+    last_ms: Option<f32>,
+}
+
+impl GpuTimer {
+    /// Returns None if the device wasn't created with TIMESTAMP_QUERY
+    pub(super) fn new(device: &wgpu::Device, queue: &wgpu::Queue) -> Option<Self> {
+        if !device.features().contains(wgpu::Features::TIMESTAMP_QUERY) {
+            return None;
+        }
+
+        let query_set = device.create_query_set(&wgpu::QuerySetDescriptor {
+            label: Some("GpuTimer QuerySet"),
+            ty: wgpu::QueryType::Timestamp,
+            count: 2,
+        });
+        let readback_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("GpuTimer Readback Buffer"),
+            size: TIMESTAMP_SIZE * 2,
+            usage: wgpu::BufferUsages::COPY_DST | wgpu::BufferUsages::MAP_READ,
+            mapped_at_creation: false,
+        });
+
+        Some(Self {
+            query_set,
+            readback_buffer,
+            timestamp_period: queue.get_timestamp_period(),
+            in_flight: false,
+            readback_state: Arc::new(AtomicU8::new(READBACK_PENDING)),
+            recording: false,
+            last_ms: None,
+        })
+    }
+
+    /// Collects a finished readback if there is one, then starts timing unless the last readback is still pending
+    pub(super) fn begin(&mut self, device: &wgpu::Device, encoder: &mut wgpu::CommandEncoder) {
+        if self.in_flight {
+            device.poll(wgpu::Maintain::Poll);
+            match self.readback_state.load(Ordering::Acquire) {
+                READBACK_MAPPED => {
+                    {
+                        let data = self.readback_buffer.slice(..).get_mapped_range();
+                        let timestamps: &[u64] = bytemuck::cast_slice(&data);
+                        let ticks = timestamps[1].saturating_sub(timestamps[0]);
+                        self.last_ms = Some(ticks as f32 * self.timestamp_period / 1_000_000.0);
+                    }
+                    self.readback_buffer.unmap();
+                    self.in_flight = false;
+                }
+                READBACK_FAILED => self.in_flight = false,
+                _ => {}
+            }
+        }
+
+        self.recording = !self.in_flight;
+        if self.recording {
+            encoder.write_timestamp(&self.query_set, 0);
+        }
+    }
+
+    pub(super) fn end(&mut self, encoder: &mut wgpu::CommandEncoder) {
+        if !self.recording {
+            return;
+        }
+
+        encoder.write_timestamp(&self.query_set, 1);
+        encoder.resolve_query_set(&self.query_set, 0..2, &self.readback_buffer, 0);
+    }
+
+    /// Must be called after the commands from begin/end were submitted
+    pub(super) fn after_submit(&mut self) {
+        if !self.recording {
+            return;
+        }
+
+        self.recording = false;
+        self.in_flight = true;
+
+        self.readback_state
+            .store(READBACK_PENDING, Ordering::Release);
+        let readback_state = self.readback_state.clone();
+        self.readback_buffer
+            .slice(..)
+            .map_async(wgpu::MapMode::Read, move |result| {
+                readback_state.store(
+                    if result.is_ok() {
+                        READBACK_MAPPED
+                    } else {
+                        READBACK_FAILED
+                    },
+                    Ordering::Release,
+                );
+            });
+    }
+
+    pub(super) fn last_ms(&self) -> Option<f32> {
+        self.last_ms
+    }
+}
@@ -0,0 +1,251 @@
+use super::bitmap_font;
+use super::needs_srgb_encode;
+use bytemuck::{Pod, Zeroable};
+use glam::Vec2;
+use std::borrow::Cow;
+
+const CIRCLE_SEGMENTS: usize = 24;
+
+#[repr(C)]
+#[derive(Pod, Zeroable, Copy, Clone, Debug)]
+struct HudVertex {
+    /// Set in pixels while building, converted to clip space on upload
+    position: [f32; 2],
+    color: [f32; 4],
+}
+
+impl HudVertex {
+    const ATTRIBUTES: [wgpu::VertexAttribute; 2] = wgpu::vertex_attr_array![
+        0 => Float32x2,
+        1 => Float32x4,
+    ];
+
+    fn desc<'a>() -> wgpu::VertexBufferLayout<'a> {
+        wgpu::VertexBufferLayout {
+            array_stride: std::mem::size_of::<HudVertex>() as wgpu::BufferAddress,
+            step_mode: wgpu::VertexStepMode::Vertex,
+            attributes: &Self::ATTRIBUTES,
+        }
+    }
+}
+
+/// Screen space shapes accumulated over a frame, in pixels from the top left corner, cleared after they are drawn
+#[derive(Default)]
+pub struct Hud {
+    vertices: Vec<HudVertex>,
+}
+
+impl Hud {
+    pub fn triangle(&mut self, points: [Vec2; 3], color: [f32; 4]) {
+        self.vertices
+            .extend(points.into_iter().map(|point| HudVertex {
+                position: point.to_array(),
+                color,
+            }));
+    }
+
+    pub fn rect(&mut self, min: Vec2, max: Vec2, color: [f32; 4]) {
+        self.triangle([min, Vec2::new(max.x, min.y), max], color);
+        self.triangle([min, max, Vec2::new(min.x, max.y)], color);
+    }
+
+    pub fn line(&mut self, from: Vec2, to: Vec2, thickness: f32, color: [f32; 4]) {
+        let Some(direction) = (to - from).try_normalize() else {
+            return;
+        };
+        let side = direction.perp() * (thickness * 0.5);
+        self.triangle([from - side, to - side, to + side], color);
+        self.triangle([from - side, to + side, from + side], color);
+    }
+
+    pub fn circle(&mut self, center: Vec2, radius: f32, thickness: f32, color: [f32; 4]) {
+        let point = |i: usize| {
+            let angle = (i as f32 / CIRCLE_SEGMENTS as f32) * std::f32::consts::TAU;
+            center + Vec2::new(angle.cos(), angle.sin()) * radius
+        };
+
+        for i in 0..CIRCLE_SEGMENTS {
+            self.line(point(i), point(i + 1), thickness, color);
+        }
+    }
+
+    /// Filled triangle with its tip at `tip`, pointing along `direction`
+    pub fn arrow(&mut self, tip: Vec2, direction: Vec2, size: f32, color: [f32; 4]) {
+        let Some(direction) = direction.try_normalize() else {
+            return;
+        };
+        let base = tip - direction * size;
+        let side = direction.perp() * (size * 0.5);
+        self.triangle([tip, base + side, base - side], color);
+    }
+
+    /// Single line of the built in bitmap font, `scale` is the size of a font pixel in screen pixels
+    pub fn text(&mut self, top_left: Vec2, text: &str, scale: f32, color: [f32; 4]) {
+        for (row, start, end) in bitmap_font::pixel_runs(text) {
+            self.rect(
+                top_left + Vec2::new(start as f32, row as f32) * scale,
+                top_left + Vec2::new(end as f32, (row + 1) as f32) * scale,
+                color,
+            );
+        }
+    }
+
+    /// Size in pixels of `text` drawn at `scale`
+    pub fn text_size(text: &str, scale: f32) -> Vec2 {
+        Vec2::new(
+            bitmap_font::text_width(text) as f32,
+            bitmap_font::GLYPH_HEIGHT as f32,
+        ) * scale
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.vertices.is_empty()
+    }
+
+    pub fn clear(&mut self) {
+        self.vertices.clear();
+    }
+}
+
+/// Draws the hud over the final image, after bloom so it isn't blurred
+pub(super) struct HudPass {
+    pipeline: wgpu::RenderPipeline,
+
+    vertex_buffer: wgpu::Buffer,
+    vertex_capacity: usize,
+    vertex_count: u32,
+}
+
+impl HudPass {
+    pub(super) fn new(device: &wgpu::Device, output_format: wgpu::TextureFormat) -> Self {
+        let vertex_capacity = 1024;
+        Self {
+            pipeline: create_hud_pipeline(device, output_format),
+            vertex_buffer: create_vertex_buffer(device, vertex_capacity),
+            vertex_capacity,
+            vertex_count: 0,
+        }
+    }
+
+    pub(super) fn rebuild_pipelines(
+        &mut self,
+        device: &wgpu::Device,
+        output_format: wgpu::TextureFormat,
+    ) {
+        self.pipeline = create_hud_pipeline(device, output_format);
+    }
+
+    /// Uploads this frame's shapes in clip space, growing the vertex buffer if needed
+    pub(super) fn update(
+        &mut self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        hud: &Hud,
+        size: [u32; 2],
+    ) {
+        self.vertex_count = hud.vertices.len() as u32;
+        if hud.is_empty() {
+            return;
+        }
+
+        if hud.vertices.len() > self.vertex_capacity {
+            self.vertex_capacity = hud.vertices.len().next_power_of_two();
+            self.vertex_buffer = create_vertex_buffer(device, self.vertex_capacity);
+        }
+
+        let scale = Vec2::new(2.0 / size[0] as f32, -2.0 / size[1] as f32);
+        let vertices: Vec<HudVertex> = hud
+            .vertices
+            .iter()
+            .map(|vertex| HudVertex {
+                position: (Vec2::from(vertex.position) * scale + Vec2::new(-1.0, 1.0)).to_array(),
+                color: vertex.color,
+            })
+            .collect();
+        queue.write_buffer(&self.vertex_buffer, 0, bytemuck::cast_slice(&vertices));
+    }
+
+    /// Loads what's already in `render_target`, returns the number of draw calls
+    pub(super) fn draw(
+        &self,
+        encoder: &mut wgpu::CommandEncoder,
+        render_target: &wgpu::TextureView,
+    ) -> u32 {
+        if self.vertex_count == 0 {
+            return 0;
+        }
+
+        let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            label: Some("Hud Pass"),
+            color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                view: render_target,
+                resolve_target: None,
+                ops: wgpu::Operations {
+                    load: wgpu::LoadOp::Load,
+                    store: true,
+                },
+            })],
+            depth_stencil_attachment: None,
+        });
+        render_pass.set_pipeline(&self.pipeline);
+        render_pass.set_vertex_buffer(0, self.vertex_buffer.slice(..));
+        render_pass.draw(0..self.vertex_count, 0..1);
+        1
+    }
+}
+
+fn create_vertex_buffer(device: &wgpu::Device, vertex_capacity: usize) -> wgpu::Buffer {
+    device.create_buffer(&wgpu::BufferDescriptor {
+        label: Some("Hud Vertex Buffer"),
+        size: (vertex_capacity * std::mem::size_of::<HudVertex>()) as wgpu::BufferAddress,
+        usage: wgpu::BufferUsages::VERTEX | wgpu::BufferUsages::COPY_DST,
+        mapped_at_creation: false,
+    })
+}
+
+fn create_hud_pipeline(
+    device: &wgpu::Device,
+    output_format: wgpu::TextureFormat,
+) -> wgpu::RenderPipeline {
+    let code = include_str!("../shader/hud.wgsl");
+    let shader_module = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+        label: Some("Hud Shader"),
+        source: wgpu::ShaderSource::Wgsl(Cow::from(code)),
+    });
+
+    let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+        label: Some("Hud PipelineLayout"),
+        bind_group_layouts: &[],
+        push_constant_ranges: &[],
+    });
+
+    device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+        label: Some("Hud Pipeline"),
+        layout: Some(&pipeline_layout),
+        vertex: wgpu::VertexState {
+            module: &shader_module,
+            entry_point: "vs_main",
+            buffers: &[HudVertex::desc()],
+        },
+        primitive: wgpu::PrimitiveState {
+            topology: wgpu::PrimitiveTopology::TriangleList,
+            ..Default::default()
+        },
+        depth_stencil: None,
+        multisample: Default::default(),
+        fragment: Some(wgpu::FragmentState {
+            module: &shader_module,
+            entry_point: if needs_srgb_encode(output_format) {
+                "fs_main_srgb_encode"
+            } else {
+                "fs_main"
+            },
+            targets: &[Some(wgpu::ColorTargetState {
+                format: output_format,
+                blend: Some(wgpu::BlendState::ALPHA_BLENDING),
+                write_mask: wgpu::ColorWrites::ALL,
+            })],
+        }),
+        multiview: None,
+    })
+}
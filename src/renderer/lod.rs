@@ -0,0 +1,64 @@
+use super::Vertex;
+use glam::Vec3;
+use std::collections::{HashMap, HashSet};
+
+/// Levels a mesh can have, also the length of `RenderStats::instances_per_lod`
+pub const MAX_LOD_LEVELS: usize = 4;
+
+/// One level of `Renderer::create_mesh_with_lods`, `V` is `[f32; 3]` for flat meshes
+#[derive(Clone, Copy, Debug)]
+pub struct LodLevel<'a, V = Vertex> {
+    pub vertices: &'a [V],
+    pub indices: &'a [u32],
+    /// Drawn up to this far from the camera, for an instance at scale 1
+    pub max_distance: f32,
+}
+
+/// Merges every vertex inside the same `cell_size` cube into their average and drops the triangles that collapsed.
+/// Meant for generating lower LODs of shared vertex meshes, bigger cells give rougher meshes
+pub fn cluster_vertices(
+    positions: &[[f32; 3]],
+    indices: &[u32],
+    cell_size: f32,
+) -> (Vec<[f32; 3]>, Vec<u32>) {
+    let cell_size = cell_size.max(f32::EPSILON);
+    let mut clusters: HashMap<[i32; 3], u32> = HashMap::new();
+    //Sum of the positions in each cluster and how many there are
+    let mut sums: Vec<(Vec3, u32)> = Vec::new();
+    let remap: Vec<u32> = positions
+        .iter()
+        .map(|position| {
+            let position = Vec3::from(*position);
+            let cell = (position / cell_size).floor().as_ivec3().to_array();
+            let cluster = *clusters.entry(cell).or_insert_with(|| {
+                sums.push((Vec3::ZERO, 0));
+                sums.len() as u32 - 1
+            });
+            let sum = &mut sums[cluster as usize];
+            sum.0 += position;
+            sum.1 += 1;
+            cluster
+        })
+        .collect();
+
+    let mut triangles = HashSet::new();
+    let mut clustered_indices = Vec::with_capacity(indices.len());
+    for triangle in indices.chunks_exact(3) {
+        let [a, b, c] = [0, 1, 2].map(|i| remap[triangle[i] as usize]);
+        if a == b || b == c || c == a {
+            continue;
+        }
+        //Several source triangles can end up on the same clusters
+        let mut key = [a, b, c];
+        key.sort_unstable();
+        if triangles.insert(key) {
+            clustered_indices.extend_from_slice(&[a, b, c]);
+        }
+    }
+
+    let clustered_positions = sums
+        .iter()
+        .map(|(sum, count)| (*sum / *count as f32).to_array())
+        .collect();
+    (clustered_positions, clustered_indices)
+}
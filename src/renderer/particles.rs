@@ -0,0 +1,440 @@
+use super::needs_srgb_encode;
+use crate::asteroid_field::FieldRng;
+use crate::transform::Transform;
+use bytemuck::{Pod, Zeroable};
+use glam::{Vec3, Vec4};
+use slotmap::SlotMap;
+use std::borrow::Cow;
+use std::ops::{Add, Mul};
+
+slotmap::new_key_type! {
+    pub struct EmitterHandle;
+}
+
+#[repr(C)]
+#[derive(Pod, Zeroable, Copy, Clone, Debug)]
+struct ParticleInstance {
+    position: [f32; 3],
+    size: f32,
+    color: [f32; 4],
+}
+
+impl ParticleInstance {
+    const ATTRIBUTES: [wgpu::VertexAttribute; 3] = wgpu::vertex_attr_array![
+        0 => Float32x3,
+        1 => Float32,
+        2 => Float32x4,
+    ];
+
+    fn desc<'a>() -> wgpu::VertexBufferLayout<'a> {
+        wgpu::VertexBufferLayout {
+            array_stride: std::mem::size_of::<ParticleInstance>() as wgpu::BufferAddress,
+            step_mode: wgpu::VertexStepMode::Instance,
+            attributes: &Self::ATTRIBUTES,
+        }
+    }
+}
+
+/// Keyframes over a particle's life, time goes from 0 at spawn to 1 when it dies and values are blended linearly between keys
+#[derive(Clone, Debug)]
+pub struct ParticleCurve<T> {
+    keys: Vec<(f32, T)>,
+}
+
+impl<T: Copy + Add<Output = T> + Mul<f32, Output = T>> ParticleCurve<T> {
+    /// Keys are sorted by time, an empty list panics
+    pub fn new(mut keys: Vec<(f32, T)>) -> Self {
+        assert!(!keys.is_empty(), "particle curve needs at least one key");
+        keys.sort_by(|a, b| a.0.total_cmp(&b.0));
+        Self { keys }
+    }
+
+    pub fn constant(value: T) -> Self {
+        Self {
+            keys: vec![(0.0, value)],
+        }
+    }
+
+    pub fn sample(&self, time: f32) -> T {
+        let next = self.keys.partition_point(|(key_time, _)| *key_time <= time);
+        match (next.checked_sub(1), self.keys.get(next)) {
+            (Some(previous), Some((next_time, next_value))) => {
+                let (previous_time, previous_value) = self.keys[previous];
+                let blend = (time - previous_time) / (next_time - previous_time);
+                previous_value * (1.0 - blend) + *next_value * blend
+            }
+            (Some(previous), None) => self.keys[previous].1,
+            (None, _) => self.keys[0].1,
+        }
+    }
+}
+
+/// How an emitter spawns its particles and how they look over their lives
+#[derive(Clone, Debug)]
+pub struct EmitterSettings {
+    /// Particles per second at a rate of 1
+    pub spawn_rate: f32,
+    /// Seconds, each particle picks a random lifetime in this range
+    pub lifetime: (f32, f32),
+    /// Meters per second relative to the emitter
+    pub speed: (f32, f32),
+    /// Half angle in radians of the cone around the emitter's +Z that particles leave in
+    pub cone_angle: f32,
+    /// Width of the billboard in meters
+    pub size: ParticleCurve<f32>,
+    /// Linear color, the alpha scales how much is added to the scene
+    pub color: ParticleCurve<Vec4>,
+    /// No more particles are spawned while this many are alive
+    pub max_particles: usize,
+}
+
+struct Particle {
+    position: Vec3,
+    velocity: Vec3,
+    age: f32,
+    lifetime: f32,
+}
+
+/// Cpu simulated particles in world space, they keep moving on their own once spawned
+struct ParticleEmitter {
+    settings: EmitterSettings,
+    transform: Transform,
+    /// Added to every particle's velocity when it spawns, so moving emitters don't leave a streak behind
+    velocity: Vec3,
+    /// Scales the spawn rate, 0 stops spawning
+    rate: f32,
+    /// Fraction of a particle carried over to the next update
+    spawn_accumulator: f32,
+    /// Removed once the last particle is gone
+    finished: bool,
+    particles: Vec<Particle>,
+}
+
+impl ParticleEmitter {
+    fn new(settings: EmitterSettings, transform: &Transform) -> Self {
+        Self {
+            particles: Vec::with_capacity(settings.max_particles.min(256)),
+            settings,
+            transform: transform.clone(),
+            velocity: Vec3::ZERO,
+            rate: 0.0,
+            spawn_accumulator: 0.0,
+            finished: false,
+        }
+    }
+
+    /// `age` is how long ago in this update the particle left the emitter
+    fn spawn(&mut self, rng: &mut FieldRng, age: f32) {
+        if self.particles.len() >= self.settings.max_particles {
+            return;
+        }
+
+        //Uniform over the cap of the cone
+        let cos_theta = 1.0 - rng.next_f32() * (1.0 - self.settings.cone_angle.cos());
+        let sin_theta = (1.0 - cos_theta * cos_theta).max(0.0).sqrt();
+        let (sin_phi, cos_phi) = (rng.next_f32() * std::f32::consts::TAU).sin_cos();
+        let direction = self.transform.rotation
+            * Vec3::new(sin_theta * cos_phi, sin_theta * sin_phi, cos_theta);
+
+        let speed = rng.range(self.settings.speed.0, self.settings.speed.1);
+        let velocity = self.velocity + direction * speed;
+        self.particles.push(Particle {
+            position: self.transform.position + velocity * age,
+            velocity,
+            age,
+            lifetime: rng
+                .range(self.settings.lifetime.0, self.settings.lifetime.1)
+                .max(f32::EPSILON),
+        });
+    }
+
+    fn update(&mut self, rng: &mut FieldRng, delta_time: f32) {
+        for particle in self.particles.iter_mut() {
+            particle.age += delta_time;
+            particle.position += particle.velocity * delta_time;
+        }
+        self.particles
+            .retain(|particle| particle.age < particle.lifetime);
+
+        if self.finished {
+            return;
+        }
+        self.spawn_accumulator += self.settings.spawn_rate * self.rate.max(0.0) * delta_time;
+        while self.spawn_accumulator >= 1.0 {
+            self.spawn_accumulator -= 1.0;
+            //Spread over the update so fast emitters don't spawn in clumps
+            let age = rng.next_f32() * delta_time;
+            self.spawn(rng, age);
+        }
+    }
+}
+
+/// Every emitter of a scene, simulated by `SceneRenderData::update_effects`
+pub(super) struct ParticleSystem {
+    emitters: SlotMap<EmitterHandle, ParticleEmitter>,
+    rng: FieldRng,
+}
+
+impl Default for ParticleSystem {
+    fn default() -> Self {
+        Self {
+            emitters: SlotMap::with_key(),
+            rng: FieldRng::new(0),
+        }
+    }
+}
+
+impl ParticleSystem {
+    pub(super) fn create(
+        &mut self,
+        settings: EmitterSettings,
+        transform: &Transform,
+    ) -> EmitterHandle {
+        self.emitters
+            .insert(ParticleEmitter::new(settings, transform))
+    }
+
+    pub(super) fn update_emitter(
+        &mut self,
+        handle: EmitterHandle,
+        transform: &Transform,
+        velocity: Vec3,
+        rate: f32,
+    ) {
+        if let Some(emitter) = self.emitters.get_mut(handle) {
+            emitter.transform = transform.clone();
+            emitter.velocity = velocity;
+            emitter.rate = rate;
+        }
+    }
+
+    /// Stops spawning, the particles already out fade away on their own
+    pub(super) fn remove(&mut self, handle: EmitterHandle) {
+        if let Some(emitter) = self.emitters.get_mut(handle) {
+            emitter.finished = true;
+        }
+    }
+
+    pub(super) fn burst(
+        &mut self,
+        settings: &EmitterSettings,
+        transform: &Transform,
+        velocity: Vec3,
+        count: usize,
+    ) {
+        let mut emitter = ParticleEmitter::new(settings.clone(), transform);
+        emitter.velocity = velocity;
+        emitter.finished = true;
+        for _ in 0..count {
+            emitter.spawn(&mut self.rng, 0.0);
+        }
+        self.emitters.insert(emitter);
+    }
+
+    pub(super) fn update(&mut self, delta_time: f32) {
+        if delta_time <= 0.0 {
+            return;
+        }
+        for emitter in self.emitters.values_mut() {
+            emitter.update(&mut self.rng, delta_time);
+        }
+        self.emitters
+            .retain(|_, emitter| !(emitter.finished && emitter.particles.is_empty()));
+    }
+
+    pub(super) fn shift_origin(&mut self, offset: Vec3) {
+        for emitter in self.emitters.values_mut() {
+            emitter.transform.position += offset;
+            for particle in emitter.particles.iter_mut() {
+                particle.position += offset;
+            }
+        }
+    }
+
+    fn write_instances(&self, instances: &mut Vec<ParticleInstance>) {
+        instances.clear();
+        for emitter in self.emitters.values() {
+            instances.extend(emitter.particles.iter().map(|particle| {
+                let time = particle.age / particle.lifetime;
+                ParticleInstance {
+                    position: particle.position.to_array(),
+                    size: emitter.settings.size.sample(time),
+                    color: emitter.settings.color.sample(time).to_array(),
+                }
+            }));
+        }
+    }
+}
+
+/// Camera facing quads added on top of the scene, drawn after everything else that is depth tested
+pub(super) struct ParticlePass {
+    pipeline_layout: wgpu::PipelineLayout,
+    pipeline: wgpu::RenderPipeline,
+
+    instances: Vec<ParticleInstance>,
+    instance_buffer: wgpu::Buffer,
+    instance_capacity: usize,
+    instance_count: u32,
+}
+
+impl ParticlePass {
+    pub(super) fn new(
+        device: &wgpu::Device,
+        scene_bind_group_layout: &wgpu::BindGroupLayout,
+        output_format: wgpu::TextureFormat,
+        depth_format: wgpu::TextureFormat,
+        sample_count: u32,
+    ) -> Self {
+        let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Particle PipelineLayout"),
+            bind_group_layouts: &[scene_bind_group_layout],
+            push_constant_ranges: &[],
+        });
+
+        let pipeline = create_particle_pipeline(
+            device,
+            &pipeline_layout,
+            output_format,
+            depth_format,
+            sample_count,
+        );
+
+        let instance_capacity = 1024;
+        let instance_buffer = create_instance_buffer(device, instance_capacity);
+
+        Self {
+            pipeline_layout,
+            pipeline,
+            instances: Vec::new(),
+            instance_buffer,
+            instance_capacity,
+            instance_count: 0,
+        }
+    }
+
+    pub(super) fn rebuild_pipelines(
+        &mut self,
+        device: &wgpu::Device,
+        output_format: wgpu::TextureFormat,
+        depth_format: wgpu::TextureFormat,
+        sample_count: u32,
+    ) {
+        self.pipeline = create_particle_pipeline(
+            device,
+            &self.pipeline_layout,
+            output_format,
+            depth_format,
+            sample_count,
+        );
+    }
+
+    /// Uploads every live particle of the scene, growing the instance buffer if needed
+    pub(super) fn update(
+        &mut self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        particles: &ParticleSystem,
+    ) {
+        particles.write_instances(&mut self.instances);
+        if self.instances.len() > self.instance_capacity {
+            self.instance_capacity = self.instances.len().next_power_of_two();
+            self.instance_buffer = create_instance_buffer(device, self.instance_capacity);
+        }
+
+        if !self.instances.is_empty() {
+            queue.write_buffer(
+                &self.instance_buffer,
+                0,
+                bytemuck::cast_slice(&self.instances),
+            );
+        }
+        self.instance_count = self.instances.len() as u32;
+    }
+
+    /// Expects the scene bind group to already be bound at group 0, returns the number of draw calls
+    pub(super) fn draw<'a>(&'a self, render_pass: &mut wgpu::RenderPass<'a>) -> u32 {
+        if self.instance_count == 0 {
+            return 0;
+        }
+
+        render_pass.set_pipeline(&self.pipeline);
+        render_pass.set_vertex_buffer(0, self.instance_buffer.slice(..));
+        render_pass.draw(0..6, 0..self.instance_count);
+        1
+    }
+}
+
+fn create_instance_buffer(device: &wgpu::Device, instance_capacity: usize) -> wgpu::Buffer {
+    device.create_buffer(&wgpu::BufferDescriptor {
+        label: Some("Particle Instance Buffer"),
+        size: (instance_capacity * std::mem::size_of::<ParticleInstance>()) as wgpu::BufferAddress,
+        usage: wgpu::BufferUsages::VERTEX | wgpu::BufferUsages::COPY_DST,
+        mapped_at_creation: false,
+    })
+}
+
+fn create_particle_pipeline(
+    device: &wgpu::Device,
+    pipeline_layout: &wgpu::PipelineLayout,
+    output_format: wgpu::TextureFormat,
+    depth_format: wgpu::TextureFormat,
+    sample_count: u32,
+) -> wgpu::RenderPipeline {
+    let code = include_str!("../shader/particles.wgsl");
+    let shader_module = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+        label: Some("Particle Shader"),
+        source: wgpu::ShaderSource::Wgsl(Cow::from(code)),
+    });
+
+    device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+        label: Some("Particle Pipeline"),
+        layout: Some(pipeline_layout),
+        vertex: wgpu::VertexState {
+            module: &shader_module,
+            entry_point: "vs_main",
+            buffers: &[ParticleInstance::desc()],
+        },
+        primitive: wgpu::PrimitiveState {
+            topology: wgpu::PrimitiveTopology::TriangleList,
+            ..Default::default()
+        },
+        //Tested against the scene but not written, so particles don't hide each other
+        depth_stencil: Some(wgpu::DepthStencilState {
+            format: depth_format,
+            depth_write_enabled: false,
+            depth_compare: wgpu::CompareFunction::GreaterEqual,
+            stencil: Default::default(),
+            bias: Default::default(),
+        }),
+        multisample: wgpu::MultisampleState {
+            count: sample_count,
+            ..Default::default()
+        },
+        fragment: Some(wgpu::FragmentState {
+            module: &shader_module,
+            entry_point: if needs_srgb_encode(output_format) {
+                "fs_main_srgb_encode"
+            } else {
+                "fs_main"
+            },
+            targets: &[Some(wgpu::ColorTargetState {
+                format: output_format,
+                //Additive, so the draw order doesn't matter
+                blend: Some(wgpu::BlendState {
+                    color: wgpu::BlendComponent {
+                        src_factor: wgpu::BlendFactor::SrcAlpha,
+                        dst_factor: wgpu::BlendFactor::One,
+                        operation: wgpu::BlendOperation::Add,
+                    },
+                    alpha: wgpu::BlendComponent {
+                        src_factor: wgpu::BlendFactor::Zero,
+                        dst_factor: wgpu::BlendFactor::One,
+                        operation: wgpu::BlendOperation::Add,
+                    },
+                }),
+                write_mask: wgpu::ColorWrites::ALL,
+            })],
+        }),
+        multiview: None,
+    })
+}
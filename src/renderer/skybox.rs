@@ -0,0 +1,300 @@
+use super::needs_srgb_encode;
+use bytemuck::{Pod, Zeroable};
+use log::error;
+use std::borrow::Cow;
+use std::path::PathBuf;
+use wgpu::util::DeviceExt;
+
+pub enum SkyboxSource {
+    /// Cubemap faces in the order +X, -X, +Y, -Y, +Z, -Z, all faces must be the same size
+    Cubemap([PathBuf; 6]),
+    /// Star field generated in the shader, star_density is the chance [0, 1] of a grid cell holding a star
+    Procedural { star_density: f32, seed: u32 },
+}
+
+#[repr(C)]
+#[derive(Pod, Zeroable, Copy, Clone, Debug)]
+struct SkyboxData {
+    inverse_view_projection_matrix: [f32; 16],
+    /// x: 1 for procedural, y: star density, z: seed
+    params: [f32; 4],
+}
+
+pub(super) struct Skybox {
+    pipeline_layout: wgpu::PipelineLayout,
+    pipeline: wgpu::RenderPipeline,
+
+    params: [f32; 4],
+    uniform_buffer: wgpu::Buffer,
+    bind_group: wgpu::BindGroup,
+
+    _cubemap: wgpu::Texture,
+}
+
+impl Skybox {
+    pub(super) fn new(
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        source: &SkyboxSource,
+        output_format: wgpu::TextureFormat,
+        depth_format: wgpu::TextureFormat,
+        sample_count: u32,
+    ) -> Option<Self> {
+        let (cubemap, params) = match source {
+            SkyboxSource::Cubemap(paths) => (load_cubemap(device, queue, paths)?, [0.0; 4]),
+            SkyboxSource::Procedural { star_density, seed } => (
+                create_cubemap(device, queue, 1, &[0; 6 * 4]),
+                [1.0, *star_density, *seed as f32, 0.0],
+            ),
+        };
+
+        let bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("Skybox BindGroupLayout"),
+            entries: &[
+                wgpu::BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        min_binding_size: None,
+                        has_dynamic_offset: false,
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 1,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Texture {
+                        sample_type: wgpu::TextureSampleType::Float { filterable: true },
+                        view_dimension: wgpu::TextureViewDimension::Cube,
+                        multisampled: false,
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 2,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+                    count: None,
+                },
+            ],
+        });
+
+        let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Skybox PipelineLayout"),
+            bind_group_layouts: &[&bind_group_layout],
+            push_constant_ranges: &[],
+        });
+
+        let uniform_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Skybox Buffer"),
+            contents: bytemuck::cast_slice(&[SkyboxData::zeroed()]),
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+        });
+
+        let cubemap_view = cubemap.create_view(&wgpu::TextureViewDescriptor {
+            dimension: Some(wgpu::TextureViewDimension::Cube),
+            ..Default::default()
+        });
+        let sampler = device.create_sampler(&wgpu::SamplerDescriptor {
+            label: Some("Skybox Sampler"),
+            mag_filter: wgpu::FilterMode::Linear,
+            min_filter: wgpu::FilterMode::Linear,
+            ..Default::default()
+        });
+
+        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("Skybox BindGroup"),
+            layout: &bind_group_layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: wgpu::BindingResource::Buffer(
+                        uniform_buffer.as_entire_buffer_binding(),
+                    ),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: wgpu::BindingResource::TextureView(&cubemap_view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 2,
+                    resource: wgpu::BindingResource::Sampler(&sampler),
+                },
+            ],
+        });
+
+        let pipeline = create_skybox_pipeline(
+            device,
+            &pipeline_layout,
+            output_format,
+            depth_format,
+            sample_count,
+        );
+
+        Some(Self {
+            pipeline_layout,
+            pipeline,
+            params,
+            uniform_buffer,
+            bind_group,
+            _cubemap: cubemap,
+        })
+    }
+
+    pub(super) fn rebuild_pipeline(
+        &mut self,
+        device: &wgpu::Device,
+        output_format: wgpu::TextureFormat,
+        depth_format: wgpu::TextureFormat,
+        sample_count: u32,
+    ) {
+        self.pipeline = create_skybox_pipeline(
+            device,
+            &self.pipeline_layout,
+            output_format,
+            depth_format,
+            sample_count,
+        );
+    }
+
+    /// Writes the camera for this frame, translation doesn't matter since only directions are reconstructed
+    pub(super) fn update(&self, queue: &wgpu::Queue, view_projection_matrix: &[f32; 16]) {
+        let inverse_view_projection_matrix = glam::Mat4::from_cols_array(view_projection_matrix)
+            .inverse()
+            .to_cols_array();
+
+        queue.write_buffer(
+            &self.uniform_buffer,
+            0,
+            bytemuck::cast_slice(&[SkyboxData {
+                inverse_view_projection_matrix,
+                params: self.params,
+            }]),
+        );
+    }
+
+    pub(super) fn draw<'a>(&'a self, render_pass: &mut wgpu::RenderPass<'a>) {
+        render_pass.set_pipeline(&self.pipeline);
+        render_pass.set_bind_group(0, &self.bind_group, &[]);
+        render_pass.draw(0..3, 0..1);
+    }
+}
+
+fn create_skybox_pipeline(
+    device: &wgpu::Device,
+    pipeline_layout: &wgpu::PipelineLayout,
+    output_format: wgpu::TextureFormat,
+    depth_format: wgpu::TextureFormat,
+    sample_count: u32,
+) -> wgpu::RenderPipeline {
+    let code = include_str!("../shader/skybox.wgsl");
+    let shader_module = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+        label: Some("Skybox Shader"),
+        source: wgpu::ShaderSource::Wgsl(Cow::from(code)),
+    });
+    device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+        label: Some("Skybox Pipeline"),
+        layout: Some(pipeline_layout),
+        vertex: wgpu::VertexState {
+            module: &shader_module,
+            entry_point: "vs_main",
+            buffers: &[],
+        },
+        primitive: Default::default(),
+        //Drawn first without writing depth so all geometry ends up in front of it
+        depth_stencil: Some(wgpu::DepthStencilState {
+            format: depth_format,
+            depth_write_enabled: false,
+            depth_compare: wgpu::CompareFunction::Always,
+            stencil: Default::default(),
+            bias: Default::default(),
+        }),
+        multisample: wgpu::MultisampleState {
+            count: sample_count,
+            ..Default::default()
+        },
+        fragment: Some(wgpu::FragmentState {
+            module: &shader_module,
+            entry_point: if needs_srgb_encode(output_format) {
+                "fs_main_srgb_encode"
+            } else {
+                "fs_main"
+            },
+            targets: &[Some(wgpu::ColorTargetState {
+                format: output_format,
+                blend: None,
+                write_mask: wgpu::ColorWrites::COLOR,
+            })],
+        }),
+        multiview: None,
+    })
+}
+
+fn create_cubemap(
+    device: &wgpu::Device,
+    queue: &wgpu::Queue,
+    face_size: u32,
+    data: &[u8],
+) -> wgpu::Texture {
+    device.create_texture_with_data(
+        queue,
+        &wgpu::TextureDescriptor {
+            label: Some("Skybox Cubemap"),
+            size: wgpu::Extent3d {
+                width: face_size,
+                height: face_size,
+                depth_or_array_layers: 6,
+            },
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format: wgpu::TextureFormat::Rgba8UnormSrgb,
+            usage: wgpu::TextureUsages::TEXTURE_BINDING,
+            view_formats: &[],
+        },
+        data,
+    )
+}
+
+fn load_cubemap(
+    device: &wgpu::Device,
+    queue: &wgpu::Queue,
+    paths: &[PathBuf; 6],
+) -> Option<wgpu::Texture> {
+    let mut face_size = None;
+    let mut data = Vec::new();
+
+    for path in paths.iter() {
+        let image = match image::open(path) {
+            Ok(image) => image.to_rgba8(),
+            Err(e) => {
+                error!("Failed to load skybox face {:?}: {}", path, e);
+                return None;
+            }
+        };
+
+        if image.width() != image.height() {
+            error!("Skybox face {:?} isn't square", path);
+            return None;
+        }
+
+        match face_size {
+            None => face_size = Some(image.width()),
+            Some(size) if size != image.width() => {
+                error!(
+                    "Skybox face {:?} is {}px but previous faces are {}px",
+                    path,
+                    image.width(),
+                    size
+                );
+                return None;
+            }
+            _ => {}
+        }
+
+        data.extend_from_slice(image.as_raw());
+    }
+
+    Some(create_cubemap(device, queue, face_size?, &data))
+}
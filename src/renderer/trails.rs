@@ -0,0 +1,403 @@
+use super::needs_srgb_encode;
+use bytemuck::{Pod, Zeroable};
+use glam::Vec3;
+use slotmap::SlotMap;
+use std::borrow::Cow;
+use std::collections::VecDeque;
+
+slotmap::new_key_type! {
+    pub struct TrailHandle;
+}
+
+#[repr(C)]
+#[derive(Pod, Zeroable, Copy, Clone, Debug)]
+struct TrailVertex {
+    position: [f32; 3],
+    color: [f32; 4],
+    /// -1 and 1 on the two edges of the ribbon, for the soft edge
+    edge: f32,
+}
+
+impl TrailVertex {
+    const ATTRIBUTES: [wgpu::VertexAttribute; 3] = wgpu::vertex_attr_array![
+        0 => Float32x3,
+        1 => Float32x4,
+        2 => Float32,
+    ];
+
+    fn desc<'a>() -> wgpu::VertexBufferLayout<'a> {
+        wgpu::VertexBufferLayout {
+            array_stride: std::mem::size_of::<TrailVertex>() as wgpu::BufferAddress,
+            step_mode: wgpu::VertexStepMode::Vertex,
+            attributes: &Self::ATTRIBUTES,
+        }
+    }
+}
+
+/// How a trail looks and how much of its owner's path it keeps
+#[derive(Clone, Debug)]
+pub struct TrailSettings {
+    /// Points kept per trail, the oldest is dropped once it's full
+    pub max_points: usize,
+    /// A new point is added once the owner moved this far, until then the newest point just follows it
+    pub segment_length: f32,
+    /// Seconds a point lasts, so the trail shrinks away once its owner stops
+    pub lifetime: f32,
+    /// Width at the head in meters, it narrows to nothing at the tail
+    pub width: f32,
+    /// Linear color at the head, the alpha fades to 0 at the tail
+    pub color: [f32; 4],
+    /// Moving further than this in one update starts a new strip instead of streaking across the jump
+    pub break_distance: f32,
+    /// Seconds a removed trail takes to fade out
+    pub fade_time: f32,
+}
+
+struct TrailPoint {
+    position: Vec3,
+    age: f32,
+    /// Not joined to the older point behind it, set on the first point after a jump
+    starts_strip: bool,
+}
+
+struct Trail {
+    settings: TrailSettings,
+    /// Newest first
+    points: VecDeque<TrailPoint>,
+    /// Seconds left once the trail is removed
+    fade: Option<f32>,
+}
+
+impl Trail {
+    fn add_point(&mut self, position: Vec3) {
+        let settings = &self.settings;
+        let jumped = self
+            .points
+            .front()
+            .is_none_or(|head| head.position.distance(position) > settings.break_distance);
+        let follows = !jumped
+            && self.points.len() >= 2
+            && !self.points[0].starts_strip
+            && self.points[1].position.distance(position) < settings.segment_length;
+
+        if follows {
+            let head = &mut self.points[0];
+            head.position = position;
+            head.age = 0.0;
+        } else {
+            self.points.push_front(TrailPoint {
+                position,
+                age: 0.0,
+                starts_strip: jumped,
+            });
+            self.points.truncate(settings.max_points.max(2));
+        }
+    }
+
+    /// Whether there's a segment between point `index` and the older one behind it
+    fn joined(&self, index: usize) -> bool {
+        index + 1 < self.points.len() && !self.points[index].starts_strip
+    }
+
+    fn write_vertices(&self, camera_position: Vec3, vertices: &mut Vec<TrailVertex>) {
+        if self.points.len() < 2 {
+            return;
+        }
+        let settings = &self.settings;
+        let fade = self
+            .fade
+            .map_or(1.0, |fade| fade / settings.fade_time.max(f32::EPSILON));
+        let last = (self.points.len() - 1) as f32;
+
+        //Each point gets its own edges from the direction of its neighbours, so the segments meet without gaps
+        let edges = |index: usize| {
+            let point = &self.points[index];
+            let newer = if index > 0 && self.joined(index - 1) {
+                self.points[index - 1].position
+            } else {
+                point.position
+            };
+            let older = if self.joined(index) {
+                self.points[index + 1].position
+            } else {
+                point.position
+            };
+            let side = (newer - older)
+                .cross(camera_position - point.position)
+                .normalize_or_zero();
+
+            let taper = (1.0 - index as f32 / last)
+                * (1.0 - point.age / settings.lifetime.max(f32::EPSILON)).max(0.0)
+                * fade;
+            let color = [
+                settings.color[0],
+                settings.color[1],
+                settings.color[2],
+                settings.color[3] * taper,
+            ];
+            let half_width = side * (settings.width * taper * 0.5);
+            (
+                TrailVertex {
+                    position: (point.position - half_width).to_array(),
+                    color,
+                    edge: -1.0,
+                },
+                TrailVertex {
+                    position: (point.position + half_width).to_array(),
+                    color,
+                    edge: 1.0,
+                },
+            )
+        };
+
+        for index in 0..self.points.len() {
+            if !self.joined(index) {
+                continue;
+            }
+            let (newer_left, newer_right) = edges(index);
+            let (older_left, older_right) = edges(index + 1);
+            vertices.extend_from_slice(&[
+                newer_left,
+                older_left,
+                older_right,
+                newer_left,
+                older_right,
+                newer_right,
+            ]);
+        }
+    }
+}
+
+/// Every trail of a scene, aged by `SceneRenderData::update_effects`
+#[derive(Default)]
+pub(super) struct TrailSystem {
+    trails: SlotMap<TrailHandle, Trail>,
+}
+
+impl TrailSystem {
+    pub(super) fn create(&mut self, settings: TrailSettings) -> TrailHandle {
+        self.trails.insert(Trail {
+            points: VecDeque::with_capacity(settings.max_points),
+            settings,
+            fade: None,
+        })
+    }
+
+    /// Ignored once the trail is fading out
+    pub(super) fn update_trail(&mut self, handle: TrailHandle, position: Vec3) {
+        if let Some(trail) = self
+            .trails
+            .get_mut(handle)
+            .filter(|trail| trail.fade.is_none())
+        {
+            trail.add_point(position);
+        }
+    }
+
+    pub(super) fn remove(&mut self, handle: TrailHandle) {
+        if let Some(trail) = self.trails.get_mut(handle) {
+            trail.fade.get_or_insert(trail.settings.fade_time);
+        }
+    }
+
+    pub(super) fn update(&mut self, delta_time: f32) {
+        if delta_time <= 0.0 {
+            return;
+        }
+        self.trails.retain(|_, trail| {
+            for point in trail.points.iter_mut() {
+                point.age += delta_time;
+            }
+            //The head stays so the trail can grow again when its owner moves
+            let lifetime = trail.settings.lifetime;
+            while trail.points.len() > 1
+                && trail
+                    .points
+                    .back()
+                    .is_some_and(|point| point.age >= lifetime)
+            {
+                trail.points.pop_back();
+            }
+
+            match &mut trail.fade {
+                Some(fade) => {
+                    *fade -= delta_time;
+                    *fade > 0.0
+                }
+                None => true,
+            }
+        });
+    }
+
+    pub(super) fn shift_origin(&mut self, offset: Vec3) {
+        for trail in self.trails.values_mut() {
+            for point in trail.points.iter_mut() {
+                point.position += offset;
+            }
+        }
+    }
+
+    fn write_vertices(&self, camera_position: Vec3, vertices: &mut Vec<TrailVertex>) {
+        vertices.clear();
+        for trail in self.trails.values() {
+            trail.write_vertices(camera_position, vertices);
+        }
+    }
+}
+
+/// Camera facing ribbons, blended over the scene with the other transparent draws
+pub(super) struct TrailPass {
+    pipeline_layout: wgpu::PipelineLayout,
+    pipeline: wgpu::RenderPipeline,
+
+    vertices: Vec<TrailVertex>,
+    vertex_buffer: wgpu::Buffer,
+    vertex_capacity: usize,
+    vertex_count: u32,
+}
+
+impl TrailPass {
+    pub(super) fn new(
+        device: &wgpu::Device,
+        scene_bind_group_layout: &wgpu::BindGroupLayout,
+        output_format: wgpu::TextureFormat,
+        depth_format: wgpu::TextureFormat,
+        sample_count: u32,
+    ) -> Self {
+        let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Trail PipelineLayout"),
+            bind_group_layouts: &[scene_bind_group_layout],
+            push_constant_ranges: &[],
+        });
+
+        let pipeline = create_trail_pipeline(
+            device,
+            &pipeline_layout,
+            output_format,
+            depth_format,
+            sample_count,
+        );
+
+        let vertex_capacity = 1024;
+        let vertex_buffer = create_vertex_buffer(device, vertex_capacity);
+
+        Self {
+            pipeline_layout,
+            pipeline,
+            vertices: Vec::new(),
+            vertex_buffer,
+            vertex_capacity,
+            vertex_count: 0,
+        }
+    }
+
+    pub(super) fn rebuild_pipelines(
+        &mut self,
+        device: &wgpu::Device,
+        output_format: wgpu::TextureFormat,
+        depth_format: wgpu::TextureFormat,
+        sample_count: u32,
+    ) {
+        self.pipeline = create_trail_pipeline(
+            device,
+            &self.pipeline_layout,
+            output_format,
+            depth_format,
+            sample_count,
+        );
+    }
+
+    /// Builds the ribbons facing `camera_position` and uploads them, growing the vertex buffer if needed
+    pub(super) fn update(
+        &mut self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        trails: &TrailSystem,
+        camera_position: Vec3,
+    ) {
+        trails.write_vertices(camera_position, &mut self.vertices);
+        if self.vertices.len() > self.vertex_capacity {
+            self.vertex_capacity = self.vertices.len().next_power_of_two();
+            self.vertex_buffer = create_vertex_buffer(device, self.vertex_capacity);
+        }
+
+        if !self.vertices.is_empty() {
+            queue.write_buffer(&self.vertex_buffer, 0, bytemuck::cast_slice(&self.vertices));
+        }
+        self.vertex_count = self.vertices.len() as u32;
+    }
+
+    /// Expects the scene bind group to already be bound at group 0, returns the number of draw calls
+    pub(super) fn draw<'a>(&'a self, render_pass: &mut wgpu::RenderPass<'a>) -> u32 {
+        if self.vertex_count == 0 {
+            return 0;
+        }
+
+        render_pass.set_pipeline(&self.pipeline);
+        render_pass.set_vertex_buffer(0, self.vertex_buffer.slice(..));
+        render_pass.draw(0..self.vertex_count, 0..1);
+        1
+    }
+}
+
+fn create_vertex_buffer(device: &wgpu::Device, vertex_capacity: usize) -> wgpu::Buffer {
+    device.create_buffer(&wgpu::BufferDescriptor {
+        label: Some("Trail Vertex Buffer"),
+        size: (vertex_capacity * std::mem::size_of::<TrailVertex>()) as wgpu::BufferAddress,
+        usage: wgpu::BufferUsages::VERTEX | wgpu::BufferUsages::COPY_DST,
+        mapped_at_creation: false,
+    })
+}
+
+fn create_trail_pipeline(
+    device: &wgpu::Device,
+    pipeline_layout: &wgpu::PipelineLayout,
+    output_format: wgpu::TextureFormat,
+    depth_format: wgpu::TextureFormat,
+    sample_count: u32,
+) -> wgpu::RenderPipeline {
+    let code = include_str!("../shader/trails.wgsl");
+    let shader_module = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+        label: Some("Trail Shader"),
+        source: wgpu::ShaderSource::Wgsl(Cow::from(code)),
+    });
+
+    device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+        label: Some("Trail Pipeline"),
+        layout: Some(pipeline_layout),
+        vertex: wgpu::VertexState {
+            module: &shader_module,
+            entry_point: "vs_main",
+            buffers: &[TrailVertex::desc()],
+        },
+        primitive: wgpu::PrimitiveState {
+            topology: wgpu::PrimitiveTopology::TriangleList,
+            ..Default::default()
+        },
+        depth_stencil: Some(wgpu::DepthStencilState {
+            format: depth_format,
+            depth_write_enabled: false,
+            depth_compare: wgpu::CompareFunction::GreaterEqual,
+            stencil: Default::default(),
+            bias: Default::default(),
+        }),
+        multisample: wgpu::MultisampleState {
+            count: sample_count,
+            ..Default::default()
+        },
+        fragment: Some(wgpu::FragmentState {
+            module: &shader_module,
+            entry_point: if needs_srgb_encode(output_format) {
+                "fs_main_srgb_encode"
+            } else {
+                "fs_main"
+            },
+            targets: &[Some(wgpu::ColorTargetState {
+                format: output_format,
+                blend: Some(wgpu::BlendState::ALPHA_BLENDING),
+                write_mask: wgpu::ColorWrites::ALL,
+            })],
+        }),
+        multiview: None,
+    })
+}
@@ -0,0 +1,317 @@
+use super::bitmap_font;
+use super::{needs_srgb_encode, SceneData};
+use bytemuck::{Pod, Zeroable};
+use glam::{Mat4, Vec2, Vec3};
+use std::borrow::Cow;
+
+/// Labels at this distance are drawn at the full scale, further ones shrink
+const FULL_SCALE_DISTANCE: f32 = 50.0;
+/// Smallest size of a font pixel, so far labels stay readable until they fade out
+const MIN_FONT_SCALE: f32 = 1.0;
+/// Labels start fading at this fraction of the max distance
+const FADE_START_FRACTION: f32 = 0.75;
+/// Gap between the labeled point and the bottom of the text, in pixels
+const LABEL_OFFSET: f32 = 12.0;
+
+#[derive(Clone, Copy, Debug)]
+pub struct WorldLabelSettings {
+    /// Labels further than this from the camera are skipped
+    pub max_distance: f32,
+    /// Size of a font pixel in screen pixels at full scale
+    pub scale: f32,
+}
+
+impl Default for WorldLabelSettings {
+    fn default() -> Self {
+        Self {
+            max_distance: 2000.0,
+            scale: 2.0,
+        }
+    }
+}
+
+struct WorldLabel {
+    position: Vec3,
+    text: String,
+    color: [f32; 4],
+    depth_test: bool,
+}
+
+/// World space labels accumulated over a frame, cleared after they are drawn
+#[derive(Default)]
+pub(super) struct WorldLabels {
+    pub(super) settings: WorldLabelSettings,
+    labels: Vec<WorldLabel>,
+}
+
+impl WorldLabels {
+    pub(super) fn push(&mut self, position: Vec3, text: &str, color: [f32; 4], depth_test: bool) {
+        self.labels.push(WorldLabel {
+            position,
+            text: text.to_string(),
+            color,
+            depth_test,
+        });
+    }
+
+    pub(super) fn clear(&mut self) {
+        self.labels.clear();
+    }
+}
+
+#[repr(C)]
+#[derive(Pod, Zeroable, Copy, Clone, Debug)]
+struct LabelVertex {
+    /// Already in clip space, the depth is the labeled point's so the whole label is tested against it
+    position: [f32; 3],
+    color: [f32; 4],
+}
+
+impl LabelVertex {
+    const ATTRIBUTES: [wgpu::VertexAttribute; 2] = wgpu::vertex_attr_array![
+        0 => Float32x3,
+        1 => Float32x4,
+    ];
+
+    fn desc<'a>() -> wgpu::VertexBufferLayout<'a> {
+        wgpu::VertexBufferLayout {
+            array_stride: std::mem::size_of::<LabelVertex>() as wgpu::BufferAddress,
+            step_mode: wgpu::VertexStepMode::Vertex,
+            attributes: &Self::ATTRIBUTES,
+        }
+    }
+}
+
+/// Drawn in the main pass after the debug lines, so depth tested labels can use the scene's depth buffer
+pub(super) struct WorldLabelPass {
+    depth_tested_pipeline: wgpu::RenderPipeline,
+    overlay_pipeline: wgpu::RenderPipeline,
+
+    vertex_buffer: wgpu::Buffer,
+    vertex_capacity: usize,
+    depth_tested_count: u32,
+    overlay_count: u32,
+}
+
+impl WorldLabelPass {
+    pub(super) fn new(
+        device: &wgpu::Device,
+        output_format: wgpu::TextureFormat,
+        depth_format: wgpu::TextureFormat,
+        sample_count: u32,
+    ) -> Self {
+        let (depth_tested_pipeline, overlay_pipeline) =
+            create_label_pipelines(device, output_format, depth_format, sample_count);
+
+        let vertex_capacity = 1024;
+        Self {
+            depth_tested_pipeline,
+            overlay_pipeline,
+            vertex_buffer: create_vertex_buffer(device, vertex_capacity),
+            vertex_capacity,
+            depth_tested_count: 0,
+            overlay_count: 0,
+        }
+    }
+
+    pub(super) fn rebuild_pipelines(
+        &mut self,
+        device: &wgpu::Device,
+        output_format: wgpu::TextureFormat,
+        depth_format: wgpu::TextureFormat,
+        sample_count: u32,
+    ) {
+        (self.depth_tested_pipeline, self.overlay_pipeline) =
+            create_label_pipelines(device, output_format, depth_format, sample_count);
+    }
+
+    /// Projects this frame's labels and uploads them, labels behind the camera, off screen or too far away are skipped
+    pub(super) fn update(
+        &mut self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        labels: &WorldLabels,
+        scene_data: &SceneData,
+        size: [u32; 2],
+    ) {
+        let settings = &labels.settings;
+        let view_projection = Mat4::from_cols_array(&scene_data.view_projection_matrix);
+        let camera_position = Vec3::from_slice(&scene_data.camera_position[0..3]);
+        let pixel_to_ndc = Vec2::new(2.0 / size[0] as f32, -2.0 / size[1] as f32);
+        let mut depth_tested = Vec::new();
+        let mut overlay = Vec::new();
+
+        for label in labels.labels.iter() {
+            let distance = label.position.distance(camera_position);
+            if distance > settings.max_distance {
+                continue;
+            }
+
+            let clip = view_projection * label.position.extend(1.0);
+            if clip.w <= f32::EPSILON {
+                continue;
+            }
+            let anchor = clip.truncate() / clip.w;
+            if anchor.x.abs() > 1.0 || anchor.y.abs() > 1.0 {
+                continue;
+            }
+
+            let scale = (settings.scale * FULL_SCALE_DISTANCE / distance)
+                .clamp(MIN_FONT_SCALE, settings.scale.max(MIN_FONT_SCALE));
+            let fade_start = settings.max_distance * FADE_START_FRACTION;
+            let fade = 1.0
+                - ((distance - fade_start) / (settings.max_distance - fade_start)).clamp(0.0, 1.0);
+            let color = [
+                label.color[0],
+                label.color[1],
+                label.color[2],
+                label.color[3] * fade,
+            ];
+
+            //Centered above the point, built in pixels and moved into clip space around the anchor
+            let text_size = Vec2::new(
+                bitmap_font::text_width(&label.text) as f32,
+                bitmap_font::GLYPH_HEIGHT as f32,
+            ) * scale;
+            let top_left = Vec2::new(-text_size.x * 0.5, -(text_size.y + LABEL_OFFSET));
+            let vertex = |pixel: Vec2| LabelVertex {
+                position: (anchor.truncate() + pixel * pixel_to_ndc)
+                    .extend(anchor.z)
+                    .to_array(),
+                color,
+            };
+
+            let vertices = if label.depth_test {
+                &mut depth_tested
+            } else {
+                &mut overlay
+            };
+            for (row, start, end) in bitmap_font::pixel_runs(&label.text) {
+                let min = top_left + Vec2::new(start as f32, row as f32) * scale;
+                let max = top_left + Vec2::new(end as f32, (row + 1) as f32) * scale;
+                vertices.extend([
+                    vertex(min),
+                    vertex(Vec2::new(max.x, min.y)),
+                    vertex(max),
+                    vertex(min),
+                    vertex(max),
+                    vertex(Vec2::new(min.x, max.y)),
+                ]);
+            }
+        }
+
+        let vertex_count = depth_tested.len() + overlay.len();
+        if vertex_count > self.vertex_capacity {
+            self.vertex_capacity = vertex_count.next_power_of_two();
+            self.vertex_buffer = create_vertex_buffer(device, self.vertex_capacity);
+        }
+
+        self.depth_tested_count = depth_tested.len() as u32;
+        self.overlay_count = overlay.len() as u32;
+        depth_tested.extend(overlay);
+        if !depth_tested.is_empty() {
+            queue.write_buffer(&self.vertex_buffer, 0, bytemuck::cast_slice(&depth_tested));
+        }
+    }
+
+    /// Returns the number of draw calls
+    pub(super) fn draw<'a>(&'a self, render_pass: &mut wgpu::RenderPass<'a>) -> u32 {
+        let mut draw_calls = 0;
+        if self.depth_tested_count == 0 && self.overlay_count == 0 {
+            return draw_calls;
+        }
+
+        render_pass.set_vertex_buffer(0, self.vertex_buffer.slice(..));
+
+        if self.depth_tested_count > 0 {
+            render_pass.set_pipeline(&self.depth_tested_pipeline);
+            render_pass.draw(0..self.depth_tested_count, 0..1);
+            draw_calls += 1;
+        }
+
+        if self.overlay_count > 0 {
+            render_pass.set_pipeline(&self.overlay_pipeline);
+            render_pass.draw(
+                self.depth_tested_count..(self.depth_tested_count + self.overlay_count),
+                0..1,
+            );
+            draw_calls += 1;
+        }
+
+        draw_calls
+    }
+}
+
+fn create_vertex_buffer(device: &wgpu::Device, vertex_capacity: usize) -> wgpu::Buffer {
+    device.create_buffer(&wgpu::BufferDescriptor {
+        label: Some("World Label Vertex Buffer"),
+        size: (vertex_capacity * std::mem::size_of::<LabelVertex>()) as wgpu::BufferAddress,
+        usage: wgpu::BufferUsages::VERTEX | wgpu::BufferUsages::COPY_DST,
+        mapped_at_creation: false,
+    })
+}
+
+fn create_label_pipelines(
+    device: &wgpu::Device,
+    output_format: wgpu::TextureFormat,
+    depth_format: wgpu::TextureFormat,
+    sample_count: u32,
+) -> (wgpu::RenderPipeline, wgpu::RenderPipeline) {
+    let code = include_str!("../shader/world_labels.wgsl");
+    let shader_module = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+        label: Some("World Label Shader"),
+        source: wgpu::ShaderSource::Wgsl(Cow::from(code)),
+    });
+
+    let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+        label: Some("World Label PipelineLayout"),
+        bind_group_layouts: &[],
+        push_constant_ranges: &[],
+    });
+
+    let create_pipeline = |depth_compare| {
+        device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+            label: Some("World Label Pipeline"),
+            layout: Some(&pipeline_layout),
+            vertex: wgpu::VertexState {
+                module: &shader_module,
+                entry_point: "vs_main",
+                buffers: &[LabelVertex::desc()],
+            },
+            primitive: wgpu::PrimitiveState {
+                topology: wgpu::PrimitiveTopology::TriangleList,
+                ..Default::default()
+            },
+            depth_stencil: Some(wgpu::DepthStencilState {
+                format: depth_format,
+                depth_write_enabled: false,
+                depth_compare,
+                stencil: Default::default(),
+                bias: Default::default(),
+            }),
+            multisample: wgpu::MultisampleState {
+                count: sample_count,
+                ..Default::default()
+            },
+            fragment: Some(wgpu::FragmentState {
+                module: &shader_module,
+                entry_point: if needs_srgb_encode(output_format) {
+                    "fs_main_srgb_encode"
+                } else {
+                    "fs_main"
+                },
+                targets: &[Some(wgpu::ColorTargetState {
+                    format: output_format,
+                    blend: Some(wgpu::BlendState::ALPHA_BLENDING),
+                    write_mask: wgpu::ColorWrites::ALL,
+                })],
+            }),
+            multiview: None,
+        })
+    };
+
+    (
+        create_pipeline(wgpu::CompareFunction::GreaterEqual),
+        create_pipeline(wgpu::CompareFunction::Always),
+    )
+}
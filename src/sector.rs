@@ -0,0 +1,101 @@
+use crate::camera::Frustum;
+use crate::world::EntityId;
+use glam::{IVec3, Vec3};
+use std::collections::{HashMap, HashSet};
+
+/// Edge length of a sector in meters
+pub const SECTOR_SIZE: f32 = 1000.0;
+
+/// Sparse grid of sectors with the entities whose position is inside each one
+#[derive(Default)]
+pub struct SectorGrid {
+    sectors: HashMap<IVec3, HashSet<EntityId>>,
+    entity_sectors: HashMap<EntityId, IVec3>,
+}
+
+impl SectorGrid {
+    pub fn sector_of(position: Vec3) -> IVec3 {
+        (position / SECTOR_SIZE).floor().as_ivec3()
+    }
+
+    fn sector_center(sector: IVec3) -> Vec3 {
+        (sector.as_vec3() + Vec3::splat(0.5)) * SECTOR_SIZE
+    }
+
+    /// Moves the entity into the sector containing `position`, adding it if it isn't in the grid yet
+    pub fn set_position(&mut self, entity_id: EntityId, position: Vec3) {
+        let sector = Self::sector_of(position);
+        match self.entity_sectors.insert(entity_id, sector) {
+            Some(old_sector) if old_sector == sector => return,
+            Some(old_sector) => self.remove_from_sector(entity_id, old_sector),
+            None => {}
+        }
+        self.sectors.entry(sector).or_default().insert(entity_id);
+    }
+
+    pub fn remove(&mut self, entity_id: EntityId) {
+        if let Some(sector) = self.entity_sectors.remove(&entity_id) {
+            self.remove_from_sector(entity_id, sector);
+        }
+    }
+
+    fn remove_from_sector(&mut self, entity_id: EntityId, sector: IVec3) {
+        if let Some(entities) = self.sectors.get_mut(&sector) {
+            entities.remove(&entity_id);
+            if entities.is_empty() {
+                self.sectors.remove(&sector);
+            }
+        }
+    }
+
+    pub fn clear(&mut self) {
+        self.sectors.clear();
+        self.entity_sectors.clear();
+    }
+
+    pub fn sector_count(&self) -> usize {
+        self.sectors.len()
+    }
+
+    /// Entities in every sector the sphere touches, some of them can be outside the sphere itself
+    pub fn candidates_in_radius(&self, position: Vec3, radius: f32) -> Vec<EntityId> {
+        let min = Self::sector_of(position - Vec3::splat(radius));
+        let max = Self::sector_of(position + Vec3::splat(radius));
+        let sector_volume = (max - min + IVec3::ONE).as_vec3();
+
+        //A large radius in a sparse grid is cheaper to answer by going through the occupied sectors
+        if sector_volume.x * sector_volume.y * sector_volume.z > self.sectors.len() as f32 {
+            return self
+                .sectors
+                .iter()
+                .filter(|(sector, _)| sector.cmpge(min).all() && sector.cmple(max).all())
+                .flat_map(|(_, entities)| entities.iter().copied())
+                .collect();
+        }
+
+        let mut candidates = Vec::new();
+        for x in min.x..=max.x {
+            for y in min.y..=max.y {
+                for z in min.z..=max.z {
+                    if let Some(entities) = self.sectors.get(&IVec3::new(x, y, z)) {
+                        candidates.extend(entities.iter().copied());
+                    }
+                }
+            }
+        }
+        candidates
+    }
+
+    /// Entities in sectors that are at least partly inside the frustum
+    pub fn candidates_in_frustum(&self, frustum: &Frustum) -> Vec<EntityId> {
+        //Radius of the sphere around a sector's corners
+        let sector_radius = SECTOR_SIZE * 0.5 * 3.0f32.sqrt();
+        self.sectors
+            .iter()
+            .filter(|(sector, _)| {
+                frustum.intersects_sphere(Self::sector_center(**sector), sector_radius)
+            })
+            .flat_map(|(_, entities)| entities.iter().copied())
+            .collect()
+    }
+}
@@ -0,0 +1,242 @@
+use log::{info, warn};
+use serde::{Deserialize, Serialize};
+use std::path::Path;
+
+#[derive(Debug, thiserror::Error)]
+pub enum SettingsError {
+    #[error("failed to access settings file: {0}")]
+    Io(#[from] std::io::Error),
+    #[cfg(feature = "ron")]
+    #[error("failed to parse settings file: {0}")]
+    Parse(#[from] ron::error::SpannedError),
+    #[cfg(feature = "ron")]
+    #[error("failed to write settings: {0}")]
+    Serialize(#[from] ron::Error),
+    #[cfg(not(feature = "ron"))]
+    #[error("settings are stored as ron but ron support isn't enabled")]
+    RonDisabled,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Backend {
+    /// Whatever wgpu picks for the platform
+    Auto,
+    Vulkan,
+    Dx12,
+    Metal,
+    Gl,
+}
+
+impl Backend {
+    pub fn backends(self) -> wgpu::Backends {
+        match self {
+            Backend::Auto => wgpu::Backends::all(),
+            Backend::Vulkan => wgpu::Backends::VULKAN,
+            Backend::Dx12 => wgpu::Backends::DX12,
+            Backend::Metal => wgpu::Backends::METAL,
+            Backend::Gl => wgpu::Backends::GL,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum FullscreenMode {
+    /// A window covering the monitor, switching in and out of it is instant
+    Borderless,
+    /// Takes over the monitor at its best video mode, falls back to borderless where the platform doesn't support it
+    Exclusive,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum VsyncMode {
+    /// Uncapped, may tear
+    Off,
+    /// Uncapped without tearing, frames that miss the refresh are dropped
+    Mailbox,
+    /// Capped to the refresh rate
+    On,
+}
+
+impl VsyncMode {
+    pub fn present_mode(self) -> wgpu::PresentMode {
+        match self {
+            VsyncMode::Off => wgpu::PresentMode::Immediate,
+            VsyncMode::Mailbox => wgpu::PresentMode::Mailbox,
+            VsyncMode::On => wgpu::PresentMode::Fifo,
+        }
+    }
+
+    /// Modes other than the three above count as on
+    pub fn from_present_mode(present_mode: wgpu::PresentMode) -> Self {
+        match present_mode {
+            wgpu::PresentMode::Immediate => VsyncMode::Off,
+            wgpu::PresentMode::Mailbox => VsyncMode::Mailbox,
+            _ => VsyncMode::On,
+        }
+    }
+}
+
+/// Window and graphics options read at startup, fields missing from the file keep their defaults
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Settings {
+    /// Size of the window when it isn't maximized or fullscreen, also the headless output size
+    pub window_size: [u32; 2],
+    pub fullscreen: bool,
+    /// Used whenever fullscreen is on, toggled with F11
+    pub fullscreen_mode: FullscreenMode,
+    /// Index into the available monitors, None for the one the window is on
+    pub monitor: Option<usize>,
+    pub maximized: bool,
+    pub backend: Backend,
+    /// Prefer an integrated gpu to save power
+    pub low_power_gpu: bool,
+    pub vsync: VsyncMode,
+    /// Frame rate cap on top of vsync, None for uncapped
+    pub max_fps: Option<u32>,
+    /// 1 disables MSAA, unsupported counts fall back when the renderer checks them
+    pub msaa_samples: u32,
+    /// Swaps which side of every triangle is the front, for meshes exported with flipped winding
+    pub flip_front_faces: bool,
+    /// Horizontal fov in degrees
+    pub fov_deg: f32,
+    /// Multiplies every draw and LOD distance, lower values draw less detail for performance
+    pub draw_distance_scale: f32,
+    /// Radians per pixel of mouse movement
+    pub mouse_sensitivity: f32,
+    /// 0 to 1, scales every sound
+    pub master_volume: f32,
+    /// 0 to 1, scales sounds in the world but not ui sounds
+    pub sfx_volume: f32,
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Self {
+            window_size: [1280, 720],
+            fullscreen: false,
+            fullscreen_mode: FullscreenMode::Borderless,
+            monitor: None,
+            maximized: true,
+            backend: Backend::Auto,
+            low_power_gpu: false,
+            vsync: VsyncMode::Mailbox,
+            max_fps: None,
+            msaa_samples: 4,
+            flip_front_faces: false,
+            fov_deg: 95.0,
+            draw_distance_scale: 1.0,
+            mouse_sensitivity: 0.002,
+            master_volume: 1.0,
+            sfx_volume: 0.8,
+        }
+    }
+}
+
+impl Settings {
+    /// Falls back to the defaults if the file can't be read, out of range values fall back one at a time
+    pub fn load_or_default<P: AsRef<Path>>(path: P) -> Self {
+        let path = path.as_ref();
+        if !path.exists() {
+            info!("No settings file at {:?}, using the default settings", path);
+            return Self::default();
+        }
+
+        match std::fs::read_to_string(path)
+            .map_err(SettingsError::from)
+            .and_then(|contents| parse_settings(&contents))
+        {
+            Ok(settings) => settings.validated(),
+            Err(e) => {
+                warn!("Failed to load settings from {:?}: {}", path, e);
+                Self::default()
+            }
+        }
+    }
+
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> Result<(), SettingsError> {
+        std::fs::write(path, write_settings(self)?)?;
+        Ok(())
+    }
+
+    fn validated(mut self) -> Self {
+        let defaults = Self::default();
+
+        if self.window_size[0] == 0 || self.window_size[1] == 0 {
+            warn!(
+                "Invalid window size {:?}, using {:?}",
+                self.window_size, defaults.window_size
+            );
+            self.window_size = defaults.window_size;
+        }
+
+        if self.max_fps == Some(0) {
+            warn!("Invalid frame rate cap 0, running uncapped");
+            self.max_fps = None;
+        }
+
+        if !self.msaa_samples.is_power_of_two() || self.msaa_samples > 16 {
+            warn!(
+                "Invalid MSAA sample count {}, using {}",
+                self.msaa_samples, defaults.msaa_samples
+            );
+            self.msaa_samples = defaults.msaa_samples;
+        }
+
+        if !(10.0..=170.0).contains(&self.fov_deg) {
+            warn!("Invalid fov {}, using {}", self.fov_deg, defaults.fov_deg);
+            self.fov_deg = defaults.fov_deg;
+        }
+
+        if !(self.draw_distance_scale.is_finite() && self.draw_distance_scale > 0.0) {
+            warn!(
+                "Invalid draw distance scale {}, using {}",
+                self.draw_distance_scale, defaults.draw_distance_scale
+            );
+            self.draw_distance_scale = defaults.draw_distance_scale;
+        }
+
+        if !(self.mouse_sensitivity.is_finite() && self.mouse_sensitivity > 0.0) {
+            warn!(
+                "Invalid mouse sensitivity {}, using {}",
+                self.mouse_sensitivity, defaults.mouse_sensitivity
+            );
+            self.mouse_sensitivity = defaults.mouse_sensitivity;
+        }
+
+        for (name, volume, default) in [
+            ("master", &mut self.master_volume, defaults.master_volume),
+            ("sfx", &mut self.sfx_volume, defaults.sfx_volume),
+        ] {
+            if !(0.0..=1.0).contains(volume) {
+                warn!("Invalid {} volume {}, using {}", name, volume, default);
+                *volume = default;
+            }
+        }
+
+        self
+    }
+}
+
+#[cfg(feature = "ron")]
+fn parse_settings(contents: &str) -> Result<Settings, SettingsError> {
+    Ok(ron::from_str(contents)?)
+}
+
+#[cfg(not(feature = "ron"))]
+fn parse_settings(_contents: &str) -> Result<Settings, SettingsError> {
+    Err(SettingsError::RonDisabled)
+}
+
+#[cfg(feature = "ron")]
+fn write_settings(settings: &Settings) -> Result<String, SettingsError> {
+    Ok(ron::ser::to_string_pretty(
+        settings,
+        ron::ser::PrettyConfig::default(),
+    )?)
+}
+
+#[cfg(not(feature = "ron"))]
+fn write_settings(_settings: &Settings) -> Result<String, SettingsError> {
+    Err(SettingsError::RonDisabled)
+}
@@ -0,0 +1,119 @@
+struct VertexOutput {
+    @builtin(position) position: vec4<f32>,
+    @location(0) uv: vec2<f32>,
+};
+
+struct BloomData {
+    // x: threshold, y: soft knee, z: intensity
+    params: vec4<f32>,
+}
+
+@group(0)
+@binding(0)
+var source_texture: texture_2d<f32>;
+
+@group(0)
+@binding(1)
+var source_sampler: sampler;
+
+@group(0)
+@binding(2)
+var<uniform> bloom_data: BloomData;
+
+// Only read by the composite pass
+@group(0)
+@binding(3)
+var bloom_texture: texture_2d<f32>;
+
+// Fullscreen triangle
+@vertex
+fn vs_main(@builtin(vertex_index) vertexIdx: u32) -> VertexOutput {
+    var uv = vec2<f32>(f32((vertexIdx << 1u) & 2u), f32(vertexIdx & 2u));
+
+    var result: VertexOutput;
+    result.position = vec4<f32>(uv * 2.0 - 1.0, 0.0, 1.0);
+    result.uv = vec2<f32>(uv.x, 1.0 - uv.y);
+    return result;
+}
+
+fn texel_size() -> vec2<f32> {
+    return 1.0 / vec2<f32>(textureDimensions(source_texture));
+}
+
+// Four bilinear taps average a 4x4 block of the larger source
+fn downsample(uv: vec2<f32>) -> vec3<f32> {
+    var offset = texel_size() * 0.5;
+    var color = textureSample(source_texture, source_sampler, uv + vec2<f32>(-offset.x, -offset.y)).xyz;
+    color += textureSample(source_texture, source_sampler, uv + vec2<f32>(offset.x, -offset.y)).xyz;
+    color += textureSample(source_texture, source_sampler, uv + vec2<f32>(-offset.x, offset.y)).xyz;
+    color += textureSample(source_texture, source_sampler, uv + vec2<f32>(offset.x, offset.y)).xyz;
+    return color * 0.25;
+}
+
+// 9 tap gaussian folded into 5 bilinear taps
+fn blur(uv: vec2<f32>, direction: vec2<f32>) -> vec3<f32> {
+    var blur_step = texel_size() * direction;
+    var color = textureSample(source_texture, source_sampler, uv).xyz * 0.2270270270;
+    color += textureSample(source_texture, source_sampler, uv + blur_step * 1.3846153846).xyz * 0.3162162162;
+    color += textureSample(source_texture, source_sampler, uv - blur_step * 1.3846153846).xyz * 0.3162162162;
+    color += textureSample(source_texture, source_sampler, uv + blur_step * 3.2307692308).xyz * 0.0702702703;
+    color += textureSample(source_texture, source_sampler, uv - blur_step * 3.2307692308).xyz * 0.0702702703;
+    return color;
+}
+
+@fragment
+fn fs_threshold(vertex: VertexOutput) -> @location(0) vec4<f32> {
+    var color = downsample(vertex.uv);
+    var threshold = bloom_data.params.x;
+    var knee = bloom_data.params.y;
+
+    // Soft knee so pixels fade in instead of popping on at the threshold
+    var brightness = max(color.x, max(color.y, color.z));
+    var soft = clamp(brightness - threshold + knee, 0.0, 2.0 * knee);
+    soft = (soft * soft) / (4.0 * knee + 0.0001);
+    var contribution = max(soft, brightness - threshold) / max(brightness, 0.0001);
+
+    return vec4<f32>(color * contribution, 1.0);
+}
+
+@fragment
+fn fs_downsample(vertex: VertexOutput) -> @location(0) vec4<f32> {
+    return vec4<f32>(downsample(vertex.uv), 1.0);
+}
+
+@fragment
+fn fs_blur_horizontal(vertex: VertexOutput) -> @location(0) vec4<f32> {
+    return vec4<f32>(blur(vertex.uv, vec2<f32>(1.0, 0.0)), 1.0);
+}
+
+@fragment
+fn fs_blur_vertical(vertex: VertexOutput) -> @location(0) vec4<f32> {
+    return vec4<f32>(blur(vertex.uv, vec2<f32>(0.0, 1.0)), 1.0);
+}
+
+@fragment
+fn fs_upsample(vertex: VertexOutput) -> @location(0) vec4<f32> {
+    return vec4<f32>(textureSample(source_texture, source_sampler, vertex.uv).xyz, 0.0);
+}
+
+fn composite(uv: vec2<f32>) -> vec3<f32> {
+    var scene = textureSample(source_texture, source_sampler, uv).xyz;
+    var bloom = textureSample(bloom_texture, source_sampler, uv).xyz;
+    return scene + bloom * bloom_data.params.z;
+}
+
+fn linear_to_srgb(color: vec3<f32>) -> vec3<f32> {
+    var low = color * 12.92;
+    var high = 1.055 * pow(color, vec3<f32>(1.0 / 2.4)) - 0.055;
+    return select(high, low, color <= vec3<f32>(0.0031308));
+}
+
+@fragment
+fn fs_composite(vertex: VertexOutput) -> @location(0) vec4<f32> {
+    return vec4<f32>(composite(vertex.uv), 1.0);
+}
+
+@fragment
+fn fs_composite_srgb_encode(vertex: VertexOutput) -> @location(0) vec4<f32> {
+    return vec4<f32>(linear_to_srgb(saturate(composite(vertex.uv))), 1.0);
+}
@@ -0,0 +1,31 @@
+struct VertexOutput {
+    @builtin(position) position: vec4<f32>,
+    @location(0) color: vec4<f32>,
+};
+
+@vertex
+fn vs_main(
+    @location(0) position: vec2<f32>,
+    @location(1) color: vec4<f32>,
+) -> VertexOutput {
+    var result: VertexOutput;
+    result.position = vec4<f32>(position, 0.0, 1.0);
+    result.color = color;
+    return result;
+}
+
+fn linear_to_srgb(color: vec3<f32>) -> vec3<f32> {
+    var low = color * 12.92;
+    var high = 1.055 * pow(color, vec3<f32>(1.0 / 2.4)) - 0.055;
+    return select(high, low, color <= vec3<f32>(0.0031308));
+}
+
+@fragment
+fn fs_main(vertex: VertexOutput) -> @location(0) vec4<f32> {
+    return vertex.color;
+}
+
+@fragment
+fn fs_main_srgb_encode(vertex: VertexOutput) -> @location(0) vec4<f32> {
+    return vec4<f32>(linear_to_srgb(saturate(vertex.color.xyz)), vertex.color.w);
+}
@@ -0,0 +1,70 @@
+struct VertexOutput {
+    @builtin(position) position: vec4<f32>,
+    @location(0) color: vec4<f32>,
+    @location(1) uv: vec2<f32>,
+};
+
+struct SceneData {
+    view_projection_matrix: mat4x4<f32>,
+    ambient_light_color: vec4<f32>,
+    sun_light_direction_intensity: vec4<f32>,
+    sun_light_color: vec4<f32>,
+    camera_position: vec4<f32>,
+}
+
+@group(0)
+@binding(0)
+var<uniform> scene_data: SceneData;
+
+@vertex
+fn vs_main(
+    @builtin(vertex_index) vertex_index: u32,
+    @location(0) position: vec3<f32>,
+    @location(1) size: f32,
+    @location(2) color: vec4<f32>,
+) -> VertexOutput {
+    //Two triangles, corners from -1 to 1
+    var corners = array<vec2<f32>, 6>(
+        vec2<f32>(-1.0, -1.0),
+        vec2<f32>(1.0, -1.0),
+        vec2<f32>(1.0, 1.0),
+        vec2<f32>(-1.0, -1.0),
+        vec2<f32>(1.0, 1.0),
+        vec2<f32>(-1.0, 1.0),
+    );
+    var corner = corners[vertex_index];
+
+    //The first two rows of the view projection point along the camera's right and up
+    var m = scene_data.view_projection_matrix;
+    var right = normalize(vec3<f32>(m[0][0], m[1][0], m[2][0]));
+    var up = normalize(vec3<f32>(m[0][1], m[1][1], m[2][1]));
+    var world_position = position + (right * corner.x + up * corner.y) * (size * 0.5);
+
+    var result: VertexOutput;
+    result.position = scene_data.view_projection_matrix * vec4<f32>(world_position, 1.0);
+    result.color = color;
+    result.uv = corner;
+    return result;
+}
+
+fn linear_to_srgb(color: vec3<f32>) -> vec3<f32> {
+    var low = color * 12.92;
+    var high = 1.055 * pow(color, vec3<f32>(1.0 / 2.4)) - 0.055;
+    return select(high, low, color <= vec3<f32>(0.0031308));
+}
+
+//Soft round particle that fades out towards the edge of the quad
+fn falloff(uv: vec2<f32>) -> f32 {
+    var distance = length(uv);
+    return 1.0 - smoothstep(0.0, 1.0, distance);
+}
+
+@fragment
+fn fs_main(vertex: VertexOutput) -> @location(0) vec4<f32> {
+    return vec4<f32>(vertex.color.xyz, vertex.color.w * falloff(vertex.uv));
+}
+
+@fragment
+fn fs_main_srgb_encode(vertex: VertexOutput) -> @location(0) vec4<f32> {
+    return vec4<f32>(linear_to_srgb(saturate(vertex.color.xyz)), vertex.color.w * falloff(vertex.uv));
+}
@@ -2,7 +2,10 @@ struct VertexOutput {
     @builtin(position) position: vec4<f32>,
     @location(0) normal_ws: vec3<f32>,
     @location(1) uv: vec2<f32>,
-
+    @location(2) tangent_ws: vec3<f32>,
+    @location(3) bitangent_ws: vec3<f32>,
+    @location(4) position_ws: vec3<f32>,
+    @location(5) tint: vec4<f32>,
 };
 
 struct SceneData {
@@ -10,47 +13,146 @@ struct SceneData {
     ambient_light_color: vec4<f32>,
     sun_light_direction_intensity: vec4<f32>,
     sun_light_color: vec4<f32>,
+    camera_position: vec4<f32>,
+}
+
+struct PunctualLight {
+    position_radius: vec4<f32>,
+    color_intensity: vec4<f32>,
+    // w is 1.0 for spot lights
+    direction_spot: vec4<f32>,
+    // cosines of the inner and outer cone angles
+    cone_cos: vec4<f32>,
+}
+
+struct LightData {
+    count: vec4<u32>,
+    lights: array<PunctualLight, 32>,
+}
+
+struct InstanceData {
+    model_matrix: mat4x4<f32>,
+    normal_matrix: mat3x3<f32>,
+    tint: vec4<f32>,
 }
 
 struct PbrMaterialData {
     color: vec4<f32>,
-    metallic_roughness_pad: vec4<f32>,
+    // z is the shading model, 0 lit, 1 unlit, 2 emissive
+    metallic_roughness_shading: vec4<f32>,
+    emissive: vec4<f32>,
 }
 
 @group(0)
 @binding(0)
 var<uniform> scene_data: SceneData;
 
+@group(0)
+@binding(1)
+var<uniform> light_data: LightData;
+
 @group(1)
 @binding(0)
-var<uniform> model_matrices: array<mat4x4<f32>, 1024>;
+var<storage, read> instances: array<InstanceData>;
 
 @group(2)
 @binding(0)
 var<uniform> material_data: PbrMaterialData;
 
+@group(2)
+@binding(1)
+var albedo_texture: texture_2d<f32>;
+
+@group(2)
+@binding(2)
+var albedo_sampler: sampler;
+
+@group(2)
+@binding(3)
+var normal_texture: texture_2d<f32>;
+
 @vertex
 fn vs_main(
     @builtin(instance_index) instanceIdx : u32,
     @location(0) position: vec3<f32>,
     @location(1) normal: vec3<f32>,
     @location(2) uv: vec2<f32>,
+    @location(3) tangent: vec4<f32>,
 ) -> VertexOutput {
-    var mvp_matrix = scene_data.view_projection_matrix * model_matrices[instanceIdx];
+    var instance = instances[instanceIdx];
+    var position_ws = instance.model_matrix * vec4<f32>(position, 1.0);
 
     var result: VertexOutput;
-    result.position = mvp_matrix * vec4<f32>(position, 1.0);
-    result.normal_ws = normalize((model_matrices[instanceIdx] * vec4<f32>(normal, 0.0)).xyz);
+    result.position = scene_data.view_projection_matrix * position_ws;
+    result.position_ws = position_ws.xyz;
+    result.normal_ws = normalize(instance.normal_matrix * normal);
+    result.tangent_ws = normalize((instance.model_matrix * vec4<f32>(tangent.xyz, 0.0)).xyz);
+    result.bitangent_ws = cross(result.normal_ws, result.tangent_ws) * tangent.w;
     result.uv = uv;
+    result.tint = instance.tint;
     return result;
 }
 
+fn punctual_light_color(light: PunctualLight, position_ws: vec3<f32>, normal_ws: vec3<f32>) -> vec3<f32> {
+    var to_light = light.position_radius.xyz - position_ws;
+    var distance = length(to_light);
+    var light_dir = to_light / max(distance, 0.0001);
+
+    // Inverse square falloff windowed to reach zero at the light radius
+    var distance_ratio = distance / max(light.position_radius.w, 0.0001);
+    var window = saturate(1.0 - distance_ratio * distance_ratio * distance_ratio * distance_ratio);
+    var attenuation = (window * window) / max(distance * distance, 0.0001);
+
+    if (light.direction_spot.w > 0.0) {
+        var spot_cos = dot(-light_dir, light.direction_spot.xyz);
+        attenuation *= smoothstep(light.cone_cos.y, light.cone_cos.x, spot_cos);
+    }
+
+    var dot_power = saturate(dot(normal_ws, light_dir));
+    return light.color_intensity.xyz * light.color_intensity.w * attenuation * dot_power;
+}
+
+fn shade(vertex: VertexOutput) -> vec4<f32> {
+    var base_color = material_data.color * vertex.tint * textureSample(albedo_texture, albedo_sampler, vertex.uv);
+    // Sampled before branching, texture sampling has to stay in uniform control flow
+    var normal_ts = textureSample(normal_texture, albedo_sampler, vertex.uv).xyz * 2.0 - 1.0;
+
+    var shading_model = u32(material_data.metallic_roughness_shading.z + 0.5);
+    if (shading_model == 1u) {
+        return base_color;
+    }
+    if (shading_model == 2u) {
+        return vec4<f32>(base_color.xyz + material_data.emissive.xyz, base_color.w);
+    }
+
+    var tbn = mat3x3<f32>(normalize(vertex.tangent_ws), normalize(vertex.bitangent_ws), normalize(vertex.normal_ws));
+    var normal_ws = normalize(tbn * normal_ts);
+
+    var ambient_color = base_color.xyz * scene_data.ambient_light_color.xyz;
+
+    var dot_power = saturate( dot(-normal_ws, scene_data.sun_light_direction_intensity.xyz));
+    var light_color = base_color.xyz * (scene_data.sun_light_color.xyz * scene_data.sun_light_direction_intensity.w * dot_power );
+
+    for (var i = 0u; i < min(light_data.count.x, 32u); i += 1u) {
+        light_color += base_color.xyz * punctual_light_color(light_data.lights[i], vertex.position_ws, normal_ws);
+    }
+
+    return vec4<f32>(ambient_color + light_color + material_data.emissive.xyz, base_color.w);
+}
+
+fn linear_to_srgb(color: vec3<f32>) -> vec3<f32> {
+    var low = color * 12.92;
+    var high = 1.055 * pow(color, vec3<f32>(1.0 / 2.4)) - 0.055;
+    return select(high, low, color <= vec3<f32>(0.0031308));
+}
+
 @fragment
 fn fs_main(vertex: VertexOutput) -> @location(0) vec4<f32> {
-    var ambient_color = material_data.color.xyz * scene_data.ambient_light_color.xyz;
-
-    var dot_power = saturate( dot(-vertex.normal_ws, scene_data.sun_light_direction_intensity.xyz));
-    var light_color = material_data.color.xyz * (scene_data.sun_light_color.xyz * scene_data.sun_light_direction_intensity.w * dot_power );
+    return shade(vertex);
+}
 
-    return vec4<f32>(ambient_color + light_color, material_data.color.w);
-}
\ No newline at end of file
+@fragment
+fn fs_main_srgb_encode(vertex: VertexOutput) -> @location(0) vec4<f32> {
+    var color = shade(vertex);
+    return vec4<f32>(linear_to_srgb(saturate(color.xyz)), color.w);
+}
@@ -0,0 +1,118 @@
+struct VertexOutput {
+    @builtin(position) position: vec4<f32>,
+    @location(0) ndc: vec2<f32>,
+};
+
+struct SkyboxData {
+    inverse_view_projection_matrix: mat4x4<f32>,
+    // x: 1.0 for procedural, y: star density, z: seed
+    params: vec4<f32>,
+}
+
+@group(0)
+@binding(0)
+var<uniform> skybox_data: SkyboxData;
+
+@group(0)
+@binding(1)
+var skybox_texture: texture_cube<f32>;
+
+@group(0)
+@binding(2)
+var skybox_sampler: sampler;
+
+// Fullscreen triangle
+@vertex
+fn vs_main(@builtin(vertex_index) vertexIdx: u32) -> VertexOutput {
+    var uv = vec2<f32>(f32((vertexIdx << 1u) & 2u), f32(vertexIdx & 2u));
+    var ndc = uv * 2.0 - 1.0;
+
+    var result: VertexOutput;
+    result.position = vec4<f32>(ndc, 0.0, 1.0);
+    result.ndc = ndc;
+    return result;
+}
+
+fn view_direction(ndc: vec2<f32>) -> vec3<f32> {
+    // Reverse-Z: 1.0 is the near plane, 0.0 is the far plane (at infinity for infinite projections)
+    var near = skybox_data.inverse_view_projection_matrix * vec4<f32>(ndc, 1.0, 1.0);
+    var far = skybox_data.inverse_view_projection_matrix * vec4<f32>(ndc, 0.0, 1.0);
+
+    // far.w is zero for an infinite far plane, which leaves just the direction
+    return normalize(far.xyz - (near.xyz / near.w) * far.w);
+}
+
+fn hash(value: u32) -> u32 {
+    var state = value * 747796405u + 2891336453u;
+    var word = ((state >> ((state >> 28u) + 4u)) ^ state) * 277803737u;
+    return (word >> 22u) ^ word;
+}
+
+fn hash_to_float(value: u32) -> f32 {
+    return f32(value) / 4294967295.0;
+}
+
+fn star_field(direction: vec3<f32>, density: f32, seed: u32) -> vec3<f32> {
+    // Project onto the faces of a cube so every direction maps to a fixed grid cell
+    let grid_size = 256.0;
+    var abs_dir = abs(direction);
+    var face: u32;
+    var face_uv: vec2<f32>;
+    if (abs_dir.x >= abs_dir.y && abs_dir.x >= abs_dir.z) {
+        face = select(1u, 0u, direction.x > 0.0);
+        face_uv = direction.yz / abs_dir.x;
+    } else if (abs_dir.y >= abs_dir.z) {
+        face = select(3u, 2u, direction.y > 0.0);
+        face_uv = direction.xz / abs_dir.y;
+    } else {
+        face = select(5u, 4u, direction.z > 0.0);
+        face_uv = direction.xy / abs_dir.z;
+    }
+
+    var grid_pos = (face_uv * 0.5 + 0.5) * grid_size;
+    var cell = vec2<u32>(floor(grid_pos));
+    var cell_hash = hash(seed ^ hash(face ^ hash(cell.x ^ hash(cell.y))));
+
+    if (hash_to_float(cell_hash) > density) {
+        return vec3<f32>(0.0);
+    }
+
+    var star_offset = vec2<f32>(hash_to_float(hash(cell_hash)), hash_to_float(hash(cell_hash + 1u)));
+    var star_pos = floor(grid_pos) + 0.25 + star_offset * 0.5;
+    var distance = length(grid_pos - star_pos);
+
+    var brightness = hash_to_float(hash(cell_hash + 2u));
+    var intensity = brightness * brightness * smoothstep(0.25, 0.0, distance);
+
+    // Slight color variation between blue and orange stars
+    var temperature = hash_to_float(hash(cell_hash + 3u));
+    var color = mix(vec3<f32>(1.0, 0.8, 0.6), vec3<f32>(0.7, 0.8, 1.0), temperature);
+    return color * intensity;
+}
+
+fn sky_color(vertex: VertexOutput) -> vec4<f32> {
+    var direction = view_direction(vertex.ndc);
+
+    if (skybox_data.params.x > 0.0) {
+        return vec4<f32>(star_field(direction, skybox_data.params.y, u32(skybox_data.params.z)), 1.0);
+    }
+
+    return textureSample(skybox_texture, skybox_sampler, direction);
+}
+
+fn linear_to_srgb(color: vec3<f32>) -> vec3<f32> {
+    var low = color * 12.92;
+    var high = 1.055 * pow(color, vec3<f32>(1.0 / 2.4)) - 0.055;
+    return select(high, low, color <= vec3<f32>(0.0031308));
+}
+
+@fragment
+fn fs_main(vertex: VertexOutput) -> @location(0) vec4<f32> {
+    return sky_color(vertex);
+}
+
+@fragment
+fn fs_main_srgb_encode(vertex: VertexOutput) -> @location(0) vec4<f32> {
+    var color = sky_color(vertex);
+    return vec4<f32>(linear_to_srgb(saturate(color.xyz)), color.w);
+}
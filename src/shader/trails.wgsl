@@ -0,0 +1,51 @@
+struct VertexOutput {
+    @builtin(position) position: vec4<f32>,
+    @location(0) color: vec4<f32>,
+    @location(1) edge: f32,
+};
+
+struct SceneData {
+    view_projection_matrix: mat4x4<f32>,
+    ambient_light_color: vec4<f32>,
+    sun_light_direction_intensity: vec4<f32>,
+    sun_light_color: vec4<f32>,
+    camera_position: vec4<f32>,
+}
+
+@group(0)
+@binding(0)
+var<uniform> scene_data: SceneData;
+
+@vertex
+fn vs_main(
+    @location(0) position: vec3<f32>,
+    @location(1) color: vec4<f32>,
+    @location(2) edge: f32,
+) -> VertexOutput {
+    var result: VertexOutput;
+    result.position = scene_data.view_projection_matrix * vec4<f32>(position, 1.0);
+    result.color = color;
+    result.edge = edge;
+    return result;
+}
+
+fn linear_to_srgb(color: vec3<f32>) -> vec3<f32> {
+    var low = color * 12.92;
+    var high = 1.055 * pow(color, vec3<f32>(1.0 / 2.4)) - 0.055;
+    return select(high, low, color <= vec3<f32>(0.0031308));
+}
+
+//Brightest along the middle of the ribbon
+fn falloff(edge: f32) -> f32 {
+    return 1.0 - edge * edge;
+}
+
+@fragment
+fn fs_main(vertex: VertexOutput) -> @location(0) vec4<f32> {
+    return vec4<f32>(vertex.color.xyz, vertex.color.w * falloff(vertex.edge));
+}
+
+@fragment
+fn fs_main_srgb_encode(vertex: VertexOutput) -> @location(0) vec4<f32> {
+    return vec4<f32>(linear_to_srgb(saturate(vertex.color.xyz)), vertex.color.w * falloff(vertex.edge));
+}
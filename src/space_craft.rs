@@ -1,8 +1,13 @@
+use crate::physics::{load_convex_hull_from_obj, ColliderShape, ColliderShapeError};
+use crate::renderer::{MaterialHandle, MeshHandle};
+use crate::Renderer;
 use glam::{IVec3, Quat, Vec3};
-use log::error;
+use log::{error, info, warn};
+use serde::de::DeserializeOwned;
 use serde::{Deserialize, Serialize};
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap, HashSet};
 use std::fmt::Debug;
+use std::path::{Path, PathBuf};
 
 #[derive(Default, Debug, Serialize, Deserialize)]
 pub struct Transform {
@@ -17,9 +22,20 @@ pub struct ModuleModel {
     pub material: String,
 }
 
+impl ModuleModel {
+    /// Looks up the mesh and material by name, loading them on first use
+    pub fn resolve(&self, renderer: &mut Renderer) -> Option<(MeshHandle, MaterialHandle)> {
+        let mesh = renderer.get_or_load_mesh(&self.mesh)?;
+        let material = renderer.get_or_load_material(&self.material)?;
+        Some((mesh, material))
+    }
+}
+
 #[derive(Debug, Serialize, Deserialize)]
 pub enum ColliderType {
     Mesh(String),
+    /// Half extents in meters
+    Box(Vec3),
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -28,7 +44,17 @@ pub struct ModuleCollider {
     pub collider_type: ColliderType,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+impl ModuleCollider {
+    /// Meshes are turned into their convex hull
+    pub fn create_shape(&self) -> Result<ColliderShape, ColliderShapeError> {
+        Ok(match &self.collider_type {
+            ColliderType::Mesh(mesh_path) => load_convex_hull_from_obj(mesh_path)?,
+            ColliderType::Box(half_extents) => ColliderShape::Box(*half_extents),
+        })
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
 pub enum GridDirection {
     Forward,
     Back,
@@ -38,10 +64,47 @@ pub enum GridDirection {
     Down,
 }
 
+impl GridDirection {
+    pub const ALL: [GridDirection; 6] = [
+        GridDirection::Forward,
+        GridDirection::Back,
+        GridDirection::Left,
+        GridDirection::Right,
+        GridDirection::Up,
+        GridDirection::Down,
+    ];
+
+    pub fn opposite(&self) -> GridDirection {
+        match self {
+            GridDirection::Forward => GridDirection::Back,
+            GridDirection::Back => GridDirection::Forward,
+            GridDirection::Left => GridDirection::Right,
+            GridDirection::Right => GridDirection::Left,
+            GridDirection::Up => GridDirection::Down,
+            GridDirection::Down => GridDirection::Up,
+        }
+    }
+
+    /// Forward is +Z, Right is +X and Up is +Y
+    pub fn as_ivec3(&self) -> IVec3 {
+        match self {
+            GridDirection::Forward => IVec3::Z,
+            GridDirection::Back => IVec3::NEG_Z,
+            GridDirection::Left => IVec3::NEG_X,
+            GridDirection::Right => IVec3::X,
+            GridDirection::Up => IVec3::Y,
+            GridDirection::Down => IVec3::NEG_Y,
+        }
+    }
+}
+
 #[derive(Debug, Serialize, Deserialize)]
 pub struct GridDockingPort {
     pub offset: IVec3,
     pub direction: GridDirection,
+    /// External ports dock with other crafts instead of connecting modules
+    #[serde(default)]
+    pub external: bool,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -50,16 +113,103 @@ pub struct ModuleHardPoint {
     pub offset: Transform,
 }
 
+/// Seat the craft can be flown from
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ModuleCockpit {
+    /// Where the pilot's camera sits relative to the center of the module
+    pub camera: Transform,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum AttachmentBehavior {
+    Turret,
+    ShieldGenerator,
+}
+
+/// Something mounted on a module's hard point, loaded from `.attachment` files next to the modules
+#[derive(Debug, Serialize, Deserialize)]
+pub struct AttachmentDefinition {
+    #[serde(default)]
+    pub version: u32,
+    pub name: String,
+    /// Fits any hard point of this size or larger
+    pub size: u16,
+    /// Mass in Kg
+    pub mass: f32,
+    pub model: Option<ModuleModel>,
+    #[serde(default)]
+    pub behavior: Option<AttachmentBehavior>,
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum ResourceType {
+    #[default]
+    Hydrogen,
+    Oxygen,
+    Water,
+    Xenon,
+}
+
+impl ResourceType {
+    /// Density in Kg/m^3, stored as a liquid
+    pub fn density(&self) -> f32 {
+        match self {
+            ResourceType::Hydrogen => 70.8,
+            ResourceType::Oxygen => 1141.0,
+            ResourceType::Water => 1000.0,
+            ResourceType::Xenon => 2942.0,
+        }
+    }
+}
+
 #[derive(Default, Debug, Serialize, Deserialize)]
 pub struct ModuleTank {
     /// Offset of the tank from the center of the module, used for mass calculations
     pub offset: Vec3,
     /// Total capacity of the tank in meters^3
     pub capacity: f32,
+    /// What the tank is filled with when the craft is assembled
+    #[serde(default)]
+    pub resource: ResourceType,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct ModuleThruster {
+    /// Offset of the thruster from the center of the module, where the force is applied
+    pub offset: Vec3,
+    /// Direction the thruster pushes the craft
+    pub direction: GridDirection,
+    /// Force in Newtons at full throttle
+    pub max_thrust: f32,
+    #[serde(default)]
+    pub propellant: ResourceType,
+    /// Specific impulse in seconds, thrusters without one don't use propellant
+    #[serde(default)]
+    pub isp: Option<f32>,
+}
+
+/// Opening in the interior walls on one face of a module cell, `blocker` closes it off
+/// unless the neighboring module has a door facing back through a shared connector
+#[derive(Debug, Serialize, Deserialize)]
+pub struct InteriorDoor {
+    pub offset: IVec3,
+    pub direction: GridDirection,
+    pub blocker: ModuleCollider,
+}
+
+/// Walkable space inside a module, its colliders live in the interior collision layer so they can sit inside the exterior hull
+#[derive(Debug, Serialize, Deserialize)]
+pub struct InteriorDefinition {
+    pub model: Option<ModuleModel>,
+    /// Floors, walls and ceilings around the walkable volume
+    pub colliders: Vec<ModuleCollider>,
+    pub doors: Vec<InteriorDoor>,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct ModuleDefinition {
+    #[serde(default)]
+    pub version: u32,
     pub name: String,
     pub categories: Vec<String>,
 
@@ -83,42 +233,309 @@ pub struct ModuleDefinition {
     /// Tanks that can contain a liquids or gases
     pub tanks: Vec<ModuleTank>,
 
+    #[serde(default)]
+    pub thrusters: Vec<ModuleThruster>,
+
     pub exterior_model: Option<ModuleModel>,
     pub exterior_colliders: Vec<ModuleCollider>,
 
-    pub interior: Option<()>,
+    #[serde(default)]
+    pub interior: Option<InteriorDefinition>,
+
+    #[serde(default)]
+    pub cockpit: Option<ModuleCockpit>,
+}
+
+/// Category for modules that don't list any
+pub const UNCATEGORIZED: &str = "Uncategorized";
+
+/// Every loaded module by name
+#[derive(Default, Debug)]
+pub struct ModuleTable {
+    modules: HashMap<String, ModuleDefinition>,
+}
+
+impl ModuleTable {
+    pub fn get(&self, name: &str) -> Option<&ModuleDefinition> {
+        self.modules.get(name)
+    }
+
+    pub fn contains_key(&self, name: &str) -> bool {
+        self.modules.contains_key(name)
+    }
+
+    pub fn len(&self) -> usize {
+        self.modules.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.modules.is_empty()
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &ModuleDefinition)> {
+        self.modules
+            .iter()
+            .map(|(name, module)| (name.as_str(), module))
+    }
+
+    /// Modules grouped by category for the build palette, both sorted by name.
+    /// Modules with several categories show up in each of them
+    pub fn by_category(&self) -> BTreeMap<String, Vec<&ModuleDefinition>> {
+        let mut categories: BTreeMap<String, Vec<&ModuleDefinition>> = BTreeMap::new();
+        for module in self.modules.values() {
+            if module.categories.is_empty() {
+                categories
+                    .entry(UNCATEGORIZED.to_string())
+                    .or_default()
+                    .push(module);
+            }
+            for category in module.categories.iter() {
+                categories.entry(category.clone()).or_default().push(module);
+            }
+        }
+        for modules in categories.values_mut() {
+            modules.sort_by(|a, b| a.name.cmp(&b.name));
+        }
+        categories
+    }
+}
+
+impl std::ops::Index<&str> for ModuleTable {
+    type Output = ModuleDefinition;
+
+    fn index(&self, name: &str) -> &ModuleDefinition {
+        &self.modules[name]
+    }
 }
 
 pub fn load_modules_from_directory(
     directory_path: &std::path::Path,
-    module_table: &mut HashMap<String, ModuleDefinition>,
+    module_table: &mut ModuleTable,
+) {
+    load_definitions_from_directory(
+        directory_path,
+        "module",
+        |module: &ModuleDefinition| &module.name,
+        &mut module_table.modules,
+    );
+}
+
+pub fn load_attachments_from_directory(
+    directory_path: &std::path::Path,
+    attachment_table: &mut HashMap<String, AttachmentDefinition>,
+) {
+    load_definitions_from_directory(
+        directory_path,
+        "attachment",
+        |attachment: &AttachmentDefinition| &attachment.name,
+        attachment_table,
+    );
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum DefinitionError {
+    #[error("failed to read {path:?}: {source}")]
+    Io {
+        path: PathBuf,
+        source: std::io::Error,
+    },
+    #[error("{path:?} isn't valid json: {source}")]
+    Syntax {
+        path: PathBuf,
+        source: serde_json::Error,
+    },
+    #[error("{path:?} doesn't match the definition: {source}")]
+    Schema {
+        path: PathBuf,
+        source: serde_path_to_error::Error<serde_json::Error>,
+    },
+    #[cfg(feature = "ron")]
+    #[error("{path:?} isn't a valid definition: {source}")]
+    Ron {
+        path: PathBuf,
+        source: ron::error::SpannedError,
+    },
+    #[cfg(not(feature = "ron"))]
+    #[error("{path:?} is a ron file but ron support isn't enabled")]
+    RonDisabled { path: PathBuf },
+    #[error("{path:?} is version {version} but only up to version {current} is supported")]
+    UnsupportedVersion {
+        path: PathBuf,
+        version: u32,
+        current: u32,
+    },
+}
+
+/// Definition files store the version of the format they were written in, files from before versioning are version 0
+pub trait VersionedDefinition: DeserializeOwned {
+    const CURRENT_VERSION: u32;
+
+    fn version(&self) -> u32;
+
+    /// Brings a definition from an older version up to date, fields added since then already have their defaults
+    fn upgrade(&mut self, from_version: u32);
+}
+
+impl VersionedDefinition for ModuleDefinition {
+    const CURRENT_VERSION: u32 = 1;
+
+    fn version(&self) -> u32 {
+        self.version
+    }
+
+    fn upgrade(&mut self, _from_version: u32) {
+        //Version 0 only lacked fields that default: thrusters, tank resources, interiors and external connectors
+        self.version = Self::CURRENT_VERSION;
+    }
+}
+
+impl VersionedDefinition for AttachmentDefinition {
+    const CURRENT_VERSION: u32 = 1;
+
+    fn version(&self) -> u32 {
+        self.version
+    }
+
+    fn upgrade(&mut self, _from_version: u32) {
+        self.version = Self::CURRENT_VERSION;
+    }
+}
+
+impl VersionedDefinition for SpaceCraftDefinition {
+    const CURRENT_VERSION: u32 = 1;
+
+    fn version(&self) -> u32 {
+        self.version
+    }
+
+    fn upgrade(&mut self, _from_version: u32) {
+        //Version 0 only lacked attachments, which default to none
+        self.version = Self::CURRENT_VERSION;
+    }
+}
+
+/// Reads a definition as ron if the file ends in `.ron` and as json otherwise, then upgrades it to the current version.
+/// Json errors include the path to the offending field
+pub fn load_definition<T: VersionedDefinition>(path: &Path) -> Result<T, DefinitionError> {
+    let contents = std::fs::read_to_string(path).map_err(|source| DefinitionError::Io {
+        path: path.to_path_buf(),
+        source,
+    })?;
+
+    let mut definition: T = if is_ron(path) {
+        parse_ron(path, &contents)?
+    } else {
+        parse_json(path, &contents)?
+    };
+
+    let version = definition.version();
+    if version > T::CURRENT_VERSION {
+        return Err(DefinitionError::UnsupportedVersion {
+            path: path.to_path_buf(),
+            version,
+            current: T::CURRENT_VERSION,
+        });
+    }
+    if version < T::CURRENT_VERSION {
+        definition.upgrade(version);
+        info!(
+            "Upgraded {:?} from version {} to {}",
+            path,
+            version,
+            T::CURRENT_VERSION
+        );
+    }
+    Ok(definition)
+}
+
+/// Missing fields are reported by serde, so fields marked `#[serde(default)]` never show up as missing
+fn parse_json<T: DeserializeOwned>(path: &Path, contents: &str) -> Result<T, DefinitionError> {
+    let mut deserializer = serde_json::Deserializer::from_str(contents);
+    let definition = serde_path_to_error::deserialize(&mut deserializer).map_err(|source| {
+        if source.inner().is_syntax() || source.inner().is_eof() {
+            DefinitionError::Syntax {
+                path: path.to_path_buf(),
+                source: source.into_inner(),
+            }
+        } else {
+            DefinitionError::Schema {
+                path: path.to_path_buf(),
+                source,
+            }
+        }
+    })?;
+    deserializer
+        .end()
+        .map_err(|source| DefinitionError::Syntax {
+            path: path.to_path_buf(),
+            source,
+        })?;
+    Ok(definition)
+}
+
+#[cfg(feature = "ron")]
+fn parse_ron<T: DeserializeOwned>(path: &Path, contents: &str) -> Result<T, DefinitionError> {
+    ron::from_str(contents).map_err(|source| DefinitionError::Ron {
+        path: path.to_path_buf(),
+        source,
+    })
+}
+
+#[cfg(not(feature = "ron"))]
+fn parse_ron<T: DeserializeOwned>(path: &Path, _contents: &str) -> Result<T, DefinitionError> {
+    Err(DefinitionError::RonDisabled {
+        path: path.to_path_buf(),
+    })
+}
+
+fn is_ron(path: &Path) -> bool {
+    path.extension().is_some_and(|ext| ext == "ron")
+}
+
+/// `name.module` is loaded as json and `name.module.ron` as ron
+fn is_definition_file(path: &Path, extension: &str) -> bool {
+    let path = if is_ron(path) {
+        match path.file_stem() {
+            Some(stem) => Path::new(stem),
+            None => return false,
+        }
+    } else {
+        path
+    };
+    path.extension().is_some_and(|ext| ext == extension)
+}
+
+/// Recursively loads every file with the extension, definitions are keyed by their name
+fn load_definitions_from_directory<T: VersionedDefinition>(
+    directory_path: &std::path::Path,
+    extension: &str,
+    name: fn(&T) -> &String,
+    table: &mut HashMap<String, T>,
 ) {
     if let Ok(entries) = std::fs::read_dir(directory_path) {
         for entry in entries {
             if let Ok(entry) = entry {
                 let path = entry.path();
-                if path.is_file() && path.extension().map_or(false, |ext| ext == "module") {
-                    let contents = match std::fs::read_to_string(&path) {
-                        Ok(contents) => contents,
+                if path.is_file() && is_definition_file(&path, extension) {
+                    let definition: T = match load_definition(&path) {
+                        Ok(definition) => definition,
                         Err(e) => {
-                            error!("Failed to read file {:?}: {}", path, e);
+                            error!("Failed to load {}: {}", extension, e);
                             continue;
                         }
                     };
-                    let module: ModuleDefinition = match serde_json::from_str(&contents) {
-                        Ok(module) => module,
-                        Err(e) => {
-                            error!("Failed to deserialize file {:?}: {}", path, e);
-                            continue;
-                        }
-                    };
-                    if module_table.contains_key(&module.name) {
-                        error!("Duplicate module name {:?} in file {:?}", module.name, path);
+                    if table.contains_key(name(&definition)) {
+                        error!(
+                            "Duplicate {} name {:?} in file {:?}",
+                            extension,
+                            name(&definition),
+                            path
+                        );
                     } else {
-                        module_table.insert(module.name.clone(), module);
+                        table.insert(name(&definition).clone(), definition);
                     }
                 } else if path.is_dir() {
-                    load_modules_from_directory(&path, module_table);
+                    load_definitions_from_directory(&path, extension, name, table);
                 }
             } else if let Err(e) = entry {
                 error!("Failed to read directory entry: {}", e);
@@ -129,9 +546,349 @@ pub fn load_modules_from_directory(
     }
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+/// Reloads every module in the directory, modules that fail to parse keep their old definition
+pub fn reload_modules_from_directory(
+    directory_path: &std::path::Path,
+    module_table: &mut ModuleTable,
+) -> usize {
+    let mut reloaded_table = ModuleTable::default();
+    load_modules_from_directory(directory_path, &mut reloaded_table);
+
+    let reloaded = reloaded_table.len();
+    module_table.modules.extend(reloaded_table.modules);
+    reloaded
+}
+
+/// Same as the modules, attachments that fail to parse keep their old definition
+pub fn reload_attachments_from_directory(
+    directory_path: &std::path::Path,
+    attachment_table: &mut HashMap<String, AttachmentDefinition>,
+) -> usize {
+    let mut reloaded_table = HashMap::new();
+    load_attachments_from_directory(directory_path, &mut reloaded_table);
+
+    let reloaded = reloaded_table.len();
+    attachment_table.extend(reloaded_table);
+    reloaded
+}
+
+/// Width of a single cell of the module grid in meters
+pub const MODULE_SIZE: f32 = 1.0;
+
+impl Transform {
+    pub fn to_transform(&self) -> crate::transform::Transform {
+        crate::transform::Transform {
+            position: self.position,
+            rotation: self.orientation,
+            scale: Vec3::ONE,
+        }
+    }
+}
+
+impl ModuleDefinition {
+    /// All exterior colliders combined into one shape relative to the module, modules without any get a grid cell sized box
+    pub fn create_collider_shape(&self) -> ColliderShape {
+        let children: Vec<(Vec3, Quat, ColliderShape)> = self
+            .exterior_colliders
+            .iter()
+            .filter_map(|collider| match collider.create_shape() {
+                Ok(shape) => Some((collider.offset.position, collider.offset.orientation, shape)),
+                Err(e) => {
+                    warn!(
+                        "Failed to create collider {:?} for module {}: {}",
+                        collider.collider_type, self.name, e
+                    );
+                    None
+                }
+            })
+            .collect();
+
+        if children.is_empty() {
+            ColliderShape::Box(Vec3::splat(MODULE_SIZE * 0.5))
+        } else {
+            ColliderShape::Compound(children)
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
 pub struct SpaceCraftDefinition {
+    #[serde(default)]
+    pub version: u32,
     pub name: String,
     pub categories: Vec<String>,
+    /// Stored as a list since json map keys have to be strings
+    #[serde(with = "module_grid_serde")]
     pub modules: HashMap<IVec3, String>,
+    #[serde(default)]
+    pub attachments: Vec<AttachmentMount>,
+    /// Only modules that differ from a freshly built one are listed
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub module_states: Vec<ModuleState>,
+}
+
+/// Saved state of a module in a craft
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct ModuleState {
+    pub grid_position: IVec3,
+    /// Contents of each tank in m^3, in the order of the module's tanks
+    #[serde(default)]
+    pub tank_amounts: Vec<f32>,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct AttachmentMount {
+    pub grid_position: IVec3,
+    /// Index into the module's hard points
+    pub hard_point: usize,
+    pub attachment: String,
+}
+
+mod module_grid_serde {
+    use glam::IVec3;
+    use serde::{Deserialize, Deserializer, Serialize, Serializer};
+    use std::collections::HashMap;
+
+    pub fn serialize<S: Serializer>(
+        modules: &HashMap<IVec3, String>,
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        let mut modules: Vec<(&IVec3, &String)> = modules.iter().collect();
+        modules.sort_by_key(|(position, _)| position.to_array());
+        modules.serialize(serializer)
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<HashMap<IVec3, String>, D::Error> {
+        let modules: Vec<(IVec3, String)> = Vec::deserialize(deserializer)?;
+        Ok(modules.into_iter().collect())
+    }
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum SpaceCraftError {
+    #[error("module {name:?} at {position} doesn't exist")]
+    UnknownModule { position: IVec3, name: String },
+    #[error("modules at {position} and {neighbor} are next to each other but don't have matching connectors")]
+    MissingConnection { position: IVec3, neighbor: IVec3 },
+    #[error("module at {position} isn't connected to the rest of the craft")]
+    Disconnected { position: IVec3 },
+}
+
+impl SpaceCraftDefinition {
+    /// Checks that every module exists, neighboring modules are joined by facing connectors,
+    /// and that all modules form one connected craft
+    pub fn validate(&self, module_table: &ModuleTable) -> Result<(), Vec<SpaceCraftError>> {
+        let mut errors = Vec::new();
+
+        let mut positions: Vec<IVec3> = self.modules.keys().copied().collect();
+        positions.sort_by_key(|position| position.to_array());
+
+        for position in positions.iter() {
+            let name = &self.modules[position];
+            if !module_table.contains_key(name) {
+                errors.push(SpaceCraftError::UnknownModule {
+                    position: *position,
+                    name: name.clone(),
+                });
+            }
+        }
+
+        //Only check the positive directions so each pair is reported once
+        for position in positions.iter() {
+            for direction in [
+                GridDirection::Forward,
+                GridDirection::Right,
+                GridDirection::Up,
+            ] {
+                let neighbor = *position + direction.as_ivec3();
+                if self.modules.contains_key(&neighbor)
+                    && !modules_connected(&self.modules, module_table, *position, neighbor)
+                {
+                    errors.push(SpaceCraftError::MissingConnection {
+                        position: *position,
+                        neighbor,
+                    });
+                }
+            }
+        }
+
+        if let Some(first) = positions.first() {
+            let connected = connected_modules(&self.modules, module_table, *first);
+            for position in positions.iter() {
+                if !connected.contains(position) {
+                    errors.push(SpaceCraftError::Disconnected {
+                        position: *position,
+                    });
+                }
+            }
+        }
+
+        if errors.is_empty() {
+            Ok(())
+        } else {
+            Err(errors)
+        }
+    }
+}
+
+/// Connectors of a module placed at `position`, as the cell each one is on and the direction it faces
+pub fn placed_connectors(
+    module: &ModuleDefinition,
+    position: IVec3,
+) -> impl Iterator<Item = (IVec3, GridDirection)> + '_ {
+    module
+        .connectors
+        .iter()
+        .filter(|connector| !connector.external)
+        .map(move |connector| (position + connector.offset, connector.direction))
+}
+
+/// Same as `placed_connectors` but only the external docking ports
+pub fn placed_docking_ports(
+    module: &ModuleDefinition,
+    position: IVec3,
+) -> impl Iterator<Item = (IVec3, GridDirection)> + '_ {
+    module
+        .connectors
+        .iter()
+        .filter(|connector| connector.external)
+        .map(move |connector| (position + connector.offset, connector.direction))
+}
+
+/// True if one side has a connector facing a cell where the other has a connector facing back
+pub fn connectors_meet(
+    connectors: &[(IVec3, GridDirection)],
+    neighbor_connectors: &[(IVec3, GridDirection)],
+) -> bool {
+    connectors.iter().any(|(cell, direction)| {
+        neighbor_connectors
+            .iter()
+            .any(|(neighbor_cell, neighbor_direction)| {
+                *cell + direction.as_ivec3() == *neighbor_cell
+                    && *neighbor_direction == direction.opposite()
+            })
+    })
+}
+
+fn module_connectors(
+    modules: &HashMap<IVec3, String>,
+    module_table: &ModuleTable,
+    position: IVec3,
+) -> Vec<(IVec3, GridDirection)> {
+    modules
+        .get(&position)
+        .and_then(|name| module_table.get(name))
+        .map(|module| placed_connectors(module, position).collect())
+        .unwrap_or_default()
+}
+
+/// Two modules are connected if their connectors meet
+pub fn modules_connected(
+    modules: &HashMap<IVec3, String>,
+    module_table: &ModuleTable,
+    position: IVec3,
+    neighbor: IVec3,
+) -> bool {
+    connectors_meet(
+        &module_connectors(modules, module_table, position),
+        &module_connectors(modules, module_table, neighbor),
+    )
+}
+
+/// Flood fills from `start` through occupied neighbors that `connected` joins
+pub fn flood_fill(
+    start: IVec3,
+    occupied: impl Fn(IVec3) -> bool,
+    connected: impl Fn(IVec3, IVec3) -> bool,
+) -> HashSet<IVec3> {
+    let mut visited = HashSet::new();
+    if !occupied(start) {
+        return visited;
+    }
+
+    visited.insert(start);
+    let mut stack = vec![start];
+    while let Some(position) = stack.pop() {
+        for direction in GridDirection::ALL {
+            let neighbor = position + direction.as_ivec3();
+            if occupied(neighbor) && !visited.contains(&neighbor) && connected(position, neighbor) {
+                visited.insert(neighbor);
+                stack.push(neighbor);
+            }
+        }
+    }
+    visited
+}
+
+/// Flood fills from `start` through connected neighbors
+pub fn connected_modules(
+    modules: &HashMap<IVec3, String>,
+    module_table: &ModuleTable,
+    start: IVec3,
+) -> HashSet<IVec3> {
+    flood_fill(
+        start,
+        |position| modules.contains_key(&position),
+        |position, neighbor| modules_connected(modules, module_table, position, neighbor),
+    )
+}
+
+/// Validation failures are logged, but the definition is still returned so broken crafts can be spawned for debugging
+pub const SPACECRAFT_DIRECTORY: &str = "resource/spacecraft/";
+
+#[derive(Debug, thiserror::Error)]
+pub enum SpaceCraftSaveError {
+    #[error("failed to write spacecraft file: {0}")]
+    Io(#[from] std::io::Error),
+    #[error("failed to serialize spacecraft: {0}")]
+    Json(#[from] serde_json::Error),
+}
+
+/// Writes the definition to `<directory>/<name>.spacecraft`, characters that can't be in a file name are replaced with `_`.
+/// Returns the path that was written
+pub fn save_space_craft_definition(
+    definition: &SpaceCraftDefinition,
+    directory: &Path,
+) -> Result<PathBuf, SpaceCraftSaveError> {
+    let mut file_name: String = definition
+        .name
+        .chars()
+        .map(|c| {
+            if c.is_alphanumeric() || c == '_' || c == '-' {
+                c
+            } else {
+                '_'
+            }
+        })
+        .collect();
+    if file_name.is_empty() {
+        file_name = "unnamed".to_string();
+    }
+
+    let path = directory.join(format!("{}.spacecraft", file_name));
+    std::fs::write(&path, serde_json::to_string_pretty(definition)?)?;
+    Ok(path)
+}
+
+pub fn load_space_craft_definition<P: AsRef<std::path::Path> + Debug>(
+    path: P,
+    module_table: &ModuleTable,
+) -> Option<SpaceCraftDefinition> {
+    let definition: SpaceCraftDefinition = match load_definition(path.as_ref()) {
+        Ok(definition) => definition,
+        Err(e) => {
+            error!("Failed to load spacecraft: {}", e);
+            return None;
+        }
+    };
+
+    if let Err(errors) = definition.validate(module_table) {
+        for error in errors {
+            warn!("Invalid spacecraft {:?}: {}", path, error);
+        }
+    }
+
+    Some(definition)
 }
@@ -0,0 +1,119 @@
+use crate::camera::{Camera, OrthographicCamera};
+use crate::renderer::{Renderer, SceneData};
+use crate::space_craft::{ModuleDefinition, ModuleModel, ModuleTable, MODULE_SIZE};
+use crate::transform::Transform;
+use glam::{EulerRot, Quat, Vec3};
+use image::RgbaImage;
+use log::{info, warn};
+use std::collections::hash_map::DefaultHasher;
+use std::collections::HashMap;
+use std::hash::Hasher;
+use std::path::{Path, PathBuf};
+
+pub const THUMBNAIL_DIRECTORY: &str = "cache/thumbnails/";
+/// Width and height in pixels
+pub const THUMBNAIL_SIZE: u32 = 64;
+
+/// Small picture of every module's exterior for the build palette.
+/// Rendered once and cached on disk, a changed definition gets a new file since the name is a hash of it
+#[derive(Default)]
+pub struct ModuleThumbnails {
+    images: HashMap<String, RgbaImage>,
+}
+
+impl ModuleThumbnails {
+    /// Modules without an exterior model don't get a thumbnail
+    pub fn new(renderer: &mut Renderer, module_table: &ModuleTable, directory: &Path) -> Self {
+        if let Err(e) = std::fs::create_dir_all(directory) {
+            warn!(
+                "Failed to create {:?}, thumbnails won't be cached: {}",
+                directory, e
+            );
+        }
+
+        let mut images = HashMap::new();
+        let mut rendered = 0;
+        for (name, module) in module_table.iter() {
+            let Some(model) = &module.exterior_model else {
+                continue;
+            };
+
+            let path = cache_path(directory, module);
+            let image = match image::open(&path) {
+                Ok(image) => image.to_rgba8(),
+                Err(_) => {
+                    let Some(image) = render_thumbnail(renderer, model) else {
+                        continue;
+                    };
+                    if let Err(e) = image.save(&path) {
+                        warn!("Failed to cache the thumbnail of {}: {}", name, e);
+                    }
+                    rendered += 1;
+                    image
+                }
+            };
+            images.insert(name.to_string(), image);
+        }
+
+        info!(
+            "Loaded {} module thumbnails, {} of them were rendered",
+            images.len(),
+            rendered
+        );
+        Self { images }
+    }
+
+    pub fn get(&self, module_name: &str) -> Option<&RgbaImage> {
+        self.images.get(module_name)
+    }
+}
+
+/// The hash comes from the serialized definition, so any change to the module renders it again
+fn cache_path(directory: &Path, module: &ModuleDefinition) -> PathBuf {
+    let mut hasher = DefaultHasher::new();
+    match serde_json::to_vec(module) {
+        Ok(bytes) => hasher.write(&bytes),
+        Err(_) => hasher.write(module.name.as_bytes()),
+    }
+    directory.join(format!("{:016x}.png", hasher.finish()))
+}
+
+/// Renders the model alone in its own scene, looking down at it from the front corner
+fn render_thumbnail(renderer: &mut Renderer, model: &ModuleModel) -> Option<RgbaImage> {
+    let (mesh, material) = model.resolve(renderer)?;
+    let mut scene = renderer.create_scene();
+    let instance = scene.create_instance(mesh, material, &model.offset.to_transform())?;
+
+    let rotation = Quat::from_euler(
+        EulerRot::YXZ,
+        -std::f32::consts::FRAC_PI_4,
+        30f32.to_radians(),
+        0.0,
+    );
+    let camera_transform = Transform {
+        position: rotation * Vec3::new(0.0, 0.0, -4.0 * MODULE_SIZE),
+        rotation,
+        scale: Vec3::ONE,
+    };
+    //Fits a whole cell from any side
+    let camera = Camera::Orthographic(OrthographicCamera::new(
+        MODULE_SIZE * 0.9,
+        0.1,
+        8.0 * MODULE_SIZE,
+    ));
+
+    let size = [THUMBNAIL_SIZE; 2];
+    let view_projection = camera.as_projection_matrix(size) * camera_transform.as_view_matrix();
+    let light_dir = Vec3::new(0.5, -2.0, 1.0).normalize();
+    let scene_data = SceneData {
+        view_projection_matrix: *view_projection.as_ref(),
+        ambient_light_color: [0.3; 4],
+        sun_light_direction_intensity: [light_dir.x, light_dir.y, light_dir.z, 0.8],
+        sun_light_color: [1.0; 4],
+        camera_position: camera_transform.position.extend(1.0).to_array(),
+    };
+
+    let pixels = renderer.render_to_texture(size, &scene_data, &mut scene);
+    scene.remove_instance(instance);
+    RgbaImage::from_raw(size[0], size[1], pixels)
+}
@@ -0,0 +1,135 @@
+use crate::physics::GravityField;
+use crate::renderer::DebugLines;
+use crate::world::World;
+use glam::{DVec3, Vec3};
+
+/// How far ahead the trajectory is predicted, in seconds
+const PREDICTION_TIME: f32 = 120.0;
+const PREDICTION_STEP: f32 = 0.5;
+/// The prediction is redone once the velocity changed by more than this many m/s, or every `PREDICTION_INTERVAL` frames
+const VELOCITY_THRESHOLD: f32 = 0.05;
+const PREDICTION_INTERVAL: u32 = 30;
+
+const TRAJECTORY_COLOR: [f32; 4] = [0.3, 0.9, 1.0, 0.9];
+const THRUST_TRAJECTORY_COLOR: [f32; 4] = [1.0, 0.7, 0.3, 0.9];
+
+/// Future path of whatever the player is flying, drawn as debug lines that fade out towards the end
+#[derive(Default)]
+pub struct TrajectoryPredictor {
+    pub enabled: bool,
+    /// Keeps the current thrust going for the whole prediction instead of coasting
+    pub include_thrust: bool,
+    points: Vec<Vec3>,
+    /// World origin the points are relative to
+    origin: DVec3,
+    last_velocity: Option<Vec3>,
+    frames_since_prediction: u32,
+}
+
+impl TrajectoryPredictor {
+    pub fn toggle(&mut self) {
+        self.enabled = !self.enabled;
+        self.invalidate();
+    }
+
+    pub fn toggle_thrust(&mut self) {
+        self.include_thrust = !self.include_thrust;
+        self.invalidate();
+    }
+
+    /// Forces the next update to predict again
+    pub fn invalidate(&mut self) {
+        self.last_velocity = None;
+    }
+
+    pub fn update(&mut self, world: &World) {
+        let input_entity = world.input_entity();
+        let body = world
+            .entities
+            .get(input_entity)
+            .and_then(|entity| entity.get_rigid_body())
+            .zip(world.get_world_transform(input_entity));
+        let Some((rigid_body, transform)) = body.filter(|_| self.enabled) else {
+            self.points.clear();
+            self.last_velocity = None;
+            return;
+        };
+
+        let physics = &world.world_info.physics;
+        let velocity = physics.get_rigid_body_linear_velocity(rigid_body);
+        self.frames_since_prediction += 1;
+        let changed = self
+            .last_velocity
+            .is_none_or(|last_velocity| last_velocity.distance(velocity) > VELOCITY_THRESHOLD);
+        if !changed && self.frames_since_prediction < PREDICTION_INTERVAL {
+            return;
+        }
+
+        //Thrust is held at its current world space direction, the craft turning during the prediction isn't known
+        let thrust = world
+            .controlled_craft()
+            .filter(|_| self.include_thrust)
+            .map_or(Vec3::ZERO, |craft| {
+                craft.thrust_force() / physics.get_mass(rigid_body).max(f32::EPSILON)
+            });
+
+        self.points = predict(
+            &physics.gravity_field(rigid_body),
+            thrust,
+            transform.position,
+            velocity,
+        );
+        self.origin = world.get_origin();
+        self.last_velocity = Some(velocity);
+        self.frames_since_prediction = 0;
+    }
+
+    /// `origin` is the world's current origin, the points are moved over if it shifted since they were predicted
+    pub fn draw(&self, lines: &mut DebugLines, origin: DVec3) {
+        let offset = (self.origin - origin).as_vec3();
+        let color = if self.include_thrust {
+            THRUST_TRAJECTORY_COLOR
+        } else {
+            TRAJECTORY_COLOR
+        };
+
+        let segments = self.points.len().saturating_sub(1);
+        for (index, pair) in self.points.windows(2).enumerate() {
+            let fade = 1.0 - index as f32 / segments as f32;
+            let color = [color[0], color[1], color[2], color[3] * fade];
+            lines.line(pair[0] + offset, pair[1] + offset, color, true);
+        }
+    }
+}
+
+/// Fixed step RK4, or a single straight segment when nothing accelerates the body
+fn predict(field: &GravityField, thrust: Vec3, position: Vec3, velocity: Vec3) -> Vec<Vec3> {
+    if field.is_zero() && thrust == Vec3::ZERO {
+        return vec![position, position + velocity * PREDICTION_TIME];
+    }
+
+    let acceleration = |position: Vec3| field.acceleration_at(position) + thrust;
+    let steps = (PREDICTION_TIME / PREDICTION_STEP).ceil() as usize;
+    let h = PREDICTION_STEP;
+
+    let mut points = Vec::with_capacity(steps + 1);
+    let (mut position, mut velocity) = (position, velocity);
+    points.push(position);
+    for _ in 0..steps {
+        let (k1_p, k1_v) = (velocity, acceleration(position));
+        let (k2_p, k2_v) = (
+            velocity + k1_v * (h * 0.5),
+            acceleration(position + k1_p * (h * 0.5)),
+        );
+        let (k3_p, k3_v) = (
+            velocity + k2_v * (h * 0.5),
+            acceleration(position + k2_p * (h * 0.5)),
+        );
+        let (k4_p, k4_v) = (velocity + k3_v * h, acceleration(position + k3_p * h));
+
+        position += (k1_p + 2.0 * k2_p + 2.0 * k3_p + k4_p) * (h / 6.0);
+        velocity += (k1_v + 2.0 * k2_v + 2.0 * k3_v + k4_v) * (h / 6.0);
+        points.push(position);
+    }
+    points
+}
@@ -1,21 +1,204 @@
-use crate::camera::PerspectiveCamera;
-use crate::physics::{ColliderShape, PhysicsScene};
-use crate::renderer::{InstanceHandle, MaterialHandle, MeshHandle, SceneRenderData};
+use crate::camera::{
+    Camera, CameraEffects, CameraMode, ChaseCamera, Frustum, OrthographicCamera, PerspectiveCamera,
+};
+use crate::docking::{DockingPortState, DockingRegistry, DockingStatus};
+use crate::orbit::{OrbitalElements, OrbitalEntity};
+use crate::physics::{ColliderMass, ColliderShape, CollisionEvent, CollisionLayer, PhysicsScene};
+use crate::player::{PhysicsPlayer, Player};
+use crate::renderer::{
+    EmitterHandle, EmitterSettings, InstanceHandle, MaterialHandle, MeshHandle, ParticleCurve,
+    SceneRenderData, TrailHandle, TrailSettings,
+};
+use crate::sector::SectorGrid;
+use crate::space_craft::{
+    connectors_meet, flood_fill, modules_connected, placed_connectors, placed_docking_ports,
+    save_space_craft_definition, AttachmentDefinition, AttachmentMount, GridDirection,
+    InteriorDefinition, ModuleCollider, ModuleDefinition, ModuleState, ModuleTable, ModuleThruster,
+    ResourceType, SpaceCraftDefinition, VersionedDefinition, MODULE_SIZE, SPACECRAFT_DIRECTORY,
+};
 use crate::transform::Transform;
 use crate::Renderer;
-use glam::Vec3;
+use glam::{DVec3, IVec3, Quat, Vec3, Vec4};
+use log::{error, info, warn};
 use rapier3d::dynamics::RigidBodyType;
 use rapier3d::prelude::{ColliderHandle, RigidBodyHandle};
-use slotmap::{new_key_type, SlotMap};
+use serde::{Deserialize, Serialize};
+use slotmap::{new_key_type, SecondaryMap, SlotMap};
+use std::any::Any;
+use std::collections::{HashMap, HashSet};
 
 new_key_type! {
     pub struct EntityId;
 }
 
+#[derive(Debug, thiserror::Error)]
+pub enum WorldSaveError {
+    #[error("failed to access save file: {0}")]
+    Io(#[from] std::io::Error),
+    #[error("failed to parse save file: {0}")]
+    Json(#[from] serde_json::Error),
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum ParentError {
+    #[error("entity {0:?} doesn't exist")]
+    MissingEntity(EntityId),
+    #[error("parenting {child:?} to {parent:?} would create a cycle")]
+    Cycle { child: EntityId, parent: EntityId },
+}
+
+/// Serialized form of an entity, meshes and materials are stored by their asset name
+#[derive(Serialize, Deserialize)]
+pub enum EntitySaveData {
+    Dynamic {
+        transform: Transform,
+        model: Option<(String, String)>,
+        collider: Option<ColliderShape>,
+        linear_velocity: Vec3,
+        angular_velocity: Vec3,
+    },
+    Player {
+        transform: Transform,
+        /// None for a noclip only player
+        #[serde(default)]
+        radius: Option<f32>,
+    },
+    PhysicsPlayer {
+        transform: Transform,
+        radius: f32,
+    },
+    SensorZone {
+        name: String,
+        transform: Transform,
+        shape: ColliderShape,
+    },
+    Static {
+        transform: Transform,
+        parts: Vec<StaticPartSaveData>,
+    },
+    Orbital {
+        elements: OrbitalElements,
+        center: Vec3,
+        time: f64,
+        transform: Transform,
+        model: Option<(String, String)>,
+        collider: Option<ColliderShape>,
+    },
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct StaticPartSaveData {
+    transform: Transform,
+    model: Option<(String, String)>,
+    collider: Option<ColliderShape>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct WorldSaveData {
+    entities: Vec<EntitySaveData>,
+    /// Index into `entities`
+    player_entity: Option<usize>,
+    time_scale: f32,
+    /// Indices into `entities` with their labels
+    #[serde(default)]
+    labels: Vec<(usize, EntityLabel)>,
+    /// Absolute position of the world's origin when it was saved
+    #[serde(default)]
+    origin: DVec3,
+}
+
+/// Name and tags the world keeps for an entity, entities added without a name have no label
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct EntityLabel {
+    pub name: String,
+    pub tags: HashSet<String>,
+}
+
+struct EntityParent {
+    parent: EntityId,
+    /// Relative to the parent's world transform
+    local_transform: Transform,
+}
+
+/// Once the camera is this far from the origin everything is shifted back around it,
+/// so positions near the player stay small enough for f32 to be precise
+const ORIGIN_REBASE_DISTANCE: f32 = 2000.0;
+
+/// Physics bodies further than this from the camera go dormant by default
+const DEFAULT_ACTIVATION_DISTANCE: f32 = 5000.0;
+/// Dormant bodies wake up once they're within this fraction of the activation distance
+const WAKE_DISTANCE_FRACTION: f32 = 0.9;
+
+/// Velocities of a body taken out of the simulation, it keeps moving with them until it wakes up
+struct DormantBody {
+    linear_velocity: Vec3,
+    angular_velocity: Vec3,
+}
+
+/// How far above the controlled entity the overhead camera sits, the far plane is twice as far
+const OVERHEAD_HEIGHT: f32 = 100.0;
+
+/// Entities further than this from the camera can't be targeted
+const TARGET_RANGE: f32 = 5000.0;
+
 pub struct World {
     pub world_info: WorldInfo,
     pub entities: SlotMap<EntityId, Box<dyn Entity>>,
     pub player_entity: EntityId,
+    /// Entity the player is piloting, takes the player's input and camera while set
+    controlled_entity: EntityId,
+    camera_mode: CameraMode,
+    chase_camera: ChaseCamera,
+    camera_effects: CameraEffects,
+    /// Velocity of the input entity's body last update, for the camera's acceleration effects
+    last_input_velocity: Option<Vec3>,
+    labels: SecondaryMap<EntityId, EntityLabel>,
+    parents: SecondaryMap<EntityId, EntityParent>,
+    /// Entity marked on the hud
+    selected_target: Option<EntityId>,
+
+    /// Absolute position of the local origin, positions in the world are relative to it
+    origin: DVec3,
+    sectors: SectorGrid,
+    /// Bodies further than this from the camera are taken out of the simulation, None keeps everything active
+    activation_distance: Option<f32>,
+    dormant: SecondaryMap<EntityId, DormantBody>,
+
+    time_scale: f32,
+    paused: bool,
+    /// Cpu time of the last physics step
+    physics_step_ms: f32,
+    /// Impacts from the last update
+    impacts: Vec<ImpactEvent>,
+}
+
+/// Hard contact between two entities, for effects like impact sounds
+#[derive(Clone, Copy, Debug)]
+pub struct ImpactEvent {
+    /// Halfway between the two entities
+    pub position: Vec3,
+    pub impulse: f32,
+}
+
+/// Softer impacts don't throw sparks, in N*s
+const SPARK_IMPULSE_THRESHOLD: f32 = 5.0;
+/// Sparks per N*s of impulse, capped at `MAX_SPARKS`
+const SPARKS_PER_IMPULSE: f32 = 0.2;
+const MAX_SPARKS: usize = 64;
+
+fn impact_spark_settings() -> EmitterSettings {
+    EmitterSettings {
+        spawn_rate: 0.0,
+        lifetime: (0.2, 0.6),
+        speed: (2.0, 12.0),
+        cone_angle: std::f32::consts::PI,
+        size: ParticleCurve::new(vec![(0.0, 0.15), (1.0, 0.05)]),
+        color: ParticleCurve::new(vec![
+            (0.0, Vec4::new(4.0, 3.0, 1.5, 1.0)),
+            (1.0, Vec4::new(1.0, 0.3, 0.05, 0.0)),
+        ]),
+        max_particles: MAX_SPARKS,
+    }
 }
 
 impl World {
@@ -28,268 +211,4182 @@ impl World {
                 physics,
                 rendering,
                 player_camera: PerspectiveCamera::new(95.0, 0.1),
+                docking: Default::default(),
+                physics_delta_time: 0.0,
+                spawn_queue: Vec::new(),
+                commands: Default::default(),
             },
             entities: SlotMap::with_key(),
             player_entity: Default::default(),
+            controlled_entity: Default::default(),
+            camera_mode: CameraMode::FirstPerson,
+            chase_camera: ChaseCamera::default(),
+            camera_effects: CameraEffects::default(),
+            last_input_velocity: None,
+            labels: SecondaryMap::new(),
+            parents: SecondaryMap::new(),
+            selected_target: None,
+            origin: DVec3::ZERO,
+            sectors: SectorGrid::default(),
+            activation_distance: Some(DEFAULT_ACTIVATION_DISTANCE),
+            dormant: SecondaryMap::new(),
+            time_scale: 1.0,
+            paused: false,
+            physics_step_ms: 0.0,
+            impacts: Vec::new(),
         }
     }
 
-    pub fn update(&mut self, delta_time: f32) {
-        self.world_info.physics.step_physics(delta_time);
+    /// Runs the update stages in order: PrePhysics, the physics step and collision events, PostPhysics,
+    /// attached entities following their parents, then PreRender.
+    /// The player entity always uses the real delta time so the camera can still move while paused
+    /// Returns the entities spawned during the update
+    pub fn update(&mut self, delta_time: f32) -> Vec<EntityId> {
+        let scaled_delta_time = if self.paused {
+            0.0
+        } else {
+            delta_time * self.time_scale
+        };
+
+        self.world_info.physics_delta_time = scaled_delta_time;
+        self.update_autopilot_targets();
+        let order = self.update_order();
+        self.run_stage(
+            UpdateStage::PrePhysics,
+            &order,
+            delta_time,
+            scaled_delta_time,
+        );
 
-        for (id, entity) in self.entities.iter_mut() {
-            entity.update(&mut self.world_info, delta_time);
+        let physics_start = std::time::Instant::now();
+        let collision_events = if scaled_delta_time > 0.0 {
+            self.world_info.physics.step_physics(scaled_delta_time)
+        } else {
+            Vec::new()
+        };
+        self.physics_step_ms = physics_start.elapsed().as_secs_f32() * 1000.0;
+
+        let input_entity = self.input_entity();
+        self.impacts.clear();
+        for (entity_id, other_entity_id, event) in collision_events {
+            if let CollisionEvent::Impact {
+                impulse,
+                point: position,
+                ..
+            } = event
+            {
+                if entity_id == input_entity || other_entity_id == input_entity {
+                    self.camera_effects.add_impact(impulse);
+                }
+                if impulse >= SPARK_IMPULSE_THRESHOLD {
+                    self.world_info.rendering.burst(
+                        &impact_spark_settings(),
+                        &Transform::new_pos(position),
+                        Vec3::ZERO,
+                        ((impulse * SPARKS_PER_IMPULSE) as usize).min(MAX_SPARKS),
+                    );
+                }
+                self.impacts.push(ImpactEvent { position, impulse });
+            }
+            if let Some(entity) = self.entities.get_mut(entity_id) {
+                entity.on_collision(other_entity_id, event, &mut self.world_info);
+            }
+            if let Some(entity) = self.entities.get_mut(other_entity_id) {
+                entity.on_collision(entity_id, event.swapped(), &mut self.world_info);
+            }
         }
+
+        self.run_stage(
+            UpdateStage::PostPhysics,
+            &order,
+            delta_time,
+            scaled_delta_time,
+        );
+        self.update_hierarchy();
+        self.run_stage(
+            UpdateStage::PreRender,
+            &order,
+            delta_time,
+            scaled_delta_time,
+        );
+        self.world_info.rendering.update_effects(scaled_delta_time);
+
+        self.update_sectors();
+        self.update_activation(scaled_delta_time);
+        self.rebase_origin();
+        self.update_chase_camera(delta_time);
+        self.update_camera_effects(delta_time, scaled_delta_time);
+
+        let mut spawned = self.spawn_queued();
+        spawned.extend(self.apply_commands());
+        spawned
     }
 
-    pub fn add_entity<T: Entity + 'static>(&mut self, entity: T) -> EntityId {
-        let id = self.entities.insert(Box::new(entity));
-        let entity = self.entities.get_mut(id).unwrap();
-        entity.set_id(id);
-        entity.add_to_world(&mut self.world_info);
-        id
+    pub fn get_origin(&self) -> DVec3 {
+        self.origin
     }
 
-    pub fn remove_entity(&mut self, entity_id: EntityId) {
-        if let Some(mut entity) = self.entities.remove(entity_id) {
-            entity.remove_from_world(&mut self.world_info);
+    /// Moves everything by `offset`, the absolute positions stay the same since the origin moves the other way
+    pub fn shift_origin(&mut self, offset: Vec3) {
+        self.origin -= offset.as_dvec3();
+        self.world_info.physics.shift_origin(offset);
+        self.world_info.rendering.shift_origin(offset);
+        for entity in self.entities.values_mut() {
+            entity.shift_origin(offset, &mut self.world_info);
         }
+        self.chase_camera.shift_origin(offset);
+        self.update_sectors();
+    }
 
-        if self.player_entity == entity_id {
-            self.player_entity = EntityId::default();
+    /// Crafts flying at another entity get its current position and velocity before they update
+    fn update_autopilot_targets(&mut self) {
+        let targeting: Vec<(EntityId, EntityId)> = self
+            .entities
+            .iter()
+            .filter_map(|(id, entity)| {
+                let craft = entity
+                    .as_ref()
+                    .as_any()
+                    .downcast_ref::<SpaceCraftEntity>()?;
+                Some((id, craft.get_autopilot().target()?))
+            })
+            .collect();
+
+        for (craft_id, target_id) in targeting {
+            let target = self.get_world_transform(target_id).map(|transform| {
+                let velocity = self
+                    .entities
+                    .get(target_id)
+                    .and_then(|entity| entity.get_rigid_body())
+                    .map_or(Vec3::ZERO, |rigid_body| {
+                        self.world_info
+                            .physics
+                            .get_rigid_body_linear_velocity(rigid_body)
+                    });
+                AutopilotTarget {
+                    position: transform.position,
+                    velocity,
+                }
+            });
+            if let Some(craft) = self.entities.get_mut(craft_id).and_then(|entity| {
+                entity
+                    .as_mut()
+                    .as_any_mut()
+                    .downcast_mut::<SpaceCraftEntity>()
+            }) {
+                craft.set_autopilot_target(target);
+            }
         }
     }
 
-    pub fn set_player(&mut self, player_id: EntityId) {
-        self.player_entity = player_id;
+    /// Whatever the player is flying, None when it isn't a craft
+    pub fn controlled_craft(&self) -> Option<&SpaceCraftEntity> {
+        self.entities
+            .get(self.input_entity())
+            .and_then(|entity| entity.as_ref().as_any().downcast_ref::<SpaceCraftEntity>())
     }
 
-    pub(crate) fn update_player_input(&mut self, linear_input: Vec3, angular_input: Vec3) {
-        if let Some(player) = self.entities.get_mut(self.player_entity) {
-            player.update_player_input(linear_input, angular_input);
+    pub fn controlled_autopilot(&self) -> Option<Autopilot> {
+        self.controlled_craft().map(SpaceCraftEntity::get_autopilot)
+    }
+
+    fn update_sectors(&mut self) {
+        for (id, entity) in self.entities.iter() {
+            match entity.get_world_transform() {
+                Some(transform) => self.sectors.set_position(id, transform.position),
+                None => self.sectors.remove(id),
+            }
         }
     }
 
-    pub fn get_player_camera(&self) -> (PerspectiveCamera, Transform) {
-        let camera_transform: Transform = self
-            .entities
-            .get(self.player_entity)
-            .and_then(|entity| entity.get_camera_transform())
-            .unwrap_or_default();
+    /// Entities whose position is within `radius` of `position`, entities without a world transform are never found
+    pub fn entities_in_radius(&self, position: Vec3, radius: f32) -> Vec<EntityId> {
+        self.sectors
+            .candidates_in_radius(position, radius)
+            .into_iter()
+            .filter(|id| {
+                self.get_world_transform(*id)
+                    .map(|transform| {
+                        transform.position.distance_squared(position) <= radius * radius
+                    })
+                    .unwrap_or(false)
+            })
+            .collect()
+    }
 
-        (self.world_info.player_camera.clone(), camera_transform)
+    /// Entities whose position is at most `margin` meters outside the frustum
+    pub fn entities_in_frustum(&self, frustum: &Frustum, margin: f32) -> Vec<EntityId> {
+        self.sectors
+            .candidates_in_frustum(frustum)
+            .into_iter()
+            .filter(|id| {
+                self.get_world_transform(*id)
+                    .map(|transform| frustum.intersects_sphere(transform.position, margin))
+                    .unwrap_or(false)
+            })
+            .collect()
     }
-}
 
-pub struct WorldInfo {
-    pub physics: PhysicsScene,
-    pub rendering: SceneRenderData,
+    pub fn get_activation_distance(&self) -> Option<f32> {
+        self.activation_distance
+    }
 
-    pub player_camera: PerspectiveCamera,
-}
+    /// None wakes every dormant body on the next update
+    pub fn set_activation_distance(&mut self, activation_distance: Option<f32>) {
+        self.activation_distance = activation_distance;
+    }
 
-pub trait Entity {
-    fn set_id(&mut self, id: EntityId);
-    fn add_to_world(&mut self, world: &mut WorldInfo);
-    fn remove_from_world(&mut self, world: &mut WorldInfo);
+    pub fn is_dormant(&self, entity_id: EntityId) -> bool {
+        self.dormant.contains_key(entity_id)
+    }
 
-    fn update(&mut self, world: &mut WorldInfo, delta_time: f32);
+    /// Far away dynamic bodies are disabled and moved on rails from the velocity they had, ignoring gravity and
+    /// collisions. They wake up a bit inside the activation distance so bodies near the edge don't flicker between states
+    fn update_activation(&mut self, scaled_delta_time: f32) {
+        let (_, camera_transform) = self.get_player_camera();
+        let (in_range, in_wake_range) = match self.activation_distance {
+            Some(distance) => (
+                self.entities_in_radius(camera_transform.position, distance)
+                    .into_iter()
+                    .collect::<HashSet<_>>(),
+                self.entities_in_radius(
+                    camera_transform.position,
+                    distance * WAKE_DISTANCE_FRACTION,
+                )
+                .into_iter()
+                .collect::<HashSet<_>>(),
+            ),
+            None => (HashSet::new(), HashSet::new()),
+        };
+        let input_entity = self.input_entity();
 
-    fn update_player_input(&mut self, linear_input: Vec3, angular_input: Vec3);
-    fn get_camera_transform(&self) -> Option<Transform>;
-}
+        for (id, entity) in self.entities.iter() {
+            let Some(rigid_body) = entity.get_rigid_body() else {
+                continue;
+            };
+            let physics = &mut self.world_info.physics;
 
-pub struct DynamicEntity {
-    id: EntityId,
-    transform: Transform,
-    model: Option<(MeshHandle, MaterialHandle)>,
-    collider: Option<ColliderShape>,
+            if let Some(state) = self.dormant.get(id) {
+                let wake = self.activation_distance.is_none()
+                    || in_wake_range.contains(&id)
+                    || id == input_entity
+                    || id == self.player_entity;
+                if wake {
+                    physics.set_rigid_body_enabled(rigid_body, true);
+                    physics.set_rigid_body_linear_velocity(rigid_body, state.linear_velocity);
+                    physics.set_rigid_body_angular_velocity(rigid_body, state.angular_velocity);
+                    self.dormant.remove(id);
+                } else if scaled_delta_time > 0.0 {
+                    let (position, rotation) = physics.get_rigid_body_transform(rigid_body);
+                    physics.set_rigid_body_transform(
+                        rigid_body,
+                        position + state.linear_velocity * scaled_delta_time,
+                        (Quat::from_scaled_axis(state.angular_velocity * scaled_delta_time)
+                            * rotation)
+                            .normalize(),
+                        false,
+                    );
+                }
+            } else if self.activation_distance.is_some()
+                && !in_range.contains(&id)
+                && id != input_entity
+                && id != self.player_entity
+                && !self.parents.contains_key(id)
+                && physics.is_rigid_body_dynamic(rigid_body)
+            {
+                self.dormant.insert(
+                    id,
+                    DormantBody {
+                        linear_velocity: physics.get_rigid_body_linear_velocity(rigid_body),
+                        angular_velocity: physics.get_rigid_body_angular_velocity(rigid_body),
+                    },
+                );
+                physics.set_rigid_body_enabled(rigid_body, false);
+            }
+        }
+    }
 
-    model_instance: Option<InstanceHandle>,
-    rigid_body_instance: Option<RigidBodyHandle>,
-    collider_instance: Option<ColliderHandle>,
-}
+    fn rebase_origin(&mut self) {
+        let (_, camera_transform) = self.get_player_camera();
+        if camera_transform.position.length() > ORIGIN_REBASE_DISTANCE {
+            self.shift_origin(-camera_transform.position);
+            info!("Moved the world origin to {:?}", self.origin);
+        }
+    }
 
-impl DynamicEntity {
-    pub fn new(
-        transform: Transform,
-        model: Option<(MeshHandle, MaterialHandle)>,
-        collider: Option<ColliderShape>,
-    ) -> Self {
-        Self {
-            id: Default::default(),
-            transform,
-            model,
-            collider,
-            model_instance: None,
-            rigid_body_instance: None,
-            collider_instance: None,
+    /// Places children from the root down so every parent is placed before its own children follow it
+    fn update_hierarchy(&mut self) {
+        let mut children: Vec<(usize, EntityId)> = self
+            .parents
+            .keys()
+            .map(|child| (self.parent_depth(child), child))
+            .collect();
+        children.sort_by_key(|(depth, _)| *depth);
+
+        for (_, child) in children {
+            let link = &self.parents[child];
+            let Some(parent_transform) = self
+                .entities
+                .get(link.parent)
+                .and_then(|parent| parent.get_world_transform())
+            else {
+                continue;
+            };
+            let transform = parent_transform.transform_by(&link.local_transform);
+            if let Some(entity) = self.entities.get_mut(child) {
+                entity.set_world_transform(&transform, &mut self.world_info);
+            }
         }
     }
-}
 
-impl Entity for DynamicEntity {
-    fn set_id(&mut self, id: EntityId) {
-        self.id = id;
+    /// Parents before their children so children see this frame's parent state, otherwise in slot order.
+    /// Entities spawned during the update aren't in it, they start updating next frame
+    fn update_order(&self) -> Vec<EntityId> {
+        let mut order: Vec<(usize, EntityId)> = self
+            .entities
+            .keys()
+            .map(|id| (self.parent_depth(id), id))
+            .collect();
+        order.sort_by_key(|(depth, _)| *depth);
+        order.into_iter().map(|(_, id)| id).collect()
     }
 
-    fn add_to_world(&mut self, world: &mut WorldInfo) {
-        if let Some((mesh, material)) = &self.model {
-            self.model_instance =
-                world
-                    .rendering
-                    .create_instance(*mesh, *material, &self.transform);
+    fn run_stage(
+        &mut self,
+        stage: UpdateStage,
+        order: &[EntityId],
+        delta_time: f32,
+        scaled_delta_time: f32,
+    ) {
+        if stage == UpdateStage::PostPhysics {
+            self.simulate_entities(delta_time, scaled_delta_time);
         }
 
-        if let Some(shape) = &self.collider {
-            self.rigid_body_instance = Some(world.physics.create_rigid_body(
-                self.transform.position,
-                self.transform.rotation,
-                RigidBodyType::Dynamic,
-            ));
-            self.collider_instance = Some(world.physics.create_collider(
-                self.rigid_body_instance.unwrap(),
-                glam::Vec3::ZERO,
-                glam::Quat::IDENTITY,
-                shape,
-                1.0,
-            ));
+        for id in order.iter().copied() {
+            let Some(delta_time) =
+                entity_delta_time(id, self.player_entity, delta_time, scaled_delta_time)
+            else {
+                continue;
+            };
+            let Some(entity) = self.entities.get_mut(id) else {
+                continue;
+            };
+            if !entity.update_stages().contains(&stage) {
+                continue;
+            }
+            match stage {
+                UpdateStage::PrePhysics => {
+                    entity.pre_physics_update(&mut self.world_info, delta_time)
+                }
+                UpdateStage::PostPhysics => entity.update(&mut self.world_info, delta_time),
+                UpdateStage::PreRender => {
+                    entity.pre_render_update(&mut self.world_info, delta_time)
+                }
+            }
         }
     }
 
-    fn remove_from_world(&mut self, world: &mut WorldInfo) {
-        if let Some(model) = self.model_instance.take() {
-            world.rendering.remove_instance(model);
+    /// Parallel half of PostPhysics, entities only change themselves here so the order they run in doesn't matter.
+    /// Anything they change in the world is left for their `update`, which runs serially in update order
+    fn simulate_entities(&mut self, delta_time: f32, scaled_delta_time: f32) {
+        let player_entity = self.player_entity;
+        let world = WorldReadOnly {
+            physics: &self.world_info.physics,
+            physics_delta_time: self.world_info.physics_delta_time,
+        };
+        let entities: Vec<(&mut Box<dyn Entity>, f32)> = self
+            .entities
+            .iter_mut()
+            .filter(|(_, entity)| entity.update_stages().contains(&UpdateStage::PostPhysics))
+            .filter_map(|(id, entity)| {
+                entity_delta_time(id, player_entity, delta_time, scaled_delta_time)
+                    .map(|delta_time| (entity, delta_time))
+            })
+            .collect();
+
+        #[cfg(feature = "parallel")]
+        {
+            use rayon::prelude::*;
+            entities
+                .into_par_iter()
+                .for_each(|(entity, delta_time)| entity.simulate(&world, delta_time));
+        }
+        #[cfg(not(feature = "parallel"))]
+        for (entity, delta_time) in entities {
+            entity.simulate(&world, delta_time);
         }
+    }
 
-        if let Some(collider) = self.collider_instance.take() {
-            world.physics.remove_collider(collider);
+    /// Number of parent links above the entity, finite since `set_parent` rejects cycles
+    fn parent_depth(&self, entity_id: EntityId) -> usize {
+        let mut depth = 0;
+        let mut current = entity_id;
+        while let Some(link) = self.parents.get(current) {
+            depth += 1;
+            current = link.parent;
         }
+        depth
+    }
 
-        if let Some(rigid_body) = self.rigid_body_instance.take() {
-            world.physics.remove_rigid_body(rigid_body);
+    /// Entities added from the queue can queue more in `add_to_world`, those are added too
+    fn spawn_queued(&mut self) -> Vec<EntityId> {
+        let mut spawned = Vec::new();
+        while !self.world_info.spawn_queue.is_empty() {
+            let queue = std::mem::take(&mut self.world_info.spawn_queue);
+            spawned.extend(
+                queue
+                    .into_iter()
+                    .map(|entity| self.add_boxed_entity(entity)),
+            );
         }
+        spawned
     }
 
-    fn update(&mut self, world: &mut WorldInfo, delta_time: f32) {
-        if let Some(rigid_body) = self.rigid_body_instance {
-            let (position, rotation) = world.physics.get_rigid_body_transform(rigid_body);
-            self.transform.position = position;
-            self.transform.rotation = rotation;
+    /// Despawns first so a replacement can be spawned in the same frame, messages to despawned entities are dropped.
+    /// Commands queued while handling messages are applied next update
+    fn apply_commands(&mut self) -> Vec<EntityId> {
+        let commands = std::mem::take(&mut self.world_info.commands);
+        for entity_id in commands.despawned {
+            self.remove_entity(entity_id);
         }
+        let spawned = commands
+            .spawned
+            .into_iter()
+            .map(|entity| self.add_boxed_entity(entity))
+            .collect();
+        for (entity_id, message) in commands.messages {
+            if let Some(entity) = self.entities.get_mut(entity_id) {
+                entity.on_message(message, &mut self.world_info);
+            }
+        }
+        spawned
+    }
 
-        if let Some(model) = self.model_instance {
-            world.rendering.update_instance(model, &self.transform);
+    /// Writes every entity that supports saving to a json file
+    pub fn save<P: AsRef<std::path::Path>>(
+        &self,
+        path: P,
+        renderer: &Renderer,
+    ) -> Result<(), WorldSaveError> {
+        let mut save_data = WorldSaveData {
+            entities: Vec::new(),
+            player_entity: None,
+            time_scale: self.time_scale,
+            labels: Vec::new(),
+            origin: self.origin,
+        };
+
+        for (id, entity) in self.entities.iter() {
+            if let Some(entity_save_data) = entity.save(&self.world_info, renderer) {
+                if id == self.player_entity {
+                    save_data.player_entity = Some(save_data.entities.len());
+                }
+                if let Some(label) = self.labels.get(id) {
+                    save_data
+                        .labels
+                        .push((save_data.entities.len(), label.clone()));
+                }
+                save_data.entities.push(entity_save_data);
+            }
         }
+
+        std::fs::write(path, serde_json::to_string_pretty(&save_data)?)?;
+        Ok(())
     }
 
-    fn update_player_input(&mut self, linear_input: Vec3, angular_input: Vec3) {
-        let _ = linear_input;
-        let _ = angular_input;
-        unimplemented!()
+    /// Meshes are loaded by name if they aren't already, materials have to already exist with a matching name
+    pub fn load<P: AsRef<std::path::Path>>(
+        path: P,
+        renderer: &mut Renderer,
+    ) -> Result<Self, WorldSaveError> {
+        let save_data: WorldSaveData = serde_json::from_str(&std::fs::read_to_string(path)?)?;
+
+        let mut world = Self::new(renderer);
+        world.set_time_scale(save_data.time_scale);
+        world.origin = save_data.origin;
+
+        let mut ids = Vec::with_capacity(save_data.entities.len());
+        for (index, entity_save_data) in save_data.entities.into_iter().enumerate() {
+            let id = match entity_save_data {
+                EntitySaveData::Dynamic {
+                    transform,
+                    model,
+                    collider,
+                    linear_velocity,
+                    angular_velocity,
+                } => {
+                    let model = model.and_then(|names| load_model(renderer, names));
+                    world.add_entity(
+                        DynamicEntity::new(transform, model, collider)
+                            .with_velocity(linear_velocity, angular_velocity),
+                    )
+                }
+                EntitySaveData::Player { transform, radius } => match radius {
+                    Some(radius) => world.add_entity(Player::new_inertial(transform, radius)),
+                    None => world.add_entity(Player::new(transform)),
+                },
+                EntitySaveData::PhysicsPlayer { transform, radius } => {
+                    world.add_entity(PhysicsPlayer::new(transform, radius))
+                }
+                EntitySaveData::SensorZone {
+                    name,
+                    transform,
+                    shape,
+                } => world.add_entity(SensorZoneEntity::new(&name, transform, shape)),
+                EntitySaveData::Static { transform, parts } => {
+                    let parts = parts
+                        .into_iter()
+                        .map(|part| StaticPart {
+                            transform: part.transform,
+                            model: part.model.and_then(|names| load_model(renderer, names)),
+                            collider: part.collider,
+                        })
+                        .collect();
+                    world.add_entity(StaticEntity::new(transform, parts))
+                }
+                EntitySaveData::Orbital {
+                    elements,
+                    center,
+                    time,
+                    transform,
+                    model,
+                    collider,
+                } => {
+                    let mut entity =
+                        OrbitalEntity::new(elements, center, transform).with_time(time);
+                    if let Some((mesh, material)) =
+                        model.and_then(|names| load_model(renderer, names))
+                    {
+                        entity = entity.with_model(mesh, material);
+                    }
+                    if let Some(collider) = collider {
+                        entity = entity.with_collider(collider);
+                    }
+                    world.add_entity(entity)
+                }
+            };
+
+            if save_data.player_entity == Some(index) {
+                world.set_player(id);
+            }
+            ids.push(id);
+        }
+
+        for (index, label) in save_data.labels {
+            match ids.get(index) {
+                Some(id) => world.set_label(*id, label),
+                None => warn!("Save file has a label for missing entity {}", index),
+            }
+        }
+
+        Ok(world)
     }
-    fn get_camera_transform(&self) -> Option<Transform> {
-        unimplemented!()
+
+    pub fn get_time_scale(&self) -> f32 {
+        self.time_scale
     }
-}
 
-pub struct SpaceCraftNode {
-    local_transform: Transform,
+    pub fn set_time_scale(&mut self, time_scale: f32) {
+        self.time_scale = time_scale.max(0.0);
+    }
 
-    model: Option<(MeshHandle, MaterialHandle)>,
-    collider: Option<ColliderShape>,
+    pub fn physics_step_ms(&self) -> f32 {
+        self.physics_step_ms
+    }
 
-    model_instance: Option<InstanceHandle>,
-    collider_instance: Option<ColliderHandle>,
-}
+    pub fn impacts(&self) -> &[ImpactEvent] {
+        &self.impacts
+    }
 
-pub struct SpaceCraftEntity {
-    id: EntityId,
-    transform: Transform,
+    pub fn is_paused(&self) -> bool {
+        self.paused
+    }
 
-    rigid_body_instance: Option<RigidBodyHandle>,
+    pub fn set_paused(&mut self, paused: bool) {
+        self.paused = paused;
+    }
 
-    nodes: Vec<SpaceCraftNode>,
-}
+    pub fn add_entity<T: Entity + 'static>(&mut self, entity: T) -> EntityId {
+        self.add_boxed_entity(Box::new(entity))
+    }
 
-impl Entity for SpaceCraftEntity {
-    fn set_id(&mut self, id: EntityId) {
-        self.id = id;
+    pub fn add_boxed_entity(&mut self, entity: Box<dyn Entity>) -> EntityId {
+        let id = self.entities.insert(entity);
+        let entity = self.entities.get_mut(id).unwrap();
+        entity.set_id(id);
+        entity.add_to_world(&mut self.world_info);
+        if let Some(transform) = entity.get_world_transform() {
+            self.sectors.set_position(id, transform.position);
+        }
+        id
     }
 
-    fn add_to_world(&mut self, world: &mut WorldInfo) {
-        self.rigid_body_instance = Some(world.physics.create_rigid_body(
-            self.transform.position,
-            self.transform.rotation,
-            RigidBodyType::Dynamic,
-        ));
+    pub fn add_entity_named<T: Entity + 'static>(
+        &mut self,
+        entity: T,
+        name: &str,
+        tags: &[&str],
+    ) -> EntityId {
+        let id = self.add_entity(entity);
+        self.set_label(
+            id,
+            EntityLabel {
+                name: name.to_string(),
+                tags: tags.iter().map(|tag| tag.to_string()).collect(),
+            },
+        );
+        id
+    }
 
-        for node in self.nodes.iter_mut() {
-            if let Some((mesh, material)) = &node.model {
-                node.model_instance = world.rendering.create_instance(
-                    *mesh,
-                    *material,
-                    &self.transform.transform_by(&node.local_transform),
-                );
-            }
+    pub fn set_label(&mut self, entity_id: EntityId, label: EntityLabel) {
+        self.labels.insert(entity_id, label);
+    }
 
-            if let Some(shape) = &node.collider {
-                node.collider_instance = Some(world.physics.create_collider(
-                    self.rigid_body_instance.unwrap(),
-                    self.transform.position,
-                    self.transform.rotation,
-                    shape,
-                    1.0,
-                ));
-            }
-        }
+    pub fn get_label(&self, entity_id: EntityId) -> Option<&EntityLabel> {
+        self.labels.get(entity_id)
     }
 
-    fn remove_from_world(&mut self, world: &mut WorldInfo) {
-        if let Some(rigid_body) = self.rigid_body_instance.take() {
-            world.physics.remove_rigid_body(rigid_body);
+    /// Names don't have to be unique, if several entities share the name one of them is returned with a warning
+    pub fn find_by_name(&self, name: &str) -> Option<EntityId> {
+        let mut matches = self
+            .labels
+            .iter()
+            .filter(|(_, label)| label.name == name)
+            .map(|(entity_id, _)| entity_id);
+        let entity_id = matches.next()?;
+
+        let others = matches.count();
+        if others > 0 {
+            warn!(
+                "{} entities are named {:?}, using {:?}",
+                others + 1,
+                name,
+                entity_id
+            );
         }
+        Some(entity_id)
+    }
 
-        for node in self.nodes.iter_mut() {
-            if let Some(model) = node.model_instance.take() {
-                world.rendering.remove_instance(model);
-            }
+    pub fn with_tag(&self, tag: &str) -> Vec<EntityId> {
+        self.labels
+            .iter()
+            .filter(|(_, label)| label.tags.contains(tag))
+            .map(|(entity_id, _)| entity_id)
+            .collect()
+    }
 
-            if let Some(collider) = node.collider_instance.take() {
-                world.physics.remove_collider(collider);
-            }
-        }
+    /// None if the entity doesn't exist or isn't a `T`
+    pub fn get_entity<T: Entity + 'static>(&self, entity_id: EntityId) -> Option<&T> {
+        self.entities
+            .get(entity_id)
+            .and_then(|entity| entity.as_ref().as_any().downcast_ref())
     }
 
-    fn update(&mut self, world: &mut WorldInfo, delta_time: f32) {
-        if let Some(rigid_body) = self.rigid_body_instance {
-            let (position, rotation) = world.physics.get_rigid_body_transform(rigid_body);
-            self.transform.position = position;
-            self.transform.rotation = rotation;
+    pub fn get_entity_mut<T: Entity + 'static>(&mut self, entity_id: EntityId) -> Option<&mut T> {
+        self.entities
+            .get_mut(entity_id)
+            .and_then(|entity| entity.as_mut().as_any_mut().downcast_mut())
+    }
+
+    pub fn entities_of_type<T: Entity + 'static>(&self) -> impl Iterator<Item = (EntityId, &T)> {
+        self.entities.iter().filter_map(|(entity_id, entity)| {
+            entity
+                .as_ref()
+                .as_any()
+                .downcast_ref()
+                .map(|entity| (entity_id, entity))
+        })
+    }
+
+    /// Removes every entity from the physics and render scenes before dropping them
+    pub fn destroy(mut self) {
+        let entity_ids: Vec<EntityId> = self.entities.keys().collect();
+        for entity_id in entity_ids {
+            self.remove_entity(entity_id);
         }
+    }
 
-        for node in self.nodes.iter() {
-            if let Some(model) = node.model_instance {
-                world
-                    .rendering
-                    .update_instance(model, &self.transform.transform_by(&node.local_transform));
+    /// Places the child at `local_transform` relative to the parent, starting at the end of the next update
+    pub fn set_parent(
+        &mut self,
+        child: EntityId,
+        parent: EntityId,
+        local_transform: Transform,
+    ) -> Result<(), ParentError> {
+        for entity_id in [child, parent] {
+            if !self.entities.contains_key(entity_id) {
+                return Err(ParentError::MissingEntity(entity_id));
             }
+        }
 
-            if let Some(collider) = &node.collider_instance {
-                world.physics.set_collider_transform(
-                    *collider,
-                    node.local_transform.position,
-                    node.local_transform.rotation,
-                );
+        let mut ancestor = Some(parent);
+        while let Some(entity_id) = ancestor {
+            if entity_id == child {
+                return Err(ParentError::Cycle { child, parent });
             }
+            ancestor = self.parents.get(entity_id).map(|link| link.parent);
+        }
+
+        let was_attached = self
+            .parents
+            .insert(
+                child,
+                EntityParent {
+                    parent,
+                    local_transform,
+                },
+            )
+            .is_some();
+        if !was_attached {
+            self.entities[child].set_attached(true, &mut self.world_info);
         }
+        Ok(())
     }
 
-    fn update_player_input(&mut self, linear_input: Vec3, angular_input: Vec3) {
-        todo!()
+    /// The child stays where its parent last placed it, returns false if it didn't have a parent
+    pub fn clear_parent(&mut self, child: EntityId) -> bool {
+        if self.parents.remove(child).is_none() {
+            return false;
+        }
+        if let Some(entity) = self.entities.get_mut(child) {
+            entity.set_attached(false, &mut self.world_info);
+        }
+        true
     }
 
-    fn get_camera_transform(&self) -> Option<Transform> {
-        todo!()
+    pub fn get_parent(&self, child: EntityId) -> Option<EntityId> {
+        self.parents.get(child).map(|link| link.parent)
+    }
+
+    pub fn get_world_transform(&self, entity_id: EntityId) -> Option<Transform> {
+        self.entities.get(entity_id)?.get_world_transform()
+    }
+
+    /// Children of the removed entity are detached where they are
+    pub fn remove_entity(&mut self, entity_id: EntityId) {
+        let children: Vec<EntityId> = self
+            .parents
+            .iter()
+            .filter(|(_, link)| link.parent == entity_id)
+            .map(|(child, _)| child)
+            .collect();
+        for child in children {
+            self.clear_parent(child);
+        }
+        self.parents.remove(entity_id);
+
+        if let Some(mut entity) = self.entities.remove(entity_id) {
+            entity.remove_from_world(&mut self.world_info);
+        }
+        self.labels.remove(entity_id);
+        self.sectors.remove(entity_id);
+        self.dormant.remove(entity_id);
+
+        if self.player_entity == entity_id {
+            self.player_entity = EntityId::default();
+        }
+        if self.controlled_entity == entity_id {
+            self.controlled_entity = EntityId::default();
+        }
+        if self.selected_target == Some(entity_id) {
+            self.selected_target = None;
+        }
+    }
+
+    pub fn set_player(&mut self, player_id: EntityId) {
+        self.player_entity = player_id;
+        self.last_input_velocity = None;
+    }
+
+    /// Routes the player's input and camera to another entity until `release_control` is called,
+    /// returns false if the entity doesn't exist
+    pub fn set_controlled_entity(&mut self, entity_id: EntityId) -> bool {
+        if !self.entities.contains_key(entity_id) {
+            warn!("Can't control missing entity {:?}", entity_id);
+            return false;
+        }
+        //The old target would keep its last input forever
+        self.update_player_input(Vec3::ZERO, Vec3::ZERO);
+        self.controlled_entity = entity_id;
+        self.last_input_velocity = None;
+        true
+    }
+
+    /// Gives the input back to the player entity, returns the entity that was controlled
+    pub fn release_control(&mut self) -> Option<EntityId> {
+        let controlled_entity = self.get_controlled_entity()?;
+        self.update_player_input(Vec3::ZERO, Vec3::ZERO);
+        self.controlled_entity = EntityId::default();
+        self.last_input_velocity = None;
+        Some(controlled_entity)
+    }
+
+    pub fn get_controlled_entity(&self) -> Option<EntityId> {
+        self.entities
+            .contains_key(self.controlled_entity)
+            .then_some(self.controlled_entity)
+    }
+
+    pub fn get_selected_target(&self) -> Option<EntityId> {
+        self.selected_target
+    }
+
+    pub fn set_selected_target(&mut self, target: Option<EntityId>) {
+        self.selected_target = target;
+    }
+
+    /// Entity under a pixel of the player camera and where the ray hit it, `screen_position` is in pixels from the
+    /// top left of a surface `surface_size` big. The input entity's own body is ignored
+    pub fn pick(
+        &self,
+        screen_position: [f32; 2],
+        surface_size: [u32; 2],
+    ) -> Option<(EntityId, Vec3)> {
+        let (origin, direction) = self.pick_ray(screen_position, surface_size);
+        self.pick_along(origin, direction, Some(self.input_entity()))
+    }
+
+    /// World space ray from the player camera through a pixel, the origin is on the near plane
+    pub fn pick_ray(&self, screen_position: [f32; 2], surface_size: [u32; 2]) -> (Vec3, Vec3) {
+        let (camera, camera_transform) = self.get_player_camera();
+        camera.screen_ray(&camera_transform, screen_position, surface_size)
+    }
+
+    /// First entity along a ray in targeting range, skipping the body of `exclude`
+    pub fn pick_along(
+        &self,
+        origin: Vec3,
+        direction: Vec3,
+        exclude: Option<EntityId>,
+    ) -> Option<(EntityId, Vec3)> {
+        let exclude_rigid_body = exclude
+            .and_then(|entity_id| self.entities.get(entity_id))
+            .and_then(|entity| entity.get_rigid_body());
+
+        let physics = &self.world_info.physics;
+        let (collider, distance) =
+            physics.cast_ray(origin, direction, TARGET_RANGE, exclude_rigid_body)?;
+        let entity_id = physics
+            .get_collider_entity(collider)
+            .filter(|entity_id| self.entities.contains_key(*entity_id))?;
+        Some((entity_id, origin + direction * distance))
+    }
+
+    /// Selects the next closest entity in range of the camera, going back to no target after the furthest one
+    pub fn cycle_target(&mut self) -> Option<EntityId> {
+        let (_, camera_transform) = self.get_player_camera();
+        let input_entity = self.input_entity();
+        let mut candidates: Vec<(f32, EntityId)> = self
+            .entities_in_radius(camera_transform.position, TARGET_RANGE)
+            .into_iter()
+            .filter(|entity_id| *entity_id != input_entity && *entity_id != self.player_entity)
+            .filter_map(|entity_id| {
+                self.get_world_transform(entity_id).map(|transform| {
+                    (
+                        transform
+                            .position
+                            .distance_squared(camera_transform.position),
+                        entity_id,
+                    )
+                })
+            })
+            .collect();
+        candidates.sort_by(|a, b| a.0.total_cmp(&b.0));
+
+        let current = self.selected_target.and_then(|target| {
+            candidates
+                .iter()
+                .position(|(_, entity_id)| *entity_id == target)
+        });
+        self.selected_target = match current {
+            Some(index) => candidates.get(index + 1),
+            None => candidates.first(),
+        }
+        .map(|(_, entity_id)| *entity_id);
+        self.selected_target
+    }
+
+    /// Label name, or the type name for unnamed entities
+    pub fn display_name(&self, entity_id: EntityId) -> Option<String> {
+        if let Some(label) = self.labels.get(entity_id) {
+            return Some(label.name.clone());
+        }
+        let type_name = self.entities.get(entity_id)?.as_ref().type_name();
+        Some(
+            type_name
+                .rsplit("::")
+                .next()
+                .unwrap_or(type_name)
+                .to_string(),
+        )
+    }
+
+    /// The controlled entity if there is one, otherwise the player entity
+    pub fn input_entity(&self) -> EntityId {
+        self.get_controlled_entity().unwrap_or(self.player_entity)
+    }
+
+    pub(crate) fn update_player_input(&mut self, linear_input: Vec3, angular_input: Vec3) {
+        if let Some(player) = self.entities.get_mut(self.input_entity()) {
+            player.update_player_input(linear_input, angular_input);
+        }
+    }
+
+    pub(crate) fn update_player_look(&mut self, yaw: f32, pitch: f32) {
+        if let Some(player) = self.entities.get_mut(self.input_entity()) {
+            player.apply_mouse_look(yaw, pitch);
+        }
+    }
+
+    pub fn player_action(&mut self, action: PlayerAction) {
+        if let Some(player) = self.entities.get_mut(self.input_entity()) {
+            player.on_player_action(action, &mut self.world_info);
+        }
+    }
+
+    /// In third person this is the chase camera from the last update, both include the camera effects.
+    /// Overhead looks down the controlled entity's up axis with its forward at the top of the screen
+    pub fn get_player_camera(&self) -> (Camera, Transform) {
+        let mut camera_transform: Transform = match self.camera_mode {
+            CameraMode::FirstPerson => self.get_first_person_camera().unwrap_or_default(),
+            CameraMode::ThirdPerson { .. } => self.chase_camera.transform().clone(),
+            CameraMode::Overhead { half_height } => {
+                let target = self
+                    .entities
+                    .get(self.input_entity())
+                    .and_then(|entity| {
+                        entity
+                            .get_world_transform()
+                            .or_else(|| entity.get_camera_transform())
+                    })
+                    .unwrap_or_default();
+                let camera_transform = Transform {
+                    position: target.position + target.rotation * (Vec3::Y * OVERHEAD_HEIGHT),
+                    rotation: target.rotation * Quat::from_rotation_x(std::f32::consts::FRAC_PI_2),
+                    ..Default::default()
+                };
+                let camera = OrthographicCamera::new(half_height, 0.1, OVERHEAD_HEIGHT * 2.0);
+                return (Camera::Orthographic(camera), camera_transform);
+            }
+        };
+        camera_transform.rotation *= self.camera_effects.shake_rotation();
+
+        let mut camera = self.world_info.player_camera.clone();
+        camera.set_fov_offset_deg(self.camera_effects.fov_offset_deg());
+        (Camera::Perspective(camera), camera_transform)
+    }
+
+    /// Shakes the player camera, 1.0 is the strongest shake
+    pub fn add_camera_trauma(&mut self, amount: f32) {
+        self.camera_effects.add_trauma(amount);
+    }
+
+    /// Acceleration comes from the input entity's body, so thrusting hard kicks the fov and rattles the camera
+    fn update_camera_effects(&mut self, delta_time: f32, scaled_delta_time: f32) {
+        let velocity = self
+            .entities
+            .get(self.input_entity())
+            .and_then(|entity| entity.get_rigid_body())
+            .map(|rigid_body| {
+                self.world_info
+                    .physics
+                    .get_rigid_body_linear_velocity(rigid_body)
+            });
+
+        let acceleration = match (velocity, self.last_input_velocity) {
+            (Some(velocity), Some(last_velocity)) if scaled_delta_time > 0.0 => {
+                let (_, camera_transform) = self.get_player_camera();
+                camera_transform.rotation.inverse() * (velocity - last_velocity) / scaled_delta_time
+            }
+            _ => Vec3::ZERO,
+        };
+        if scaled_delta_time > 0.0 {
+            self.last_input_velocity = velocity;
+        }
+
+        self.camera_effects.update(delta_time, acceleration);
+    }
+
+    fn get_first_person_camera(&self) -> Option<Transform> {
+        self.entities
+            .get(self.input_entity())
+            .and_then(|entity| entity.get_camera_transform())
+    }
+
+    pub fn get_camera_mode(&self) -> CameraMode {
+        self.camera_mode
+    }
+
+    pub fn set_camera_mode(&mut self, camera_mode: CameraMode) {
+        if !matches!(self.camera_mode, CameraMode::ThirdPerson { .. }) {
+            self.chase_camera.reset();
+        }
+        self.camera_mode = camera_mode;
+        //Places the chase camera right away so it's never drawn from where it was last time
+        self.update_chase_camera(0.0);
+    }
+
+    /// Moves the third person camera closer or further away and zooms the overhead view,
+    /// does nothing in first person
+    pub fn adjust_camera_distance(&mut self, delta: f32) {
+        match &mut self.camera_mode {
+            CameraMode::ThirdPerson { distance, .. }
+            | CameraMode::Overhead {
+                half_height: distance,
+            } => {
+                *distance =
+                    (*distance + delta).clamp(CameraMode::MIN_DISTANCE, CameraMode::MAX_DISTANCE);
+            }
+            CameraMode::FirstPerson => {}
+        }
+    }
+
+    fn update_chase_camera(&mut self, delta_time: f32) {
+        let CameraMode::ThirdPerson {
+            distance,
+            pivot_offset,
+        } = self.camera_mode
+        else {
+            return;
+        };
+        let Some(target) = self.get_first_person_camera() else {
+            return;
+        };
+        let exclude_rigid_body = self
+            .entities
+            .get(self.input_entity())
+            .and_then(|entity| entity.get_rigid_body());
+        self.chase_camera.update(
+            &target,
+            distance,
+            pivot_offset,
+            delta_time,
+            &self.world_info.physics,
+            exclude_rigid_body,
+        );
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum PlayerAction {
+    CycleFlightMode,
+    SetAutopilot(Autopilot),
+    /// Docks if a port is aligned, undocks if already docked
+    ToggleDocking,
+    /// Writes the controlled craft to a spacecraft file
+    SaveDefinition,
+    /// Automatic braking of a player flying on its own
+    ToggleDampeners,
+    /// Lets a player fly through everything, for debugging
+    ToggleNoclip,
+}
+
+#[derive(Debug)]
+pub enum EntityMessage {
+    /// Hit on one of the receiver's colliders
+    Damage {
+        collider: ColliderHandle,
+        amount: f32,
+    },
+    Custom(Box<dyn Any>),
+}
+
+/// Changes entities ask for while the world is updating, applied by the world after every entity has updated
+#[derive(Default)]
+pub struct WorldCommands {
+    spawned: Vec<Box<dyn Entity>>,
+    despawned: Vec<EntityId>,
+    messages: Vec<(EntityId, EntityMessage)>,
+}
+
+impl WorldCommands {
+    pub fn spawn<T: Entity + 'static>(&mut self, entity: T) {
+        self.spawned.push(Box::new(entity));
+    }
+
+    pub fn despawn(&mut self, entity_id: EntityId) {
+        self.despawned.push(entity_id);
+    }
+
+    pub fn send(&mut self, entity_id: EntityId, message: EntityMessage) {
+        self.messages.push((entity_id, message));
+    }
+}
+
+pub struct WorldInfo {
+    pub physics: PhysicsScene,
+    pub rendering: SceneRenderData,
+
+    pub player_camera: PerspectiveCamera,
+    pub docking: DockingRegistry,
+
+    /// Delta time of this update's physics step, 0 while paused
+    pub physics_delta_time: f32,
+    /// Entities pushed here during an update are added to the world once every entity has updated
+    pub spawn_queue: Vec<Box<dyn Entity>>,
+    pub commands: WorldCommands,
+}
+
+/// Lets `World` hand out entities as their concrete type, implemented for every type so entities don't have to
+pub trait AsAny {
+    fn as_any(&self) -> &dyn Any;
+    fn as_any_mut(&mut self) -> &mut dyn Any;
+    /// Full path of the concrete type, for debugging
+    fn type_name(&self) -> &'static str;
+}
+
+impl<T: Any> AsAny for T {
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn as_any_mut(&mut self) -> &mut dyn Any {
+        self
+    }
+
+    fn type_name(&self) -> &'static str {
+        std::any::type_name::<T>()
+    }
+}
+
+impl WorldInfo {
+    pub fn spawn<T: Entity + 'static>(&mut self, entity: T) {
+        self.spawn_queue.push(Box::new(entity));
+    }
+
+    fn spawn_detached(&mut self, pieces: Vec<SpaceCraftEntity>) {
+        for piece in pieces {
+            self.spawn(piece);
+        }
+    }
+}
+
+/// The player entity always gets the real delta time, everything else the scaled one and nothing while paused
+fn entity_delta_time(
+    id: EntityId,
+    player_entity: EntityId,
+    delta_time: f32,
+    scaled_delta_time: f32,
+) -> Option<f32> {
+    if id == player_entity {
+        Some(delta_time)
+    } else if scaled_delta_time > 0.0 {
+        Some(scaled_delta_time)
+    } else {
+        None
+    }
+}
+
+/// What `Entity::simulate` can see of the world, shared between the worker threads
+pub struct WorldReadOnly<'a> {
+    pub physics: &'a PhysicsScene,
+    /// Delta time of this update's physics step, 0 while paused
+    pub physics_delta_time: f32,
+}
+
+/// Parts of `World::update` entities can run in, listed in the order they run
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum UpdateStage {
+    /// Before the physics step, for forces and kinematic moves so the step uses this frame's input
+    PrePhysics,
+    /// After the step, for reading transforms back from the bodies
+    PostPhysics,
+    /// After attached entities were moved to their parents, for anything that needs this frame's final transforms
+    PreRender,
+}
+
+/// Send so entities can be simulated on worker threads
+pub trait Entity: AsAny + Send {
+    fn set_id(&mut self, id: EntityId);
+    fn add_to_world(&mut self, world: &mut WorldInfo);
+    fn remove_from_world(&mut self, world: &mut WorldInfo);
+
+    /// Stages the world calls this entity in
+    fn update_stages(&self) -> &'static [UpdateStage] {
+        &[UpdateStage::PostPhysics]
+    }
+
+    fn pre_physics_update(&mut self, world: &mut WorldInfo, delta_time: f32) {
+        let _ = (world, delta_time);
+    }
+
+    /// First half of the PostPhysics stage, called from worker threads with every other entity simulating at the same time.
+    /// Only for work on the entity itself like reading its body back, changes to the world wait for `update`
+    fn simulate(&mut self, world: &WorldReadOnly, delta_time: f32) {
+        let _ = (world, delta_time);
+    }
+
+    /// Second half of the PostPhysics stage, serially in update order once every entity has simulated
+    fn update(&mut self, world: &mut WorldInfo, delta_time: f32);
+
+    fn pre_render_update(&mut self, world: &mut WorldInfo, delta_time: f32) {
+        let _ = (world, delta_time);
+    }
+
+    fn update_player_input(&mut self, linear_input: Vec3, angular_input: Vec3);
+    fn get_camera_transform(&self) -> Option<Transform>;
+
+    /// Mouse-look angles in radians since the last frame, only sent to the player entity
+    fn apply_mouse_look(&mut self, yaw: f32, pitch: f32) {
+        let _ = (yaw, pitch);
+    }
+
+    /// Body the entity is simulated with, if it has one
+    fn get_rigid_body(&self) -> Option<RigidBodyHandle> {
+        None
+    }
+
+    /// Where the entity is this frame, children parented to entities without one don't move
+    fn get_world_transform(&self) -> Option<Transform> {
+        None
+    }
+
+    /// Called after the world moved its origin by `offset`, bodies have already been moved by the physics scene
+    fn shift_origin(&mut self, offset: Vec3, world: &mut WorldInfo) {
+        let _ = (offset, world);
+    }
+
+    /// Called when the entity gets or loses a parent with `World::set_parent`
+    fn set_attached(&mut self, attached: bool, world: &mut WorldInfo) {
+        let _ = (attached, world);
+    }
+
+    /// Every update while attached, with the transform the parent puts the entity at
+    fn set_world_transform(&mut self, transform: &Transform, world: &mut WorldInfo) {
+        let _ = (transform, world);
+    }
+
+    /// Moves the entity from outside the simulation, like the debug inspector, entities that can't be moved ignore it
+    fn set_transform(&mut self, transform: &Transform, world: &mut WorldInfo) {
+        let _ = (transform, world);
+    }
+
+    /// Discrete player input, only sent to the player entity
+    fn on_player_action(&mut self, action: PlayerAction, world: &mut WorldInfo) {
+        let _ = (action, world);
+    }
+
+    /// Called after the physics step for every contact or sensor intersection that started or stopped
+    fn on_collision(&mut self, other: EntityId, event: CollisionEvent, world: &mut WorldInfo) {
+        let _ = (other, event, world);
+    }
+
+    /// Messages sent with `WorldCommands::send`, delivered after every entity has updated
+    fn on_message(&mut self, message: EntityMessage, world: &mut WorldInfo) {
+        let _ = (message, world);
+    }
+
+    /// Entities that return None aren't written to save files
+    fn save(&self, world: &WorldInfo, renderer: &Renderer) -> Option<EntitySaveData> {
+        let _ = (world, renderer);
+        None
+    }
+}
+
+pub struct DynamicEntity {
+    id: EntityId,
+    transform: Transform,
+    model: Option<(MeshHandle, MaterialHandle)>,
+    collider: Option<ColliderShape>,
+
+    linear_velocity: Vec3,
+    angular_velocity: Vec3,
+    /// Follows a parent instead of the physics body, the body is kinematic while attached
+    attached: bool,
+
+    model_instance: Option<InstanceHandle>,
+    rigid_body_instance: Option<RigidBodyHandle>,
+    collider_instance: Option<ColliderHandle>,
+}
+
+impl DynamicEntity {
+    pub fn new(
+        transform: Transform,
+        model: Option<(MeshHandle, MaterialHandle)>,
+        collider: Option<ColliderShape>,
+    ) -> Self {
+        Self {
+            id: Default::default(),
+            transform,
+            model,
+            collider,
+            linear_velocity: Vec3::ZERO,
+            angular_velocity: Vec3::ZERO,
+            attached: false,
+            model_instance: None,
+            rigid_body_instance: None,
+            collider_instance: None,
+        }
+    }
+
+    /// Initial velocity, only used if the entity has a collider
+    pub fn with_velocity(mut self, linear_velocity: Vec3, angular_velocity: Vec3) -> Self {
+        self.linear_velocity = linear_velocity;
+        self.angular_velocity = angular_velocity;
+        self
+    }
+
+    pub fn model_instance(&self) -> Option<InstanceHandle> {
+        self.model_instance
+    }
+
+    pub fn collider_instance(&self) -> Option<ColliderHandle> {
+        self.collider_instance
+    }
+}
+
+impl Entity for DynamicEntity {
+    fn set_id(&mut self, id: EntityId) {
+        self.id = id;
+    }
+
+    fn add_to_world(&mut self, world: &mut WorldInfo) {
+        if let Some((mesh, material)) = &self.model {
+            self.model_instance =
+                world
+                    .rendering
+                    .create_instance(*mesh, *material, &self.transform);
+        }
+
+        if let Some(shape) = &self.collider {
+            let rigid_body = world.physics.create_rigid_body(
+                self.transform.position,
+                self.transform.rotation,
+                RigidBodyType::Dynamic,
+            );
+            world
+                .physics
+                .set_rigid_body_linear_velocity(rigid_body, self.linear_velocity);
+            world
+                .physics
+                .set_rigid_body_angular_velocity(rigid_body, self.angular_velocity);
+            self.rigid_body_instance = Some(rigid_body);
+            self.collider_instance = world.physics.create_collider(
+                rigid_body,
+                &Transform::default(),
+                shape,
+                ColliderMass::Mass(1.0),
+                false,
+                self.id,
+            );
+        }
+    }
+
+    fn remove_from_world(&mut self, world: &mut WorldInfo) {
+        if let Some(model) = self.model_instance.take() {
+            world.rendering.remove_instance(model);
+        }
+
+        if let Some(collider) = self.collider_instance.take() {
+            world.physics.remove_collider(collider);
+        }
+
+        if let Some(rigid_body) = self.rigid_body_instance.take() {
+            world.physics.remove_rigid_body(rigid_body);
+        }
+    }
+
+    fn simulate(&mut self, world: &WorldReadOnly, _delta_time: f32) {
+        if let Some(rigid_body) = self.rigid_body_instance.filter(|_| !self.attached) {
+            let (position, rotation) = world.physics.get_rigid_body_transform(rigid_body);
+            self.transform.position = position;
+            self.transform.rotation = rotation;
+        }
+    }
+
+    fn update(&mut self, world: &mut WorldInfo, _delta_time: f32) {
+        if let Some(model) = self.model_instance {
+            world.rendering.update_instance(model, &self.transform);
+        }
+    }
+
+    fn update_player_input(&mut self, linear_input: Vec3, angular_input: Vec3) {
+        let _ = linear_input;
+        let _ = angular_input;
+        unimplemented!()
+    }
+    fn get_camera_transform(&self) -> Option<Transform> {
+        unimplemented!()
+    }
+
+    fn get_rigid_body(&self) -> Option<RigidBodyHandle> {
+        self.rigid_body_instance
+    }
+
+    fn get_world_transform(&self) -> Option<Transform> {
+        Some(self.transform.clone())
+    }
+
+    fn shift_origin(&mut self, offset: Vec3, _world: &mut WorldInfo) {
+        self.transform.position += offset;
+    }
+
+    fn set_attached(&mut self, attached: bool, world: &mut WorldInfo) {
+        self.attached = attached;
+        if let Some(rigid_body) = self.rigid_body_instance {
+            world.physics.set_rigid_body_type(
+                rigid_body,
+                if attached {
+                    RigidBodyType::KinematicPositionBased
+                } else {
+                    RigidBodyType::Dynamic
+                },
+            );
+        }
+    }
+
+    fn set_world_transform(&mut self, transform: &Transform, world: &mut WorldInfo) {
+        self.transform = transform.clone();
+        if let Some(rigid_body) = self.rigid_body_instance {
+            world
+                .physics
+                .set_next_kinematic_transform(rigid_body, &self.transform);
+        }
+        if let Some(model) = self.model_instance {
+            world.rendering.update_instance(model, &self.transform);
+        }
+    }
+
+    /// Scale only changes the model, the collider keeps its size
+    fn set_transform(&mut self, transform: &Transform, world: &mut WorldInfo) {
+        self.transform = transform.clone();
+        if let Some(rigid_body) = self.rigid_body_instance {
+            world.physics.set_rigid_body_transform(
+                rigid_body,
+                transform.position,
+                transform.rotation,
+                true,
+            );
+        }
+        if let Some(model) = self.model_instance {
+            world.rendering.update_instance(model, &self.transform);
+        }
+    }
+
+    fn save(&self, world: &WorldInfo, renderer: &Renderer) -> Option<EntitySaveData> {
+        let model = self
+            .model
+            .and_then(|model| model_names(renderer, self.id, model));
+
+        let (linear_velocity, angular_velocity) = match self.rigid_body_instance {
+            Some(rigid_body) => (
+                world.physics.get_rigid_body_linear_velocity(rigid_body),
+                world.physics.get_rigid_body_angular_velocity(rigid_body),
+            ),
+            None => (self.linear_velocity, self.angular_velocity),
+        };
+
+        Some(EntitySaveData::Dynamic {
+            transform: self.transform.clone(),
+            model,
+            collider: self.collider.clone(),
+            linear_velocity,
+            angular_velocity,
+        })
+    }
+}
+
+/// Asset names of a model for save files
+pub(crate) fn model_names(
+    renderer: &Renderer,
+    entity_id: EntityId,
+    (mesh, material): (MeshHandle, MaterialHandle),
+) -> Option<(String, String)> {
+    let names = renderer
+        .assets()
+        .get_mesh_name(mesh)
+        .zip(renderer.assets().get_material_name(material))
+        .map(|(mesh_name, material_name)| (mesh_name.to_string(), material_name.to_string()));
+    if names.is_none() {
+        warn!(
+            "Entity {:?} uses an unnamed mesh or material, saving it without a model",
+            entity_id
+        );
+    }
+    names
+}
+
+fn load_model(
+    renderer: &mut Renderer,
+    (mesh_name, material_name): (String, String),
+) -> Option<(MeshHandle, MaterialHandle)> {
+    let mesh = renderer.get_or_load_mesh(&mesh_name);
+    let material = renderer.assets().get_material(&material_name);
+    if mesh.is_none() || material.is_none() {
+        warn!(
+            "Failed to find mesh {} or material {}, loading entity without a model",
+            mesh_name, material_name
+        );
+    }
+    Some((mesh?, material?))
+}
+
+/// Fixed trigger volume that logs entities entering and leaving it
+pub struct SensorZoneEntity {
+    id: EntityId,
+    name: String,
+    transform: Transform,
+    shape: ColliderShape,
+
+    rigid_body_instance: Option<RigidBodyHandle>,
+    collider_instance: Option<ColliderHandle>,
+}
+
+impl SensorZoneEntity {
+    pub fn new(name: &str, transform: Transform, shape: ColliderShape) -> Self {
+        Self {
+            id: Default::default(),
+            name: name.to_string(),
+            transform,
+            shape,
+            rigid_body_instance: None,
+            collider_instance: None,
+        }
+    }
+}
+
+impl Entity for SensorZoneEntity {
+    fn set_id(&mut self, id: EntityId) {
+        self.id = id;
+    }
+
+    fn add_to_world(&mut self, world: &mut WorldInfo) {
+        let rigid_body = world.physics.create_rigid_body(
+            self.transform.position,
+            self.transform.rotation,
+            RigidBodyType::Fixed,
+        );
+        self.rigid_body_instance = Some(rigid_body);
+        self.collider_instance = world.physics.create_collider(
+            rigid_body,
+            &Transform::default(),
+            &self.shape,
+            ColliderMass::Mass(0.0),
+            true,
+            self.id,
+        );
+    }
+
+    fn remove_from_world(&mut self, world: &mut WorldInfo) {
+        if let Some(collider) = self.collider_instance.take() {
+            world.physics.remove_collider(collider);
+        }
+
+        if let Some(rigid_body) = self.rigid_body_instance.take() {
+            world.physics.remove_rigid_body(rigid_body);
+        }
+    }
+
+    fn update(&mut self, _world: &mut WorldInfo, _delta_time: f32) {}
+
+    fn update_player_input(&mut self, _linear_input: Vec3, _angular_input: Vec3) {}
+
+    fn get_camera_transform(&self) -> Option<Transform> {
+        None
+    }
+
+    fn get_world_transform(&self) -> Option<Transform> {
+        Some(self.transform.clone())
+    }
+
+    fn shift_origin(&mut self, offset: Vec3, _world: &mut WorldInfo) {
+        self.transform.position += offset;
+    }
+
+    fn on_collision(&mut self, other: EntityId, event: CollisionEvent, _world: &mut WorldInfo) {
+        match event {
+            CollisionEvent::Started { sensor: true, .. } => {
+                info!("{:?} entered sensor zone {}", other, self.name)
+            }
+            CollisionEvent::Stopped { sensor: true, .. } => {
+                info!("{:?} left sensor zone {}", other, self.name)
+            }
+            _ => {}
+        }
+    }
+
+    fn save(&self, _world: &WorldInfo, _renderer: &Renderer) -> Option<EntitySaveData> {
+        Some(EntitySaveData::SensorZone {
+            name: self.name.clone(),
+            transform: self.transform.clone(),
+            shape: self.shape.clone(),
+        })
+    }
+}
+
+/// Piece of a static entity, placed relative to the entity's transform
+#[derive(Clone)]
+pub struct StaticPart {
+    pub transform: Transform,
+    pub model: Option<(MeshHandle, MaterialHandle)>,
+    /// Collider scale comes from the shape, the part's scale only applies to the model
+    pub collider: Option<ColliderShape>,
+}
+
+/// Immovable scenery like asteroids and station pieces, all parts share one fixed body
+pub struct StaticEntity {
+    id: EntityId,
+    transform: Transform,
+    parts: Vec<StaticPart>,
+
+    model_instances: Vec<InstanceHandle>,
+    rigid_body_instance: Option<RigidBodyHandle>,
+    collider_instances: Vec<ColliderHandle>,
+}
+
+impl StaticEntity {
+    pub fn new(transform: Transform, parts: Vec<StaticPart>) -> Self {
+        Self {
+            id: Default::default(),
+            transform,
+            parts,
+            model_instances: Vec::new(),
+            rigid_body_instance: None,
+            collider_instances: Vec::new(),
+        }
+    }
+
+    /// Render instances of the parts with a model, empty until it's added to a world
+    pub fn model_instances(&self) -> &[InstanceHandle] {
+        &self.model_instances
+    }
+}
+
+impl Entity for StaticEntity {
+    fn set_id(&mut self, id: EntityId) {
+        self.id = id;
+    }
+
+    fn add_to_world(&mut self, world: &mut WorldInfo) {
+        let rigid_body = world.physics.create_rigid_body(
+            self.transform.position,
+            self.transform.rotation,
+            RigidBodyType::Fixed,
+        );
+        self.rigid_body_instance = Some(rigid_body);
+
+        for part in self.parts.iter() {
+            if let Some((mesh, material)) = &part.model {
+                self.model_instances.extend(world.rendering.create_instance(
+                    *mesh,
+                    *material,
+                    &self.transform.transform_by(&part.transform),
+                ));
+            }
+
+            if let Some(shape) = &part.collider {
+                self.collider_instances
+                    .extend(world.physics.create_collider(
+                        rigid_body,
+                        &part.transform,
+                        shape,
+                        ColliderMass::Mass(0.0),
+                        false,
+                        self.id,
+                    ));
+            }
+        }
+    }
+
+    fn remove_from_world(&mut self, world: &mut WorldInfo) {
+        for model in self.model_instances.drain(..) {
+            world.rendering.remove_instance(model);
+        }
+
+        for collider in self.collider_instances.drain(..) {
+            world.physics.remove_collider(collider);
+        }
+
+        if let Some(rigid_body) = self.rigid_body_instance.take() {
+            world.physics.remove_rigid_body(rigid_body);
+        }
+    }
+
+    fn update(&mut self, _world: &mut WorldInfo, _delta_time: f32) {}
+
+    fn update_player_input(&mut self, _linear_input: Vec3, _angular_input: Vec3) {}
+
+    fn get_camera_transform(&self) -> Option<Transform> {
+        None
+    }
+
+    fn get_rigid_body(&self) -> Option<RigidBodyHandle> {
+        self.rigid_body_instance
+    }
+
+    fn get_world_transform(&self) -> Option<Transform> {
+        Some(self.transform.clone())
+    }
+
+    fn shift_origin(&mut self, offset: Vec3, world: &mut WorldInfo) {
+        self.transform.position += offset;
+        let model_parts = self.parts.iter().filter(|part| part.model.is_some());
+        for (model, part) in self.model_instances.iter().zip(model_parts) {
+            world
+                .rendering
+                .update_instance(*model, &self.transform.transform_by(&part.transform));
+        }
+    }
+
+    fn save(&self, _world: &WorldInfo, renderer: &Renderer) -> Option<EntitySaveData> {
+        Some(EntitySaveData::Static {
+            transform: self.transform.clone(),
+            parts: self
+                .parts
+                .iter()
+                .map(|part| StaticPartSaveData {
+                    transform: part.transform.clone(),
+                    model: part
+                        .model
+                        .and_then(|model| model_names(renderer, self.id, model)),
+                    collider: part.collider.clone(),
+                })
+                .collect(),
+        })
+    }
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum PlacementError {
+    #[error("module {0:?} doesn't exist")]
+    UnknownModule(String),
+    #[error("grid cell {0} is already occupied")]
+    Occupied(IVec3),
+    #[error("grid cell {0} is empty")]
+    Empty(IVec3),
+    #[error(
+        "module at {position} doesn't have matching connectors with its neighbor at {neighbor}"
+    )]
+    IncompatibleNeighbor { position: IVec3, neighbor: IVec3 },
+    #[error("module at {0} isn't connected to the craft")]
+    NotConnected(IVec3),
+    #[error("removing the module at {position} would disconnect {orphaned:?}")]
+    WouldSplit {
+        position: IVec3,
+        orphaned: Vec<IVec3>,
+    },
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum AttachmentError {
+    #[error("attachment {0:?} doesn't exist")]
+    UnknownAttachment(String),
+    #[error("grid cell {0} is empty")]
+    Empty(IVec3),
+    #[error("module at {position} has no hard point {index}")]
+    UnknownHardPoint { position: IVec3, index: usize },
+    #[error("hard point {index} of the module at {position} already holds {attachment:?}")]
+    Occupied {
+        position: IVec3,
+        index: usize,
+        attachment: String,
+    },
+    #[error("attachment {attachment:?} is size {size} but the hard point is only size {hard_point_size}")]
+    TooLarge {
+        attachment: String,
+        size: u16,
+        hard_point_size: u16,
+    },
+    #[error("hard point {index} of the module at {position} is empty")]
+    NotMounted { position: IVec3, index: usize },
+}
+
+struct MountedAttachment {
+    name: String,
+    mass: f32,
+    model: Option<(MeshHandle, MaterialHandle)>,
+    model_offset: Transform,
+    model_instance: Option<InstanceHandle>,
+}
+
+struct SpaceCraftDoor {
+    /// Craft grid cell and the face of it the door is on
+    cell: IVec3,
+    direction: GridDirection,
+    /// Relative to the node
+    offset: Transform,
+    blocker: Option<ColliderShape>,
+    blocker_instance: Option<ColliderHandle>,
+}
+
+/// Inside of a module, only put into the world for crafts with their interior enabled
+struct SpaceCraftInterior {
+    model: Option<(MeshHandle, MaterialHandle)>,
+    model_offset: Transform,
+    /// Relative to the node
+    colliders: Vec<(Transform, ColliderShape)>,
+    doors: Vec<SpaceCraftDoor>,
+
+    model_instance: Option<InstanceHandle>,
+    collider_instances: Vec<ColliderHandle>,
+}
+
+impl SpaceCraftInterior {
+    fn new(
+        grid_position: IVec3,
+        module_name: &str,
+        interior: &InteriorDefinition,
+        renderer: &mut Renderer,
+    ) -> Self {
+        let create_shape = |collider: &ModuleCollider| match collider.create_shape() {
+            Ok(shape) => Some(shape),
+            Err(e) => {
+                warn!(
+                    "Failed to create interior collider {:?} for module {}: {}",
+                    collider.collider_type, module_name, e
+                );
+                None
+            }
+        };
+
+        let (model, model_offset) = match &interior.model {
+            Some(model) => (model.resolve(renderer), model.offset.to_transform()),
+            None => (None, Transform::default()),
+        };
+        Self {
+            model,
+            model_offset,
+            colliders: interior
+                .colliders
+                .iter()
+                .filter_map(|collider| {
+                    create_shape(collider).map(|shape| (collider.offset.to_transform(), shape))
+                })
+                .collect(),
+            doors: interior
+                .doors
+                .iter()
+                .map(|door| SpaceCraftDoor {
+                    cell: grid_position + door.offset,
+                    direction: door.direction,
+                    offset: door.blocker.offset.to_transform(),
+                    blocker: create_shape(&door.blocker),
+                    blocker_instance: None,
+                })
+                .collect(),
+            model_instance: None,
+            collider_instances: Vec::new(),
+        }
+    }
+
+    /// Creates the model and walls if they don't exist yet, then adds or removes door blockers so only the doors in `open_doors` are open
+    fn add_to_world(
+        &mut self,
+        world: &mut WorldInfo,
+        rigid_body: RigidBodyHandle,
+        node_transform: &Transform,
+        craft_transform: &Transform,
+        entity_id: EntityId,
+        open_doors: &HashSet<(IVec3, GridDirection)>,
+    ) {
+        //Interior colliders are massless so they don't change how the craft flies
+        let create_collider = |world: &mut WorldInfo, offset: &Transform, shape: &ColliderShape| {
+            let transform = node_transform.transform_by(offset);
+            let collider = world.physics.create_collider(
+                rigid_body,
+                &transform,
+                shape,
+                ColliderMass::Mass(0.0),
+                false,
+                entity_id,
+            )?;
+            world
+                .physics
+                .set_collider_layer(collider, CollisionLayer::Interior);
+            Some(collider)
+        };
+
+        if self.collider_instances.is_empty() {
+            self.collider_instances = self
+                .colliders
+                .iter()
+                .filter_map(|(offset, shape)| create_collider(world, offset, shape))
+                .collect();
+        }
+
+        for door in self.doors.iter_mut() {
+            let open = open_doors.contains(&(door.cell, door.direction));
+            match (open, door.blocker_instance) {
+                (true, Some(blocker)) => {
+                    world.physics.remove_collider(blocker);
+                    door.blocker_instance = None;
+                }
+                (false, None) => {
+                    door.blocker_instance = door
+                        .blocker
+                        .as_ref()
+                        .and_then(|shape| create_collider(world, &door.offset, shape));
+                }
+                _ => {}
+            }
+        }
+
+        if let (Some((mesh, material)), None) = (&self.model, self.model_instance) {
+            self.model_instance = world.rendering.create_instance(
+                *mesh,
+                *material,
+                &self.model_transform(node_transform, craft_transform),
+            );
+        }
+    }
+
+    fn remove_from_world(&mut self, world: &mut WorldInfo) {
+        if let Some(model) = self.model_instance.take() {
+            world.rendering.remove_instance(model);
+        }
+        for collider in self.collider_instances.drain(..) {
+            world.physics.remove_collider(collider);
+        }
+        for door in self.doors.iter_mut() {
+            if let Some(blocker) = door.blocker_instance.take() {
+                world.physics.remove_collider(blocker);
+            }
+        }
+    }
+
+    fn colliders(&self) -> impl Iterator<Item = ColliderHandle> + '_ {
+        self.collider_instances
+            .iter()
+            .copied()
+            .chain(self.doors.iter().filter_map(|door| door.blocker_instance))
+    }
+
+    fn model_transform(
+        &self,
+        node_transform: &Transform,
+        craft_transform: &Transform,
+    ) -> Transform {
+        craft_transform
+            .transform_by(node_transform)
+            .transform_by(&self.model_offset)
+    }
+}
+
+struct SpaceCraftHardPoint {
+    size: u16,
+    /// Relative to the craft
+    transform: Transform,
+    attachment: Option<MountedAttachment>,
+}
+
+impl SpaceCraftHardPoint {
+    fn attachment_transform(&self, craft_transform: &Transform) -> Option<Transform> {
+        self.attachment.as_ref().map(|attachment| {
+            craft_transform
+                .transform_by(&self.transform)
+                .transform_by(&attachment.model_offset)
+        })
+    }
+
+    fn add_instance(&mut self, world: &mut WorldInfo, craft_transform: &Transform) {
+        let Some(transform) = self.attachment_transform(craft_transform) else {
+            return;
+        };
+        if let Some(attachment) = &mut self.attachment {
+            if let (Some((mesh, material)), None) = (attachment.model, attachment.model_instance) {
+                attachment.model_instance =
+                    world.rendering.create_instance(mesh, material, &transform);
+            }
+        }
+    }
+
+    fn remove_instance(&mut self, world: &mut WorldInfo) {
+        if let Some(model) = self
+            .attachment
+            .as_mut()
+            .and_then(|attachment| attachment.model_instance.take())
+        {
+            world.rendering.remove_instance(model);
+        }
+    }
+}
+
+pub struct SpaceCraftNode {
+    local_transform: Transform,
+    /// Offset of the model from the node
+    model_offset: Transform,
+    mass: f32,
+
+    model: Option<(MeshHandle, MaterialHandle)>,
+    collider: Option<ColliderShape>,
+
+    thrusters: Vec<ModuleThruster>,
+    tanks: Vec<SpaceCraftTank>,
+    /// Connectors in craft grid space
+    connectors: Vec<(IVec3, GridDirection)>,
+    docking_ports: Vec<(IVec3, GridDirection)>,
+    hard_points: Vec<SpaceCraftHardPoint>,
+
+    /// None if the module has no local health and all damage goes to the craft's structure
+    health: Option<f32>,
+    max_health: Option<f32>,
+    damage_multiplier: f32,
+
+    interior: Option<SpaceCraftInterior>,
+    /// Pilot camera in craft space, only set for cockpit modules
+    cockpit_camera: Option<Transform>,
+
+    model_instance: Option<InstanceHandle>,
+    collider_instance: Option<ColliderHandle>,
+}
+
+impl SpaceCraftNode {
+    fn new(grid_position: IVec3, module: &ModuleDefinition, renderer: &mut Renderer) -> Self {
+        let (model, model_offset) = match &module.exterior_model {
+            Some(model) => (model.resolve(renderer), model.offset.to_transform()),
+            None => (None, Transform::default()),
+        };
+
+        let local_transform = Transform::new_pos(grid_position.as_vec3() * MODULE_SIZE);
+        Self {
+            hard_points: module
+                .hard_points
+                .iter()
+                .map(|hard_point| SpaceCraftHardPoint {
+                    size: hard_point.size,
+                    transform: local_transform.transform_by(&hard_point.offset.to_transform()),
+                    attachment: None,
+                })
+                .collect(),
+            cockpit_camera: module
+                .cockpit
+                .as_ref()
+                .map(|cockpit| local_transform.transform_by(&cockpit.camera.to_transform())),
+            local_transform,
+            model_offset,
+            mass: module.base_mass,
+            model,
+            collider: Some(module.create_collider_shape()),
+            thrusters: module.thrusters.clone(),
+            connectors: placed_connectors(module, grid_position).collect(),
+            docking_ports: placed_docking_ports(module, grid_position).collect(),
+            health: module.local_max_health,
+            max_health: module.local_max_health,
+            damage_multiplier: module.damage_multiplier,
+            tanks: module
+                .tanks
+                .iter()
+                .map(|tank| SpaceCraftTank {
+                    position: grid_position.as_vec3() * MODULE_SIZE + tank.offset,
+                    capacity: tank.capacity,
+                    state: TankState {
+                        resource: tank.resource,
+                        amount_m3: tank.capacity,
+                    },
+                })
+                .collect(),
+            interior: module.interior.as_ref().map(|interior| {
+                SpaceCraftInterior::new(grid_position, &module.name, interior, renderer)
+            }),
+            model_instance: None,
+            collider_instance: None,
+        }
+    }
+
+    fn add_to_world(
+        &mut self,
+        world: &mut WorldInfo,
+        rigid_body: RigidBodyHandle,
+        craft_transform: &Transform,
+        entity_id: EntityId,
+    ) {
+        if let (Some((mesh, material)), None) = (&self.model, self.model_instance) {
+            self.model_instance = world.rendering.create_instance(
+                *mesh,
+                *material,
+                &self.model_transform(craft_transform),
+            );
+        }
+        for hard_point in self.hard_points.iter_mut() {
+            hard_point.add_instance(world, craft_transform);
+        }
+
+        if self.collider_instance.is_some() {
+            //Migrated from another craft, the colliders already live on the right body
+            for collider in self.colliders().collect::<Vec<_>>() {
+                world.physics.set_collider_entity(collider, entity_id);
+            }
+        } else if let Some(shape) = &self.collider {
+            self.collider_instance = world.physics.create_collider(
+                rigid_body,
+                &self.local_transform,
+                shape,
+                ColliderMass::Mass(self.mass),
+                false,
+                entity_id,
+            );
+            if let Some(collider) = self.collider_instance {
+                world
+                    .physics
+                    .enable_impact_events(collider, IMPACT_FORCE_THRESHOLD);
+            }
+        }
+    }
+
+    fn remove_from_world(&mut self, world: &mut WorldInfo) {
+        if let Some(model) = self.model_instance.take() {
+            world.rendering.remove_instance(model);
+        }
+        for hard_point in self.hard_points.iter_mut() {
+            hard_point.remove_instance(world);
+        }
+
+        if let Some(collider) = self.collider_instance.take() {
+            world.physics.remove_collider(collider);
+        }
+        if let Some(interior) = &mut self.interior {
+            interior.remove_from_world(world);
+        }
+    }
+
+    /// The exterior collider followed by any interior ones
+    fn colliders(&self) -> impl Iterator<Item = ColliderHandle> + '_ {
+        self.collider_instance.into_iter().chain(
+            self.interior
+                .iter()
+                .flat_map(|interior| interior.colliders()),
+        )
+    }
+
+    fn model_transform(&self, craft_transform: &Transform) -> Transform {
+        craft_transform
+            .transform_by(&self.local_transform)
+            .transform_by(&self.model_offset)
+    }
+
+    fn update_instances(&self, world: &mut WorldInfo, craft_transform: &Transform) {
+        if let Some(model) = self.model_instance {
+            world
+                .rendering
+                .update_instance(model, &self.model_transform(craft_transform));
+        }
+        for hard_point in self.hard_points.iter() {
+            if let (Some(model), Some(transform)) = (
+                hard_point
+                    .attachment
+                    .as_ref()
+                    .and_then(|attachment| attachment.model_instance),
+                hard_point.attachment_transform(craft_transform),
+            ) {
+                world.rendering.update_instance(model, &transform);
+            }
+        }
+        if let Some(interior) = &self.interior {
+            if let Some(model) = interior.model_instance {
+                world.rendering.update_instance(
+                    model,
+                    &interior.model_transform(&self.local_transform, craft_transform),
+                );
+            }
+        }
+    }
+}
+
+/// Contacts pushing softer than this don't cause damage, keeps resting contacts from wearing crafts down
+const IMPACT_FORCE_THRESHOLD: f32 = 10_000.0;
+/// Damage per Newton second of impact impulse
+const DEFAULT_IMPACT_DAMAGE_FACTOR: f32 = 0.002;
+/// Structure health the craft gets for every Kg of module mass
+const STRUCTURE_HEALTH_PER_KG: f32 = 0.1;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum DamageTarget {
+    Module(IVec3),
+    Collider(ColliderHandle),
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct ModuleHealth {
+    pub health: f32,
+    pub max_health: f32,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct TankState {
+    pub resource: ResourceType,
+    pub amount_m3: f32,
+}
+
+impl TankState {
+    pub fn mass(&self) -> f32 {
+        self.amount_m3 * self.resource.density()
+    }
+}
+
+/// Runtime tank of an assembled craft, position is relative to the craft
+#[derive(Clone, Debug)]
+pub struct SpaceCraftTank {
+    pub position: Vec3,
+    pub capacity: f32,
+    pub state: TankState,
+}
+
+/// A tank is addressed by the grid cell of its module and its index in the module's tank list
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct TankId {
+    pub grid_position: IVec3,
+    pub index: usize,
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum TankError {
+    #[error("tank {0:?} doesn't exist")]
+    UnknownTank(TankId),
+    #[error("can't move {from:?} into a tank holding {to:?}")]
+    ResourceMismatch {
+        from: ResourceType,
+        to: ResourceType,
+    },
+}
+
+/// A thruster collected from the craft's modules, position and direction are relative to the craft
+#[derive(Clone, Debug)]
+pub struct SpaceCraftThruster {
+    pub grid_position: IVec3,
+    pub position: Vec3,
+    pub direction: Vec3,
+    pub max_thrust: f32,
+    pub propellant: ResourceType,
+    pub isp: Option<f32>,
+    /// 0 to 1, resolved from the throttle command every update
+    pub throttle: f32,
+}
+
+const STANDARD_GRAVITY: f32 = 9.80665;
+
+/// Faint wake behind every craft, broken when the craft is moved instead of flown
+fn craft_trail_settings() -> TrailSettings {
+    TrailSettings {
+        max_points: 64,
+        segment_length: 2.0,
+        lifetime: 3.0,
+        width: 1.0,
+        color: [0.5, 0.7, 1.0, 0.25],
+        break_distance: 500.0,
+        fade_time: 1.0,
+    }
+}
+
+/// Exhaust of one thruster at full throttle, the spawn rate is scaled by the throttle
+fn thruster_plume_settings() -> EmitterSettings {
+    EmitterSettings {
+        spawn_rate: 120.0,
+        lifetime: (0.25, 0.5),
+        speed: (15.0, 25.0),
+        cone_angle: 0.15,
+        size: ParticleCurve::new(vec![(0.0, 0.4), (1.0, 1.5)]),
+        color: ParticleCurve::new(vec![
+            (0.0, Vec4::new(1.5, 1.8, 3.0, 0.8)),
+            (0.3, Vec4::new(1.5, 0.8, 0.3, 0.5)),
+            (1.0, Vec4::new(0.6, 0.2, 0.05, 0.0)),
+        ]),
+        max_particles: 128,
+    }
+}
+
+impl SpaceCraftThruster {
+    /// Volume of propellant burned over `delta_time` at the current throttle
+    fn propellant_use(&self, delta_time: f32) -> Option<f32> {
+        let isp = self.isp?;
+        let mass_flow = self.max_thrust * self.throttle / (isp * STANDARD_GRAVITY);
+        Some(mass_flow * delta_time / self.propellant.density())
+    }
+}
+
+#[derive(Clone, Debug)]
+pub enum ThrottleCommand {
+    /// Linear and angular (torque axis) demand in craft space, each thruster fires for the part it can help with
+    Axis { linear: Vec3, angular: Vec3 },
+    /// One throttle per thruster, in the order of `SpaceCraftEntity::thrusters`
+    PerThruster(Vec<f32>),
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum FlightMode {
+    /// Player input drives the thrusters directly
+    Direct,
+    /// Player input sets target velocities, thrust is solved to hold them
+    Assist,
+    /// Linear input is direct, only rotation is assisted
+    DampenersOff,
+}
+
+/// Automatic flying on top of flight assist, axes the mode doesn't control still follow the pilot's input
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Autopilot {
+    Off,
+    /// Brings the angular velocity to zero
+    KillRotation,
+    /// Points the nose along the velocity
+    Prograde,
+    Retrograde,
+    FaceTarget(EntityId),
+    /// Faces the target and closes in until `standoff_distance` away, then matches its velocity
+    Approach {
+        target: EntityId,
+        standoff_distance: f32,
+    },
+}
+
+impl Autopilot {
+    pub fn target(&self) -> Option<EntityId> {
+        match self {
+            Autopilot::FaceTarget(target) | Autopilot::Approach { target, .. } => Some(*target),
+            _ => None,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Autopilot::Off => "Off",
+            Autopilot::KillRotation => "Kill rotation",
+            Autopilot::Prograde => "Prograde",
+            Autopilot::Retrograde => "Retrograde",
+            Autopilot::FaceTarget(_) => "Face target",
+            Autopilot::Approach { .. } => "Approach",
+        }
+    }
+}
+
+/// Where an autopilot's target was at the start of the update
+#[derive(Clone, Copy, Debug)]
+pub struct AutopilotTarget {
+    pub position: Vec3,
+    pub velocity: Vec3,
+}
+
+//Autopilot tuning, the turn and approach gains are in 1/s like the assist gains
+const AUTOPILOT_TURN_GAIN: f32 = 2.0;
+const AUTOPILOT_APPROACH_GAIN: f32 = 0.5;
+/// Fraction of the thrusters' braking the autopilot plans with, the rest covers the assist's tracking lag
+const AUTOPILOT_BRAKING_MARGIN: f32 = 0.7;
+/// Below this speed prograde and retrograde only kill rotation, the direction is mostly noise
+const AUTOPILOT_MIN_SPEED: f32 = 0.1;
+
+//Flight assist PD gains, the proportional gains are in 1/s so errors decay in a few seconds
+const ASSIST_LINEAR_GAIN: f32 = 2.0;
+const ASSIST_ANGULAR_GAIN: f32 = 4.0;
+const ASSIST_DERIVATIVE_GAIN: f32 = 0.05;
+const ASSIST_MAX_SPEED: f32 = 50.0;
+const ASSIST_MAX_ANGULAR_SPEED: f32 = 1.0;
+
+/// Sum of the force and torque all thrusters can produce along each positive and negative craft axis
+#[derive(Default)]
+struct ThrustCapacity {
+    force_positive: Vec3,
+    force_negative: Vec3,
+    torque_positive: Vec3,
+    torque_negative: Vec3,
+}
+
+impl ThrustCapacity {
+    fn new(thrusters: &[SpaceCraftThruster], center_of_mass: Vec3) -> Self {
+        let mut capacity = Self::default();
+        for thruster in thrusters {
+            let force = thruster.direction * thruster.max_thrust;
+            let torque = (thruster.position - center_of_mass).cross(force);
+            capacity.force_positive += force.max(Vec3::ZERO);
+            capacity.force_negative -= force.min(Vec3::ZERO);
+            capacity.torque_positive += torque.max(Vec3::ZERO);
+            capacity.torque_negative -= torque.min(Vec3::ZERO);
+        }
+        capacity
+    }
+
+    /// Largest force or torque along `direction` when every axis is limited on its own, like `demand` does
+    fn along(direction: Vec3, positive: Vec3, negative: Vec3) -> f32 {
+        (0..3)
+            .filter(|axis| direction[*axis].abs() > f32::EPSILON)
+            .map(|axis| {
+                let capacity = if direction[axis] > 0.0 {
+                    positive[axis]
+                } else {
+                    negative[axis]
+                };
+                capacity / direction[axis].abs()
+            })
+            .fold(f32::INFINITY, f32::min)
+    }
+
+    /// Fraction of the capacity needed on each axis, clamped to -1..1
+    fn demand(required: Vec3, positive: Vec3, negative: Vec3) -> Vec3 {
+        let axis = |required: f32, positive: f32, negative: f32| {
+            let capacity = if required >= 0.0 { positive } else { negative };
+            if capacity > 0.0 {
+                (required / capacity).clamp(-1.0, 1.0)
+            } else {
+                0.0
+            }
+        };
+        Vec3::new(
+            axis(required.x, positive.x, negative.x),
+            axis(required.y, positive.y, negative.y),
+            axis(required.z, positive.z, negative.z),
+        )
+    }
+}
+
+/// Turn rate in craft space that swings the nose (+z) onto `direction`, slowing in time to stop on it
+fn face_direction(direction: Vec3, capacity: &ThrustCapacity, inertia: Vec3) -> Vec3 {
+    let Some(direction) = direction.try_normalize() else {
+        return Vec3::ZERO;
+    };
+    let angle = Vec3::Z.angle_between(direction);
+    if angle < f32::EPSILON {
+        return Vec3::ZERO;
+    }
+    //Straight behind any axis works, pitching is as good as yawing
+    let axis = Vec3::Z.cross(direction).try_normalize().unwrap_or(Vec3::X);
+
+    let braking = ThrustCapacity::along(-axis, capacity.torque_positive, capacity.torque_negative)
+        / (inertia * axis * axis).dot(Vec3::ONE).max(f32::EPSILON)
+        * AUTOPILOT_BRAKING_MARGIN;
+    let rate = (angle * AUTOPILOT_TURN_GAIN)
+        .min((2.0 * braking * angle).sqrt())
+        .min(ASSIST_MAX_ANGULAR_SPEED);
+    axis * rate
+}
+
+/// A thruster fires for every axis where it pushes or turns the craft the way the demand asks
+fn thruster_throttle(
+    thruster: &SpaceCraftThruster,
+    center_of_mass: Vec3,
+    linear: Vec3,
+    angular: Vec3,
+) -> f32 {
+    let torque = (thruster.position - center_of_mass).cross(thruster.direction);
+    let mut throttle = 0.0;
+    for axis in 0..3 {
+        if thruster.direction[axis] * linear[axis] > 0.0 {
+            throttle += (thruster.direction[axis] * linear[axis]).abs();
+        }
+        if torque[axis] * angular[axis] > f32::EPSILON {
+            throttle += angular[axis].abs();
+        }
+    }
+    throttle
+}
+
+impl Default for ThrottleCommand {
+    fn default() -> Self {
+        ThrottleCommand::Axis {
+            linear: Vec3::ZERO,
+            angular: Vec3::ZERO,
+        }
+    }
+}
+
+pub struct SpaceCraftEntity {
+    id: EntityId,
+    transform: Transform,
+    name: String,
+    categories: Vec<String>,
+
+    rigid_body_instance: Option<RigidBodyHandle>,
+
+    /// Module name in each occupied grid cell
+    modules: HashMap<IVec3, String>,
+    nodes: HashMap<IVec3, SpaceCraftNode>,
+
+    thrusters: Vec<SpaceCraftThruster>,
+    /// Exhaust emitter of each thruster, in the same order
+    plumes: Vec<EmitterHandle>,
+    trail: Option<TrailHandle>,
+    throttle: ThrottleCommand,
+    flight_mode: FlightMode,
+    autopilot: Autopilot,
+    /// Set by the world before every update while the autopilot has a target
+    autopilot_target: Option<AutopilotTarget>,
+    /// Last linear and angular velocity errors for the flight assist derivative term
+    assist_errors: (Vec3, Vec3),
+    /// Set when tank contents or attachments change, the body's mass is updated on the next update
+    point_masses_dirty: bool,
+
+    /// Remaining fraction of the structure health shared by the whole craft
+    structure_fraction: f32,
+    impact_damage_factor: f32,
+    /// Only used to log when docking becomes available
+    docking_available: bool,
+    /// Builds the interior colliders of the modules so players can walk inside
+    interior_enabled: bool,
+}
+
+impl SpaceCraftEntity {
+    /// One node per module in the definition, modules missing from the table are skipped.
+    /// Each module collider carries the module's base mass so the body's center of mass follows the grid
+    pub fn new(
+        transform: Transform,
+        definition: &SpaceCraftDefinition,
+        module_table: &ModuleTable,
+        attachment_table: &HashMap<String, AttachmentDefinition>,
+        renderer: &mut Renderer,
+    ) -> Self {
+        let mut modules = HashMap::new();
+        let mut nodes = HashMap::new();
+        for (grid_position, module_name) in definition.modules.iter() {
+            let Some(module) = module_table.get(module_name) else {
+                warn!(
+                    "Spacecraft {} uses unknown module {}",
+                    definition.name, module_name
+                );
+                continue;
+            };
+
+            modules.insert(*grid_position, module_name.clone());
+            nodes.insert(
+                *grid_position,
+                SpaceCraftNode::new(*grid_position, module, renderer),
+            );
+        }
+
+        let mut craft = Self::from_parts(transform, None, modules, nodes);
+        craft.name = definition.name.clone();
+        craft.categories = definition.categories.clone();
+        for state in definition.module_states.iter() {
+            let Some(node) = craft.nodes.get_mut(&state.grid_position) else {
+                warn!(
+                    "Spacecraft {} has module state for empty cell {}",
+                    definition.name, state.grid_position
+                );
+                continue;
+            };
+            for (tank, amount) in node.tanks.iter_mut().zip(state.tank_amounts.iter()) {
+                tank.state.amount_m3 = amount.clamp(0.0, tank.capacity);
+            }
+        }
+        for mount in definition.attachments.iter() {
+            if let Err(e) = craft.mount_attachment(
+                mount.grid_position,
+                mount.hard_point,
+                &mount.attachment,
+                attachment_table,
+                renderer,
+                None,
+            ) {
+                warn!("Spacecraft {}: {}", definition.name, e);
+            }
+        }
+        craft
+    }
+
+    /// Has to be set before the craft is added to a world
+    pub fn with_interior(mut self, enabled: bool) -> Self {
+        self.interior_enabled = enabled;
+        self
+    }
+
+    /// Builds missing interior colliders and opens the doors facing a matching door on the other side of
+    /// a connector, so connected interiors form one space
+    fn update_interior(&mut self, world: &mut WorldInfo) {
+        let Some(rigid_body) = self.rigid_body_instance else {
+            return;
+        };
+        if !self.interior_enabled {
+            return;
+        }
+
+        let doors: HashSet<(IVec3, GridDirection)> = self
+            .nodes
+            .values()
+            .flat_map(|node| node.interior.iter())
+            .flat_map(|interior| {
+                interior
+                    .doors
+                    .iter()
+                    .map(|door| (door.cell, door.direction))
+            })
+            .collect();
+        let connectors: HashSet<(IVec3, GridDirection)> = self
+            .nodes
+            .values()
+            .flat_map(|node| node.connectors.iter().copied())
+            .collect();
+        let open_doors: HashSet<(IVec3, GridDirection)> = doors
+            .iter()
+            .copied()
+            .filter(|(cell, direction)| {
+                let facing = (*cell + direction.as_ivec3(), direction.opposite());
+                doors.contains(&facing)
+                    && connectors.contains(&(*cell, *direction))
+                    && connectors.contains(&facing)
+            })
+            .collect();
+
+        for node in self.nodes.values_mut() {
+            if let Some(interior) = &mut node.interior {
+                interior.add_to_world(
+                    world,
+                    rigid_body,
+                    &node.local_transform,
+                    &self.transform,
+                    self.id,
+                    &open_doors,
+                );
+            }
+        }
+    }
+
+    /// Render instances are only created when `world` is given, crafts that aren't in a world yet create them when added
+    pub fn mount_attachment(
+        &mut self,
+        grid_position: IVec3,
+        hard_point_index: usize,
+        attachment_name: &str,
+        attachment_table: &HashMap<String, AttachmentDefinition>,
+        renderer: &mut Renderer,
+        world: Option<&mut WorldInfo>,
+    ) -> Result<(), AttachmentError> {
+        let attachment = attachment_table
+            .get(attachment_name)
+            .ok_or_else(|| AttachmentError::UnknownAttachment(attachment_name.to_string()))?;
+        let node = self
+            .nodes
+            .get_mut(&grid_position)
+            .ok_or(AttachmentError::Empty(grid_position))?;
+        let hard_point = node.hard_points.get_mut(hard_point_index).ok_or(
+            AttachmentError::UnknownHardPoint {
+                position: grid_position,
+                index: hard_point_index,
+            },
+        )?;
+
+        if let Some(mounted) = &hard_point.attachment {
+            return Err(AttachmentError::Occupied {
+                position: grid_position,
+                index: hard_point_index,
+                attachment: mounted.name.clone(),
+            });
+        }
+        if attachment.size > hard_point.size {
+            return Err(AttachmentError::TooLarge {
+                attachment: attachment_name.to_string(),
+                size: attachment.size,
+                hard_point_size: hard_point.size,
+            });
+        }
+
+        let (model, model_offset) = match &attachment.model {
+            Some(model) => (model.resolve(renderer), model.offset.to_transform()),
+            None => (None, Transform::default()),
+        };
+        hard_point.attachment = Some(MountedAttachment {
+            name: attachment.name.clone(),
+            mass: attachment.mass,
+            model,
+            model_offset,
+            model_instance: None,
+        });
+
+        if let Some(world) = world {
+            if self.rigid_body_instance.is_some() {
+                hard_point.add_instance(world, &self.transform);
+            }
+        }
+        self.point_masses_dirty = true;
+        Ok(())
+    }
+
+    /// Returns the name of the attachment that was removed
+    pub fn unmount_attachment(
+        &mut self,
+        grid_position: IVec3,
+        hard_point_index: usize,
+        world: &mut WorldInfo,
+    ) -> Result<String, AttachmentError> {
+        let hard_point = self
+            .nodes
+            .get_mut(&grid_position)
+            .ok_or(AttachmentError::Empty(grid_position))?
+            .hard_points
+            .get_mut(hard_point_index)
+            .ok_or(AttachmentError::UnknownHardPoint {
+                position: grid_position,
+                index: hard_point_index,
+            })?;
+
+        hard_point.remove_instance(world);
+        let attachment = hard_point
+            .attachment
+            .take()
+            .ok_or(AttachmentError::NotMounted {
+                position: grid_position,
+                index: hard_point_index,
+            })?;
+        self.point_masses_dirty = true;
+        Ok(attachment.name)
+    }
+
+    /// Render instances of every module model
+    pub fn model_instances(&self) -> Vec<InstanceHandle> {
+        self.nodes
+            .values()
+            .filter_map(|node| node.model_instance)
+            .collect()
+    }
+
+    /// Pilot camera in craft space, the cockpit closest to the grid origin if there are several
+    pub fn cockpit_camera(&self) -> Option<&Transform> {
+        self.nodes
+            .iter()
+            .filter_map(|(grid_position, node)| {
+                node.cockpit_camera
+                    .as_ref()
+                    .map(|camera| (grid_position, camera))
+            })
+            .min_by_key(|(grid_position, _)| {
+                let distance = grid_position.abs();
+                (
+                    distance.x + distance.y + distance.z,
+                    grid_position.to_array(),
+                )
+            })
+            .map(|(_, camera)| camera)
+    }
+
+    /// True if the collider belongs to one of the craft's cockpit modules
+    pub fn is_cockpit_collider(&self, collider: ColliderHandle) -> bool {
+        self.nodes
+            .values()
+            .any(|node| node.cockpit_camera.is_some() && node.collider_instance == Some(collider))
+    }
+
+    /// The craft as it is now, modules whose tanks aren't full get a module state
+    pub fn to_definition(&self) -> SpaceCraftDefinition {
+        let mut module_states: Vec<ModuleState> = self
+            .nodes
+            .iter()
+            .filter(|(_, node)| {
+                node.tanks
+                    .iter()
+                    .any(|tank| tank.state.amount_m3 != tank.capacity)
+            })
+            .map(|(grid_position, node)| ModuleState {
+                grid_position: *grid_position,
+                tank_amounts: node.tanks.iter().map(|tank| tank.state.amount_m3).collect(),
+            })
+            .collect();
+        module_states.sort_by_key(|state| state.grid_position.to_array());
+
+        SpaceCraftDefinition {
+            version: SpaceCraftDefinition::CURRENT_VERSION,
+            name: self.name.clone(),
+            categories: self.categories.clone(),
+            modules: self.modules.clone(),
+            attachments: self.attachment_mounts(),
+            module_states,
+        }
+    }
+
+    /// Every mounted attachment, in the form stored in spacecraft definitions
+    pub fn attachment_mounts(&self) -> Vec<AttachmentMount> {
+        let mut mounts: Vec<AttachmentMount> = self
+            .nodes
+            .iter()
+            .flat_map(|(grid_position, node)| {
+                node.hard_points
+                    .iter()
+                    .enumerate()
+                    .filter_map(move |(index, hard_point)| {
+                        hard_point
+                            .attachment
+                            .as_ref()
+                            .map(|attachment| AttachmentMount {
+                                grid_position: *grid_position,
+                                hard_point: index,
+                                attachment: attachment.name.clone(),
+                            })
+                    })
+            })
+            .collect();
+        mounts.sort_by_key(|mount| (mount.grid_position.to_array(), mount.hard_point));
+        mounts
+    }
+
+    fn from_parts(
+        transform: Transform,
+        rigid_body_instance: Option<RigidBodyHandle>,
+        modules: HashMap<IVec3, String>,
+        nodes: HashMap<IVec3, SpaceCraftNode>,
+    ) -> Self {
+        let mut craft = Self {
+            id: Default::default(),
+            transform,
+            name: String::new(),
+            categories: Vec::new(),
+            rigid_body_instance,
+            modules,
+            nodes,
+            thrusters: Vec::new(),
+            plumes: Vec::new(),
+            trail: None,
+            throttle: Default::default(),
+            flight_mode: FlightMode::Assist,
+            autopilot: Autopilot::Off,
+            autopilot_target: None,
+            assist_errors: (Vec3::ZERO, Vec3::ZERO),
+            point_masses_dirty: true,
+            structure_fraction: 1.0,
+            impact_damage_factor: DEFAULT_IMPACT_DAMAGE_FACTOR,
+            docking_available: false,
+            interior_enabled: false,
+        };
+        craft.rebuild();
+        craft
+    }
+
+    /// Connected pieces of the craft as if `ignored` was empty, largest first
+    fn connected_pieces(&self, ignored: Option<IVec3>) -> Vec<HashSet<IVec3>> {
+        let occupied =
+            |position: IVec3| Some(position) != ignored && self.nodes.contains_key(&position);
+        let connected = |position: IVec3, neighbor: IVec3| {
+            connectors_meet(
+                &self.nodes[&position].connectors,
+                &self.nodes[&neighbor].connectors,
+            )
+        };
+
+        let mut pieces: Vec<HashSet<IVec3>> = Vec::new();
+        for position in self.nodes.keys() {
+            if occupied(*position) && !pieces.iter().any(|piece| piece.contains(position)) {
+                pieces.push(flood_fill(*position, occupied, connected));
+            }
+        }
+        pieces.sort_by_key(|piece| std::cmp::Reverse(piece.len()));
+        pieces
+    }
+
+    /// Rebuilds what is derived from the nodes after modules change,
+    /// thrusters are sorted by grid position so indices stay stable
+    fn rebuild(&mut self) {
+        self.point_masses_dirty = true;
+
+        let mut grid_positions: Vec<IVec3> = self.nodes.keys().copied().collect();
+        grid_positions.sort_by_key(|position| position.to_array());
+
+        self.thrusters = grid_positions
+            .iter()
+            .flat_map(|grid_position| {
+                let node = &self.nodes[grid_position];
+                node.thrusters.iter().map(|thruster| SpaceCraftThruster {
+                    grid_position: *grid_position,
+                    position: node.local_transform.position
+                        + node.local_transform.rotation * thruster.offset,
+                    direction: node.local_transform.rotation
+                        * thruster.direction.as_ivec3().as_vec3(),
+                    max_thrust: thruster.max_thrust,
+                    propellant: thruster.propellant,
+                    isp: thruster.isp,
+                    throttle: 0.0,
+                })
+            })
+            .collect();
+    }
+
+    /// Tanks in grid order
+    pub fn tanks(&self) -> impl Iterator<Item = (TankId, &SpaceCraftTank)> {
+        let mut grid_positions: Vec<IVec3> = self.nodes.keys().copied().collect();
+        grid_positions.sort_by_key(|position| position.to_array());
+
+        grid_positions.into_iter().flat_map(move |grid_position| {
+            self.nodes[&grid_position]
+                .tanks
+                .iter()
+                .enumerate()
+                .map(move |(index, tank)| {
+                    (
+                        TankId {
+                            grid_position,
+                            index,
+                        },
+                        tank,
+                    )
+                })
+        })
+    }
+
+    pub fn get_tank(&self, tank: TankId) -> Option<&SpaceCraftTank> {
+        self.nodes
+            .get(&tank.grid_position)
+            .and_then(|node| node.tanks.get(tank.index))
+    }
+
+    fn get_tank_mut(&mut self, tank: TankId) -> Option<&mut SpaceCraftTank> {
+        self.nodes
+            .get_mut(&tank.grid_position)
+            .and_then(|node| node.tanks.get_mut(tank.index))
+    }
+
+    /// Moves up to `rate * delta_time` m^3 between tanks, limited by what the source holds and the target can fit.
+    /// An empty target takes on the source's resource, returns the amount moved
+    pub fn transfer(
+        &mut self,
+        from_tank: TankId,
+        to_tank: TankId,
+        rate: f32,
+        delta_time: f32,
+    ) -> Result<f32, TankError> {
+        let from = self
+            .get_tank(from_tank)
+            .ok_or(TankError::UnknownTank(from_tank))?
+            .clone();
+        let to = self
+            .get_tank(to_tank)
+            .ok_or(TankError::UnknownTank(to_tank))?
+            .clone();
+        if from_tank == to_tank {
+            return Ok(0.0);
+        }
+
+        if to.state.amount_m3 > 0.0 && to.state.resource != from.state.resource {
+            return Err(TankError::ResourceMismatch {
+                from: from.state.resource,
+                to: to.state.resource,
+            });
+        }
+
+        let amount = (rate * delta_time)
+            .min(from.state.amount_m3)
+            .min(to.capacity - to.state.amount_m3)
+            .max(0.0);
+        if amount > 0.0 {
+            if let Some(tank) = self.get_tank_mut(from_tank) {
+                tank.state.amount_m3 -= amount;
+            }
+            if let Some(tank) = self.get_tank_mut(to_tank) {
+                tank.state.resource = from.state.resource;
+                tank.state.amount_m3 += amount;
+            }
+            self.point_masses_dirty = true;
+        }
+        Ok(amount)
+    }
+
+    /// Volume of a resource across every tank on the craft
+    pub fn total_propellant(&self, resource: ResourceType) -> f32 {
+        self.tanks()
+            .filter(|(_, tank)| tank.state.resource == resource)
+            .map(|(_, tank)| tank.state.amount_m3)
+            .sum()
+    }
+
+    /// Rocket equation estimate for burning all of a resource with the thrusters that use it
+    pub fn delta_v(&self, world: &WorldInfo, resource: ResourceType) -> f32 {
+        let Some(rigid_body) = self.rigid_body_instance else {
+            return 0.0;
+        };
+
+        let isps: Vec<f32> = self
+            .thrusters
+            .iter()
+            .filter(|thruster| thruster.propellant == resource)
+            .filter_map(|thruster| thruster.isp)
+            .collect();
+        if isps.is_empty() {
+            return 0.0;
+        }
+        let isp = isps.iter().sum::<f32>() / isps.len() as f32;
+
+        let full_mass = world.physics.get_mass(rigid_body);
+        let dry_mass = full_mass - self.total_propellant(resource) * resource.density();
+        if dry_mass <= 0.0 {
+            return 0.0;
+        }
+        isp * STANDARD_GRAVITY * (full_mass / dry_mass).ln()
+    }
+
+    /// Takes up to `amount_m3` of a resource from every tank holding it, proportional to what each tank holds
+    /// so they all run dry together. Returns how much was actually drained
+    pub fn drain(&mut self, resource: ResourceType, amount_m3: f32) -> f32 {
+        let available = self.total_propellant(resource);
+        if available <= 0.0 || amount_m3 <= 0.0 {
+            return 0.0;
+        }
+
+        let fraction = (amount_m3 / available).min(1.0);
+        for node in self.nodes.values_mut() {
+            for tank in node.tanks.iter_mut() {
+                if tank.state.resource == resource {
+                    tank.state.amount_m3 -= tank.state.amount_m3 * fraction;
+                }
+            }
+        }
+
+        self.point_masses_dirty = true;
+        available * fraction
+    }
+
+    /// Tank contents and attachments are added to the body as point masses on top of the module colliders
+    fn update_point_masses(&mut self, world: &mut WorldInfo) {
+        let Some(rigid_body) = self.rigid_body_instance else {
+            return;
+        };
+        self.point_masses_dirty = false;
+
+        let tanks = self
+            .nodes
+            .values()
+            .flat_map(|node| node.tanks.iter())
+            .map(|tank| (tank.position, tank.state.mass()));
+        let attachments = self
+            .nodes
+            .values()
+            .flat_map(|node| node.hard_points.iter())
+            .filter_map(|hard_point| {
+                let attachment = hard_point.attachment.as_ref()?;
+                Some((hard_point.transform.position, attachment.mass))
+            });
+        let point_masses: Vec<(Vec3, f32)> = tanks.chain(attachments).collect();
+
+        let mass: f32 = point_masses.iter().map(|(_, mass)| mass).sum();
+        let center_of_mass = if mass > 0.0 {
+            point_masses
+                .iter()
+                .map(|(position, point_mass)| *position * *point_mass)
+                .sum::<Vec3>()
+                / mass
+        } else {
+            Vec3::ZERO
+        };
+        let inertia = point_masses
+            .iter()
+            .map(|(position, point_mass)| {
+                let offset = *position - center_of_mass;
+                Vec3::new(
+                    offset.y * offset.y + offset.z * offset.z,
+                    offset.x * offset.x + offset.z * offset.z,
+                    offset.x * offset.x + offset.y * offset.y,
+                ) * *point_mass
+            })
+            .sum();
+
+        world
+            .physics
+            .set_mass_properties(rigid_body, mass, center_of_mass, inertia);
+    }
+
+    pub fn thrusters(&self) -> &[SpaceCraftThruster] {
+        &self.thrusters
+    }
+
+    /// Axis commands are interpreted by the flight mode, per thruster commands always go straight to the thrusters
+    pub fn set_throttle(&mut self, throttle: ThrottleCommand) {
+        self.throttle = throttle;
+    }
+
+    pub fn get_flight_mode(&self) -> FlightMode {
+        self.flight_mode
+    }
+
+    pub fn set_flight_mode(&mut self, flight_mode: FlightMode) {
+        self.flight_mode = flight_mode;
+        self.assist_errors = (Vec3::ZERO, Vec3::ZERO);
+    }
+
+    /// Thrust being made as a fraction of what every thruster together could make, 0 when there are none
+    pub fn throttle_fraction(&self) -> f32 {
+        let (thrust, max_thrust) =
+            self.thrusters
+                .iter()
+                .fold((0.0, 0.0), |(thrust, max_thrust), thruster| {
+                    (
+                        thrust + thruster.max_thrust * thruster.throttle,
+                        max_thrust + thruster.max_thrust,
+                    )
+                });
+        if max_thrust > 0.0 {
+            thrust / max_thrust
+        } else {
+            0.0
+        }
+    }
+
+    /// World space force of the thrusters at their current throttle
+    pub fn thrust_force(&self) -> Vec3 {
+        self.thrusters
+            .iter()
+            .map(|thruster| {
+                self.transform.rotation
+                    * (thruster.direction * thruster.max_thrust * thruster.throttle)
+            })
+            .sum()
+    }
+
+    pub fn get_autopilot(&self) -> Autopilot {
+        self.autopilot
+    }
+
+    /// Works in every flight mode, even direct flight goes through the assist solver while it's on
+    pub fn set_autopilot(&mut self, autopilot: Autopilot) {
+        self.autopilot = autopilot;
+        self.autopilot_target = None;
+        self.assist_errors = (Vec3::ZERO, Vec3::ZERO);
+    }
+
+    /// None once the target is gone, the autopilot then only kills rotation
+    pub fn set_autopilot_target(&mut self, target: Option<AutopilotTarget>) {
+        self.autopilot_target = target;
+    }
+
+    /// Craft space target velocities for the assist solver, None for the axes left to the pilot
+    fn autopilot_targets(
+        &self,
+        world: &WorldInfo,
+        rigid_body: RigidBodyHandle,
+        capacity: &ThrustCapacity,
+        linear_velocity: Vec3,
+    ) -> (Option<Vec3>, Option<Vec3>) {
+        let inertia = world.physics.get_principal_angular_inertia(rigid_body);
+        let face = |direction: Vec3| Some(face_direction(direction, capacity, inertia));
+        let to_local = self.transform.rotation.inverse();
+
+        match (self.autopilot, self.autopilot_target) {
+            (Autopilot::Off, _) => (None, None),
+            (Autopilot::Prograde, _) if linear_velocity.length() >= AUTOPILOT_MIN_SPEED => {
+                (None, face(linear_velocity))
+            }
+            (Autopilot::Retrograde, _) if linear_velocity.length() >= AUTOPILOT_MIN_SPEED => {
+                (None, face(-linear_velocity))
+            }
+            (Autopilot::FaceTarget(_), Some(target)) => (
+                None,
+                face(to_local * (target.position - self.transform.position)),
+            ),
+            (
+                Autopilot::Approach {
+                    standoff_distance, ..
+                },
+                Some(target),
+            ) => {
+                let offset = target.position - self.transform.position;
+                let toward = to_local * offset.normalize_or_zero();
+                let remaining = offset.length() - standoff_distance;
+
+                //Closing speed is capped so the craft can always brake to a stop by the standoff distance
+                let braking = ThrustCapacity::along(
+                    -toward * remaining.signum(),
+                    capacity.force_positive,
+                    capacity.force_negative,
+                ) / world.physics.get_mass(rigid_body)
+                    * AUTOPILOT_BRAKING_MARGIN;
+                let closing_speed = (remaining.abs() * AUTOPILOT_APPROACH_GAIN)
+                    .min((2.0 * braking * remaining.abs()).sqrt())
+                    .min(ASSIST_MAX_SPEED)
+                    * remaining.signum();
+
+                (
+                    Some(to_local * target.velocity + toward * closing_speed),
+                    face(toward),
+                )
+            }
+            //Too slow for a direction or the target is gone
+            _ => (None, Some(Vec3::ZERO)),
+        }
+    }
+
+    /// Per axis PD controller, the needed force and torque are turned into fractions of what the thrusters can produce.
+    /// Input is scaled to craft space target velocities, with dampeners off linear input stays direct
+    fn solve_assist(
+        &mut self,
+        world: &WorldInfo,
+        rigid_body: RigidBodyHandle,
+        center_of_mass: Vec3,
+        linear_input: Vec3,
+        angular_input: Vec3,
+        delta_time: f32,
+    ) -> (Vec3, Vec3) {
+        let to_local = self.transform.rotation.inverse();
+        let linear_velocity = to_local * world.physics.get_rigid_body_linear_velocity(rigid_body);
+        let angular_velocity = to_local * world.physics.get_rigid_body_angular_velocity(rigid_body);
+        let capacity = ThrustCapacity::new(&self.thrusters, center_of_mass);
+
+        let (autopilot_linear, autopilot_angular) =
+            self.autopilot_targets(world, rigid_body, &capacity, linear_velocity);
+        let linear_target =
+            autopilot_linear
+                .or((self.flight_mode == FlightMode::Assist)
+                    .then_some(linear_input * ASSIST_MAX_SPEED));
+        let angular_target = autopilot_angular.unwrap_or(angular_input * ASSIST_MAX_ANGULAR_SPEED);
+
+        let (last_linear_error, last_angular_error) = self.assist_errors;
+        let linear_error = linear_target.map_or(Vec3::ZERO, |target| target - linear_velocity);
+        let angular_error = angular_target - angular_velocity;
+        self.assist_errors = (linear_error, angular_error);
+
+        let linear = match linear_target {
+            Some(_) => {
+                let acceleration = linear_error * ASSIST_LINEAR_GAIN
+                    + (linear_error - last_linear_error) * ASSIST_DERIVATIVE_GAIN / delta_time;
+                ThrustCapacity::demand(
+                    acceleration * world.physics.get_mass(rigid_body),
+                    capacity.force_positive,
+                    capacity.force_negative,
+                )
+            }
+            None => linear_input,
+        };
+
+        let angular_acceleration = angular_error * ASSIST_ANGULAR_GAIN
+            + (angular_error - last_angular_error) * ASSIST_DERIVATIVE_GAIN / delta_time;
+        let angular = ThrustCapacity::demand(
+            angular_acceleration * world.physics.get_principal_angular_inertia(rigid_body),
+            capacity.torque_positive,
+            capacity.torque_negative,
+        );
+
+        (linear, angular)
+    }
+
+    /// Keeps one emitter per thruster as modules change, each spawning with its thruster's throttle
+    fn update_plumes(&mut self, world: &mut WorldInfo, velocity: Vec3) {
+        while self.plumes.len() > self.thrusters.len() {
+            if let Some(plume) = self.plumes.pop() {
+                world.rendering.remove_emitter(plume);
+            }
+        }
+        while self.plumes.len() < self.thrusters.len() {
+            self.plumes.push(
+                world
+                    .rendering
+                    .create_emitter(thruster_plume_settings(), &self.transform),
+            );
+        }
+
+        for (thruster, plume) in self.thrusters.iter().zip(self.plumes.iter()) {
+            //Exhaust leaves the opposite way the thruster pushes
+            let transform = Transform {
+                position: self.transform.position + self.transform.rotation * thruster.position,
+                rotation: self.transform.rotation
+                    * Quat::from_rotation_arc(Vec3::Z, -thruster.direction),
+                scale: Vec3::ONE,
+            };
+            world
+                .rendering
+                .update_emitter(*plume, &transform, velocity, thruster.throttle);
+        }
+    }
+
+    /// Thrust is applied at each thruster's position so off-center thrusters also produce torque.
+    /// Thrusters burn propellant for the physics step, when the tanks can't supply it all thrust is scaled down to match
+    fn apply_thrust(&mut self, world: &mut WorldInfo, rigid_body: RigidBodyHandle) {
+        //Thrust only acts during physics steps, so it follows the physics delta time even for the player's craft
+        let delta_time = world.physics_delta_time;
+        let center_of_mass = world.physics.get_local_center_of_mass(rigid_body);
+
+        let axis_demand = match (&self.throttle, self.flight_mode) {
+            (ThrottleCommand::PerThruster(_), _) => None,
+            (ThrottleCommand::Axis { linear, angular }, FlightMode::Direct)
+                if self.autopilot == Autopilot::Off =>
+            {
+                Some((*linear, *angular))
+            }
+            (ThrottleCommand::Axis { linear, angular }, _) if delta_time > 0.0 => {
+                let (linear, angular) = (*linear, *angular);
+                Some(self.solve_assist(
+                    world,
+                    rigid_body,
+                    center_of_mass,
+                    linear,
+                    angular,
+                    delta_time,
+                ))
+            }
+            _ => Some((Vec3::ZERO, Vec3::ZERO)),
+        };
+
+        for (index, thruster) in self.thrusters.iter_mut().enumerate() {
+            thruster.throttle = match (&self.throttle, axis_demand) {
+                (_, Some((linear, angular))) => {
+                    thruster_throttle(thruster, center_of_mass, linear, angular)
+                }
+                (ThrottleCommand::PerThruster(throttles), None) => {
+                    throttles.get(index).copied().unwrap_or(0.0)
+                }
+                _ => 0.0,
+            }
+            .clamp(0.0, 1.0);
+        }
+
+        let mut propellant_needed: HashMap<ResourceType, f32> = HashMap::new();
+        for thruster in self.thrusters.iter() {
+            if let Some(volume) = thruster.propellant_use(delta_time) {
+                *propellant_needed.entry(thruster.propellant).or_default() += volume;
+            }
+        }
+        let supplied: HashMap<ResourceType, f32> = propellant_needed
+            .into_iter()
+            .map(|(resource, needed)| {
+                let fraction = if needed > 0.0 {
+                    self.drain(resource, needed) / needed
+                } else {
+                    1.0
+                };
+                (resource, fraction)
+            })
+            .collect();
+
+        for thruster in self.thrusters.iter_mut() {
+            if thruster.isp.is_some() {
+                thruster.throttle *= supplied.get(&thruster.propellant).copied().unwrap_or(1.0);
+            }
+
+            if thruster.throttle > 0.0 && delta_time > 0.0 {
+                world.physics.apply_force_at_point(
+                    rigid_body,
+                    self.transform.rotation
+                        * (thruster.direction * thruster.max_thrust * thruster.throttle),
+                    self.transform.position + self.transform.rotation * thruster.position,
+                );
+            }
+        }
+    }
+
+    /// The new module has to connect to at least one neighbor and match the connectors of every neighbor it touches
+    pub fn add_module(
+        &mut self,
+        grid_position: IVec3,
+        module_name: &str,
+        module_table: &ModuleTable,
+        renderer: &mut Renderer,
+        world: &mut WorldInfo,
+    ) -> Result<(), PlacementError> {
+        self.check_placement(grid_position, module_name, module_table)?;
+        let module = &module_table[module_name];
+
+        let mut node = SpaceCraftNode::new(grid_position, module, renderer);
+        if let Some(rigid_body) = self.rigid_body_instance {
+            node.add_to_world(world, rigid_body, &self.transform, self.id);
+        }
+
+        self.modules.insert(grid_position, module_name.to_string());
+        self.nodes.insert(grid_position, node);
+        self.rebuild();
+        self.update_interior(world);
+        Ok(())
+    }
+
+    /// Runs the checks of `add_module` without changing anything
+    pub fn check_placement(
+        &self,
+        grid_position: IVec3,
+        module_name: &str,
+        module_table: &ModuleTable,
+    ) -> Result<(), PlacementError> {
+        if !module_table.contains_key(module_name) {
+            return Err(PlacementError::UnknownModule(module_name.to_string()));
+        }
+        if self.modules.contains_key(&grid_position) {
+            return Err(PlacementError::Occupied(grid_position));
+        }
+
+        let mut modules = self.modules.clone();
+        modules.insert(grid_position, module_name.to_string());
+
+        let mut has_neighbor = false;
+        for direction in GridDirection::ALL {
+            let neighbor = grid_position + direction.as_ivec3();
+            if !modules.contains_key(&neighbor) {
+                continue;
+            }
+            if !modules_connected(&modules, module_table, grid_position, neighbor) {
+                return Err(PlacementError::IncompatibleNeighbor {
+                    position: grid_position,
+                    neighbor,
+                });
+            }
+            has_neighbor = true;
+        }
+        if !has_neighbor && !self.modules.is_empty() {
+            return Err(PlacementError::NotConnected(grid_position));
+        }
+        Ok(())
+    }
+
+    /// Name of the module in a grid cell
+    pub fn module_at(&self, grid_position: IVec3) -> Option<&str> {
+        self.modules.get(&grid_position).map(String::as_str)
+    }
+
+    /// Refuses to remove a module if that would split the craft, the error lists the cells that would be cut off
+    pub fn remove_module(
+        &mut self,
+        grid_position: IVec3,
+        world: &mut WorldInfo,
+    ) -> Result<(), PlacementError> {
+        if !self.modules.contains_key(&grid_position) {
+            return Err(PlacementError::Empty(grid_position));
+        }
+
+        //Everything outside the largest remaining piece is orphaned
+        let pieces = self.connected_pieces(Some(grid_position));
+        if pieces.len() > 1 {
+            let mut orphaned: Vec<IVec3> = pieces[1..].iter().flatten().copied().collect();
+            orphaned.sort_by_key(|position| position.to_array());
+            return Err(PlacementError::WouldSplit {
+                position: grid_position,
+                orphaned,
+            });
+        }
+
+        self.destroy_module(grid_position, world);
+        Ok(())
+    }
+
+    /// Removes a module without any connectivity checks
+    fn destroy_module(&mut self, grid_position: IVec3, world: &mut WorldInfo) {
+        if let Some(mut node) = self.nodes.remove(&grid_position) {
+            node.remove_from_world(world);
+        }
+        self.modules.remove(&grid_position);
+        self.rebuild();
+        self.update_interior(world);
+    }
+
+    /// Docking ports sit on the face of their cell, sorted by grid position so port indices stay stable
+    fn publish_docking_ports(&self, world: &mut WorldInfo, rigid_body: RigidBodyHandle) {
+        let mut ports: Vec<(IVec3, GridDirection)> = self
+            .nodes
+            .values()
+            .flat_map(|node| node.docking_ports.iter().copied())
+            .collect();
+        ports.sort_by_key(|(cell, direction)| (cell.to_array(), *direction as u8));
+
+        let linear_velocity = world.physics.get_rigid_body_linear_velocity(rigid_body);
+        let angular_velocity = world.physics.get_rigid_body_angular_velocity(rigid_body);
+        let center_of_mass = world.physics.get_center_of_mass(rigid_body);
+        let ports: Vec<DockingPortState> = ports
+            .into_iter()
+            .map(|(cell, direction)| {
+                let direction = direction.as_ivec3().as_vec3();
+                let local_position = (cell.as_vec3() + direction * 0.5) * MODULE_SIZE;
+                let position = self.transform.position + self.transform.rotation * local_position;
+                DockingPortState {
+                    rigid_body,
+                    local_position,
+                    position,
+                    direction: self.transform.rotation * direction,
+                    velocity: linear_velocity + angular_velocity.cross(position - center_of_mass),
+                }
+            })
+            .collect();
+        world.docking.set_ports(self.id, ports.into_iter());
+    }
+
+    pub fn docking_status(&self, world: &WorldInfo) -> DockingStatus {
+        world.docking.status(self.id)
+    }
+
+    pub fn dock(&mut self, world: &mut WorldInfo) -> Option<EntityId> {
+        let other = world.docking.dock(&mut world.physics, self.id);
+        if let Some(other) = other {
+            info!("Spacecraft {:?} docked with {:?}", self.id, other);
+        }
+        other
+    }
+
+    pub fn undock(&mut self, world: &mut WorldInfo) -> bool {
+        let undocked = world.docking.undock(&mut world.physics, self.id);
+        if undocked {
+            info!("Spacecraft {:?} undocked", self.id);
+        }
+        undocked
+    }
+
+    /// Structure health as (current, max), max follows the mass of the remaining modules
+    pub fn structure_health(&self) -> (f32, f32) {
+        let max_health: f32 = self
+            .nodes
+            .values()
+            .map(|node| node.mass * STRUCTURE_HEALTH_PER_KG)
+            .sum();
+        (max_health * self.structure_fraction, max_health)
+    }
+
+    /// Local health of every module that has one
+    pub fn health_map(&self) -> HashMap<IVec3, ModuleHealth> {
+        self.nodes
+            .iter()
+            .filter_map(|(grid_position, node)| {
+                Some((
+                    *grid_position,
+                    ModuleHealth {
+                        health: node.health?,
+                        max_health: node.max_health?,
+                    },
+                ))
+            })
+            .collect()
+    }
+
+    pub fn set_impact_damage_factor(&mut self, impact_damage_factor: f32) {
+        self.impact_damage_factor = impact_damage_factor;
+    }
+
+    /// Damage is scaled by the module's multiplier. Modules with local health absorb it and pass any overflow on to
+    /// the structure, modules without local health pass all of it on. A module at zero health is destroyed and the
+    /// craft splits if that disconnected it, with no structure left every module is destroyed.
+    /// Returns the pieces that broke off, they still need to be added to the world
+    pub fn apply_damage(
+        &mut self,
+        target: DamageTarget,
+        amount: f32,
+        world: &mut WorldInfo,
+    ) -> Vec<SpaceCraftEntity> {
+        let grid_position = match target {
+            DamageTarget::Module(grid_position) => Some(grid_position),
+            DamageTarget::Collider(collider) => self
+                .nodes
+                .iter()
+                .find(|(_, node)| node.collider_instance == Some(collider))
+                .map(|(grid_position, _)| *grid_position),
+        };
+        let Some(node) = grid_position.and_then(|grid_position| self.nodes.get_mut(&grid_position))
+        else {
+            return Vec::new();
+        };
+
+        let damage = amount * node.damage_multiplier;
+        let (structure_damage, destroyed) = match &mut node.health {
+            Some(health) => {
+                *health -= damage;
+                ((-*health).max(0.0), *health <= 0.0)
+            }
+            None => (damage, false),
+        };
+
+        let (_, max_structure_health) = self.structure_health();
+        if max_structure_health > 0.0 {
+            self.structure_fraction -= structure_damage / max_structure_health;
+        }
+
+        if self.structure_fraction <= 0.0 {
+            info!("Spacecraft {:?} destroyed", self.id);
+            let grid_positions: Vec<IVec3> = self.nodes.keys().copied().collect();
+            for grid_position in grid_positions {
+                self.destroy_module(grid_position, world);
+            }
+            return Vec::new();
+        }
+
+        match grid_position {
+            Some(grid_position) if destroyed => {
+                self.destroy_module(grid_position, world);
+                self.split(world)
+            }
+            _ => Vec::new(),
+        }
+    }
+
+    /// Splits the craft into its connected pieces, the largest stays on this body and the rest are returned
+    /// with their own bodies already carrying the migrated colliders and render instances.
+    /// The returned crafts still need to be added to the world
+    pub fn split(&mut self, world: &mut WorldInfo) -> Vec<SpaceCraftEntity> {
+        let mut pieces = self.connected_pieces(None);
+        if pieces.len() <= 1 {
+            return Vec::new();
+        }
+
+        //Velocity of the parent body, every piece moves like the point of the parent it ends up centered on
+        let parent_motion = self.rigid_body_instance.map(|rigid_body| {
+            (
+                world.physics.get_rigid_body_linear_velocity(rigid_body),
+                world.physics.get_rigid_body_angular_velocity(rigid_body),
+                world.physics.get_center_of_mass(rigid_body),
+            )
+        });
+
+        let mut new_crafts = Vec::new();
+        for piece in pieces.drain(1..) {
+            let mut modules = HashMap::new();
+            let mut nodes = HashMap::new();
+            for position in piece {
+                if let Some(module_name) = self.modules.remove(&position) {
+                    modules.insert(position, module_name);
+                }
+                if let Some(node) = self.nodes.remove(&position) {
+                    nodes.insert(position, node);
+                }
+            }
+
+            let rigid_body_instance = self.rigid_body_instance.map(|_| {
+                let rigid_body = world.physics.create_rigid_body(
+                    self.transform.position,
+                    self.transform.rotation,
+                    RigidBodyType::Dynamic,
+                );
+                for collider in nodes.values().flat_map(|node| node.colliders()) {
+                    world.physics.set_collider_parent(collider, rigid_body);
+                }
+                rigid_body
+            });
+
+            let mut craft =
+                Self::from_parts(self.transform.clone(), rigid_body_instance, modules, nodes);
+            craft.name = self.name.clone();
+            craft.categories = self.categories.clone();
+            craft.flight_mode = self.flight_mode;
+            craft.structure_fraction = self.structure_fraction;
+            craft.impact_damage_factor = self.impact_damage_factor;
+            craft.interior_enabled = self.interior_enabled;
+            craft.update_point_masses(world);
+            new_crafts.push(craft);
+        }
+        self.rebuild();
+        self.update_interior(world);
+        self.update_point_masses(world);
+
+        if let Some((linear_velocity, angular_velocity, center_of_mass)) = parent_motion {
+            let rigid_bodies = self.rigid_body_instance.iter().chain(
+                new_crafts
+                    .iter()
+                    .flat_map(|craft| &craft.rigid_body_instance),
+            );
+            for rigid_body in rigid_bodies {
+                let offset = world.physics.get_center_of_mass(*rigid_body) - center_of_mass;
+                world.physics.set_rigid_body_linear_velocity(
+                    *rigid_body,
+                    linear_velocity + angular_velocity.cross(offset),
+                );
+                world
+                    .physics
+                    .set_rigid_body_angular_velocity(*rigid_body, angular_velocity);
+            }
+        }
+
+        new_crafts
+    }
+}
+
+impl Entity for SpaceCraftEntity {
+    fn set_id(&mut self, id: EntityId) {
+        self.id = id;
+    }
+
+    fn add_to_world(&mut self, world: &mut WorldInfo) {
+        //Pieces split off another craft already have a body
+        let rigid_body = *self.rigid_body_instance.get_or_insert_with(|| {
+            world.physics.create_rigid_body(
+                self.transform.position,
+                self.transform.rotation,
+                RigidBodyType::Dynamic,
+            )
+        });
+
+        for node in self.nodes.values_mut() {
+            node.add_to_world(world, rigid_body, &self.transform, self.id);
+        }
+        self.update_interior(world);
+        self.update_point_masses(world);
+    }
+
+    fn remove_from_world(&mut self, world: &mut WorldInfo) {
+        world.docking.undock(&mut world.physics, self.id);
+        world.docking.remove_ports(self.id);
+
+        for node in self.nodes.values_mut() {
+            node.remove_from_world(world);
+        }
+        for plume in self.plumes.drain(..) {
+            world.rendering.remove_emitter(plume);
+        }
+        if let Some(trail) = self.trail.take() {
+            world.rendering.remove_trail(trail);
+        }
+
+        if let Some(rigid_body) = self.rigid_body_instance.take() {
+            world.physics.remove_rigid_body(rigid_body);
+        }
+    }
+
+    fn update_stages(&self) -> &'static [UpdateStage] {
+        &[UpdateStage::PrePhysics, UpdateStage::PostPhysics]
+    }
+
+    fn pre_physics_update(&mut self, world: &mut WorldInfo, _delta_time: f32) {
+        if let Some(rigid_body) = self.rigid_body_instance {
+            if self.point_masses_dirty {
+                self.update_point_masses(world);
+            }
+            self.apply_thrust(world, rigid_body);
+        }
+    }
+
+    fn update(&mut self, world: &mut WorldInfo, _delta_time: f32) {
+        if let Some(rigid_body) = self.rigid_body_instance {
+            let (position, rotation) = world.physics.get_rigid_body_transform(rigid_body);
+            self.transform.position = position;
+            self.transform.rotation = rotation;
+            self.publish_docking_ports(world, rigid_body);
+
+            let docking_available =
+                matches!(self.docking_status(world), DockingStatus::Available { .. });
+            if docking_available && !self.docking_available {
+                info!("Spacecraft {:?}: docking available", self.id);
+            }
+            self.docking_available = docking_available;
+
+            let velocity = world.physics.get_rigid_body_linear_velocity(rigid_body);
+            self.update_plumes(world, velocity);
+            let trail = *self
+                .trail
+                .get_or_insert_with(|| world.rendering.create_trail(craft_trail_settings()));
+            world.rendering.update_trail(trail, self.transform.position);
+        }
+
+        //Colliders are attached to the body, so only the render instances need to follow it
+        for node in self.nodes.values() {
+            node.update_instances(world, &self.transform);
+        }
+    }
+
+    /// Scale only changes the models, the colliders keep their size
+    fn set_transform(&mut self, transform: &Transform, world: &mut WorldInfo) {
+        self.transform = transform.clone();
+        if let Some(rigid_body) = self.rigid_body_instance {
+            world.physics.set_rigid_body_transform(
+                rigid_body,
+                transform.position,
+                transform.rotation,
+                true,
+            );
+        }
+        for node in self.nodes.values() {
+            node.update_instances(world, &self.transform);
+        }
+    }
+
+    /// Same axes as the player camera: x yaws, y pitches and z rolls
+    fn update_player_input(&mut self, linear_input: Vec3, angular_input: Vec3) {
+        self.throttle = ThrottleCommand::Axis {
+            linear: linear_input,
+            angular: Vec3::new(angular_input.y, angular_input.x, -angular_input.z),
+        };
+    }
+
+    /// Ramming damages the module that was hit
+    fn on_collision(&mut self, _other: EntityId, event: CollisionEvent, world: &mut WorldInfo) {
+        if let CollisionEvent::Impact {
+            collider, impulse, ..
+        } = event
+        {
+            let detached = self.apply_damage(
+                DamageTarget::Collider(collider),
+                impulse * self.impact_damage_factor,
+                world,
+            );
+            world.spawn_detached(detached);
+        }
+    }
+
+    fn on_message(&mut self, message: EntityMessage, world: &mut WorldInfo) {
+        if let EntityMessage::Damage { collider, amount } = message {
+            let detached = self.apply_damage(DamageTarget::Collider(collider), amount, world);
+            world.spawn_detached(detached);
+        }
+    }
+
+    fn on_player_action(&mut self, action: PlayerAction, world: &mut WorldInfo) {
+        match action {
+            PlayerAction::CycleFlightMode => {
+                self.set_flight_mode(match self.flight_mode {
+                    FlightMode::Direct => FlightMode::Assist,
+                    FlightMode::Assist => FlightMode::DampenersOff,
+                    FlightMode::DampenersOff => FlightMode::Direct,
+                });
+                info!("Flight mode: {:?}", self.flight_mode);
+            }
+            PlayerAction::SetAutopilot(autopilot) => {
+                self.set_autopilot(autopilot);
+                info!("Autopilot: {:?}", autopilot);
+            }
+            PlayerAction::ToggleDocking => {
+                if !self.undock(world) {
+                    self.dock(world);
+                }
+            }
+            PlayerAction::SaveDefinition => match save_space_craft_definition(
+                &self.to_definition(),
+                std::path::Path::new(SPACECRAFT_DIRECTORY),
+            ) {
+                Ok(path) => info!("Saved spacecraft {} to {:?}", self.name, path),
+                Err(e) => error!("Failed to save spacecraft {}: {}", self.name, e),
+            },
+            PlayerAction::ToggleDampeners => {
+                self.set_flight_mode(match self.flight_mode {
+                    FlightMode::DampenersOff => FlightMode::Assist,
+                    FlightMode::Direct | FlightMode::Assist => FlightMode::DampenersOff,
+                });
+                info!("Flight mode: {:?}", self.flight_mode);
+            }
+            PlayerAction::ToggleNoclip => {}
+        }
+    }
+
+    /// Pilot's view from the cockpit, crafts without one get a chase camera behind and above the craft
+    fn get_camera_transform(&self) -> Option<Transform> {
+        Some(match self.cockpit_camera() {
+            Some(cockpit_camera) => self.transform.transform_by(cockpit_camera),
+            None => self
+                .transform
+                .transform_by(&Transform::new_pos(Vec3::new(0.0, 3.0, -10.0))),
+        })
+    }
+
+    fn get_rigid_body(&self) -> Option<RigidBodyHandle> {
+        self.rigid_body_instance
+    }
+
+    fn get_world_transform(&self) -> Option<Transform> {
+        Some(self.transform.clone())
+    }
+
+    fn shift_origin(&mut self, offset: Vec3, _world: &mut WorldInfo) {
+        self.transform.position += offset;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::renderer::test_renderer;
+    use std::path::Path;
+    use std::sync::{Arc, Mutex};
+
+    fn load_module_table() -> ModuleTable {
+        let mut module_table = ModuleTable::default();
+        crate::space_craft::load_modules_from_directory(
+            Path::new("resource/module"),
+            &mut module_table,
+        );
+        module_table
+    }
+
+    /// What the test entities were asked to do, in the order they did it
+    type EventLog = Arc<Mutex<Vec<String>>>;
+
+    /// Logs being added and removed, `generation` tells replacements apart from the entity they replaced
+    struct Marker {
+        generation: u32,
+        log: EventLog,
+    }
+
+    impl Entity for Marker {
+        fn set_id(&mut self, _id: EntityId) {}
+
+        fn add_to_world(&mut self, _world: &mut WorldInfo) {
+            let event = format!("add {}", self.generation);
+            self.log.lock().unwrap().push(event);
+        }
+
+        fn remove_from_world(&mut self, _world: &mut WorldInfo) {
+            let event = format!("remove {}", self.generation);
+            self.log.lock().unwrap().push(event);
+        }
+
+        fn update(&mut self, _world: &mut WorldInfo, _delta_time: f32) {}
+        fn update_player_input(&mut self, _linear_input: Vec3, _angular_input: Vec3) {}
+
+        fn get_camera_transform(&self) -> Option<Transform> {
+            None
+        }
+    }
+
+    /// Replaces its target with the next generation of marker on its first update
+    struct Replacer {
+        target: EntityId,
+        generation: u32,
+        log: EventLog,
+    }
+
+    impl Entity for Replacer {
+        fn set_id(&mut self, _id: EntityId) {}
+        fn add_to_world(&mut self, _world: &mut WorldInfo) {}
+        fn remove_from_world(&mut self, _world: &mut WorldInfo) {}
+
+        fn update(&mut self, world: &mut WorldInfo, _delta_time: f32) {
+            if self.generation == 0 {
+                self.generation = 1;
+                world.commands.despawn(self.target);
+                world.commands.spawn(Marker {
+                    generation: self.generation,
+                    log: self.log.clone(),
+                });
+            }
+        }
+
+        fn update_player_input(&mut self, _linear_input: Vec3, _angular_input: Vec3) {}
+
+        fn get_camera_transform(&self) -> Option<Transform> {
+            None
+        }
+    }
+
+    /// Logs every stage the world calls it in
+    struct StageRecorder {
+        stages: &'static [UpdateStage],
+        log: EventLog,
+    }
+
+    impl StageRecorder {
+        fn record(&self, event: &str) {
+            self.log.lock().unwrap().push(event.to_string());
+        }
+    }
+
+    impl Entity for StageRecorder {
+        fn set_id(&mut self, _id: EntityId) {}
+        fn add_to_world(&mut self, _world: &mut WorldInfo) {}
+        fn remove_from_world(&mut self, _world: &mut WorldInfo) {}
+
+        fn update_stages(&self) -> &'static [UpdateStage] {
+            self.stages
+        }
+
+        fn pre_physics_update(&mut self, _world: &mut WorldInfo, _delta_time: f32) {
+            self.record("pre_physics");
+        }
+
+        fn simulate(&mut self, _world: &WorldReadOnly, _delta_time: f32) {
+            self.record("simulate");
+        }
+
+        fn update(&mut self, _world: &mut WorldInfo, _delta_time: f32) {
+            self.record("update");
+        }
+
+        fn pre_render_update(&mut self, _world: &mut WorldInfo, _delta_time: f32) {
+            self.record("pre_render");
+        }
+
+        fn update_player_input(&mut self, _linear_input: Vec3, _angular_input: Vec3) {}
+
+        fn get_camera_transform(&self) -> Option<Transform> {
+            None
+        }
+    }
+
+    fn momentum(world: &WorldInfo, rigid_body: RigidBodyHandle) -> Vec3 {
+        world.physics.get_mass(rigid_body)
+            * world.physics.get_rigid_body_linear_velocity(rigid_body)
+    }
+
+    #[test]
+    fn losing_the_bridge_splits_an_h_craft_in_two() {
+        let Some(mut renderer) = test_renderer() else {
+            eprintln!("No adapter, skipping");
+            return;
+        };
+        let module_table = load_module_table();
+
+        //Two columns joined by a bridge at the center of mass
+        let mut modules = HashMap::new();
+        for y in -1..=1 {
+            modules.insert(IVec3::new(-1, y, 0), "CubeHull".to_string());
+            modules.insert(IVec3::new(1, y, 0), "CubeHull".to_string());
+        }
+        modules.insert(IVec3::ZERO, "CubeHull".to_string());
+        let definition = SpaceCraftDefinition {
+            version: SpaceCraftDefinition::CURRENT_VERSION,
+            name: "H".to_string(),
+            categories: Vec::new(),
+            modules,
+            attachments: Vec::new(),
+            module_states: Vec::new(),
+        };
+
+        let mut world = World::new(&mut renderer);
+        let craft_id = world.add_entity(SpaceCraftEntity::new(
+            Transform::default(),
+            &definition,
+            &module_table,
+            &HashMap::new(),
+            &mut renderer,
+        ));
+        let rigid_body = world.entities[craft_id].get_rigid_body().unwrap();
+        let linear_velocity = Vec3::new(1.0, -2.0, 3.0);
+        let physics = &mut world.world_info.physics;
+        physics.set_rigid_body_linear_velocity(rigid_body, linear_velocity);
+        physics.set_rigid_body_angular_velocity(rigid_body, Vec3::new(0.0, 0.0, 0.5));
+        let original_mass = physics.get_mass(rigid_body);
+        let original_momentum = momentum(&world.world_info, rigid_body);
+
+        let craft = world.entities[craft_id]
+            .as_mut()
+            .as_any_mut()
+            .downcast_mut::<SpaceCraftEntity>()
+            .unwrap();
+        let pieces = craft.apply_damage(
+            DamageTarget::Module(IVec3::ZERO),
+            50.0,
+            &mut world.world_info,
+        );
+        assert_eq!(pieces.len(), 1);
+        assert_eq!(craft.modules.len(), 3);
+        assert_eq!(pieces[0].modules.len(), 3);
+
+        let rigid_bodies: Vec<RigidBodyHandle> = std::iter::once(rigid_body)
+            .chain(pieces.iter().flat_map(|piece| piece.rigid_body_instance))
+            .collect();
+        assert_eq!(rigid_bodies.len(), 2);
+        assert_ne!(rigid_bodies[0], rigid_bodies[1]);
+
+        //The bridge takes its share of the momentum with it
+        let remaining_mass: f32 = rigid_bodies
+            .iter()
+            .map(|rigid_body| world.world_info.physics.get_mass(*rigid_body))
+            .sum();
+        let total_momentum: Vec3 = rigid_bodies
+            .iter()
+            .map(|rigid_body| momentum(&world.world_info, *rigid_body))
+            .sum();
+        let expected_momentum = original_momentum * remaining_mass / original_mass;
+        assert!(remaining_mass < original_mass);
+        assert!(
+            (total_momentum - expected_momentum).length() < expected_momentum.length() * 1e-3,
+            "{} != {}",
+            total_momentum,
+            expected_momentum
+        );
+
+        //The spin turns into opposite sideways motion of the two halves
+        let velocities: Vec<Vec3> = rigid_bodies
+            .iter()
+            .map(|rigid_body| {
+                world
+                    .world_info
+                    .physics
+                    .get_rigid_body_linear_velocity(*rigid_body)
+            })
+            .collect();
+        assert_ne!(velocities[0], velocities[1]);
+
+        for piece in pieces {
+            world.add_entity(piece);
+        }
+        assert_eq!(world.entities_of_type::<SpaceCraftEntity>().count(), 2);
+    }
+
+    #[test]
+    fn entities_run_their_stages_in_order() {
+        let Some(mut renderer) = test_renderer() else {
+            eprintln!("No adapter, skipping");
+            return;
+        };
+        let mut world = World::new(&mut renderer);
+        //Recorders get their own log since simulate can run them in any order
+        let cases: [(&'static [UpdateStage], &[&str]); 3] = [
+            (
+                &[
+                    UpdateStage::PrePhysics,
+                    UpdateStage::PostPhysics,
+                    UpdateStage::PreRender,
+                ],
+                &["pre_physics", "simulate", "update", "pre_render"],
+            ),
+            (&[UpdateStage::PostPhysics], &["simulate", "update"]),
+            (
+                &[UpdateStage::PreRender, UpdateStage::PrePhysics],
+                &["pre_physics", "pre_render"],
+            ),
+        ];
+        let logs: Vec<EventLog> = cases
+            .iter()
+            .map(|(stages, _)| {
+                let log = EventLog::default();
+                world.add_entity(StageRecorder {
+                    stages,
+                    log: log.clone(),
+                });
+                log
+            })
+            .collect();
+
+        world.update(1.0 / 60.0);
+        for (log, (stages, expected)) in logs.iter().zip(cases.iter()) {
+            assert_eq!(*log.lock().unwrap(), *expected, "{:?}", stages);
+        }
+    }
+
+    #[test]
+    fn despawned_entities_can_be_replaced_in_the_same_frame() {
+        let Some(mut renderer) = test_renderer() else {
+            eprintln!("No adapter, skipping");
+            return;
+        };
+        let mut world = World::new(&mut renderer);
+        let log = EventLog::default();
+        let target = world.add_entity(Marker {
+            generation: 0,
+            log: log.clone(),
+        });
+        world.add_entity(Replacer {
+            target,
+            generation: 0,
+            log: log.clone(),
+        });
+
+        let spawned = world.update(1.0 / 60.0);
+        assert_eq!(*log.lock().unwrap(), ["add 0", "remove 0", "add 1"]);
+        assert_eq!(spawned.len(), 1);
+        assert!(!world.entities.contains_key(target));
+        assert_ne!(spawned[0], target);
+        let markers: Vec<u32> = world
+            .entities_of_type::<Marker>()
+            .map(|(_, marker)| marker.generation)
+            .collect();
+        assert_eq!(markers, vec![1]);
+        assert_eq!(world.entities.len(), 2);
+
+        //The commands were used up, nothing changes on the next frame
+        assert!(world.update(1.0 / 60.0).is_empty());
+        assert_eq!(world.entities.len(), 2);
+    }
+
+    #[test]
+    fn saved_definitions_load_back_the_same() {
+        let Some(mut renderer) = test_renderer() else {
+            eprintln!("No adapter, skipping");
+            return;
+        };
+        let module_table = load_module_table();
+        let mut attachment_table = HashMap::new();
+        crate::space_craft::load_attachments_from_directory(
+            Path::new("resource/module"),
+            &mut attachment_table,
+        );
+
+        let definition = crate::space_craft::load_space_craft_definition(
+            "resource/spacecraft/test_craft.spacecraft",
+            &module_table,
+        )
+        .unwrap();
+        let mut craft = SpaceCraftEntity::new(
+            Transform::default(),
+            &definition,
+            &module_table,
+            &attachment_table,
+            &mut renderer,
+        );
+        assert!(!definition.attachments.is_empty());
+        assert_eq!(craft.to_definition(), definition);
+
+        //A partly drained tank has to come back as a module state
+        assert!(craft.drain(ResourceType::Hydrogen, 0.3) > 0.0);
+        let drained = craft.to_definition();
+        assert_eq!(drained.module_states.len(), 1);
+
+        let directory = std::env::temp_dir().join("untitled_space_game_definition_round_trip");
+        std::fs::create_dir_all(&directory).unwrap();
+        let path = save_space_craft_definition(&drained, &directory).unwrap();
+        let loaded: SpaceCraftDefinition = crate::space_craft::load_definition(&path).unwrap();
+        assert_eq!(loaded, drained);
+
+        let rebuilt = SpaceCraftEntity::new(
+            Transform::default(),
+            &loaded,
+            &module_table,
+            &attachment_table,
+            &mut renderer,
+        );
+        assert_eq!(rebuilt.to_definition(), drained);
     }
 }
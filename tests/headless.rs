@@ -0,0 +1,35 @@
+//! Runs the game binary headless, skips when the machine has no adapter at all
+
+use std::process::Command;
+
+#[test]
+fn headless_frame_is_not_black() {
+    let capture_path = std::env::temp_dir().join("untitled_space_game_headless_frame.png");
+    let _ = std::fs::remove_file(&capture_path);
+
+    let output = Command::new(env!("CARGO_BIN_EXE_untitled_space_game"))
+        .current_dir(env!("CARGO_MANIFEST_DIR"))
+        .args(["--headless", "1", "--capture"])
+        .arg(&capture_path)
+        .env("RUST_LOG", "error")
+        .output()
+        .unwrap();
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    if !output.status.success() && stderr.contains("No adapter available") {
+        eprintln!("No adapter, skipping");
+        return;
+    }
+    //Gpu errors also make the run fail
+    assert!(output.status.success(), "headless run failed:\n{stderr}");
+
+    let frame = image::open(&capture_path).unwrap().to_rgba8();
+    let _ = std::fs::remove_file(&capture_path);
+    let lit_pixels = frame
+        .pixels()
+        .filter(|pixel| pixel.0[..3].iter().any(|channel| *channel > 16))
+        .count();
+    assert!(
+        lit_pixels * 100 > frame.pixels().len(),
+        "only {lit_pixels} pixels aren't black"
+    );
+}